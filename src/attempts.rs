@@ -0,0 +1,117 @@
+// Model requests made while generating one message, for failure reports.
+
+use std::time::Duration;
+
+use crate::truncate;
+
+/// One request of the fallback loop.
+pub struct Attempt {
+    pub model: String,
+    pub budget: &'static str,
+    /// Approximate tokens of change context sent.
+    pub context_tokens: usize,
+    /// HTTP status, when the error names one.
+    pub status: Option<u16>,
+    /// Fallback class such as `rateLimit`, or why the reply was rejected.
+    pub class: &'static str,
+    pub duration: Duration,
+    pub error: String,
+}
+
+/// The HTTP status in errors such as `API request failed with status 429`.
+pub fn http_status(message: &str) -> Option<u16> {
+    let (_, rest) = message.split_once("status ")?;
+    let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    (digits.len() == 3).then(|| digits.parse().ok()).flatten()
+}
+
+/// A table with one row per attempt, followed by the last error in full.
+pub fn report(attempts: &[Attempt]) -> String {
+    let header = ["#", "model", "budget", "context", "status", "class", "time"];
+    let rows: Vec<[String; 7]> = attempts
+        .iter()
+        .enumerate()
+        .map(|(index, attempt)| {
+            [
+                (index + 1).to_string(),
+                attempt.model.clone(),
+                attempt.budget.to_string(),
+                format!("~{} tok", attempt.context_tokens),
+                attempt
+                    .status
+                    .map_or_else(|| "-".to_string(), |status| status.to_string()),
+                attempt.class.to_string(),
+                format!("{:.1}s", attempt.duration.as_secs_f64()),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(truncate::width);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(truncate::width(cell));
+        }
+    }
+    let line = |cells: &[String]| {
+        let mut line = String::from("\n");
+        for (cell, width) in cells.iter().zip(widths) {
+            line.push_str(&format!(
+                "  {cell}{}",
+                " ".repeat(width - truncate::width(cell))
+            ));
+        }
+        line.trim_end().to_string()
+    };
+
+    let mut table = line(&header.map(str::to_string));
+    for row in &rows {
+        table.push_str(&line(row));
+    }
+    if let Some(last) = attempts.last() {
+        table.push_str(&format!("\nLast error: {}", last.error));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_tabulates_attempts() {
+        assert_eq!(
+            http_status("API request failed with status 429 Too Many Requests"),
+            Some(429)
+        );
+        assert_eq!(http_status("request timed out"), None);
+
+        let attempts = [
+            Attempt {
+                model: "openai/gpt-4o-mini".to_string(),
+                budget: "primary",
+                context_tokens: 12000,
+                status: Some(413),
+                class: "payloadTooLarge",
+                duration: Duration::from_millis(1250),
+                error: "status 413 Payload Too Large".to_string(),
+            },
+            Attempt {
+                model: "xai/grok-3-mini".to_string(),
+                budget: "fallback",
+                context_tokens: 6000,
+                status: None,
+                class: "timeout",
+                duration: Duration::from_secs(60),
+                error: "request to xai/grok-3-mini timed out after 60s".to_string(),
+            },
+        ];
+        assert_eq!(
+            report(&attempts),
+            "
+  #  model               budget    context     status  class            time
+  1  openai/gpt-4o-mini  primary   ~12000 tok  413     payloadTooLarge  1.2s
+  2  xai/grok-3-mini     fallback  ~6000 tok   -       timeout          60.0s
+Last error: request to xai/grok-3-mini timed out after 60s"
+        );
+    }
+}
@@ -0,0 +1,326 @@
+// GitHub token resolution and authentication error handling.
+
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::profile;
+
+const TOKEN_ENV_KEYS: [&str; 3] = ["GH_TOKEN", "GITHUB_TOKEN", "GITHUB_OAUTH_TOKEN"];
+const TOKEN_CACHE_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Where the GitHub token was resolved from.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum TokenSource {
+    Env(&'static str),
+    GhCli,
+    Cache,
+}
+
+pub struct Token {
+    pub value: String,
+    pub source: TokenSource,
+}
+
+pub fn resolve_host() -> String {
+    env::var("GH_HOST")
+        .or_else(|_| env::var("GITHUB_HOST"))
+        .unwrap_or_else(|_| "github.com".to_string())
+}
+
+/// Resolves a token, consulting the short-lived on-disk cache before `gh`.
+pub fn resolve_token(host: &str, use_cache: bool) -> Result<Token, Box<dyn Error>> {
+    let _span = profile::span("auth", "resolve token");
+    if let Some(token) = env_token() {
+        return Ok(token);
+    }
+
+    if use_cache && let Some(value) = read_cached_token(host) {
+        return Ok(Token {
+            value,
+            source: TokenSource::Cache,
+        });
+    }
+
+    let token = gh_token(host)?;
+    if use_cache {
+        // A failed cache write only costs the next run a `gh` call.
+        let _ = write_cached_token(host, &token.value);
+    }
+    Ok(token)
+}
+
+/// Reads a token from `GH_TOKEN`, `GITHUB_TOKEN`, or `GITHUB_OAUTH_TOKEN`.
+pub fn env_token() -> Option<Token> {
+    for key in TOKEN_ENV_KEYS {
+        if let Ok(token) = env::var(key) {
+            let trimmed = token.trim();
+            if !trimmed.is_empty() {
+                return Some(Token {
+                    value: trimmed.to_string(),
+                    source: TokenSource::Env(key),
+                });
+            }
+        }
+    }
+
+    None
+}
+
+/// Asks `gh` for a fresh token, bypassing the cache.
+pub fn gh_token(host: &str) -> Result<Token, Box<dyn Error>> {
+    let output = Command::new("gh")
+        .args(["auth", "token", "--hostname", host])
+        .output()?;
+
+    if !output.status.success() {
+        return Err("no GitHub token found, please run 'gh auth login' to authenticate".into());
+    }
+
+    let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if token.is_empty() {
+        return Err("no GitHub token found, please run 'gh auth login' to authenticate".into());
+    }
+
+    Ok(Token {
+        value: token,
+        source: TokenSource::GhCli,
+    })
+}
+
+pub fn invalidate_cached_token(host: &str) {
+    if let Some(dir) = cache_dir() {
+        let _ = fs::remove_file(token_cache_path(&dir, host));
+    }
+}
+
+fn read_cached_token(host: &str) -> Option<String> {
+    read_cached_token_in(&cache_dir()?, host, SystemTime::now())
+}
+
+fn read_cached_token_in(dir: &Path, host: &str, now: SystemTime) -> Option<String> {
+    let contents = fs::read_to_string(token_cache_path(dir, host)).ok()?;
+    parse_cached_token(&contents, now)
+}
+
+fn write_cached_token(host: &str, token: &str) -> Result<(), Box<dyn Error>> {
+    let dir = cache_dir().ok_or("no cache directory available")?;
+    write_cached_token_in(&dir, host, token, SystemTime::now())
+}
+
+/// Stores `token` in a file readable only by the user, tightening the
+/// directory and file permissions even when they already existed.
+fn write_cached_token_in(
+    dir: &Path,
+    host: &str,
+    token: &str,
+    now: SystemTime,
+) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+    restrict_permissions(dir, 0o700)?;
+
+    let path = token_cache_path(dir, host);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&path)?;
+    restrict_permissions(&path, 0o600)?;
+
+    let issued = now.duration_since(UNIX_EPOCH)?.as_secs();
+    write!(file, "{issued}\n{token}\n")?;
+    Ok(())
+}
+
+/// Sets unix permission bits on `path`; other platforms rely on the per-user
+/// cache directory.
+fn restrict_permissions(path: &Path, mode: u32) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+}
+
+fn parse_cached_token(contents: &str, now: SystemTime) -> Option<String> {
+    let mut lines = contents.lines();
+    let issued: u64 = lines.next()?.trim().parse().ok()?;
+    let token = lines.next()?.trim();
+    if token.is_empty() {
+        return None;
+    }
+
+    let age = now
+        .duration_since(UNIX_EPOCH + Duration::from_secs(issued))
+        .ok()?;
+    if age > TOKEN_CACHE_TTL {
+        return None;
+    }
+
+    Some(token.to_string())
+}
+
+fn token_cache_path(dir: &Path, host: &str) -> PathBuf {
+    dir.join(format!("token-{}", host.replace(['/', '\\', ':'], "_")))
+}
+
+/// Per-user cache directory for gh-sparkle state.
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("gh-sparkle"))
+}
+
+/// Runs `gh auth refresh` to add the `models` scope to the stored token.
+pub fn refresh_models_scope(host: &str) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("gh")
+        .args(["auth", "refresh", "--hostname", host, "--scopes", "models"])
+        .status()?;
+
+    if !status.success() {
+        return Err(format!("gh auth refresh failed with status {status}").into());
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AuthErrorKind {
+    /// The token was rejected outright (expired, revoked, or malformed).
+    InvalidToken,
+    /// The token is valid but lacks the `models` scope or permission.
+    MissingScope,
+}
+
+/// Authentication failure reported by the GitHub Models API.
+#[derive(Debug)]
+pub struct AuthError {
+    pub kind: AuthErrorKind,
+    pub status: u16,
+    pub from_env: Option<&'static str>,
+}
+
+impl AuthError {
+    pub fn classify(status: u16, body: &str, source: TokenSource) -> Option<Self> {
+        let lower = body.to_lowercase();
+        let kind = match status {
+            401 => AuthErrorKind::InvalidToken,
+            403 if lower.contains("bad credentials") || lower.contains("expired") => {
+                AuthErrorKind::InvalidToken
+            }
+            403 => AuthErrorKind::MissingScope,
+            _ => return None,
+        };
+        let from_env = match source {
+            TokenSource::Env(key) => Some(key),
+            TokenSource::GhCli | TokenSource::Cache => None,
+        };
+
+        Some(Self {
+            kind,
+            status,
+            from_env,
+        })
+    }
+
+    /// Whether `gh auth refresh` can plausibly fix this error.
+    pub fn is_refreshable(&self) -> bool {
+        self.from_env.is_none()
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.kind {
+            AuthErrorKind::InvalidToken => "GitHub token was rejected (expired or revoked)",
+            AuthErrorKind::MissingScope => {
+                "GitHub token lacks access to GitHub Models (missing `models` scope)"
+            }
+        };
+        write!(f, "{reason} (status {})", self.status)?;
+
+        match (self.from_env, self.kind) {
+            (Some(key), _) => write!(f, "; update the token in ${key} or unset it to use gh"),
+            (None, AuthErrorKind::InvalidToken) => {
+                write!(f, "; run 'gh auth login' to sign in again")
+            }
+            (None, AuthErrorKind::MissingScope) => {
+                write!(f, "; run 'gh auth refresh -s models' to grant access")
+            }
+        }
+    }
+}
+
+impl Error for AuthError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_distinguishes_expiry_and_scope() {
+        let expired = AuthError::classify(401, "", TokenSource::GhCli).unwrap();
+        assert_eq!(expired.kind, AuthErrorKind::InvalidToken);
+
+        let scope = AuthError::classify(403, "no access to models", TokenSource::GhCli).unwrap();
+        assert_eq!(scope.kind, AuthErrorKind::MissingScope);
+        assert!(scope.is_refreshable());
+
+        let env = AuthError::classify(403, "", TokenSource::Env("GH_TOKEN")).unwrap();
+        assert!(!env.is_refreshable());
+        assert!(env.to_string().contains("$GH_TOKEN"));
+
+        assert!(AuthError::classify(500, "", TokenSource::GhCli).is_none());
+    }
+
+    #[test]
+    fn parse_cached_token_honors_ttl() {
+        let issued = UNIX_EPOCH + Duration::from_secs(1_000);
+        let contents = "1000\ngho_abc\n";
+        assert_eq!(
+            parse_cached_token(contents, issued + Duration::from_secs(60)).as_deref(),
+            Some("gho_abc")
+        );
+        assert!(parse_cached_token(contents, issued + TOKEN_CACHE_TTL * 2).is_none());
+        assert!(parse_cached_token("garbage", issued).is_none());
+    }
+
+    #[test]
+    fn cached_tokens_are_private_and_expire() {
+        let dir = std::env::temp_dir().join(format!("sparkle-token-{}", std::process::id()));
+        let issued = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let path = token_cache_path(&dir, "github.com");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "stale").unwrap();
+        restrict_permissions(&path, 0o644).unwrap();
+        write_cached_token_in(&dir, "github.com", "gho_secret", issued).unwrap();
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777
+        };
+        let fresh = read_cached_token_in(&dir, "github.com", issued + Duration::from_secs(60));
+        let expired = read_cached_token_in(&dir, "github.com", issued + TOKEN_CACHE_TTL * 2);
+        let other_host = read_cached_token_in(&dir, "ghe.example.com", issued);
+        let _ = fs::remove_dir_all(&dir);
+
+        #[cfg(unix)]
+        assert_eq!(mode, 0o600);
+        assert_eq!(fresh.as_deref(), Some("gho_secret"));
+        assert_eq!(expired, None);
+        assert_eq!(other_host, None);
+    }
+}
@@ -0,0 +1,151 @@
+// Repository discovery for `sparkle batch`.
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::failure::{Classify, Failure, FailureKind};
+use crate::{Cli, load_prompt_config, quota, resolve_model_chain};
+
+/// Repositories listed in `path`, one per line; blank lines and `#` comments
+/// are skipped and relative paths are read from the file's directory.
+pub fn repos_from_file(path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("reading {} failed: {err}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+    Ok(parse_repos_list(&text, base))
+}
+
+fn parse_repos_list(text: &str, base: &Path) -> Vec<PathBuf> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| base.join(line))
+        .collect()
+}
+
+/// The git repositories directly under `root`, sorted by name.
+pub fn child_repos(root: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut repos: Vec<PathBuf> = fs::read_dir(root)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir() && path.join(".git").exists())
+        .collect();
+    repos.sort();
+    Ok(repos)
+}
+
+/// Whether `repo` is a git work tree with changes in its index.
+pub fn has_staged_changes(repo: &Path) -> Result<bool, String> {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["diff", "--cached", "--quiet"])
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|err| err.to_string())?;
+    match status.code() {
+        Some(0) => Ok(false),
+        Some(1) => Ok(true),
+        _ => Err("not a git repository".to_string()),
+    }
+}
+
+/// The options given before the `batch` subcommand, passed on to each run.
+pub fn forwarded_args(args: impl IntoIterator<Item = String>) -> Vec<String> {
+    args.into_iter()
+        .skip(1)
+        .take_while(|arg| arg != "batch")
+        .collect()
+}
+
+/// Runs sparkle in every listed repository that has staged changes, one at
+/// a time, waiting out a shared rate limit before each generation.
+pub fn run(cli: &Cli, repos_file: Option<&Path>, interval: u64) -> Result<(), Box<dyn Error>> {
+    let repos = match repos_file {
+        Some(path) => repos_from_file(path).classify(FailureKind::Config)?,
+        None => child_repos(&std::env::current_dir()?).classify(FailureKind::Git)?,
+    };
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)
+        .classify(FailureKind::Config)?;
+    let exe = std::env::current_exe()?;
+    let args = forwarded_args(std::env::args());
+
+    let (mut committed, mut skipped, mut failed) = (0, 0, Vec::new());
+    for repo in &repos {
+        match has_staged_changes(repo) {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("  {}: nothing staged, skipped", repo.display());
+                skipped += 1;
+                continue;
+            }
+            Err(err) => {
+                println!("  {}: {err}, skipped", repo.display());
+                skipped += 1;
+                continue;
+            }
+        }
+        if committed + failed.len() > 0 && interval > 0 {
+            std::thread::sleep(Duration::from_secs(interval));
+        }
+        let now = quota::now();
+        if let Some(refill) = quota::next_refill(&model_chain, &quota::load(), now) {
+            println!(
+                "  Rate limit reached for {}; waiting {}s for it to reset",
+                model_chain.join(", "),
+                refill - now
+            );
+            std::thread::sleep(Duration::from_secs(refill - now));
+        }
+
+        println!("==> {}", repo.display());
+        let status = std::process::Command::new(&exe)
+            .args(&args)
+            .current_dir(repo)
+            .status()?;
+        match status.code() {
+            Some(0) => committed += 1,
+            code => failed.push((repo, code)),
+        }
+    }
+
+    println!(
+        "Batch done: {committed} succeeded, {skipped} skipped, {} failed.",
+        failed.len()
+    );
+    let Some((repo, code)) = failed.first() else {
+        return Ok(());
+    };
+    let message = format!(
+        "{} of {} repositories failed, first {}",
+        failed.len(),
+        repos.len(),
+        repo.display()
+    );
+    Err(match code.and_then(FailureKind::from_exit_code) {
+        Some(kind) => Failure::new(kind, message).into(),
+        None => message.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repo_list_and_forwarded_options() {
+        let text = "# services\napi\n\n  /srv/web  \n";
+        assert_eq!(
+            parse_repos_list(text, Path::new("/work")),
+            [PathBuf::from("/work/api"), PathBuf::from("/srv/web")]
+        );
+        let args = ["gh-sparkle", "-m", "auto", "--yes", "batch", "--recurse"];
+        assert_eq!(
+            forwarded_args(args.map(String::from)),
+            ["-m", "auto", "--yes"]
+        );
+    }
+}
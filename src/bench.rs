@@ -0,0 +1,202 @@
+// Scoring of generated commit messages against the real ones in history.
+
+use std::error::Error;
+
+use crate::duplicate;
+use crate::failure::{Classify, Failure, FailureKind};
+use crate::{
+    Cli, GenerationContext, git, load_prompt_config, new_llm_client, parse_language, policy,
+    produce_commit_message, resolve_model_chain,
+};
+
+/// Subject length git tooling and most style guides recommend staying under.
+const MAX_SUBJECT_CHARS: usize = 72;
+
+/// How one model did across the benchmarked commits.
+pub struct ModelScore {
+    pub model: String,
+    generated: usize,
+    failed: usize,
+    subject_chars: usize,
+    short_subjects: usize,
+    typed: usize,
+    type_matches: usize,
+    similarity: f64,
+}
+
+impl ModelScore {
+    pub fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            generated: 0,
+            failed: 0,
+            subject_chars: 0,
+            short_subjects: 0,
+            typed: 0,
+            type_matches: 0,
+            similarity: 0.0,
+        }
+    }
+
+    /// Scores a generated message against the subject that was committed.
+    pub fn record(&mut self, generated: &str, actual_subject: &str) {
+        let subject = generated.lines().next().unwrap_or_default();
+        let chars = subject.chars().count();
+        self.generated += 1;
+        self.subject_chars += chars;
+        if chars <= MAX_SUBJECT_CHARS {
+            self.short_subjects += 1;
+        }
+        if let Some(actual_type) = commit_type(actual_subject) {
+            self.typed += 1;
+            if commit_type(subject) == Some(actual_type) {
+                self.type_matches += 1;
+            }
+        }
+        self.similarity += duplicate::subject_similarity(subject, actual_subject);
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failed += 1;
+    }
+}
+
+/// The Conventional Commit type of a subject such as `feat(ui)!: add`.
+pub fn commit_type(subject: &str) -> Option<&str> {
+    let (prefix, _) = subject.split_once(':')?;
+    let kind = prefix.split(['(', '!']).next().unwrap_or_default().trim();
+    (!kind.is_empty() && kind.chars().all(|ch| ch.is_ascii_alphabetic())).then_some(kind)
+}
+
+/// Renders one row per model.
+pub fn format_table(scores: &[ModelScore]) -> String {
+    let width = scores
+        .iter()
+        .map(|score| score.model.len())
+        .max()
+        .unwrap_or(0)
+        .max("MODEL".len());
+    let mut table = format!(
+        "{:<width$}  {:>6}  {:>7}  {:>7}  {:>6}  {:>10}\n",
+        "MODEL", "OK", "AVG LEN", "<=72", "TYPE", "SIMILARITY"
+    );
+    for score in scores {
+        let total = score.generated + score.failed;
+        let type_accuracy = if score.typed == 0 {
+            "n/a".to_string()
+        } else {
+            percent(score.type_matches, score.typed)
+        };
+        let (average_chars, short, similarity) =
+            match score.subject_chars.checked_div(score.generated) {
+                None => ("-".to_string(), "-".to_string(), "-".to_string()),
+                Some(average_chars) => (
+                    average_chars.to_string(),
+                    percent(score.short_subjects, score.generated),
+                    format!("{:.2}", score.similarity / score.generated as f64),
+                ),
+            };
+        table.push_str(&format!(
+            "{:<width$}  {:>6}  {:>7}  {:>7}  {:>6}  {:>10}\n",
+            score.model,
+            format!("{}/{total}", score.generated),
+            average_chars,
+            short,
+            type_accuracy,
+            similarity,
+        ));
+    }
+    table
+}
+
+fn percent(part: usize, whole: usize) -> String {
+    format!("{}%", part * 100 / whole)
+}
+
+/// Replays recent commits through each model and prints how close the
+/// generated messages come to the committed ones.
+pub fn run(cli: &Cli, count: usize, models: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    let language_spec = parse_language(&cli.language)?;
+    let models = if models.is_empty() {
+        resolve_model_chain(&cli.model, &prompt_config.model_policy)
+            .classify(FailureKind::Config)?
+    } else {
+        let org_policy = policy::current().map_err(|err| Failure::new(FailureKind::Config, err))?;
+        if let Some(reason) = org_policy.and_then(|org_policy| org_policy.models_violation(&models))
+        {
+            return Err(Failure::new(FailureKind::Config, reason).into());
+        }
+        models
+    };
+
+    let commits = git::recent_commits(count).classify(FailureKind::Git)?;
+    if commits.is_empty() {
+        return Err(Failure::new(FailureKind::Git, "no commits to benchmark").into());
+    }
+    let diffs = commits
+        .iter()
+        .map(|commit| git::commit_diff(&commit.sha))
+        .collect::<Result<Vec<_>, _>>()
+        .classify(FailureKind::Git)?;
+
+    let llm_client = new_llm_client(cli, &prompt_config.network).classify(FailureKind::Auth)?;
+    let mut scores = Vec::with_capacity(models.len());
+    for model in &models {
+        let mut score = ModelScore::new(model);
+        for (index, (commit, diff)) in commits.iter().zip(&diffs).enumerate() {
+            println!(
+                "  {model}: {}/{} {}",
+                index + 1,
+                commits.len(),
+                &commit.sha[..7]
+            );
+            let context = GenerationContext::new(
+                &prompt_config,
+                &diff.summary,
+                &diff.patch,
+                std::slice::from_ref(model),
+                &language_spec,
+            );
+            match produce_commit_message(&llm_client, &context, |_| {}, |_| false) {
+                Ok(draft) => score.record(&draft.message, &commit.subject),
+                Err(err) => {
+                    println!("  {model}: {}: {err}", &commit.sha[..7]);
+                    score.record_failure();
+                }
+            }
+        }
+        scores.push(score);
+    }
+
+    println!();
+    print!("{}", format_table(&scores));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commit_type_reads_conventional_prefixes() {
+        assert_eq!(commit_type("feat(ui)!: add spinner"), Some("feat"));
+        assert_eq!(commit_type("fix: handle empty input"), Some("fix"));
+        assert_eq!(commit_type("Merge branch 'main'"), None);
+        assert_eq!(commit_type("see http://example.com: docs"), None);
+    }
+
+    #[test]
+    fn format_table_reports_averages_per_model() {
+        let mut score = ModelScore::new("openai/gpt-4o-mini");
+        score.record("fix: handle empty input\n", "fix: handle empty input");
+        score.record("feat: add cache\n", "perf: cache lookups");
+        score.record_failure();
+        let table = format_table(&[score]);
+        let row = table.lines().nth(1).unwrap();
+        assert_eq!(
+            row.split_whitespace().collect::<Vec<_>>(),
+            ["openai/gpt-4o-mini", "2/3", "19", "100%", "50%", "0.60"]
+        );
+    }
+}
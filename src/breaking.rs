@@ -0,0 +1,158 @@
+// Heuristic detection of API-breaking changes in a staged patch.
+
+const PUBLIC_DECLARATIONS: [&str; 14] = [
+    "pub fn ",
+    "pub async fn ",
+    "pub struct ",
+    "pub enum ",
+    "pub trait ",
+    "pub type ",
+    "pub const ",
+    "export function ",
+    "export async function ",
+    "export class ",
+    "export interface ",
+    "export type ",
+    "export const ",
+    "export default function ",
+];
+
+const VERSION_FILES: [&str; 3] = ["Cargo.toml", "package.json", "pyproject.toml"];
+
+/// Reasons the patch looks API-breaking, one per finding.
+pub fn detect(patch: &str) -> Vec<String> {
+    let mut reasons = Vec::new();
+    let mut file = "";
+    let mut removed: Vec<(&str, String)> = Vec::new();
+    let mut added: Vec<(&str, String)> = Vec::new();
+    let mut old_version: Option<String> = None;
+
+    for line in patch.lines() {
+        if let Some(path) = line.strip_prefix("+++ b/") {
+            file = path;
+            continue;
+        }
+        if line.starts_with("--- ") || line.starts_with("+++ ") {
+            continue;
+        }
+
+        let (removed_line, content) = match (line.strip_prefix('-'), line.strip_prefix('+')) {
+            (Some(content), _) => (true, content),
+            (_, Some(content)) => (false, content),
+            _ => continue,
+        };
+
+        if let Some(name) = declared_name(content) {
+            let entry = (name, normalize(content));
+            if removed_line {
+                removed.push(entry);
+            } else {
+                added.push(entry);
+            }
+        }
+
+        if is_version_file(file)
+            && let Some(version) = version_value(content)
+        {
+            if removed_line {
+                old_version = Some(version);
+            } else if let Some(old) = old_version.take()
+                && major(&version) > major(&old)
+                && major(&old) > Some(0)
+            {
+                reasons.push(format!("{file}: major version bump {old} -> {version}"));
+            }
+        }
+    }
+
+    for (name, signature) in &removed {
+        match added.iter().find(|(added_name, _)| added_name == name) {
+            None => reasons.push(format!("removed public item `{name}`")),
+            Some((_, new_signature)) if new_signature != signature => {
+                reasons.push(format!("changed signature of `{name}`"))
+            }
+            Some(_) => {}
+        }
+    }
+
+    reasons
+}
+
+fn declared_name(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let rest = PUBLIC_DECLARATIONS
+        .iter()
+        .find_map(|prefix| line.strip_prefix(prefix))?;
+    let name: &str = rest
+        .split(|ch: char| !(ch.is_alphanumeric() || ch == '_'))
+        .next()?;
+    (!name.is_empty()).then_some(name)
+}
+
+fn normalize(line: &str) -> String {
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn is_version_file(path: &str) -> bool {
+    VERSION_FILES
+        .iter()
+        .any(|name| path == *name || path.ends_with(&format!("/{name}")))
+}
+
+/// Reads `version = "1.2.3"` or `"version": "1.2.3"`.
+fn version_value(line: &str) -> Option<String> {
+    let line = line.trim().trim_end_matches(',');
+    let rest = line
+        .strip_prefix("version")
+        .or_else(|| line.strip_prefix("\"version\""))?;
+    let value = rest.trim_start().strip_prefix(['=', ':'])?.trim();
+    Some(value.trim_matches('"').to_string())
+}
+
+fn major(version: &str) -> Option<u64> {
+    version.split('.').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_finds_removed_and_changed_public_items() {
+        let patch = "\
+diff --git a/src/lib.rs b/src/lib.rs
+--- a/src/lib.rs
++++ b/src/lib.rs
+-pub fn load(path: &str) -> Config {
++pub fn load(path: &Path) -> Config {
+-pub struct Legacy;
+-fn private_helper() {}
+ pub fn untouched() {}
+";
+        assert_eq!(
+            detect(patch),
+            vec![
+                "changed signature of `load`".to_string(),
+                "removed public item `Legacy`".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn detect_finds_major_version_bumps() {
+        let patch = "\
+--- a/Cargo.toml
++++ b/Cargo.toml
+-version = \"1.4.2\"
++version = \"2.0.0\"
+--- a/web/package.json
++++ b/web/package.json
+-  \"version\": \"0.9.0\",
++  \"version\": \"1.0.0\",
+";
+        assert_eq!(
+            detect(patch),
+            vec!["Cargo.toml: major version bump 1.4.2 -> 2.0.0".to_string()]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+// The GitHub Models catalog, cached between runs for chain preflight.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::auth;
+use crate::llm;
+use crate::quota;
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Cached {
+    /// Unix seconds at which the catalog was fetched.
+    fetched_at: u64,
+    models: Vec<String>,
+}
+
+/// Model ids the token can see, from a cache younger than `max_age` seconds
+/// or else from the catalog endpoint.
+pub fn models(client: &llm::Client, max_age: u64) -> Result<Vec<String>, Box<dyn Error>> {
+    let path = cache_path(&auth::resolve_host());
+    let now = quota::now();
+    if let Some(cached) = path
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|text| serde_json::from_str::<Cached>(&text).ok())
+        .filter(|cached| now.saturating_sub(cached.fetched_at) < max_age)
+    {
+        return Ok(cached.models);
+    }
+
+    let models = client.list_models()?;
+    if let Some(path) = path {
+        let cached = Cached {
+            fetched_at: now,
+            models,
+        };
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = fs::write(&path, serde_json::to_string(&cached)?);
+        return Ok(cached.models);
+    }
+    Ok(models)
+}
+
+/// Splits `chain` into the models listed in `available` and the rest. When
+/// none are listed the chain is kept whole: a stale or partial catalog must
+/// not leave nothing to try.
+pub fn entitled(chain: Vec<String>, available: &[String]) -> (Vec<String>, Vec<String>) {
+    let (kept, dropped): (Vec<String>, Vec<String>) = chain
+        .into_iter()
+        .partition(|model| available.iter().any(|id| id.eq_ignore_ascii_case(model)));
+    if kept.is_empty() {
+        return (dropped, Vec::new());
+    }
+    (kept, dropped)
+}
+
+fn cache_path(host: &str) -> Option<PathBuf> {
+    let file_name = format!("catalog-{}.json", host.replace(['/', '\\', ':'], "_"));
+    Some(auth::cache_dir()?.join(file_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entitled_drops_unlisted_models_but_never_all() {
+        let chain = || {
+            vec![
+                "openai/gpt-4o-mini".to_string(),
+                "xai/grok-3".to_string(),
+                "openai/gpt-4.1".to_string(),
+            ]
+        };
+        let available = [
+            "OpenAI/gpt-4o-mini".to_string(),
+            "openai/gpt-4.1".to_string(),
+        ];
+        assert_eq!(
+            entitled(chain(), &available),
+            (
+                vec![
+                    "openai/gpt-4o-mini".to_string(),
+                    "openai/gpt-4.1".to_string()
+                ],
+                vec!["xai/grok-3".to_string()]
+            )
+        );
+        assert_eq!(entitled(chain(), &[]), (chain(), Vec::new()));
+    }
+}
@@ -0,0 +1,192 @@
+// Non-interactive CI integration: GitHub Actions outputs and annotations.
+
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::failure::{Classify, Failure, FailureKind};
+use crate::{
+    Cli, GenerationContext, auth, convention_document, git, llm, load_prompt_config,
+    new_llm_client, open_recording, parse_language, produce_commit_message, project_docs,
+    resolve_model_chain,
+};
+
+/// CI systems sparkle knows how to report to.
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Mode {
+    /// Write step outputs, a step summary, and `::error::` annotations
+    GithubActions,
+}
+
+/// The branch a pull request targets, as set by the `pull_request` event.
+pub fn base_ref() -> Option<String> {
+    std::env::var("GITHUB_BASE_REF")
+        .ok()
+        .filter(|base| !base.trim().is_empty())
+}
+
+/// Appends `name=value` pairs to `$GITHUB_OUTPUT`, or prints them when the
+/// variable is unset (local dry runs).
+pub fn write_outputs(outputs: &[(&str, &str)]) -> Result<(), Box<dyn Error>> {
+    let mut text = String::new();
+    for (name, value) in outputs {
+        text.push_str(&output_entry(name, value));
+    }
+    append_to_env_file("GITHUB_OUTPUT", &text)
+}
+
+/// Appends markdown to the job's step summary.
+pub fn write_summary(markdown: &str) -> Result<(), Box<dyn Error>> {
+    append_to_env_file("GITHUB_STEP_SUMMARY", markdown)
+}
+
+fn append_to_env_file(variable: &str, text: &str) -> Result<(), Box<dyn Error>> {
+    let Some(path) = std::env::var_os(variable) else {
+        print!("{text}");
+        return Ok(());
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|err| format!("could not open ${variable}: {err}"))?;
+    file.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+/// Formats one output with the heredoc syntax, which also carries multi-line
+/// values. The delimiter is chosen so it cannot occur in `value`.
+fn output_entry(name: &str, value: &str) -> String {
+    let mut delimiter = "SPARKLE_EOF".to_string();
+    while value.lines().any(|line| line == delimiter) {
+        delimiter.push('_');
+    }
+    let value = value.strip_suffix('\n').unwrap_or(value);
+    format!("{name}<<{delimiter}\n{value}\n{delimiter}\n")
+}
+
+/// An `::error::` workflow command, which Actions turns into an annotation.
+pub fn error_command(message: &str) -> String {
+    format!("::error title=sparkle::{}", escape_data(message))
+}
+
+fn escape_data(message: &str) -> String {
+    message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Generates a pull request title and description from the merge-base diff
+/// and reports it through the CI system. Errors are also emitted as
+/// annotations.
+pub fn run(cli: &Cli, mode: Mode) -> Result<(), Box<dyn Error>> {
+    let Mode::GithubActions = mode;
+    let result = describe(cli);
+    if let Err(err) = &result {
+        println!("{}", error_command(&err.to_string()));
+    }
+    result
+}
+
+fn describe(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    let deadline = started + Duration::from_secs(prompt_config.network.run_deadline);
+    let language_spec = parse_language(&cli.language)?;
+    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)
+        .classify(FailureKind::Config)?;
+
+    let base = match base_ref() {
+        Some(base) => format!("origin/{base}"),
+        None => git::default_base().classify(FailureKind::Git)?,
+    };
+    let fork_point = git::merge_base(&base).classify(FailureKind::Git)?;
+    let diff = git::diff_since(&fork_point).classify(FailureKind::Git)?;
+    if diff.patch.trim().is_empty() {
+        return Err(Failure::new(
+            FailureKind::NoStagedChanges,
+            format!("no changes between {base} and HEAD"),
+        )
+        .into());
+    }
+
+    let llm_client = match &cli.replay {
+        Some(_) => new_llm_client(cli, &prompt_config.network),
+        None => auth::env_token()
+            .ok_or_else(|| {
+                "GITHUB_TOKEN is not set; pass it to the step and grant the job `models: read`"
+                    .into()
+            })
+            .and_then(|token| {
+                llm::Client::from_token(auth::resolve_host(), token, &prompt_config.network)
+            })
+            .and_then(|client| Ok(client.with_recording(open_recording(cli)?))),
+    }
+    .classify(FailureKind::Auth)?;
+
+    let docs = project_docs(&prompt_config, &diff.patch);
+    let convention = convention_document(&prompt_config);
+    let context = GenerationContext {
+        deadline,
+        docs: &docs,
+        convention: &convention,
+        ..GenerationContext::new(
+            &prompt_config,
+            &diff.summary,
+            &diff.patch,
+            &model_chain,
+            &language_spec,
+        )
+    };
+    let draft = produce_commit_message(
+        &llm_client,
+        &context,
+        |message| println!("{message}"),
+        |_| false,
+    )?;
+
+    let (title, body) = draft
+        .message
+        .split_once('\n')
+        .unwrap_or((draft.message.as_str(), ""));
+    let body = body.trim();
+    write_outputs(&[
+        ("title", title),
+        ("body", body),
+        ("message", &draft.message),
+    ])?;
+    write_summary(&format!("### {title}\n\n{body}\n"))?;
+    println!(
+        "Generated with {} in {:.1?}.",
+        draft.model,
+        started.elapsed()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_entry_uses_a_delimiter_absent_from_the_value() {
+        assert_eq!(
+            output_entry("title", "feat: add ci mode\n"),
+            "title<<SPARKLE_EOF\nfeat: add ci mode\nSPARKLE_EOF\n"
+        );
+        assert_eq!(
+            output_entry("body", "a\nSPARKLE_EOF\nb"),
+            "body<<SPARKLE_EOF_\na\nSPARKLE_EOF\nb\nSPARKLE_EOF_\n"
+        );
+    }
+
+    #[test]
+    fn error_command_escapes_line_breaks() {
+        assert_eq!(
+            error_command("request failed: 100%\nretry later"),
+            "::error title=sparkle::request failed: 100%25%0Aretry later"
+        );
+    }
+}
@@ -0,0 +1,828 @@
+// Layered configuration: embedded prompt defaults, an optional prompt file,
+// and repository overrides, nested ones included, under an optional
+// organization policy.
+
+use serde_yaml::Value;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use crate::git;
+use crate::migrate;
+use crate::policy;
+use crate::prompt::{self, PromptConfig};
+
+pub const REPO_CONFIG_FILE: &str = ".sparkle.yml";
+pub const CONFIG_SCHEMA_JSON: &str = include_str!("../assets/sparkle.schema.json");
+pub const PROMPT_FILE_ENV: &str = "SPARKLE_PROMPT";
+const ENV_PREFIX: &str = "SPARKLE_";
+/// Command line options that environment variables can set.
+const CLI_ENV: &[&str] = &["LANGUAGE", "MODEL", "STYLE"];
+/// Short names for frequently set config keys.
+const ENV_ALIASES: &[(&str, &str)] = &[
+    ("MAX_TOKENS", "contextPolicy.budgets.responseTokens"),
+    ("TEMPERATURE", "modelParameters.temperature"),
+    ("TOP_P", "modelParameters.topP"),
+    ("TIMEOUT", "network.requestTimeout"),
+];
+
+/// Config keys that make sparkle run a program or write a file, with what
+/// they do. A repository's `.sparkle.yml` may set them only once the user
+/// trusts it, so cloning a repository and running sparkle never runs
+/// commands it ships or appends to a path it picks.
+const COMMAND_KEYS: &[(&str, &str)] = &[
+    ("contextPolicy.builderCommand", "runs a command"),
+    ("contextPolicy.commands", "runs a command"),
+    ("postprocess.command", "runs a command"),
+    ("security.auditLog", "writes to a file"),
+];
+/// Config keys that decide where requests carrying the GitHub token go and
+/// which servers they trust. A repository's `.sparkle.yml` may never set
+/// them, trusted or not; they come from the prompt file or `SPARKLE_*`
+/// variables.
+const TRANSPORT_KEYS: &[&str] = &[
+    "network.proxy",
+    "network.caBundle",
+    "network.clientCert",
+    "network.clientKey",
+];
+/// The git config setting that lets `.sparkle.yml` files set `COMMAND_KEYS`.
+/// Git never copies it with a clone.
+const TRUST_COMMANDS_SETTING: &str = "trustcommands";
+
+static PROMPT_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Layers `path` over the embedded config for the rest of the run
+/// (`--prompt-file` or `SPARKLE_PROMPT`).
+pub fn set_prompt_file(path: PathBuf) {
+    let _ = PROMPT_FILE.set(path);
+}
+
+/// Loads the embedded config merged with the prompt file, if any, the
+/// repository `.sparkle.yml` files, and `SPARKLE_*` environment variables.
+pub fn load() -> Result<PromptConfig, Box<dyn Error>> {
+    load_layers(&layer_paths(), env_layer(std::env::vars()))
+}
+
+/// `SPARKLE_LANGUAGE`, `SPARKLE_MODEL`, and `SPARKLE_STYLE`, named like their
+/// `git config sparkle.*` counterparts.
+pub fn cli_env_settings(vars: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+    vars.filter_map(|(name, value)| {
+        let name = name.strip_prefix(ENV_PREFIX)?;
+        CLI_ENV
+            .contains(&name)
+            .then(|| (name.to_lowercase(), value))
+    })
+    .collect()
+}
+
+/// Builds a config layer from environment variables. `SPARKLE_A__B_C` sets
+/// `a.bC`: `__` separates keys, each written in upper snake case. A few
+/// common keys also have short names such as `SPARKLE_MAX_TOKENS`. Values
+/// are read as YAML, so numbers, booleans, and `[a, b]` lists work.
+fn env_layer(vars: impl Iterator<Item = (String, String)>) -> Value {
+    let mut layer = Value::Mapping(Default::default());
+    for (name, value) in vars {
+        let Some(name) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let path: Vec<String> = match ENV_ALIASES.iter().find(|(alias, _)| *alias == name) {
+            Some((_, path)) => path.split('.').map(str::to_string).collect(),
+            None if name.contains("__") => name.split("__").map(camel_case).collect(),
+            None => continue,
+        };
+        let value = serde_yaml::from_str(&value).unwrap_or(Value::String(value));
+        let overlay = path.iter().rev().fold(value, |value, key| {
+            let mut map = serde_yaml::Mapping::new();
+            map.insert(Value::String(key.clone()), value);
+            Value::Mapping(map)
+        });
+        merge_values(&mut layer, overlay);
+    }
+    layer
+}
+
+fn camel_case(key: &str) -> String {
+    let mut words = key.split('_').filter(|word| !word.is_empty());
+    let mut name = words.next().unwrap_or_default().to_lowercase();
+    for word in words {
+        let word = word.to_lowercase();
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+    name
+}
+
+/// Config files layered over the embedded defaults, lowest first.
+pub fn layer_paths() -> Vec<PathBuf> {
+    PROMPT_FILE
+        .get()
+        .cloned()
+        .into_iter()
+        .chain(repo_config_paths())
+        .collect()
+}
+
+/// The `.sparkle.yml` files of the repository that apply to this run, root
+/// first: every one from the root down to the nested directory whose config
+/// covers most staged files, or down to the current directory when nothing
+/// is staged.
+pub fn repo_config_paths() -> Vec<PathBuf> {
+    let Ok(cwd) = std::env::current_dir() else {
+        return Vec::new();
+    };
+    let Some(root) = cwd.ancestors().find(|dir| dir.join(".git").exists()) else {
+        return find_repo_config().into_iter().collect();
+    };
+    let has_config = |dir: &Path| root.join(dir).join(REPO_CONFIG_FILE).is_file();
+    let staged = git::staged_paths();
+    let target = if staged.is_empty() {
+        cwd.strip_prefix(root)
+            .unwrap_or(Path::new(""))
+            .to_path_buf()
+    } else {
+        majority_config_dir(&staged, has_config)
+    };
+    config_chain(&target, has_config)
+        .into_iter()
+        .map(|dir| root.join(dir).join(REPO_CONFIG_FILE))
+        .collect()
+}
+
+/// The directory, relative to the root, of the nested config that covers
+/// the most `staged` paths; ties go to the shallower one.
+fn majority_config_dir(staged: &[String], has_config: impl Fn(&Path) -> bool) -> PathBuf {
+    let mut counts: Vec<(PathBuf, usize)> = Vec::new();
+    for path in staged {
+        let dir = Path::new(path)
+            .ancestors()
+            .skip(1)
+            .find(|dir| dir.as_os_str().is_empty() || has_config(dir))
+            .unwrap_or(Path::new(""));
+        match counts.iter_mut().find(|(counted, _)| counted == dir) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((dir.to_path_buf(), 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(dir, count)| (*count, std::cmp::Reverse(dir.components().count())))
+        .map(|(dir, _)| dir)
+        .unwrap_or_default()
+}
+
+/// The directories from the root down to `target` that hold a config.
+fn config_chain(target: &Path, has_config: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut chain: Vec<PathBuf> = target
+        .ancestors()
+        .filter(|dir| has_config(dir))
+        .map(Path::to_path_buf)
+        .collect();
+    chain.reverse();
+    chain
+}
+
+/// `defaults.language`, `defaults.model`, and `defaults.style` from the
+/// repository config files, as settings like `git config sparkle.*`.
+pub fn repo_defaults() -> Vec<(String, String)> {
+    let mut defaults = Vec::new();
+    for path in repo_config_paths() {
+        let Some(layer) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|source| serde_yaml::from_str::<Value>(&source).ok())
+        else {
+            continue;
+        };
+        let Some(section) = layer.get("defaults").and_then(|section| {
+            serde_yaml::from_value::<prompt::DefaultsConfig>(section.clone()).ok()
+        }) else {
+            continue;
+        };
+        let settings = [
+            ("language", section.language),
+            ("model", section.model),
+            ("style", section.style),
+        ];
+        for (key, value) in settings {
+            if let Some(value) = value {
+                defaults.push((key.to_string(), value));
+            }
+        }
+    }
+    defaults
+}
+
+type Stamp = (PathBuf, Option<(SystemTime, u64)>);
+
+/// Notices edits to the config files of a long-running process.
+pub struct Reloader {
+    paths: Box<dyn Fn() -> Vec<PathBuf>>,
+    stamps: Vec<Stamp>,
+}
+
+impl Reloader {
+    /// Watches the files `load` reads, including a `.sparkle.yml` created
+    /// later.
+    pub fn new() -> Self {
+        Self::watching(Box::new(layer_paths))
+    }
+
+    fn watching(paths: Box<dyn Fn() -> Vec<PathBuf>>) -> Self {
+        let stamps = stamps(&paths());
+        Self { paths, stamps }
+    }
+
+    /// Loads and validates the config again once a file changed. On error
+    /// the caller should keep using its previous config; the next change is
+    /// tried again.
+    pub fn poll(&mut self) -> Option<Result<PromptConfig, Box<dyn Error>>> {
+        let paths = (self.paths)();
+        let current = stamps(&paths);
+        if current == self.stamps {
+            return None;
+        }
+        self.stamps = current;
+        Some(load_layers(&paths, env_layer(std::env::vars())))
+    }
+}
+
+fn stamps(paths: &[PathBuf]) -> Vec<Stamp> {
+    paths
+        .iter()
+        .map(|path| {
+            let stamp = fs::metadata(path)
+                .and_then(|meta| Ok((meta.modified()?, meta.len())))
+                .ok();
+            (path.clone(), stamp)
+        })
+        .collect()
+}
+
+fn load_layers(paths: &[PathBuf], env: Value) -> Result<PromptConfig, Box<dyn Error>> {
+    let org_policy = policy::current()?;
+    let mut merged = prompt::default_config_value()?;
+    for path in paths {
+        let source =
+            fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        let overlay =
+            parse_layer(&source, Some(path)).map_err(|issue| issue.with_path(path).to_string())?;
+        if let Some(message) = org_policy
+            .and_then(|policy| policy.locked_violation(&overlay, &path.display().to_string()))
+        {
+            return Err(issue_at_key(&source, &message).with_path(path).into());
+        }
+        if is_repo_layer(path)
+            && let Some(message) = repo_transport(&overlay, path)
+                .or_else(|| untrusted_command(&overlay, path, trusts_repo_commands))
+        {
+            return Err(issue_at_key(&source, &message).with_path(path).into());
+        }
+        merge_values(&mut merged, overlay);
+    }
+    if let Some(message) = org_policy
+        .and_then(|policy| policy.locked_violation(&env, "the SPARKLE_* environment variables"))
+    {
+        return Err(message.into());
+    }
+    merge_values(&mut merged, env);
+    if let Some(policy) = org_policy {
+        merge_values(&mut merged, policy.locked.clone());
+    }
+
+    let config = from_merged(&merged)?;
+    prompt::validate_prompt_config(&config)?;
+    Ok(config)
+}
+
+/// Whether `path` is one of the repository's `.sparkle.yml` files rather
+/// than the prompt file the user chose.
+fn is_repo_layer(path: &Path) -> bool {
+    PROMPT_FILE
+        .get()
+        .is_none_or(|prompt_file| prompt_file != path)
+}
+
+/// Whether the user set `git config sparkle.trustCommands true`.
+fn trusts_repo_commands() -> bool {
+    git::sparkle_settings()
+        .iter()
+        .any(|(key, value)| key == TRUST_COMMANDS_SETTING && is_true(value))
+}
+
+fn is_true(value: &str) -> bool {
+    matches!(
+        value.to_ascii_lowercase().as_str(),
+        "true" | "yes" | "on" | "1"
+    )
+}
+
+/// The first of `keys` that `layer` sets.
+fn set_key<'a>(layer: &Value, keys: &[&'a str]) -> Option<&'a str> {
+    keys.iter().copied().find(|key| {
+        key.split('.')
+            .try_fold(layer, |value, segment| value.get(segment))
+            .is_some()
+    })
+}
+
+/// The message for a transport key that the repository `layer` at `path`
+/// sets.
+fn repo_transport(layer: &Value, path: &Path) -> Option<String> {
+    let key = set_key(layer, TRANSPORT_KEYS)?;
+    Some(format!(
+        "{key} controls where requests with your GitHub token go and is not accepted from {}: \
+         set it in your --prompt-file or a SPARKLE_* variable instead",
+        path.display()
+    ))
+}
+
+/// The message for a command key that the repository `layer` at `path` sets
+/// without the user's trust. `trusted` is only asked when one is set.
+fn untrusted_command(layer: &Value, path: &Path, trusted: impl Fn() -> bool) -> Option<String> {
+    let keys: Vec<&str> = COMMAND_KEYS.iter().map(|(key, _)| *key).collect();
+    let key = set_key(layer, &keys)?;
+    if trusted() {
+        return None;
+    }
+    let action = COMMAND_KEYS
+        .iter()
+        .find_map(|(name, action)| (*name == key).then_some(*action))?;
+    Some(format!(
+        "{key} {action} and is not accepted from {} unless you trust this repository: \
+         review the file, then run `git config sparkle.trustCommands true`, or set it in your \
+         --prompt-file instead",
+        path.display()
+    ))
+}
+
+/// Validates a config file as a layer over the embedded defaults.
+pub fn validate_file(path: &Path) -> Result<(), ConfigIssue> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| ConfigIssue::new(err.to_string()).with_path(path))?;
+    validate_source(&source, Some(path)).map_err(|issue| issue.with_path(path))
+}
+
+fn validate_source(source: &str, path: Option<&Path>) -> Result<(), ConfigIssue> {
+    let overlay = parse_layer(source, path)?;
+    let org_policy = policy::current().map_err(ConfigIssue::new)?;
+    if let Some(message) = org_policy.and_then(|policy| {
+        let name = path.map_or("the config".into(), |path| path.display().to_string());
+        policy.locked_violation(&overlay, &name)
+    }) {
+        return Err(issue_at_key(source, &message));
+    }
+    if let Some(message) = path.filter(|path| is_repo_layer(path)).and_then(|path| {
+        repo_transport(&overlay, path)
+            .or_else(|| untrusted_command(&overlay, path, trusts_repo_commands))
+    }) {
+        return Err(issue_at_key(source, &message));
+    }
+    let mut merged =
+        prompt::default_config_value().map_err(|err| ConfigIssue::new(err.to_string()))?;
+    merge_values(&mut merged, overlay);
+
+    let config = from_merged(&merged).map_err(|err| issue_at_key(source, &err.to_string()))?;
+    prompt::validate_prompt_config(&config).map_err(|err| issue_at_key(source, &err.to_string()))
+}
+
+/// Finds `.sparkle.yml` in the current directory or its ancestors, stopping
+/// at the repository root.
+pub fn find_repo_config() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    for dir in cwd.ancestors() {
+        let candidate = dir.join(REPO_CONFIG_FILE);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if dir.join(".git").exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// Parses one config file and upgrades it to the current config version,
+/// reporting any migration on stderr.
+fn parse_layer(source: &str, path: Option<&Path>) -> Result<Value, ConfigIssue> {
+    let mut value: Value =
+        serde_yaml::from_str(source).map_err(|err| ConfigIssue::from_yaml(&err))?;
+    match value {
+        Value::Null => return Ok(Value::Mapping(Default::default())),
+        Value::Mapping(_) => {}
+        _ => return Err(ConfigIssue::new("config must be a YAML mapping").at(1, 1)),
+    }
+    let (version, changes) = migrate::migrate(&mut value).map_err(ConfigIssue::new)?;
+    if !changes.is_empty() {
+        let name = path.map_or("config".into(), |path| path.display().to_string());
+        eprintln!(
+            "sparkle: {name} uses config version {version}: {}. Update the file and set \
+             `version: {}`.",
+            changes.join(", "),
+            migrate::CURRENT_VERSION
+        );
+    }
+    Ok(value)
+}
+
+fn from_merged(merged: &Value) -> Result<PromptConfig, Box<dyn Error>> {
+    // Round-trip through text so serde_yaml reports the key path of errors.
+    let text = serde_yaml::to_string(merged)?;
+    serde_yaml::from_str(&text).map_err(|err| strip_location(&err.to_string()).into())
+}
+
+/// Deep-merges `overlay` into `base`; mappings merge, everything else replaces.
+pub fn merge_values(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base_map), Value::Mapping(overlay_map)) => {
+            for (key, value) in overlay_map {
+                match base_map.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base_map.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// A config problem with an optional file position.
+#[derive(Debug)]
+pub struct ConfigIssue {
+    message: String,
+    path: Option<PathBuf>,
+    location: Option<(usize, usize)>,
+}
+
+impl ConfigIssue {
+    fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            path: None,
+            location: None,
+        }
+    }
+
+    fn from_yaml(err: &serde_yaml::Error) -> Self {
+        let issue = Self::new(strip_location(&err.to_string()));
+        match err.location() {
+            Some(location) => issue.at(location.line(), location.column()),
+            None => issue,
+        }
+    }
+
+    fn at(mut self, line: usize, column: usize) -> Self {
+        self.location = Some((line, column));
+        self
+    }
+
+    fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}:", path.display())?;
+        }
+        if let Some((line, column)) = self.location {
+            write!(f, "{line}:{column}:")?;
+        }
+        if self.path.is_some() || self.location.is_some() {
+            f.write_str(" ")?;
+        }
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ConfigIssue {}
+
+fn strip_location(message: &str) -> String {
+    match message.rfind(" at line ") {
+        Some(index) => message[..index].to_string(),
+        None => message.to_string(),
+    }
+}
+
+/// Points an error that starts with a key path at that key in `source`.
+fn issue_at_key(source: &str, message: &str) -> ConfigIssue {
+    let issue = ConfigIssue::new(message);
+    let Some(key_path) = leading_key_path(message) else {
+        return issue;
+    };
+
+    match locate_key(source, key_path) {
+        Some((line, column)) => issue.at(line, column),
+        None => issue,
+    }
+}
+
+fn leading_key_path(message: &str) -> Option<&str> {
+    let token = message.split([' ', ':']).next()?;
+    let is_path = !token.is_empty()
+        && token.starts_with(|ch: char| ch.is_ascii_lowercase())
+        && token
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '.' || ch == '[' || ch == ']');
+    is_path.then_some(token)
+}
+
+/// Finds the 1-based line and column of a dotted key path in YAML text,
+/// matching the deepest key that exists.
+fn locate_key(source: &str, key_path: &str) -> Option<(usize, usize)> {
+    let segments: Vec<&str> = key_path
+        .split('.')
+        .map(|segment| segment.split('[').next().unwrap_or(segment))
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    let mut found = None;
+    let mut depth = 0usize;
+    let mut parent_indent: Option<usize> = None;
+    for (index, line) in source.lines().enumerate() {
+        if depth == segments.len() {
+            break;
+        }
+        let content = line.trim_start().trim_start_matches("- ");
+        let indent = line.len() - content.len();
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        if let Some(parent) = parent_indent
+            && indent <= parent
+            && found.is_some()
+        {
+            break;
+        }
+        if content.starts_with(&format!("{}:", segments[depth])) {
+            found = Some((index + 1, indent + 1));
+            parent_indent = Some(indent);
+            depth += 1;
+        }
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_values_overrides_nested_keys() {
+        let mut base: Value = serde_yaml::from_str("a:\n  b: 1\n  c: 2\nd: [1]\n").unwrap();
+        let overlay: Value = serde_yaml::from_str("a:\n  c: 3\nd: [2, 3]\n").unwrap();
+        merge_values(&mut base, overlay);
+        let expected: Value = serde_yaml::from_str("a:\n  b: 1\n  c: 3\nd: [2, 3]\n").unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn env_layer_maps_variable_names_to_config_keys() {
+        let layer = env_layer(
+            [
+                ("SPARKLE_OUTPUT__WORD_FILTER__DENYLIST", "[secret]"),
+                ("SPARKLE_TEMPERATURE", "0.2"),
+                ("SPARKLE_NETWORK__PROXY", "http://proxy:8080"),
+                ("SPARKLE_LANGUAGE", "french"),
+                ("SPARKLE_UNKNOWN_NAME", "1"),
+                ("GIT_DIR", ".git"),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+        );
+        let expected: Value = serde_yaml::from_str(
+            "output:\n  wordFilter:\n    denylist: [secret]\n\
+             modelParameters:\n  temperature: 0.2\n\
+             network:\n  proxy: http://proxy:8080\n",
+        )
+        .unwrap();
+        assert_eq!(layer, expected);
+    }
+
+    #[test]
+    fn repository_commands_need_the_users_trust() {
+        let path = Path::new("repo/.sparkle.yml");
+        let layer: Value =
+            serde_yaml::from_str("contextPolicy:\n  builderCommand: touch pwned\n").unwrap();
+        let message = untrusted_command(&layer, path, || false).unwrap();
+        assert!(message.starts_with("contextPolicy.builderCommand runs a command"));
+        assert!(message.contains("git config sparkle.trustCommands true"));
+        assert_eq!(untrusted_command(&layer, path, || true), None);
+
+        let audit: Value = serde_yaml::from_str("security:\n  auditLog: ~/.bashrc\n").unwrap();
+        let message = untrusted_command(&audit, path, || false).unwrap();
+        assert!(message.starts_with("security.auditLog writes to a file"));
+        assert_eq!(untrusted_command(&audit, path, || true), None);
+
+        let harmless: Value =
+            serde_yaml::from_str("contextPolicy:\n  tokenCharRatio: 3\n").unwrap();
+        assert_eq!(
+            untrusted_command(&harmless, path, || panic!("not asked")),
+            None
+        );
+    }
+
+    #[test]
+    fn untrusted_repository_layers_cannot_set_postprocess_commands() {
+        let dir = std::env::temp_dir().join(format!("sparkle-untrusted-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let repo_file = dir.join(".sparkle.yml");
+        fs::write(&repo_file, "postprocess:\n  command: touch pwned\n").unwrap();
+        let layer = parse_layer(&fs::read_to_string(&repo_file).unwrap(), None).unwrap();
+        let message = untrusted_command(&layer, &repo_file, || false);
+        let validated = validate_file(&repo_file);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(
+            message
+                .is_some_and(|message| message.starts_with("postprocess.command runs a command"))
+        );
+        if !trusts_repo_commands() {
+            let issue = validated.unwrap_err();
+            assert!(issue.message.starts_with("postprocess.command"), "{issue}");
+            assert_eq!(issue.location, Some((2, 3)));
+        }
+    }
+
+    #[test]
+    fn repository_layers_cannot_set_transport_keys() {
+        let dir = std::env::temp_dir().join(format!("sparkle-transport-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let repo_file = dir.join(".sparkle.yml");
+        fs::write(
+            &repo_file,
+            "network:\n  connectTimeout: 5\n  proxy: socks5://attacker:1080\n",
+        )
+        .unwrap();
+        let empty = || Value::Mapping(Default::default());
+        let from_repo = load_layers(std::slice::from_ref(&repo_file), empty());
+        let validated = validate_file(&repo_file);
+        let env = env_layer(std::iter::once((
+            "SPARKLE_NETWORK__PROXY".to_string(),
+            "socks5://localhost:1080".to_string(),
+        )));
+        let from_env = load_layers(&[], env);
+        let _ = fs::remove_dir_all(&dir);
+
+        let err = from_repo.err().unwrap().to_string();
+        assert!(
+            err.contains("network.proxy controls where requests"),
+            "{err}"
+        );
+        assert_eq!(validated.unwrap_err().location, Some((3, 3)));
+        assert_eq!(from_env.unwrap().network.proxy, "socks5://localhost:1080");
+
+        let path = Path::new("repo/.sparkle.yml");
+        for key in ["caBundle", "clientCert", "clientKey"] {
+            let layer: Value =
+                serde_yaml::from_str(&format!("network:\n  {key}: x.pem\n")).unwrap();
+            let message = repo_transport(&layer, path).unwrap();
+            assert!(
+                message.starts_with(&format!("network.{key} controls")),
+                "{message}"
+            );
+        }
+    }
+
+    #[test]
+    fn load_layers_applies_files_in_order() {
+        let dir = std::env::temp_dir().join(format!("sparkle-layers-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let prompt_file = dir.join("prompt.yml");
+        let repo_file = dir.join(".sparkle.yml");
+        fs::write(
+            &prompt_file,
+            "messages:\n  - role: user\n    content: \"Describe {{changes}}\"\nnetwork:\n  connectTimeout: 3\n",
+        )
+        .unwrap();
+        fs::write(&repo_file, "network:\n  connectTimeout: 7\n").unwrap();
+
+        let env = env_layer(
+            [
+                ("SPARKLE_NETWORK__REQUEST_TIMEOUT", "90"),
+                ("SPARKLE_MAX_TOKENS", "128"),
+                ("SPARKLE_MODEL_POLICY__AUTO_MODELS", "[a/b, c/d]"),
+                ("SPARKLE_MODEL", "openai/gpt-4o"),
+                ("HOME", "/root"),
+            ]
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+        );
+        let config = load_layers(&[prompt_file.clone(), repo_file], env).unwrap();
+        let missing = load_layers(
+            &[dir.join("missing.yml")],
+            Value::Mapping(Default::default()),
+        );
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(config.messages.len(), 1);
+        assert_eq!(config.network.connect_timeout, 7);
+        assert_eq!(config.network.request_timeout, 90);
+        assert_eq!(config.context_policy.budgets.response_tokens, 128);
+        assert_eq!(config.model_policy.auto_models, ["a/b", "c/d"]);
+        assert!(
+            missing
+                .err()
+                .is_some_and(|err| err.to_string().contains("missing.yml"))
+        );
+    }
+
+    #[test]
+    fn nested_configs_follow_the_majority_of_staged_files() {
+        let configs = ["", "services", "services/payments"].map(Path::new);
+        let has_config = |dir: &Path| configs.contains(&dir);
+        let staged = [
+            "services/payments/api.rs",
+            "services/payments/db/schema.sql",
+            "services/auth/login.rs",
+            "README.md",
+        ]
+        .map(String::from);
+        let target = majority_config_dir(&staged, has_config);
+        assert_eq!(target, Path::new("services/payments"));
+        assert_eq!(config_chain(&target, has_config), configs);
+
+        let tied = ["services/payments/api.rs", "docs/guide.md"].map(String::from);
+        assert_eq!(majority_config_dir(&tied, has_config), Path::new(""));
+    }
+
+    #[test]
+    fn reloader_keeps_reporting_changes_after_errors() {
+        let dir = std::env::temp_dir().join(format!("sparkle-reload-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(".sparkle.yml");
+        fs::write(&path, "network:\n  connectTimeout: 3\n").unwrap();
+        let watched = path.clone();
+        let mut reloader = Reloader::watching(Box::new(move || vec![watched.clone()]));
+        assert!(reloader.poll().is_none());
+
+        fs::write(&path, "network:\n  connectTimeout: nope\n").unwrap();
+        let broken = reloader.poll();
+        assert!(reloader.poll().is_none());
+        fs::write(&path, "network:\n  connectTimeout: 12\n").unwrap();
+        let fixed = reloader.poll();
+        let _ = fs::remove_dir_all(&dir);
+
+        assert!(broken.is_some_and(|outcome| outcome.is_err()));
+        assert_eq!(fixed.unwrap().unwrap().network.connect_timeout, 12);
+    }
+
+    #[test]
+    fn validate_source_reports_key_locations() {
+        assert!(validate_source("network:\n  connectTimeout: 5\n", None).is_ok());
+
+        let issue = validate_source("contextPolicy:\n  tokenCharRatio: 0\n", None).unwrap_err();
+        assert_eq!(issue.location, Some((2, 3)));
+
+        let issue = validate_source("network:\n  retries: 3\n", None).unwrap_err();
+        assert!(issue.message.contains("unknown field `retries`"));
+        assert_eq!(issue.location, Some((1, 1)));
+
+        let issue = validate_source("network: [\n", None).unwrap_err();
+        assert!(issue.location.is_some());
+    }
+
+    #[test]
+    fn schema_covers_embedded_config_keys() {
+        let schema: serde_json::Value = serde_json::from_str(CONFIG_SCHEMA_JSON).unwrap();
+        let config = prompt::default_config_value().unwrap();
+        assert_schema_covers(&schema, &schema, &config, "");
+    }
+
+    fn assert_schema_covers(
+        root: &serde_json::Value,
+        schema: &serde_json::Value,
+        value: &Value,
+        path: &str,
+    ) {
+        let schema = match schema["$ref"].as_str() {
+            Some(reference) => root.pointer(reference.trim_start_matches('#')).unwrap(),
+            None => schema,
+        };
+        match value {
+            Value::Mapping(map) => {
+                for (key, child) in map {
+                    let key = key.as_str().unwrap();
+                    let child_schema = &schema["properties"][key];
+                    assert!(!child_schema.is_null(), "schema is missing {path}.{key}");
+                    assert_schema_covers(root, child_schema, child, &format!("{path}.{key}"));
+                }
+            }
+            Value::Sequence(items) => {
+                for item in items {
+                    assert_schema_covers(root, &schema["items"], item, path);
+                }
+            }
+            _ => {}
+        }
+    }
+}
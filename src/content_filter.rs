@@ -0,0 +1,122 @@
+// Denylist filter applied to generated messages before they reach git.
+
+/// Returns the first denylist pattern that matches `text`.
+pub fn find_denied<'a>(text: &str, denylist: &'a [String]) -> Option<&'a str> {
+    let words = word_spans(text);
+    denylist
+        .iter()
+        .find(|pattern| !matches(text, &words, pattern).is_empty())
+        .map(String::as_str)
+}
+
+/// Replaces every match of every pattern with asterisks.
+pub fn mask(text: &str, denylist: &[String]) -> String {
+    let words = word_spans(text);
+    let mut masked: Vec<char> = text.chars().collect();
+    let char_offsets: Vec<usize> = text.char_indices().map(|(offset, _)| offset).collect();
+    for pattern in denylist {
+        for (start, end) in matches(text, &words, pattern) {
+            for (index, offset) in char_offsets.iter().enumerate() {
+                if (start..end).contains(offset) && !masked[index].is_whitespace() {
+                    masked[index] = '*';
+                }
+            }
+        }
+    }
+    masked.into_iter().collect()
+}
+
+/// Byte ranges of alphanumeric words in `text`.
+fn word_spans(text: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    for (offset, ch) in text.char_indices() {
+        match (ch.is_alphanumeric(), start) {
+            (true, None) => start = Some(offset),
+            (false, Some(begin)) => {
+                spans.push((begin, offset));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(begin) = start {
+        spans.push((begin, text.len()));
+    }
+    spans
+}
+
+/// Byte ranges where `pattern` matches a run of consecutive words. Patterns
+/// are case-insensitive words or phrases; `*` matches any characters within a
+/// word.
+fn matches(text: &str, words: &[(usize, usize)], pattern: &str) -> Vec<(usize, usize)> {
+    let parts: Vec<String> = pattern.split_whitespace().map(str::to_lowercase).collect();
+    if parts.is_empty() || parts.len() > words.len() {
+        return Vec::new();
+    }
+
+    words
+        .windows(parts.len())
+        .filter(|window| {
+            window
+                .iter()
+                .zip(&parts)
+                .all(|((start, end), part)| glob_match(part, &text[*start..*end].to_lowercase()))
+        })
+        .map(|window| (window[0].0, window[window.len() - 1].1))
+        .collect()
+}
+
+pub(crate) fn glob_match(pattern: &str, word: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == word;
+    };
+    let Some(mut remaining) = word.strip_prefix(prefix) else {
+        return false;
+    };
+
+    let mut pieces = rest.split('*').peekable();
+    while let Some(piece) = pieces.next() {
+        if pieces.peek().is_none() {
+            return remaining.ends_with(piece);
+        }
+        match remaining.find(piece) {
+            Some(index) => remaining = &remaining[index + piece.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_denied_matches_words_phrases_and_wildcards() {
+        let denylist = vec![
+            "hack".to_string(),
+            "quick fix".to_string(),
+            "wtf*".to_string(),
+        ];
+        assert_eq!(
+            find_denied("fix: Hack around cache", &denylist),
+            Some("hack")
+        );
+        assert_eq!(find_denied("fix: shackle parser", &denylist), None);
+        assert_eq!(
+            find_denied("chore: quick  fix for CI", &denylist),
+            Some("quick fix")
+        );
+        assert_eq!(find_denied("fix: WTFs in parser", &denylist), Some("wtf*"));
+    }
+
+    #[test]
+    fn mask_replaces_matches_with_asterisks() {
+        let denylist = vec!["hack".to_string(), "quick fix".to_string()];
+        assert_eq!(
+            mask("fix: hack in a quick fix", &denylist),
+            "fix: **** in a ***** ***"
+        );
+    }
+}
@@ -0,0 +1,297 @@
+// Pluggable change-context assembly: a `ContextBuilder` installed by a program
+// embedding sparkle, `contextPolicy.builderCommand`, and the output of
+// `contextPolicy.commands`.
+
+use serde::Serialize;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::failure::{Failure, FailureKind};
+use crate::prompt::ContextCommand;
+use crate::truncate;
+
+static BUILDER: OnceLock<Box<dyn ContextBuilder>> = OnceLock::new();
+
+/// What a builder gets to work with for one budget tier.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContextRequest<'a> {
+    /// `git diff --staged --stat` style summary.
+    pub summary: &'a str,
+    /// The staged diff, or its per-file summaries with `summarizeFiles`.
+    pub diff: &'a str,
+    /// Excerpts of project docs near the changed files.
+    pub docs: &'a str,
+    /// Title and body of the linked issue.
+    pub issue: &'a str,
+    /// Output of the `contextPolicy.commands`.
+    pub commands: &'a [CommandOutput],
+    /// Tokens the context may take; what the tier leaves after the reply
+    /// reserve and the prompt template.
+    pub budget_tokens: usize,
+    /// Characters per token used to turn `budget_tokens` into a length.
+    pub token_char_ratio: usize,
+    /// Set on the last tier, which should send only what is essential.
+    pub summary_only: bool,
+}
+
+impl ContextRequest<'_> {
+    /// The budget in characters.
+    pub fn budget_chars(&self) -> usize {
+        self.budget_tokens.saturating_mul(self.token_char_ratio)
+    }
+}
+
+/// What one `contextPolicy.commands` entry printed for this run.
+#[derive(Serialize)]
+pub struct CommandOutput {
+    pub name: String,
+    pub output: String,
+}
+
+/// Runs every context command once. A command that fails or runs past its
+/// `timeout` is logged and left out, like a missing issue or README.
+pub fn run_commands(
+    commands: &[ContextCommand],
+    mut log: impl FnMut(String),
+) -> Vec<CommandOutput> {
+    commands
+        .iter()
+        .filter_map(
+            |entry| match shell(&entry.command, b"", Duration::from_secs(entry.timeout)) {
+                Ok(output) => Some(CommandOutput {
+                    name: entry.name.clone(),
+                    output,
+                }),
+                Err(detail) => {
+                    log(format!(
+                        "Context command {} failed and was left out: {detail}",
+                        entry.name
+                    ));
+                    None
+                }
+            },
+        )
+        .collect()
+}
+
+/// How long `contextPolicy.builderCommand` may run for one tier.
+const BUILDER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs `command` through the platform shell (`sh -c`, or `cmd /C` on
+/// Windows) with `input` on stdin and returns its stdout, or what went wrong.
+/// The command is killed once `timeout` passes.
+pub fn shell(command: &str, input: &[u8], timeout: Duration) -> Result<String, String> {
+    let mut child = shell_command(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| err.to_string())?;
+    let stdin = child.stdin.take();
+    let input = input.to_vec();
+    // Fed from its own thread: a command that ignores its input may exit
+    // before reading it, or fill its stdout while we are still writing.
+    thread::spawn(move || {
+        if let Some(mut stdin) = stdin {
+            let _ = stdin.write_all(&input);
+        }
+    });
+    let stdout = child.stdout.take().map(read_in_background);
+    let stderr = child.stderr.take().map(read_in_background);
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("timed out after {}s", timeout.as_secs()));
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(10)),
+            Err(err) => return Err(err.to_string()),
+        }
+    };
+    let collect = |reader: Option<thread::JoinHandle<Vec<u8>>>| {
+        reader
+            .and_then(|reader| reader.join().ok())
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .unwrap_or_default()
+    };
+    let stdout = collect(stdout);
+    if !status.success() {
+        let stderr = collect(stderr);
+        return Err(match stderr.trim() {
+            "" => status.to_string(),
+            stderr => format!("{status}: {stderr}"),
+        });
+    }
+    Ok(stdout)
+}
+
+fn shell_command(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command);
+        shell
+    }
+}
+
+fn read_in_background(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        let _ = pipe.read_to_end(&mut bytes);
+        bytes
+    })
+}
+
+/// A change context ready to go into the prompt.
+pub struct BuiltContext {
+    pub context: String,
+    /// Whether part of the change was left out; logged with the attempt.
+    pub truncated: bool,
+}
+
+/// Assembles the change context in place of `contextPolicy.sections`.
+/// Anything longer than the request's budget is cut to it.
+pub trait ContextBuilder: Send + Sync {
+    fn build(&self, request: &ContextRequest<'_>) -> Result<BuiltContext, Box<dyn Error>>;
+}
+
+/// Uses `builder` for every context of this run. Only the first call counts.
+pub fn set_builder(builder: Box<dyn ContextBuilder>) {
+    let _ = BUILDER.set(builder);
+}
+
+/// The installed builder, or a command builder for `command`; `None` leaves
+/// the context to the configured sections.
+pub fn custom(command: &str) -> Option<Box<dyn ContextBuilder + '_>> {
+    if let Some(builder) = BUILDER.get() {
+        return Some(Box::new(Installed(builder.as_ref())));
+    }
+    (!command.trim().is_empty()).then(|| Box::new(CommandBuilder { command }) as Box<_>)
+}
+
+/// Runs `builder` and holds its result to the budget.
+pub fn build(
+    builder: &dyn ContextBuilder,
+    request: &ContextRequest<'_>,
+) -> Result<BuiltContext, Box<dyn Error>> {
+    let built = builder.build(request)?;
+    let limit = request.budget_chars();
+    if truncate::width(&built.context) <= limit {
+        return Ok(built);
+    }
+    Ok(BuiltContext {
+        context: truncate::truncate(&built.context, limit),
+        truncated: true,
+    })
+}
+
+struct Installed<'a>(&'a dyn ContextBuilder);
+
+impl ContextBuilder for Installed<'_> {
+    fn build(&self, request: &ContextRequest<'_>) -> Result<BuiltContext, Box<dyn Error>> {
+        self.0.build(request)
+    }
+}
+
+/// Runs `command` through the platform shell with the request as JSON on
+/// stdin and takes its stdout as the context.
+struct CommandBuilder<'a> {
+    command: &'a str,
+}
+
+impl ContextBuilder for CommandBuilder<'_> {
+    fn build(&self, request: &ContextRequest<'_>) -> Result<BuiltContext, Box<dyn Error>> {
+        let input = serde_json::to_vec(request)?;
+        let context = shell(self.command, &input, BUILDER_TIMEOUT).map_err(|detail| {
+            Failure::new(
+                FailureKind::Config,
+                format!(
+                    "contextPolicy.builderCommand `{}` failed: {detail}",
+                    self.command
+                ),
+            )
+        })?;
+        Ok(BuiltContext {
+            context,
+            truncated: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(budget_tokens: usize) -> ContextRequest<'static> {
+        ContextRequest {
+            summary: " a.rs | 2 +-\n",
+            diff: "+x\n",
+            docs: "",
+            issue: "",
+            commands: &[],
+            budget_tokens,
+            token_char_ratio: 1,
+            summary_only: false,
+        }
+    }
+
+    #[test]
+    fn command_builder_reads_the_request_and_is_held_to_the_budget() {
+        let builder = CommandBuilder {
+            command: "grep -o '\"budgetTokens\":[0-9]*'",
+        };
+        let built = build(&builder, &request(200)).unwrap();
+        assert_eq!(built.context, "\"budgetTokens\":200\n");
+        assert!(!built.truncated);
+
+        let built = build(&builder, &request(5)).unwrap();
+        assert!(built.truncated);
+        assert!(truncate::width(&built.context) <= 5);
+
+        let failing = CommandBuilder {
+            command: "echo nope >&2; exit 3",
+        };
+        let err = build(&failing, &request(200)).err().unwrap();
+        assert!(err.to_string().contains("nope"), "{err}");
+    }
+
+    #[test]
+    fn run_commands_leaves_out_failing_commands() {
+        let command = |name: &str, command: &str| ContextCommand {
+            name: name.to_string(),
+            command: command.to_string(),
+            header: "\n".to_string(),
+            max_ratio: 0.1,
+            timeout: 1,
+        };
+        let mut logged = Vec::new();
+        let outputs = run_commands(
+            &[
+                command("check", "echo ok"),
+                command("tickets", "exit 1"),
+                command("hangs", "sleep 5"),
+            ],
+            |line| logged.push(line),
+        );
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(
+            (outputs[0].name.as_str(), outputs[0].output.as_str()),
+            ("check", "ok\n")
+        );
+        assert_eq!(logged.len(), 2);
+        assert!(logged[0].starts_with("Context command tickets failed"));
+        assert!(logged[1].ends_with("timed out after 1s"), "{}", logged[1]);
+    }
+}
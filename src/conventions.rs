@@ -0,0 +1,225 @@
+// Allowed Conventional Commit types and scopes.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::prompt::ConventionsConfig;
+
+/// Files that may document the commit rules, in order of preference.
+const DOCUMENTS: &[&str] = &[
+    "docs/COMMIT_CONVENTION.md",
+    "COMMIT_CONVENTION.md",
+    ".github/COMMIT_CONVENTION.md",
+    "CONTRIBUTING.md",
+    ".github/CONTRIBUTING.md",
+    "docs/CONTRIBUTING.md",
+];
+
+/// Prompt rules rendered into `{{conventions}}`.
+pub fn render_rules(config: &ConventionsConfig) -> String {
+    let mut rules = format!("- Use one of: {}", config.types.join(", "));
+    if !config.scopes.is_empty() {
+        rules.push_str(&format!(
+            "\n\n- If you add a scope, use one of: {}",
+            config.scopes.join(", ")
+        ));
+    }
+    rules
+}
+
+/// The commit rules documented under `root`, with the path they came from:
+/// the configured `document`, or the first convention file found. Of
+/// contributing guides only the section whose heading mentions commits is
+/// used. Paths that resolve outside `root`, such as through a symlink, are
+/// never read.
+pub fn document(root: &Path, config: &ConventionsConfig) -> Option<(String, String)> {
+    let configured = [config.document.as_str()];
+    let candidates: &[&str] = if config.document.is_empty() {
+        DOCUMENTS
+    } else {
+        &configured
+    };
+    candidates.iter().find_map(|path| {
+        let text = fs::read_to_string(inside(root, path)?).ok()?;
+        let rules = if path.to_uppercase().contains("CONTRIBUTING") {
+            commit_section(&text)?
+        } else {
+            text.trim().to_string()
+        };
+        (!rules.is_empty()).then(|| (path.to_string(), rules))
+    })
+}
+
+/// `path` under `root` once symlinks are resolved, if it stays there.
+fn inside(root: &Path, path: &str) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    let resolved = root.join(path).canonicalize().ok()?;
+    resolved.starts_with(&root).then_some(resolved)
+}
+
+/// The Markdown section whose heading mentions commits, up to the next
+/// heading of the same or a higher level.
+fn commit_section(text: &str) -> Option<String> {
+    let level = |line: &str| line.chars().take_while(|ch| *ch == '#').count();
+    let mut lines = text.lines();
+    let heading_level = lines.by_ref().find_map(|line| {
+        let level = level(line);
+        (level > 0 && line.to_lowercase().contains("commit")).then_some(level)
+    })?;
+    let section: Vec<&str> = lines
+        .take_while(|line| !(1..=heading_level).contains(&level(line)))
+        .collect();
+    Some(section.join("\n").trim().to_string())
+}
+
+/// Describes how `subject` breaks the configured conventions, if it does.
+pub fn violation(subject: &str, config: &ConventionsConfig) -> Option<String> {
+    let Some((prefix, _)) = subject.split_once(':') else {
+        return Some("the subject has no `<type>:` prefix".to_string());
+    };
+    let prefix = prefix.trim_end_matches('!');
+    let (commit_type, scope) = match prefix.split_once('(') {
+        Some((commit_type, rest)) => (commit_type, rest.strip_suffix(')')),
+        None => (prefix, None),
+    };
+
+    if !config.types.iter().any(|allowed| allowed == commit_type) {
+        return Some(format!(
+            "type `{commit_type}` is not one of: {}",
+            config.types.join(", ")
+        ));
+    }
+    if let Some(scope) = scope
+        && !config.scopes.is_empty()
+        && !config.scopes.iter().any(|allowed| allowed == scope)
+    {
+        return Some(format!(
+            "scope `{scope}` is not one of: {}",
+            config.scopes.join(", ")
+        ));
+    }
+    None
+}
+
+/// `subject` with its Conventional Commit type replaced by `commit_type`,
+/// keeping the scope and `!`; `None` when it has no `<type>:` prefix.
+pub fn with_type(subject: &str, commit_type: &str) -> Option<String> {
+    let current = subject_type(subject)?;
+    Some(format!("{commit_type}{}", &subject[current.len()..]))
+}
+
+/// The type of a Conventional Commit subject.
+pub fn subject_type(subject: &str) -> Option<&str> {
+    let (prefix, _) = subject.split_once(':')?;
+    let end = prefix.find(['(', '!']).unwrap_or(prefix.len());
+    Some(&prefix[..end]).filter(|commit_type| !commit_type.is_empty() && !commit_type.contains(' '))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ConventionsConfig {
+        ConventionsConfig {
+            types: vec!["feat".to_string(), "infra".to_string()],
+            scopes: vec!["api".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn render_rules_lists_types_and_scopes() {
+        assert_eq!(
+            render_rules(&config()),
+            "- Use one of: feat, infra\n\n- If you add a scope, use one of: api"
+        );
+    }
+
+    #[test]
+    fn violation_checks_type_and_scope() {
+        assert_eq!(violation("infra: add runners", &config()), None);
+        assert_eq!(violation("feat(api)!: drop v1", &config()), None);
+        assert!(violation("fix: typo", &config()).unwrap().contains("`fix`"));
+        assert!(
+            violation("feat(ui): add spinner", &config())
+                .unwrap()
+                .contains("`ui`")
+        );
+        assert!(violation("add spinner", &config()).is_some());
+    }
+
+    #[test]
+    fn with_type_keeps_scope_and_breaking_marker() {
+        assert_eq!(
+            with_type("chore(api)!: drop v1", "feat").as_deref(),
+            Some("feat(api)!: drop v1")
+        );
+        assert_eq!(subject_type("fix: typo"), Some("fix"));
+        assert_eq!(with_type("Update the readme", "docs"), None);
+    }
+
+    #[test]
+    fn document_prefers_convention_files_and_commit_sections() {
+        let root = std::env::temp_dir().join(format!("sparkle-conv-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(
+            root.join("CONTRIBUTING.md"),
+            "# Contributing\n\n## Setup\n\nRun make.\n\n## Commit messages\n\nUse the\n\n### Types\n\nfeat, fix\n\n## Reviews\n\nBe kind.\n",
+        )
+        .unwrap();
+        let config = ConventionsConfig::default();
+        assert_eq!(
+            document(&root, &config),
+            Some((
+                "CONTRIBUTING.md".to_string(),
+                "Use the\n\n### Types\n\nfeat, fix".to_string()
+            ))
+        );
+
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(
+            root.join("docs/COMMIT_CONVENTION.md"),
+            "Subjects in lowercase.\n",
+        )
+        .unwrap();
+        let found = document(&root, &config).map(|(path, _)| path);
+        let missing = document(
+            &root,
+            &ConventionsConfig {
+                document: "RULES.md".to_string(),
+                ..Default::default()
+            },
+        );
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(found.as_deref(), Some("docs/COMMIT_CONVENTION.md"));
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn document_stays_inside_the_repository() {
+        let base = std::env::temp_dir().join(format!("sparkle-conv-out-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let root = base.join("repo");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(base.join("secret.md"), "private key\n").unwrap();
+        let configured = |document: &str| ConventionsConfig {
+            document: document.to_string(),
+            ..Default::default()
+        };
+
+        let parent = document(&root, &configured("../secret.md"));
+        let absolute = document(&root, &configured(base.join("secret.md").to_str().unwrap()));
+        #[cfg(unix)]
+        let linked = {
+            std::os::unix::fs::symlink(base.join("secret.md"), root.join("RULES.md")).unwrap();
+            document(&root, &configured("RULES.md"))
+        };
+        let _ = fs::remove_dir_all(&base);
+
+        assert_eq!(parent, None);
+        assert_eq!(absolute, None);
+        #[cfg(unix)]
+        assert_eq!(linked, None);
+    }
+}
@@ -0,0 +1,311 @@
+// Interactive staged diff viewer for excluding files and hunks from the
+// model context.
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor, Stylize};
+use crossterm::terminal::{
+    self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode,
+    enable_raw_mode,
+};
+use crossterm::{ExecutableCommand, QueueableCommand};
+use std::error::Error;
+use std::io::{self, Write};
+
+/// One file of a patch: its header lines and hunks.
+pub struct DiffFile {
+    pub path: String,
+    header: Vec<String>,
+    hunks: Vec<Hunk>,
+}
+
+struct Hunk {
+    lines: Vec<String>,
+    included: bool,
+}
+
+impl DiffFile {
+    fn is_included(&self) -> bool {
+        self.hunks.iter().any(|hunk| hunk.included)
+    }
+
+    fn set_included(&mut self, included: bool) {
+        for hunk in &mut self.hunks {
+            hunk.included = included;
+        }
+    }
+}
+
+/// Splits a unified patch into files and hunks, all included.
+pub fn parse_patch(patch: &str) -> Vec<DiffFile> {
+    let mut files: Vec<DiffFile> = Vec::new();
+    for line in patch.lines() {
+        if line.starts_with("diff --git ") {
+            let path = line.rsplit(" b/").next().unwrap_or(line).to_string();
+            files.push(DiffFile {
+                path,
+                header: vec![line.to_string()],
+                hunks: Vec::new(),
+            });
+            continue;
+        }
+        let Some(file) = files.last_mut() else {
+            continue;
+        };
+        if line.starts_with("@@") {
+            file.hunks.push(Hunk {
+                lines: vec![line.to_string()],
+                included: true,
+            });
+        } else if let Some(hunk) = file.hunks.last_mut() {
+            hunk.lines.push(line.to_string());
+        } else {
+            file.header.push(line.to_string());
+        }
+    }
+
+    // Binary files and renames have no hunks; give them one empty hunk so
+    // they can still be toggled.
+    for file in &mut files {
+        if file.hunks.is_empty() {
+            file.hunks.push(Hunk {
+                lines: Vec::new(),
+                included: true,
+            });
+        }
+    }
+    files
+}
+
+/// Rebuilds the patch from the included hunks only.
+pub fn render_patch(files: &[DiffFile]) -> String {
+    let mut patch = String::new();
+    for file in files.iter().filter(|file| file.is_included()) {
+        for line in &file.header {
+            patch.push_str(line);
+            patch.push('\n');
+        }
+        for hunk in file.hunks.iter().filter(|hunk| hunk.included) {
+            for line in &hunk.lines {
+                patch.push_str(line);
+                patch.push('\n');
+            }
+        }
+    }
+    patch
+}
+
+/// Drops `--stat` lines of files that were excluded entirely.
+pub fn filter_summary(summary: &str, files: &[DiffFile]) -> String {
+    summary
+        .lines()
+        .filter(|line| {
+            let path = line.split('|').next().unwrap_or("").trim();
+            !files.iter().any(|file| {
+                !file.is_included() && (file.path == path || path.ends_with(&file.path))
+            })
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+enum Row {
+    File(usize),
+    Hunk(usize, usize),
+}
+
+/// Shows the diff in an alternate screen until the user confirms (`enter`)
+/// or cancels (`q`/`esc`). Returns `false` on cancel.
+pub fn review(files: &mut [DiffFile]) -> Result<bool, Box<dyn Error>> {
+    let mut stdout = io::stdout();
+    enable_raw_mode()?;
+    stdout.execute(EnterAlternateScreen)?.execute(Hide)?;
+
+    let result = review_loop(&mut stdout, files);
+
+    stdout.execute(Show)?.execute(LeaveAlternateScreen)?;
+    disable_raw_mode()?;
+    result
+}
+
+fn review_loop(stdout: &mut io::Stdout, files: &mut [DiffFile]) -> Result<bool, Box<dyn Error>> {
+    let mut cursor = 0usize;
+    loop {
+        let rows = build_rows(files);
+        cursor = cursor.min(rows.len().saturating_sub(1));
+        draw(stdout, files, &rows, cursor)?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => cursor = cursor.saturating_sub(1),
+            KeyCode::Down | KeyCode::Char('j') => cursor += 1,
+            KeyCode::Char(' ') => match rows.get(cursor) {
+                Some(Row::File(index)) => {
+                    let included = files[*index].is_included();
+                    files[*index].set_included(!included);
+                }
+                Some(Row::Hunk(file, hunk)) => {
+                    let hunk = &mut files[*file].hunks[*hunk];
+                    hunk.included = !hunk.included;
+                }
+                None => {}
+            },
+            KeyCode::Char('a') => {
+                let included = files.iter().all(DiffFile::is_included);
+                for file in files.iter_mut() {
+                    file.set_included(!included);
+                }
+            }
+            KeyCode::Enter => return Ok(true),
+            KeyCode::Esc | KeyCode::Char('q') => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+fn build_rows(files: &[DiffFile]) -> Vec<Row> {
+    let mut rows = Vec::new();
+    for (file_index, file) in files.iter().enumerate() {
+        rows.push(Row::File(file_index));
+        if file.hunks.len() > 1 {
+            rows.extend((0..file.hunks.len()).map(|hunk| Row::Hunk(file_index, hunk)));
+        }
+    }
+    rows
+}
+
+fn draw(
+    stdout: &mut io::Stdout,
+    files: &[DiffFile],
+    rows: &[Row],
+    cursor: usize,
+) -> Result<(), Box<dyn Error>> {
+    let (width, height) = terminal::size()?;
+    let width = width as usize;
+    let list_height = (height as usize / 3).max(3);
+    let preview_height = (height as usize).saturating_sub(list_height + 3);
+
+    stdout.queue(Clear(ClearType::All))?.queue(MoveTo(0, 0))?;
+    stdout.queue(Print(
+        "Exclude from model context: ↑/↓ move, space toggle, a all, enter generate, q cancel"
+            .bold(),
+    ))?;
+
+    let first = cursor.saturating_sub(list_height.saturating_sub(1));
+    for (line, (index, row)) in rows
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(list_height)
+        .enumerate()
+    {
+        let (mark, label) = match row {
+            Row::File(file) => {
+                let file = &files[*file];
+                let mark = match file.hunks.iter().filter(|hunk| hunk.included).count() {
+                    0 => "[ ]",
+                    count if count == file.hunks.len() => "[x]",
+                    _ => "[~]",
+                };
+                (mark, file.path.clone())
+            }
+            Row::Hunk(file, hunk) => {
+                let hunk = &files[*file].hunks[*hunk];
+                let mark = if hunk.included { "  [x]" } else { "  [ ]" };
+                (mark, hunk.lines.first().cloned().unwrap_or_default())
+            }
+        };
+        let text = truncate(&format!("{mark} {label}"), width);
+        stdout.queue(MoveTo(0, line as u16 + 2))?;
+        if index == cursor {
+            stdout.queue(Print(text.reverse()))?;
+        } else {
+            stdout.queue(Print(text))?;
+        }
+    }
+
+    let preview: Vec<&String> = match rows.get(cursor) {
+        Some(Row::File(file)) => files[*file]
+            .hunks
+            .iter()
+            .flat_map(|hunk| &hunk.lines)
+            .collect(),
+        Some(Row::Hunk(file, hunk)) => files[*file].hunks[*hunk].lines.iter().collect(),
+        None => Vec::new(),
+    };
+    let top = list_height as u16 + 3;
+    for (offset, line) in preview.iter().take(preview_height).enumerate() {
+        let color = match line.chars().next() {
+            Some('+') => Color::Green,
+            Some('-') => Color::Red,
+            Some('@') => Color::Cyan,
+            _ => Color::Reset,
+        };
+        stdout
+            .queue(MoveTo(0, top + offset as u16))?
+            .queue(SetForegroundColor(color))?
+            .queue(Print(truncate(line, width)))?
+            .queue(ResetColor)?;
+    }
+
+    stdout.flush()?;
+    Ok(())
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "\
+diff --git a/src/a.rs b/src/a.rs
+index 1..2 100644
+--- a/src/a.rs
++++ b/src/a.rs
+@@ -1 +1 @@
+-old
++new
+@@ -10 +10 @@
+-x
++y
+diff --git a/Cargo.lock b/Cargo.lock
+--- a/Cargo.lock
++++ b/Cargo.lock
+@@ -1 +1 @@
+-v1
++v2
+";
+
+    #[test]
+    fn render_patch_drops_excluded_hunks_and_files() {
+        let mut files = parse_patch(PATCH);
+        assert_eq!(files.len(), 2);
+        assert_eq!(render_patch(&files), PATCH);
+
+        files[0].hunks[1].included = false;
+        files[1].set_included(false);
+        assert_eq!(
+            render_patch(&files),
+            "diff --git a/src/a.rs b/src/a.rs\nindex 1..2 100644\n--- a/src/a.rs\n+++ b/src/a.rs\n@@ -1 +1 @@\n-old\n+new\n"
+        );
+    }
+
+    #[test]
+    fn filter_summary_drops_fully_excluded_files() {
+        let mut files = parse_patch(PATCH);
+        files[1].set_included(false);
+        let summary = " src/a.rs   | 4 ++--\n Cargo.lock | 2 +-\n 2 files changed";
+        assert_eq!(
+            filter_summary(summary, &files),
+            " src/a.rs   | 4 ++--\n 2 files changed"
+        );
+    }
+}
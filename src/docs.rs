@@ -0,0 +1,144 @@
+// Project documentation near the changed files, for the docs context section.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+use crate::truncate;
+
+/// Files whose first paragraph describes a directory, in order of preference.
+const DOC_FILES: &[&str] = &[
+    "README.md",
+    "README",
+    "README.rst",
+    "README.txt",
+    "mod.rs",
+    "lib.rs",
+    "main.rs",
+    "__init__.py",
+    "doc.go",
+];
+const MAX_EXCERPTS: usize = 5;
+const MAX_EXCERPT_WIDTH: usize = 300;
+
+/// First paragraphs of the README or module docs nearest to each directory
+/// `patch` touches, one `path: paragraph` line each. Directories without docs
+/// fall back to their closest documented parent inside `root`.
+pub fn excerpts(root: &Path, patch: &str) -> String {
+    let mut dirs = BTreeSet::new();
+    for line in patch.lines() {
+        if let Some((_, path)) = line
+            .strip_prefix("diff --git ")
+            .and_then(|paths| paths.rsplit_once(" b/"))
+        {
+            dirs.insert(
+                Path::new(path)
+                    .parent()
+                    .unwrap_or(Path::new(""))
+                    .to_path_buf(),
+            );
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut lines = Vec::new();
+    for dir in dirs {
+        let Some((file, paragraph)) = dir.ancestors().find_map(|dir| nearest(root, dir)) else {
+            continue;
+        };
+        if seen.insert(file.clone()) {
+            lines.push(format!("{file}: {paragraph}\n"));
+        }
+        if lines.len() == MAX_EXCERPTS {
+            break;
+        }
+    }
+    lines.concat()
+}
+
+fn nearest(root: &Path, dir: &Path) -> Option<(String, String)> {
+    DOC_FILES.iter().find_map(|name| {
+        let relative = dir.join(name);
+        let text = fs::read_to_string(root.join(&relative)).ok()?;
+        let paragraph = first_paragraph(name, &text)?;
+        Some((
+            relative.to_string_lossy().replace('\\', "/"),
+            truncate::truncate(&paragraph, MAX_EXCERPT_WIDTH),
+        ))
+    })
+}
+
+/// The first paragraph of prose in `text`, on one line. Markdown headings,
+/// badges, and images are skipped; source files only contribute their module
+/// doc comment.
+fn first_paragraph(name: &str, text: &str) -> Option<String> {
+    let prose: Vec<&str> = match name {
+        "mod.rs" | "lib.rs" | "main.rs" => text
+            .lines()
+            .map_while(|line| line.trim_start().strip_prefix("//!"))
+            .collect(),
+        "__init__.py" => {
+            let body = text.trim_start().strip_prefix("\"\"\"")?;
+            body.split("\"\"\"").next()?.lines().collect()
+        }
+        "doc.go" => text
+            .lines()
+            .skip_while(|line| !line.starts_with("//"))
+            .map_while(|line| line.strip_prefix("//"))
+            .collect(),
+        _ => text.lines().collect(),
+    };
+    let paragraph: Vec<&str> = prose
+        .iter()
+        .map(|line| line.trim())
+        .skip_while(|line| {
+            line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("![")
+                || line.starts_with("[![")
+                || line.starts_with("<")
+                || line.starts_with("===")
+                || line.starts_with("---")
+        })
+        .take_while(|line| !line.is_empty())
+        .collect();
+    (!paragraph.is_empty()).then(|| paragraph.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excerpts_use_the_nearest_documented_directory() {
+        let root = std::env::temp_dir().join(format!("sparkle-docs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src/render")).unwrap();
+        fs::write(
+            root.join("README.md"),
+            "# Tool\n\n[![CI](badge)](ci)\n\nA tool that draws\nthings.\n\nMore.\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("src/render/mod.rs"),
+            "//! The renderer turns scenes\n//! into frames.\n\nuse x;\n",
+        )
+        .unwrap();
+
+        let patch = "\
+diff --git a/src/render/gl.rs b/src/render/gl.rs
++x
+diff --git a/src/render/vk.rs b/src/render/vk.rs
++y
+diff --git a/src/app/main.rs b/src/app/main.rs
++z
+";
+        let text = excerpts(&root, patch);
+        let _ = fs::remove_dir_all(&root);
+        assert_eq!(
+            text,
+            "README.md: A tool that draws things.\n\
+             src/render/mod.rs: The renderer turns scenes into frames.\n"
+        );
+    }
+}
@@ -0,0 +1,502 @@
+// Environment diagnostics for `gh sparkle doctor`.
+
+use std::error::Error;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::auth::{self, AuthError, TokenSource};
+use crate::config;
+use crate::failure::{Failure, FailureKind};
+use crate::llm::{self, ConnectivityError};
+use crate::prompt::{NetworkConfig, PromptConfig};
+use crate::quota;
+use crate::{Cli, git, resolve_model_chain};
+
+/// Oldest git known to support every command sparkle runs.
+const MIN_GIT_VERSION: (u32, u32) = (2, 25);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Status {
+    Pass,
+    Warn,
+    /// Not run: an earlier check failed or it does not apply.
+    Skip,
+    Fail(FailureKind),
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+/// Runs every check, prints the report, and fails with the class of the
+/// first failed check.
+pub fn run(cli: &Cli, ping: bool) -> Result<(), Box<dyn Error>> {
+    let checks = collect(cli, ping);
+    print!("{}", format_report(&checks));
+    let failed: Vec<FailureKind> = checks
+        .iter()
+        .filter_map(|check| match check.status {
+            Status::Fail(kind) => Some(kind),
+            _ => None,
+        })
+        .collect();
+    match failed.first() {
+        None => Ok(()),
+        Some(kind) => Err(Failure::new(
+            *kind,
+            format!(
+                "{} check{} failed",
+                failed.len(),
+                if failed.len() == 1 { "" } else { "s" }
+            ),
+        )
+        .into()),
+    }
+}
+
+fn collect(cli: &Cli, ping: bool) -> Vec<Check> {
+    let mut checks = vec![check_git(), check_repository()];
+    let prompt_config = check_config(&mut checks);
+
+    let host = auth::resolve_host();
+    let token = match auth::resolve_token(&host, false) {
+        Ok(token) => {
+            let source = match token.source {
+                TokenSource::Env(key) => format!("from ${key}"),
+                TokenSource::GhCli | TokenSource::Cache => format!("from gh for {host}"),
+            };
+            checks.push(Check::new("token", Status::Pass, source));
+            checks.push(check_scopes(&host, token.source));
+            Some(token)
+        }
+        Err(err) => {
+            checks.push(
+                Check::new("token", Status::Fail(FailureKind::Auth), err.to_string())
+                    .hint("run 'gh auth login', or set GH_TOKEN"),
+            );
+            None
+        }
+    };
+
+    let default_network = NetworkConfig::default();
+    let network = prompt_config
+        .as_ref()
+        .map_or(&default_network, |config| &config.network);
+    let client = match token {
+        None => {
+            checks.push(Check::new("endpoint", Status::Skip, "needs a token"));
+            None
+        }
+        Some(token) => match llm::Client::from_token(host, token, network) {
+            Ok(client) => Some(client),
+            Err(err) => {
+                checks.push(endpoint_failure(err.as_ref()));
+                None
+            }
+        },
+    };
+    let available = match &client {
+        None => None,
+        Some(client) => match client.list_models() {
+            Ok(models) => {
+                checks.push(Check::new(
+                    "endpoint",
+                    Status::Pass,
+                    format!("GitHub Models lists {} models", models.len()),
+                ));
+                Some(models)
+            }
+            Err(err) => {
+                checks.push(endpoint_failure(err.as_ref()));
+                None
+            }
+        },
+    };
+
+    match (prompt_config, available) {
+        (Some(prompt_config), Some(available)) => {
+            let models = check_models(&cli.model, &prompt_config, &available);
+            let chain = (models.status == Status::Pass)
+                .then(|| resolve_model_chain(&cli.model, &prompt_config.model_policy).ok())
+                .flatten();
+            checks.push(models);
+            match (ping, &client, chain) {
+                (true, Some(client), Some(chain)) => {
+                    checks.extend(check_latency(client, &prompt_config, &chain));
+                }
+                (true, _, _) => {
+                    checks.push(Check::new(
+                        "ping",
+                        Status::Skip,
+                        "needs every model available",
+                    ));
+                }
+                _ => {}
+            }
+        }
+        _ => checks.push(Check::new(
+            "models",
+            Status::Skip,
+            "needs a valid config and a reachable endpoint",
+        )),
+    }
+    checks
+}
+
+/// Sends a one-token completion to each model in `chain` and suggests the
+/// fastest-first order when it differs from the chain.
+fn check_latency(
+    client: &llm::Client,
+    prompt_config: &PromptConfig,
+    chain: &[String],
+) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let mut timings = Vec::new();
+    for model in chain {
+        match client.ping(prompt_config, model) {
+            Ok(elapsed) => {
+                let mut detail = format!("{model} answered in {}ms", elapsed.as_millis());
+                if let Some(quota) = client.quota(model) {
+                    detail.push_str(&format!(", {}", quota.describe(quota::now())));
+                }
+                checks.push(Check::new("ping", Status::Pass, detail));
+                timings.push((model.as_str(), elapsed));
+            }
+            Err(err) => checks.push(Check::new(
+                "ping",
+                Status::Fail(FailureKind::Api),
+                format!("{model}: {err}"),
+            )),
+        }
+    }
+    let fastest = fastest_first(&timings);
+    let pinged: Vec<&str> = timings.iter().map(|(model, _)| *model).collect();
+    if fastest != pinged {
+        checks.push(
+            Check::new(
+                "ping",
+                Status::Warn,
+                format!("fastest first: {}", fastest.join(", ")),
+            )
+            .hint("list modelPolicy.autoModels in this order to try the fastest model first"),
+        );
+    }
+    checks
+}
+
+/// Models ordered by round-trip time, fastest first.
+fn fastest_first<'a>(timings: &[(&'a str, Duration)]) -> Vec<&'a str> {
+    let mut sorted = timings.to_vec();
+    sorted.sort_by_key(|(_, elapsed)| *elapsed);
+    sorted.into_iter().map(|(model, _)| model).collect()
+}
+
+fn check_git() -> Check {
+    let output = Command::new("git").arg("--version").output();
+    let text = match output {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        _ => {
+            return Check::new("git", Status::Fail(FailureKind::Git), "git was not found")
+                .hint("install git and make sure it is on PATH");
+        }
+    };
+    match parse_git_version(&text) {
+        Some(version) if version < MIN_GIT_VERSION => {
+            Check::new("git", Status::Warn, text).hint(format!(
+                "upgrade to git {}.{} or later",
+                MIN_GIT_VERSION.0, MIN_GIT_VERSION.1
+            ))
+        }
+        _ => Check::new("git", Status::Pass, text),
+    }
+}
+
+/// Major and minor version from `git --version` output such as
+/// `git version 2.39.3 (Apple Git-146)`.
+fn parse_git_version(text: &str) -> Option<(u32, u32)> {
+    let version = text
+        .strip_prefix("git version ")?
+        .split_whitespace()
+        .next()?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+fn check_repository() -> Check {
+    let root = match git::toplevel() {
+        Ok(root) => root,
+        Err(err) => {
+            return Check::new(
+                "repository",
+                Status::Fail(FailureKind::Git),
+                err.to_string(),
+            )
+            .hint("run sparkle inside a git work tree");
+        }
+    };
+    let branch = git::current_branch().unwrap_or_else(|_| "detached HEAD".to_string());
+    let staged = Command::new("git")
+        .args(["diff", "--cached", "--name-only"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).lines().count())
+        .unwrap_or(0);
+    let check = Check::new(
+        "repository",
+        Status::Pass,
+        format!(
+            "{} on {branch}, {staged} file{} staged",
+            root.display(),
+            if staged == 1 { "" } else { "s" }
+        ),
+    );
+    if staged == 0 {
+        check.hint("stage changes with 'git add' before generating a message")
+    } else {
+        check
+    }
+}
+
+/// Validates each config file on its own, then all layers together with the
+/// `SPARKLE_*` environment overrides.
+fn check_config(checks: &mut Vec<Check>) -> Option<PromptConfig> {
+    let mut files_valid = true;
+    for path in config::layer_paths() {
+        match config::validate_file(&path) {
+            Ok(()) => checks.push(Check::new(
+                "config",
+                Status::Pass,
+                format!("{} is valid", path.display()),
+            )),
+            Err(issue) => {
+                files_valid = false;
+                checks.push(
+                    Check::new(
+                        "config",
+                        Status::Fail(FailureKind::Config),
+                        issue.to_string(),
+                    )
+                    .hint(format!(
+                        "fix the file; 'gh sparkle config validate {}' shows the same error",
+                        path.display()
+                    )),
+                );
+            }
+        }
+    }
+    if !files_valid {
+        return None;
+    }
+
+    match config::load() {
+        Ok(prompt_config) => {
+            checks.push(Check::new(
+                "config",
+                Status::Pass,
+                "all layers and SPARKLE_* overrides merge cleanly",
+            ));
+            Some(prompt_config)
+        }
+        Err(err) => {
+            checks.push(
+                Check::new("config", Status::Fail(FailureKind::Config), err.to_string())
+                    .hint("check the SPARKLE_* environment variables"),
+            );
+            None
+        }
+    }
+}
+
+fn check_scopes(host: &str, source: TokenSource) -> Check {
+    if let TokenSource::Env(key) = source {
+        return Check::new(
+            "scopes",
+            Status::Skip,
+            format!("not listed for ${key}; the endpoint check shows its access"),
+        );
+    }
+    let output = Command::new("gh")
+        .args(["auth", "status", "--hostname", host])
+        .output();
+    let scopes = output.ok().and_then(|output| {
+        // Older gh releases print the status to stderr.
+        let text = String::from_utf8_lossy(&output.stdout).to_string()
+            + &String::from_utf8_lossy(&output.stderr);
+        parse_scopes(&text)
+    });
+    match scopes {
+        None => Check::new("scopes", Status::Skip, "gh did not list token scopes"),
+        Some(scopes) if scopes.iter().any(|scope| scope == "models") => {
+            Check::new("scopes", Status::Pass, scopes.join(", "))
+        }
+        Some(scopes) => Check::new(
+            "scopes",
+            Status::Warn,
+            format!("no models scope ({})", scopes.join(", ")),
+        )
+        .hint("run 'gh auth refresh -s models' if the endpoint check fails"),
+    }
+}
+
+/// Scopes from the `Token scopes: 'gist', 'repo'` line of `gh auth status`.
+fn parse_scopes(text: &str) -> Option<Vec<String>> {
+    let (_, list) = text
+        .lines()
+        .find_map(|line| line.split_once("Token scopes:"))?;
+    Some(
+        list.split(',')
+            .map(|scope| scope.trim().trim_matches(['\'', '"']).to_string())
+            .filter(|scope| !scope.is_empty() && scope != "none")
+            .collect(),
+    )
+}
+
+fn endpoint_failure(err: &(dyn Error + 'static)) -> Check {
+    if let Some(auth_err) = err.downcast_ref::<AuthError>() {
+        return Check::new(
+            "endpoint",
+            Status::Fail(FailureKind::Auth),
+            auth_err.to_string(),
+        );
+    }
+    let check = Check::new("endpoint", Status::Fail(FailureKind::Api), err.to_string());
+    if err.is::<ConnectivityError>() {
+        check.hint(
+            "check the network, HTTPS_PROXY or network.proxy, or raise network.connectTimeout",
+        )
+    } else {
+        check
+    }
+}
+
+fn check_models(requested: &str, prompt_config: &PromptConfig, available: &[String]) -> Check {
+    let chain = match resolve_model_chain(requested, &prompt_config.model_policy) {
+        Ok(chain) => chain,
+        Err(err) => {
+            return Check::new("models", Status::Fail(FailureKind::Config), err.to_string())
+                .hint("set modelPolicy.autoModels or pass --model");
+        }
+    };
+    let missing: Vec<&str> = chain
+        .iter()
+        .filter(|model| !available.contains(model))
+        .map(String::as_str)
+        .collect();
+    if missing.is_empty() {
+        Check::new("models", Status::Pass, chain.join(", "))
+    } else {
+        Check::new(
+            "models",
+            Status::Fail(FailureKind::Config),
+            format!("not available: {}", missing.join(", ")),
+        )
+        .hint("pick models from 'gh models list' for --model or modelPolicy.autoModels")
+    }
+}
+
+fn format_report(checks: &[Check]) -> String {
+    let mut report = String::new();
+    for check in checks {
+        let label = match check.status {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Skip => "skip",
+            Status::Fail(_) => "FAIL",
+        };
+        report.push_str(&format!(
+            "  {label}  {:<10}  {}\n",
+            check.name, check.detail
+        ));
+        if let Some(hint) = &check.hint {
+            report.push_str(&format!("{:18}hint: {hint}\n", ""));
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_git_versions_and_gh_scopes() {
+        assert_eq!(
+            parse_git_version("git version 2.39.3 (Apple Git-146)"),
+            Some((2, 39))
+        );
+        assert_eq!(
+            parse_git_version("git version 2.45.1.windows.1"),
+            Some((2, 45))
+        );
+        assert_eq!(parse_git_version("not git"), None);
+
+        let status = "github.com\n  ✓ Logged in to github.com account octo (keyring)\n  \
+                      - Token scopes: 'gist', 'models', 'repo'\n";
+        assert_eq!(
+            parse_scopes(status),
+            Some(vec!["gist".into(), "models".into(), "repo".into()])
+        );
+        assert_eq!(parse_scopes("  - Token scopes: none\n"), Some(Vec::new()));
+        assert_eq!(parse_scopes("github.com\n"), None);
+    }
+
+    #[test]
+    fn fastest_first_orders_models_by_latency() {
+        let timings = [
+            ("openai/gpt-4.1", Duration::from_millis(900)),
+            ("openai/gpt-4o-mini", Duration::from_millis(300)),
+            ("openai/gpt-4.1-nano", Duration::from_millis(450)),
+        ];
+        assert_eq!(
+            fastest_first(&timings),
+            [
+                "openai/gpt-4o-mini",
+                "openai/gpt-4.1-nano",
+                "openai/gpt-4.1"
+            ]
+        );
+    }
+
+    #[test]
+    fn report_lists_checks_with_hints() {
+        let checks = [
+            Check::new("git", Status::Pass, "git version 2.43.0"),
+            Check::new(
+                "token",
+                Status::Fail(FailureKind::Auth),
+                "no GitHub token found",
+            )
+            .hint("run 'gh auth login'"),
+            Check::new("endpoint", Status::Skip, "needs a token"),
+        ];
+        assert_eq!(
+            format_report(&checks),
+            "  pass  git         git version 2.43.0\n  \
+             FAIL  token       no GitHub token found\n                  \
+             hint: run 'gh auth login'\n  \
+             skip  endpoint    needs a token\n"
+        );
+    }
+}
@@ -0,0 +1,63 @@
+// Near-duplicate detection of commit subjects against recent history.
+
+use std::collections::HashSet;
+
+/// Word overlap above which two subjects count as the same message.
+const SIMILARITY_THRESHOLD: f64 = 0.8;
+
+/// Returns the first recent subject that `subject` nearly repeats.
+pub fn find_near_duplicate<'a>(subject: &str, recent: &'a [String]) -> Option<&'a str> {
+    let words = normalized_words(subject);
+    if words.is_empty() {
+        return None;
+    }
+
+    recent
+        .iter()
+        .find(|candidate| similarity(&words, &normalized_words(candidate)) >= SIMILARITY_THRESHOLD)
+        .map(String::as_str)
+}
+
+/// Word overlap of two subjects, from `0.0` (disjoint) to `1.0` (same words).
+pub fn subject_similarity(left: &str, right: &str) -> f64 {
+    similarity(&normalized_words(left), &normalized_words(right))
+}
+
+fn normalized_words(subject: &str) -> HashSet<String> {
+    subject
+        .split(|ch: char| !ch.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Jaccard index of two word sets.
+fn similarity(left: &HashSet<String>, right: &HashSet<String>) -> f64 {
+    let union = left.union(right).count();
+    if union == 0 {
+        return 0.0;
+    }
+    left.intersection(right).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_near_duplicate_ignores_case_and_punctuation() {
+        let recent = vec![
+            "feat(ui): add spinner".to_string(),
+            "fix: fix tests".to_string(),
+        ];
+        assert_eq!(
+            find_near_duplicate("Fix: fix tests.", &recent),
+            Some("fix: fix tests")
+        );
+        assert_eq!(
+            find_near_duplicate("fix: fix flaky auth tests", &recent),
+            None
+        );
+        assert_eq!(find_near_duplicate("", &recent), None);
+    }
+}
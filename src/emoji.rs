@@ -0,0 +1,101 @@
+// Emoji detection and removal for the `output.emoji` policy.
+
+/// Whether `c` is a pictographic emoji, a regional indicator, or one of the
+/// joiners and selectors that glue emoji sequences together.
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F1E6..=0x1F1FF
+            | 0x1F300..=0x1F5FF
+            | 0x1F600..=0x1F64F
+            | 0x1F680..=0x1F6FF
+            | 0x1F900..=0x1F9FF
+            | 0x1FA70..=0x1FAFF
+            | 0x2600..=0x27BF
+            | 0x2B50
+            | 0x2B55
+            | 0x200D
+            | 0xFE0F
+            | 0x20E3
+    )
+}
+
+/// A gitmoji shortcode such as `:sparkles:`.
+fn is_shortcode(word: &str) -> bool {
+    word.len() > 2
+        && word.starts_with(':')
+        && word.ends_with(':')
+        && word[1..word.len() - 1]
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+/// Whether `message` contains an emoji or a leading gitmoji shortcode.
+pub fn contains(message: &str) -> bool {
+    message.chars().any(is_emoji) || starts_with_shortcode(message)
+}
+
+fn starts_with_shortcode(message: &str) -> bool {
+    message.split_whitespace().next().is_some_and(is_shortcode)
+}
+
+/// Whether `subject` opens with an emoji or a gitmoji shortcode.
+pub fn starts_with_emoji(subject: &str) -> bool {
+    let subject = subject.trim_start();
+    subject.chars().next().is_some_and(is_emoji) || starts_with_shortcode(subject)
+}
+
+/// Removes emoji and a leading shortcode from every line, dropping the
+/// spaces they leave behind.
+pub fn strip(message: &str) -> String {
+    let mut stripped = String::with_capacity(message.len());
+    for (index, line) in message.lines().enumerate() {
+        if index > 0 {
+            stripped.push('\n');
+        }
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let mut words: Vec<String> = line
+            .split(' ')
+            .map(|word| word.chars().filter(|c| !is_emoji(*c)).collect::<String>())
+            .collect();
+        if let Some(first) = words.iter().position(|word| !word.is_empty())
+            && is_shortcode(&words[first])
+        {
+            words[first].clear();
+        }
+        let text = words
+            .iter()
+            .filter(|word| !word.is_empty())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !text.is_empty() {
+            stripped.push_str(indent);
+        }
+        stripped.push_str(&text);
+    }
+    if message.ends_with('\n') {
+        stripped.push('\n');
+    }
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_emoji_and_detects_a_leading_gitmoji() {
+        assert_eq!(
+            strip("✨ feat: add login 🎉\n\n- handle 👍🏽 tokens\n"),
+            "feat: add login\n\n- handle tokens\n"
+        );
+        assert_eq!(strip(":bug: fix crash"), "fix crash");
+        assert_eq!(strip("fix: keep a:b: ratio"), "fix: keep a:b: ratio");
+        assert!(starts_with_emoji("🐛 fix crash"));
+        assert!(starts_with_emoji(":bug: fix crash"));
+        assert!(!starts_with_emoji("fix: crash 🐛"));
+        assert!(contains("fix: crash 🐛"));
+        assert!(!contains("fix: crash"));
+    }
+}
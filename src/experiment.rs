@@ -0,0 +1,135 @@
+// Prompt A/B experiments: per-run variant assignment and comparison.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::history::Entry;
+use crate::prompt::PromptConfig;
+
+/// The experiment and variant a run was assigned to.
+pub struct Assignment {
+    pub experiment: String,
+    pub variant: &'static str,
+}
+
+/// Picks a variant for this run and, for variant `b`, swaps the
+/// experiment's messages into `config`.
+pub fn assign(config: &mut PromptConfig) -> Option<Assignment> {
+    let experiment = &mut config.experiment;
+    if experiment.messages.is_empty() {
+        return None;
+    }
+    let variant = if random_unit() < experiment.split {
+        config.messages = std::mem::take(&mut experiment.messages);
+        "b"
+    } else {
+        "a"
+    };
+    Some(Assignment {
+        experiment: config.experiment.name.clone(),
+        variant,
+    })
+}
+
+/// A uniformly distributed value in `[0, 1)` from the std hasher's random
+/// keys, which is plenty for a traffic split.
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Compares acceptance and refinement rates per variant of `experiment`, or
+/// of every recorded experiment when `None`.
+pub fn format_comparison(entries: &[Entry], experiment: Option<&str>) -> String {
+    let mut groups: Vec<(&str, &str, usize, usize, usize)> = Vec::new();
+    for entry in entries {
+        let (Some(name), Some(variant)) = (&entry.experiment, &entry.variant) else {
+            continue;
+        };
+        if experiment.is_some_and(|wanted| wanted != name) {
+            continue;
+        }
+        let accepted = usize::from(entry.refinements == 0);
+        match groups
+            .iter_mut()
+            .find(|(group, group_variant, ..)| group == name && group_variant == variant)
+        {
+            Some(group) => {
+                group.2 += 1;
+                group.3 += accepted;
+                group.4 += entry.refinements;
+            }
+            None => groups.push((name, variant, 1, accepted, entry.refinements)),
+        }
+    }
+    if groups.is_empty() {
+        return "No experiment runs recorded in this repository yet.\n".to_string();
+    }
+    groups.sort_by(|left, right| (left.0, left.1).cmp(&(right.0, right.1)));
+
+    let width = groups
+        .iter()
+        .map(|group| group.0.len())
+        .max()
+        .unwrap_or(0)
+        .max("EXPERIMENT".len());
+    let mut table = format!(
+        "{:<width$}  {:<7}  {:>4}  {:>8}  {:>11}\n",
+        "EXPERIMENT", "VARIANT", "RUNS", "ACCEPTED", "REFINES/RUN"
+    );
+    for (name, variant, runs, accepted, refinements) in groups {
+        table.push_str(&format!(
+            "{:<width$}  {:<7}  {:>4}  {:>8}  {:>11}\n",
+            name,
+            variant,
+            runs,
+            format!("{}%", accepted * 100 / runs),
+            format!("{:.1}", refinements as f64 / runs as f64),
+        ));
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(variant: &str, refinements: usize) -> Entry {
+        let mut entry = Entry::new("m", false, 0, refinements, "fix: a\n");
+        entry.experiment = Some("terse".to_string());
+        entry.variant = Some(variant.to_string());
+        entry
+    }
+
+    #[test]
+    fn format_comparison_groups_runs_by_variant() {
+        let entries = vec![
+            entry("a", 0),
+            entry("b", 1),
+            entry("a", 2),
+            Entry::new("m", false, 0, 0, "fix: b\n"),
+        ];
+        let table = format_comparison(&entries, Some("terse"));
+        let rows: Vec<Vec<&str>> = table
+            .lines()
+            .skip(1)
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ["terse", "a", "2", "50%", "1.0"],
+                ["terse", "b", "1", "0%", "1.0"]
+            ]
+        );
+    }
+
+    #[test]
+    fn random_unit_stays_in_range() {
+        for _ in 0..100 {
+            let value = random_unit();
+            assert!((0.0..1.0).contains(&value));
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// Context budget report for `--explain-context`.
+
+/// How one context section fared under a budget.
+pub struct Section {
+    /// The source, or the name of a context command.
+    pub name: String,
+    /// Columns the section could use, including what earlier sections left.
+    pub allowed: usize,
+    /// Columns of the source text before truncation.
+    pub available: usize,
+    /// Columns of header and content that went into the context.
+    pub used: usize,
+    pub outcome: &'static str,
+}
+
+/// The context assembled for one budget tier.
+pub struct Tier {
+    pub label: &'static str,
+    pub budget_tokens: usize,
+    /// What the budget leaves for the change context after the reply
+    /// reserve and the prompt template.
+    pub context_tokens: usize,
+    pub sections: Vec<Section>,
+    pub context_chars: usize,
+    /// Characters of the whole rendered prompt, context included.
+    pub prompt_chars: usize,
+}
+
+/// Renders a table of sections for every tier followed by its totals.
+/// Token counts are estimates: characters divided by `token_char_ratio`.
+pub fn report(tiers: &[Tier], token_char_ratio: usize, reserve_tokens: usize) -> String {
+    let tokens = |chars: usize| chars.div_ceil(token_char_ratio.max(1));
+    let mut report = String::new();
+    for (index, tier) in tiers.iter().enumerate() {
+        if index > 0 {
+            report.push('\n');
+        }
+        report.push_str(&format!(
+            "{} budget: {} tokens, {} for the context ({} chars)\n",
+            tier.label,
+            tier.budget_tokens,
+            tier.context_tokens,
+            tier.context_tokens.saturating_mul(token_char_ratio)
+        ));
+        report.push_str(&format!(
+            "  {:<8}  {:>9}  {:>9}  {:>9}  {:>7}  outcome\n",
+            "section", "available", "allowed", "used", "~tokens"
+        ));
+        for section in &tier.sections {
+            report.push_str(&format!(
+                "  {:<8}  {:>9}  {:>9}  {:>9}  {:>7}  {}\n",
+                section.name,
+                section.available,
+                section.allowed,
+                section.used,
+                tokens(section.used),
+                section.outcome
+            ));
+        }
+        report.push_str(&format!(
+            "  context ~{} tokens, whole prompt ~{} tokens, {} reserved for the reply\n",
+            tokens(tier.context_chars),
+            tokens(tier.prompt_chars),
+            reserve_tokens
+        ));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_lists_sections_and_token_estimates_per_tier() {
+        let tiers = [Tier {
+            label: "minimal",
+            budget_tokens: 400,
+            context_tokens: 100,
+            sections: vec![
+                Section {
+                    name: "summary".to_string(),
+                    allowed: 400,
+                    available: 1000,
+                    used: 399,
+                    outcome: "truncated",
+                },
+                Section {
+                    name: "diff".to_string(),
+                    allowed: 0,
+                    available: 5000,
+                    used: 0,
+                    outcome: "left out (summary only)",
+                },
+            ],
+            context_chars: 399,
+            prompt_chars: 1201,
+        }];
+        assert_eq!(
+            report(&tiers, 4, 256),
+            "minimal budget: 400 tokens, 100 for the context (400 chars)\n  \
+             section   available    allowed       used  ~tokens  outcome\n  \
+             summary        1000        400        399      100  truncated\n  \
+             diff           5000          0          0        0  left out (summary only)\n  \
+             context ~100 tokens, whole prompt ~301 tokens, 256 reserved for the reply\n"
+        );
+    }
+}
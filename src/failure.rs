@@ -0,0 +1,130 @@
+// Failure classification and the documented process exit codes.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::auth::AuthError;
+use crate::llm::ConnectivityError;
+
+/// Broad failure classes, each mapped to a stable exit code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FailureKind {
+    NoStagedChanges,
+    Auth,
+    Api,
+    Git,
+    Config,
+    Aborted,
+    /// Existing commit messages break the configured conventions.
+    Lint,
+}
+
+impl FailureKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureKind::NoStagedChanges => 2,
+            FailureKind::Auth => 3,
+            FailureKind::Api => 4,
+            FailureKind::Git => 5,
+            FailureKind::Config => 6,
+            FailureKind::Aborted => 7,
+            FailureKind::Lint => 8,
+        }
+    }
+
+    /// The class a child `gh sparkle` process reported through its exit code.
+    pub fn from_exit_code(code: i32) -> Option<Self> {
+        [
+            FailureKind::NoStagedChanges,
+            FailureKind::Auth,
+            FailureKind::Api,
+            FailureKind::Git,
+            FailureKind::Config,
+            FailureKind::Aborted,
+            FailureKind::Lint,
+        ]
+        .into_iter()
+        .find(|kind| kind.exit_code() == code)
+    }
+}
+
+/// An error tagged with the failure class used for the exit code.
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: FailureKind,
+    message: String,
+}
+
+impl Failure {
+    pub fn new(kind: FailureKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Failure {}
+
+/// Returns the failure class of an error, if it carries one.
+pub fn kind_of(err: &(dyn Error + 'static)) -> Option<FailureKind> {
+    if let Some(failure) = err.downcast_ref::<Failure>() {
+        return Some(failure.kind);
+    }
+    if err.is::<AuthError>() {
+        return Some(FailureKind::Auth);
+    }
+    if err.is::<ConnectivityError>() {
+        return Some(FailureKind::Api);
+    }
+    None
+}
+
+/// Exit code for an error; unclassified errors exit with 1.
+pub fn exit_code(err: &(dyn Error + 'static)) -> i32 {
+    kind_of(err).map(FailureKind::exit_code).unwrap_or(1)
+}
+
+pub trait Classify<T> {
+    /// Tags an error with `kind` unless it is already classified.
+    fn classify(self, kind: FailureKind) -> Result<T, Box<dyn Error>>;
+}
+
+impl<T> Classify<T> for Result<T, Box<dyn Error>> {
+    fn classify(self, kind: FailureKind) -> Result<T, Box<dyn Error>> {
+        self.map_err(|err| {
+            if kind_of(err.as_ref()).is_some() {
+                err
+            } else {
+                Box::new(Failure::new(kind, err.to_string()))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_keeps_existing_kind() {
+        let result: Result<(), Box<dyn Error>> = Err("boom".into());
+        let err = result.classify(FailureKind::Git).unwrap_err();
+        assert_eq!(exit_code(err.as_ref()), 5);
+
+        let err = Err::<(), _>(err).classify(FailureKind::Api).unwrap_err();
+        assert_eq!(exit_code(err.as_ref()), 5);
+    }
+
+    #[test]
+    fn unclassified_errors_exit_with_one() {
+        let err: Box<dyn Error> = "boom".into();
+        assert_eq!(exit_code(err.as_ref()), 1);
+    }
+}
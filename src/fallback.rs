@@ -0,0 +1,137 @@
+// Classification of failed model requests for the fallback policy.
+
+use crate::prompt::{FallbackAction, FallbackPolicy};
+
+/// Error classes the fallback policy can react to.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ErrorClass {
+    PayloadTooLarge,
+    RateLimit,
+    ModelUnavailable,
+    ContentFilter,
+    Timeout,
+}
+
+impl ErrorClass {
+    /// Reads the class from an API or transport error message.
+    pub fn of(message: &str) -> Option<Self> {
+        let lower = message.to_lowercase();
+        if is_payload_too_large(&lower) {
+            Some(Self::PayloadTooLarge)
+        } else if lower.contains("status 429")
+            || lower.contains("too many requests")
+            || lower.contains("ratelimitreached")
+        {
+            Some(Self::RateLimit)
+        } else if lower.contains("content_filter")
+            || lower.contains("responsibleaipolicyviolation")
+            || lower.contains("content management policy")
+        {
+            Some(Self::ContentFilter)
+        } else if lower.contains("status 404")
+            || lower.contains("status 503")
+            || lower.contains("unknown_model")
+            || lower.contains("unavailable_model")
+        {
+            Some(Self::ModelUnavailable)
+        } else if lower.contains("timed out") {
+            Some(Self::Timeout)
+        } else {
+            None
+        }
+    }
+
+    /// The configured reaction to this class.
+    pub fn action(self, policy: &FallbackPolicy) -> FallbackAction {
+        match self {
+            Self::PayloadTooLarge => policy.payload_too_large,
+            Self::RateLimit => policy.rate_limit,
+            Self::ModelUnavailable => policy.model_unavailable,
+            Self::ContentFilter => policy.content_filter,
+            Self::Timeout => policy.timeout,
+        }
+    }
+
+    /// The key of this class under modelPolicy.fallback.
+    pub fn key(self) -> &'static str {
+        match self {
+            Self::PayloadTooLarge => "payloadTooLarge",
+            Self::RateLimit => "rateLimit",
+            Self::ModelUnavailable => "modelUnavailable",
+            Self::ContentFilter => "contentFilter",
+            Self::Timeout => "timeout",
+        }
+    }
+
+    /// Starts the log line announcing a retry.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::PayloadTooLarge => "Request too large",
+            Self::RateLimit => "Rate limited",
+            Self::ModelUnavailable => "Model unavailable",
+            Self::ContentFilter => "Blocked by the content filter",
+            Self::Timeout => "Request timed out",
+        }
+    }
+
+    /// Replaces the raw API error body where it does not help the reader.
+    pub fn explain(self, message: &str) -> String {
+        match self {
+            Self::ContentFilter => "the GitHub Models content filter rejected the request; \
+                 write this message by hand or unstage the files that trigger it"
+                .to_string(),
+            _ => message.to_string(),
+        }
+    }
+}
+
+fn is_payload_too_large(message: &str) -> bool {
+    let lower = message.to_lowercase();
+    lower.contains("status 413")
+        || lower.contains("payload too large")
+        || lower.contains("tokens_limit_reached")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_payload_too_large_detects_error_signals() {
+        assert!(is_payload_too_large("status 413"));
+        assert!(is_payload_too_large("Payload Too Large"));
+        assert!(is_payload_too_large("tokens_limit_reached"));
+        assert!(!is_payload_too_large("other error"));
+    }
+
+    #[test]
+    fn error_classes_map_to_policy_actions() {
+        let policy = FallbackPolicy::default();
+        let class = |message| ErrorClass::of(message);
+        assert_eq!(
+            class("API request failed with status 429 Too Many Requests: slow down"),
+            Some(ErrorClass::RateLimit)
+        );
+        assert_eq!(
+            class(r#"status 400 Bad Request: {"error":{"code":"content_filter"}}"#),
+            Some(ErrorClass::ContentFilter)
+        );
+        assert_eq!(
+            class("status 404 Not Found: unknown_model"),
+            Some(ErrorClass::ModelUnavailable)
+        );
+        assert_eq!(
+            class("request to m timed out after 60s (network.requestTimeout)"),
+            Some(ErrorClass::Timeout)
+        );
+        assert_eq!(class("status 500 Internal Server Error"), None);
+        assert_eq!(
+            ErrorClass::RateLimit.action(&policy),
+            FallbackAction::NextModel
+        );
+        assert_eq!(
+            ErrorClass::Timeout.action(&policy),
+            FallbackAction::SmallerContext
+        );
+    }
+}
@@ -2,89 +2,781 @@
 
 use std::error::Error;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
-pub fn get_staged_changes() -> Result<String, Box<dyn Error>> {
-    if !is_git_repository() {
-        return Err("current directory is not a git repository".into());
+use crate::hook;
+use crate::profile;
+
+/// Staged changes as a `--stat` summary and the full patch.
+pub struct StagedDiff {
+    pub summary: String,
+    pub patch: String,
+}
+
+/// The git operations the commit pipeline needs; faked in tests.
+pub trait GitBackend {
+    /// Collects the staged stat summary and patch.
+    fn staged_diff(&self) -> Result<StagedDiff, Box<dyn Error>>;
+    /// Reads the subjects of the last `count` commits; empty before the first
+    /// commit.
+    fn recent_subjects(&self, count: usize) -> Result<Vec<String>, Box<dyn Error>>;
+    /// Commits the index with `message`.
+    fn commit(&self, message: &str, quiet: bool) -> Result<(), Box<dyn Error>>;
+}
+
+/// The encoding `i18n.commitEncoding` names, unless UTF-8. Messages are
+/// still written as UTF-8: sparkle has no tables to transcode them.
+pub fn message_encoding() -> Option<String> {
+    Repo::current().message_encoding()
+}
+
+/// Why committing `message` as is may go wrong: it is not ASCII and git
+/// will label its UTF-8 bytes with another `i18n.commitEncoding`.
+pub fn encoding_warning(message: &str) -> Option<String> {
+    if message.is_ascii() {
+        return None;
     }
+    let encoding = message_encoding()?;
+    Some(format!(
+        "the message is written as UTF-8, but git will label the commit as {encoding} \
+         (i18n.commitEncoding)"
+    ))
+}
+
+/// A repository driven through the `git` binary.
+pub struct Repo {
+    dir: Option<PathBuf>,
+    allow_empty: bool,
+}
+
+impl Repo {
+    /// The repository containing the current directory.
+    pub fn current() -> Self {
+        Self {
+            dir: None,
+            allow_empty: false,
+        }
+    }
+
+    /// Lets commits go through with nothing staged, like `--allow-empty`.
+    pub fn allowing_empty(mut self, allow_empty: bool) -> Self {
+        self.allow_empty = allow_empty;
+        self
+    }
+
+    /// The repository containing `dir`.
+    pub fn at(dir: &Path) -> Self {
+        Self {
+            dir: Some(dir.to_path_buf()),
+            allow_empty: false,
+        }
+    }
+
+    fn git(&self) -> Command {
+        let mut command = Command::new("git");
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    fn message_encoding(&self) -> Option<String> {
+        let output = self
+            .git()
+            .args(["config", "--get", "i18n.commitEncoding"])
+            .output()
+            .ok()?;
+        let label = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let utf8 = label.is_empty() || label.replace(['-', '_'], "").eq_ignore_ascii_case("utf8");
+        (!utf8).then_some(label)
+    }
+
+    /// Paths with staged changes, relative to the repository root.
+    pub fn staged_paths(&self) -> Vec<String> {
+        changed_paths(self.git(), &["diff", "--cached", "--name-only", "-z"])
+    }
+
+    fn is_git_repository(&self) -> bool {
+        self.git()
+            .args(["rev-parse", "--is-inside-work-tree"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    }
+}
+
+impl GitBackend for Repo {
+    /// Uses a single `git diff` call for both parts.
+    fn staged_diff(&self) -> Result<StagedDiff, Box<dyn Error>> {
+        if !self.is_git_repository() {
+            return Err("current directory is not a git repository".into());
+        }
 
+        let _span = profile::span("git", "diff --staged");
+        let output = self
+            .git()
+            .args(["diff", "--staged", "--stat", "--patch", "--color=never"])
+            .output()?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "error executing git diff --staged --stat --patch: {}",
+                output.status
+            )
+            .into());
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (summary, patch) = split_stat_and_patch(&stdout);
+        Ok(StagedDiff {
+            summary: with_mode_changes(summary, patch),
+            patch: patch.to_string(),
+        })
+    }
+
+    fn recent_subjects(&self, count: usize) -> Result<Vec<String>, Box<dyn Error>> {
+        let _span = profile::span("git", "log subjects");
+        let output = self
+            .git()
+            .args(["log", "-n", &count.to_string(), "--format=%s"])
+            .output()?;
+
+        if !output.status.success() {
+            return Ok(Vec::new());
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn commit(&self, message: &str, quiet: bool) -> Result<(), Box<dyn Error>> {
+        let _span = profile::span("git", "commit");
+        let mut command = self.git();
+        command
+            .args(["commit", "-F", "-"])
+            .env(hook::GUARD_ENV, "1");
+        if self.allow_empty {
+            command.arg("--allow-empty");
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(if quiet {
+                Stdio::null()
+            } else {
+                Stdio::inherit()
+            })
+            .stderr(if quiet {
+                Stdio::null()
+            } else {
+                Stdio::inherit()
+            })
+            .spawn()?;
+
+        {
+            let stdin = child
+                .stdin
+                .as_mut()
+                .ok_or("failed to open git commit stdin")?;
+            stdin.write_all(message.as_bytes())?;
+        }
+
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(format!("git commit failed with status {}", status).into());
+        }
+
+        Ok(())
+    }
+}
+
+/// `summary` followed by a line per file whose mode changed, which the stat
+/// alone shows as ` path | 0`.
+fn with_mode_changes(summary: &str, patch: &str) -> String {
+    let mut changes = Vec::new();
+    let mut path = "";
+    let mut old_mode = "";
+    for line in patch.lines() {
+        if let Some(paths) = line.strip_prefix("diff --git a/") {
+            path = paths.split_once(" b/").map_or(paths, |(_, new)| new);
+        } else if let Some(mode) = line.strip_prefix("old mode ") {
+            old_mode = mode;
+        } else if let Some(new_mode) = line.strip_prefix("new mode ") {
+            let what = match (old_mode.ends_with("755"), new_mode.ends_with("755")) {
+                (false, true) => "now executable",
+                (true, false) => "no longer executable",
+                _ => "mode changed",
+            };
+            changes.push(format!(" {path}: {what} ({old_mode} -> {new_mode})"));
+        }
+    }
+    if changes.is_empty() {
+        return summary.to_string();
+    }
+    format!("{summary}\n\nMode changes:\n{}", changes.join("\n"))
+}
+
+fn split_stat_and_patch(output: &str) -> (&str, &str) {
+    if output.starts_with("diff --git ") {
+        return ("", output);
+    }
+
+    match output.find("\ndiff --git ") {
+        Some(index) => {
+            let summary = output[..index].trim_end_matches('\n');
+            (summary, &output[index + 1..])
+        }
+        None => (output, ""),
+    }
+}
+
+/// Reads recent commits as `git log` shows them, one entry per commit;
+/// callers must already be inside a repository.
+pub fn get_commit_messages(count: usize) -> Result<Vec<String>, Box<dyn Error>> {
+    let _span = profile::span("git", "log");
     let output = Command::new("git")
-        .args(["diff", "--staged", "--color=never"])
+        .args(["log", "-z", "-n", &count.to_string()])
         .output()?;
 
     if !output.status.success() {
-        return Err(format!("error executing git diff --staged: {}", output.status).into());
+        return Err(format!("error executing git log: {}", output.status).into());
     }
 
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .map(|entry| entry.trim_end().to_string())
+        .filter(|entry| !entry.is_empty())
+        .collect())
 }
 
-pub fn get_staged_summary() -> Result<String, Box<dyn Error>> {
-    if !is_git_repository() {
+/// The subject of a `git log` entry: its first indented message line.
+pub fn log_entry_subject(entry: &str) -> &str {
+    entry
+        .lines()
+        .find(|line| line.starts_with("    "))
+        .map_or("", str::trim)
+}
+
+/// Reads `sparkle.*` settings from git config (repository, user, and system
+/// levels, the most specific winning), keyed by the lowercased name after
+/// `sparkle.`.
+pub fn sparkle_settings() -> Vec<(String, String)> {
+    let output = Command::new("git")
+        .args(["config", "--get-regexp", r"^sparkle\."])
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            parse_settings(&String::from_utf8_lossy(&output.stdout))
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn parse_settings(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ').unwrap_or((line, "true"));
+            let name = key.strip_prefix("sparkle.")?;
+            Some((name.to_lowercase(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Returns the root of the working tree.
+pub fn toplevel() -> Result<PathBuf, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .output()?;
+    if !output.status.success() {
+        return Err("current directory is not a git repository".into());
+    }
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim(),
+    ))
+}
+
+/// Returns the repository's git directory, e.g. `.git`.
+pub fn git_dir() -> Result<PathBuf, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--absolute-git-dir"])
+        .output()?;
+    if !output.status.success() {
         return Err("current directory is not a git repository".into());
     }
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim(),
+    ))
+}
+
+/// A multi-step git operation stopped part way, usually on a conflict.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operation {
+    Rebase,
+    Am,
+    CherryPick,
+    Revert,
+    Bisect,
+}
+
+impl Operation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rebase => "rebase",
+            Self::Am => "am",
+            Self::CherryPick => "cherry-pick",
+            Self::Revert => "revert",
+            Self::Bisect => "bisect",
+        }
+    }
+}
+
+/// The operation in progress in the repository whose git directory is
+/// `git_dir`, if any.
+pub fn operation_in_progress(git_dir: &Path) -> Option<Operation> {
+    if git_dir.join("rebase-merge").is_dir() {
+        Some(Operation::Rebase)
+    } else if git_dir.join("rebase-apply").is_dir() {
+        if git_dir.join("rebase-apply/applying").exists() {
+            Some(Operation::Am)
+        } else {
+            Some(Operation::Rebase)
+        }
+    } else if git_dir.join("CHERRY_PICK_HEAD").exists() {
+        Some(Operation::CherryPick)
+    } else if git_dir.join("REVERT_HEAD").exists() {
+        Some(Operation::Revert)
+    } else if git_dir.join("BISECT_LOG").exists() {
+        Some(Operation::Bisect)
+    } else {
+        None
+    }
+}
 
+/// The subject of the message git prepared for the stopped step
+/// (`MERGE_MSG`), if any.
+pub fn prepared_subject(git_dir: &Path) -> Option<String> {
+    let text = std::fs::read_to_string(git_dir.join("MERGE_MSG")).ok()?;
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+}
+
+/// Returns the checked-out branch name; fails on a detached HEAD.
+pub fn current_branch() -> Result<String, Box<dyn Error>> {
     let output = Command::new("git")
-        .args(["diff", "--staged", "--stat", "--color=never"])
+        .args(["symbolic-ref", "--short", "-q", "HEAD"])
         .output()?;
 
+    if !output.status.success() {
+        return Err("HEAD is detached".into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Paths with changes in the work tree that are not staged.
+pub fn unstaged_paths() -> Vec<String> {
+    changed_paths(Command::new("git"), &["diff", "--name-only", "-z"])
+}
+
+/// Paths with staged changes in the current repository, relative to its
+/// root.
+pub fn staged_paths() -> Vec<String> {
+    Repo::current().staged_paths()
+}
+
+fn changed_paths(mut git: Command, args: &[&str]) -> Vec<String> {
+    let output = git.args(args).stderr(Stdio::null()).output();
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|path| !path.is_empty())
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether HEAD points at a commit; `false` on the unborn branch of a new
+/// repository.
+pub fn has_head() -> bool {
+    Command::new("git")
+        .args(["rev-parse", "--verify", "-q", "HEAD"])
+        .stdout(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// A commit on the current branch.
+pub struct BranchCommit {
+    pub sha: String,
+    pub subject: String,
+}
+
+/// Guesses the branch a pull request would target: `origin/HEAD`, then
+/// `main`, then `master`.
+pub fn default_base() -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "origin/HEAD"])
+        .stderr(Stdio::null())
+        .output()?;
+    if output.status.success() {
+        return Ok(String::from_utf8_lossy(&output.stdout).trim().to_string());
+    }
+
+    for candidate in ["main", "master"] {
+        let exists = Command::new("git")
+            .args(["rev-parse", "--verify", "--quiet", candidate])
+            .stdout(Stdio::null())
+            .status()?
+            .success();
+        if exists {
+            return Ok(candidate.to_string());
+        }
+    }
+    Err("could not determine the base branch; pass --base".into())
+}
+
+/// Returns the commit where HEAD forked from `base`.
+pub fn merge_base(base: &str) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args(["merge-base", base, "HEAD"])
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("no common ancestor between {base} and HEAD").into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Lists commits after `fork_point` up to HEAD, oldest first. Fails when the
+/// range contains merges, which a linear rebase would flatten.
+pub fn branch_commits(fork_point: &str) -> Result<Vec<BranchCommit>, Box<dyn Error>> {
+    let range = format!("{fork_point}..HEAD");
+    let merges = Command::new("git")
+        .args(["rev-list", "--merges", &range])
+        .output()?;
+    if !merges.stdout.is_empty() {
+        return Err("the branch contains merge commits; rebase it first".into());
+    }
+
+    let output = Command::new("git")
+        .args(["log", "--reverse", "--format=%H %s", &range])
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("error executing git log: {}", output.status).into());
+    }
+
+    Ok(parse_commit_lines(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Lists the non-merge commits in `range` (e.g. `main..HEAD`), oldest
+/// first.
+pub fn range_commits(range: &str) -> Result<Vec<BranchCommit>, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args([
+            "log",
+            "--reverse",
+            "--no-merges",
+            "--format=%H %s",
+            range,
+            "--",
+        ])
+        .output()?;
     if !output.status.success() {
         return Err(format!(
-            "error executing git diff --staged --stat: {}",
-            output.status
+            "error executing git log {range}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
         )
         .into());
     }
-
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    Ok(parse_commit_lines(&String::from_utf8_lossy(&output.stdout)))
 }
 
-pub fn get_commit_messages(count: usize) -> Result<String, Box<dyn Error>> {
-    if !is_git_repository() {
-        return Err("current directory is not a git repository".into());
+/// `git log` of non-merge commits with the files each touched, oldest
+/// first, in `format`. `author` filters by name or email; `all_branches`
+/// looks beyond HEAD.
+pub fn log_with_files(
+    format: &str,
+    since: &str,
+    until: Option<&str>,
+    author: Option<&str>,
+    all_branches: bool,
+) -> Result<String, Box<dyn Error>> {
+    let mut command = Command::new("git");
+    command.args([
+        "log",
+        "--reverse",
+        "--no-merges",
+        "--name-only",
+        format,
+        &format!("--since={since}"),
+    ]);
+    if let Some(until) = until {
+        command.arg(format!("--until={until}"));
+    }
+    if let Some(author) = author {
+        command.arg(format!("--author={author}"));
+    }
+    if all_branches {
+        command.arg("--all");
     }
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "error executing git log: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
 
+/// The configured `user.email`.
+pub fn user_email() -> Result<String, Box<dyn Error>> {
     let output = Command::new("git")
-        .args(["log", "-n", &count.to_string()])
+        .args(["config", "user.email"])
         .output()?;
+    let email = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if email.is_empty() {
+        return Err("user.email is not set; pass --author or --everyone".into());
+    }
+    Ok(email)
+}
 
+/// Lists the last `count` non-merge commits on HEAD, newest first.
+pub fn recent_commits(count: usize) -> Result<Vec<BranchCommit>, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args([
+            "log",
+            "-n",
+            &count.to_string(),
+            "--no-merges",
+            "--format=%H %s",
+        ])
+        .output()?;
     if !output.status.success() {
         return Err(format!("error executing git log: {}", output.status).into());
     }
+    Ok(parse_commit_lines(&String::from_utf8_lossy(&output.stdout)))
+}
 
-    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+fn parse_commit_lines(output: &str) -> Vec<BranchCommit> {
+    output
+        .lines()
+        .map(|line| {
+            let (sha, subject) = line.split_once(' ').unwrap_or((line, ""));
+            BranchCommit {
+                sha: sha.to_string(),
+                subject: subject.to_string(),
+            }
+        })
+        .collect()
 }
 
-pub fn commit_with_message(message: &str, quiet: bool) -> Result<(), Box<dyn Error>> {
-    if !is_git_repository() {
-        return Err("current directory is not a git repository".into());
+/// Collects the stat summary and patch a commit introduced.
+pub fn commit_diff(sha: &str) -> Result<StagedDiff, Box<dyn Error>> {
+    let output = Command::new("git")
+        .args([
+            "show",
+            "--stat",
+            "--patch",
+            "--format=",
+            "--color=never",
+            sha,
+        ])
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("error executing git show {sha}: {}", output.status).into());
     }
 
-    let mut child = Command::new("git")
-        .args(["commit", "-F", "-"])
-        .stdin(Stdio::piped())
-        .stdout(if quiet {
-            Stdio::null()
-        } else {
-            Stdio::inherit()
-        })
-        .stderr(if quiet {
-            Stdio::null()
-        } else {
-            Stdio::inherit()
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (summary, patch) = split_stat_and_patch(stdout.trim_start_matches('\n'));
+    Ok(StagedDiff {
+        summary: summary.to_string(),
+        patch: patch.to_string(),
+    })
+}
+
+/// Collects the stat summary and patch between `fork_point` and HEAD.
+pub fn diff_since(fork_point: &str) -> Result<StagedDiff, Box<dyn Error>> {
+    let _span = profile::span("git", "diff fork point");
+    let output = Command::new("git")
+        .args([
+            "diff",
+            "--stat",
+            "--patch",
+            "--color=never",
+            fork_point,
+            "HEAD",
+        ])
+        .output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "error executing git diff {fork_point} HEAD: {}",
+            output.status
+        )
+        .into());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (summary, patch) = split_stat_and_patch(&stdout);
+    Ok(StagedDiff {
+        summary: summary.to_string(),
+        patch: patch.to_string(),
+    })
+}
+
+/// Runs `git rebase -i onto` with the todo list replaced by `todo_path`.
+pub fn rebase_with_todo(onto: &str, todo_path: &Path) -> Result<(), Box<dyn Error>> {
+    let status = Command::new("git")
+        .args(["rebase", "-i", onto])
+        .env(hook::GUARD_ENV, "1")
+        .env(
+            "GIT_SEQUENCE_EDITOR",
+            format!("cp \"{}\"", todo_path.display()),
+        )
+        .status()?;
+    if !status.success() {
+        return Err(format!(
+            "git rebase failed with status {status}; run 'git rebase --abort' to undo"
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Opens git's editor on `message` and returns the edited message without
+/// comment lines. An empty message is an error.
+pub fn edit_message(message: &str) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git").args(["var", "GIT_EDITOR"]).output()?;
+    if !output.status.success() {
+        return Err("no editor configured; set core.editor or $EDITOR".into());
+    }
+    let editor = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    let comment_char = comment_char();
+    let path = std::env::temp_dir().join(format!("sparkle-message-{}", std::process::id()));
+    std::fs::write(
+        &path,
+        format!(
+            "{}\n\n{}",
+            hook::escape_comment_lines(message.trim_end(), comment_char),
+            commented_status(comment_char)
+        ),
+    )?;
+    let status = editor_command(&editor, &path).status();
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+
+    let status = status?;
+    if !status.success() {
+        return Err(format!("editor exited with status {status}").into());
+    }
+    let edited: String = edited?
+        .lines()
+        .filter(|line| !line.starts_with(comment_char))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    let edited = edited.trim();
+    if edited.is_empty() {
+        return Err("the edited message is empty".into());
+    }
+    Ok(format!("{edited}\n"))
+}
+
+/// Runs `editor` on `path` through the platform shell, as git does, so
+/// editors with arguments such as `code --wait` work.
+#[cfg(not(windows))]
+fn editor_command(editor: &str, path: &Path) -> Command {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(editor)
+        .arg(path);
+    command
+}
+
+/// Runs `editor` on `path` through `cmd /C`. The line is passed unescaped,
+/// since cmd does not understand the backslash quoting of other programs.
+#[cfg(windows)]
+fn editor_command(editor: &str, path: &Path) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    let mut command = Command::new("cmd");
+    command
+        .arg("/C")
+        .raw_arg(format!("{editor} \"{}\"", path.display()));
+    command
+}
+
+/// The character git's cleanup treats as starting a comment line:
+/// `core.commentChar` or `core.commentString`, `#` when unset or `auto`
+/// (git only picks another one for messages it already has).
+pub fn comment_char() -> char {
+    ["core.commentString", "core.commentChar"]
+        .iter()
+        .find_map(|key| {
+            let output = Command::new("git")
+                .args(["config", "--get", key])
+                .output()
+                .ok()?;
+            let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            (value != "auto").then(|| value.chars().next()).flatten()
         })
-        .spawn()?;
+        .unwrap_or('#')
+}
 
-    {
-        let stdin = child
-            .stdin
-            .as_mut()
-            .ok_or("failed to open git commit stdin")?;
-        stdin.write_all(message.as_bytes())?;
+/// The commented instructions and `git status` that git shows under a
+/// message it opens in the editor.
+fn commented_status(comment_char: char) -> String {
+    let mut section = format!(
+        "{comment_char} Please enter the commit message for your changes. Lines starting\n\
+         {comment_char} with '{comment_char}' will be ignored, and an empty message aborts the commit.\n"
+    );
+    let status = Command::new("git")
+        .args(["-c", "color.status=never", "status"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default();
+    if !status.is_empty() {
+        section.push(comment_char);
+        section.push('\n');
+    }
+    for line in status.lines() {
+        match line {
+            "" => section.push(comment_char),
+            line if line.starts_with('\t') => section.push_str(&format!("{comment_char}{line}")),
+            line => section.push_str(&format!("{comment_char} {line}")),
+        }
+        section.push('\n');
     }
+    section
+}
 
-    let status = child.wait()?;
+/// Opens the user's editor on `template` and commits the edited message.
+///
+/// Git aborts the commit when the template is left unchanged.
+pub fn commit_with_template(template: &str) -> Result<(), Box<dyn Error>> {
+    let path = std::env::temp_dir().join(format!("sparkle-template-{}", std::process::id()));
+    std::fs::write(&path, template)?;
+
+    let status = Command::new("git")
+        .env(hook::GUARD_ENV, "1")
+        .arg("commit")
+        .arg("--template")
+        .arg(&path)
+        .status();
+    let _ = std::fs::remove_file(&path);
+
+    let status = status?;
     if !status.success() {
         return Err(format!("git commit failed with status {}", status).into());
     }
@@ -92,12 +784,76 @@ pub fn commit_with_message(message: &str, quiet: bool) -> Result<(), Box<dyn Err
     Ok(())
 }
 
-fn is_git_repository() -> bool {
-    Command::new("git")
-        .args(["rev-parse", "--is-inside-work-tree"])
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .map(|status| status.success())
-        .unwrap_or(false)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operation_in_progress_reads_sequencer_state() {
+        let dir = std::env::temp_dir().join(format!("sparkle-operation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(operation_in_progress(&dir), None);
+        std::fs::write(dir.join("BISECT_LOG"), "").unwrap();
+        assert_eq!(operation_in_progress(&dir), Some(Operation::Bisect));
+        std::fs::write(dir.join("CHERRY_PICK_HEAD"), "").unwrap();
+        std::fs::write(
+            dir.join("MERGE_MSG"),
+            "\nfix: keep tokens\n\n# Conflicts:\n",
+        )
+        .unwrap();
+        assert_eq!(operation_in_progress(&dir), Some(Operation::CherryPick));
+        assert_eq!(prepared_subject(&dir).as_deref(), Some("fix: keep tokens"));
+        std::fs::create_dir_all(dir.join("rebase-apply")).unwrap();
+        std::fs::write(dir.join("rebase-apply/applying"), "").unwrap();
+        assert_eq!(operation_in_progress(&dir), Some(Operation::Am));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn parse_settings_reads_sparkle_keys() {
+        let output = "sparkle.language chinese\nsparkle.Model openai/gpt-4.1\nsparkle.flag\n";
+        assert_eq!(
+            parse_settings(output),
+            [
+                ("language".to_string(), "chinese".to_string()),
+                ("model".to_string(), "openai/gpt-4.1".to_string()),
+                ("flag".to_string(), "true".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn mode_changes_are_summarized() {
+        let patch = "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n";
+        assert_eq!(
+            with_mode_changes(" run.sh | 0\n 1 file changed", patch),
+            " run.sh | 0\n 1 file changed\n\nMode changes:\n run.sh: now executable \
+             (100644 -> 100755)"
+        );
+        assert_eq!(
+            with_mode_changes(" a | 1 +", "diff --git a/a b/a\n+x\n"),
+            " a | 1 +"
+        );
+    }
+
+    #[test]
+    fn split_stat_and_patch_separates_sections() {
+        let output =
+            " a.txt | 1 +\n 1 file changed, 1 insertion(+)\n\ndiff --git a/a.txt b/a.txt\n+b\n";
+        let (summary, patch) = split_stat_and_patch(output);
+        assert_eq!(summary, " a.txt | 1 +\n 1 file changed, 1 insertion(+)");
+        assert_eq!(patch, "diff --git a/a.txt b/a.txt\n+b\n");
+        assert_eq!(split_stat_and_patch(""), ("", ""));
+    }
+    #[cfg(unix)]
+    #[test]
+    fn editor_command_passes_the_path_as_one_argument() {
+        let output = editor_command("printf '%s|'", Path::new("/tmp/sparkle message"))
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "/tmp/sparkle message|"
+        );
+    }
 }
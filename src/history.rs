@@ -0,0 +1,274 @@
+// Per-repository generation history kept under the git directory.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::git;
+
+const HISTORY_FILE: &str = "sparkle/history.jsonl";
+
+/// One committed generation.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub model: String,
+    /// Whether a smaller context budget or a later model had to answer.
+    #[serde(default)]
+    pub fallback: bool,
+    /// Estimated from the final prompt's length and `contextPolicy.tokenCharRatio`.
+    #[serde(default)]
+    pub prompt_tokens: usize,
+    /// Interactive refinements requested before committing.
+    #[serde(default)]
+    pub refinements: usize,
+    pub message: String,
+    /// Prompt experiment and variant this run was assigned to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub experiment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// Set afterwards with `sparkle feedback`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feedback: Option<Feedback>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// A user's verdict on a generated message.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Feedback {
+    /// Prefer this message as an example in later runs
+    Good,
+    /// Never use this message as an example
+    Bad,
+}
+
+impl Entry {
+    pub fn new(
+        model: &str,
+        fallback: bool,
+        prompt_tokens: usize,
+        refinements: usize,
+        message: &str,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+            model: model.to_string(),
+            fallback,
+            prompt_tokens,
+            refinements,
+            message: message.to_string(),
+            experiment: None,
+            variant: None,
+            feedback: None,
+            note: None,
+        }
+    }
+}
+
+fn history_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(git::git_dir()?.join(HISTORY_FILE))
+}
+
+/// Appends `entry` to the repository history.
+pub fn append(entry: &Entry) -> Result<(), Box<dyn Error>> {
+    let path = history_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Reads every entry, oldest first; lines that no longer parse are skipped.
+pub fn load() -> Result<Vec<Entry>, Box<dyn Error>> {
+    let path = history_path()?;
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok(parse(&text)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn parse(text: &str) -> Vec<Entry> {
+    text.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+fn render(entries: &[Entry]) -> Result<String, Box<dyn Error>> {
+    let mut text = String::new();
+    for entry in entries {
+        text.push_str(&serde_json::to_string(entry)?);
+        text.push('\n');
+    }
+    Ok(text)
+}
+
+/// Tags the most recent entry and returns its message.
+pub fn tag_last(feedback: Feedback, note: Option<String>) -> Result<String, Box<dyn Error>> {
+    let mut entries = load()?;
+    let message = tag_latest(&mut entries, feedback, note)?;
+    fs::write(history_path()?, render(&entries)?)?;
+    Ok(message)
+}
+
+fn tag_latest(
+    entries: &mut [Entry],
+    feedback: Feedback,
+    note: Option<String>,
+) -> Result<String, Box<dyn Error>> {
+    let last = entries
+        .last_mut()
+        .ok_or("no generations recorded in this repository yet")?;
+    last.feedback = Some(feedback);
+    last.note = note;
+    Ok(last.message.clone())
+}
+
+/// Messages tagged `feedback`, newest first.
+pub fn tagged_messages(feedback: Feedback) -> Vec<String> {
+    tagged(load().unwrap_or_default(), feedback)
+}
+
+fn tagged(entries: Vec<Entry>, feedback: Feedback) -> Vec<String> {
+    entries
+        .into_iter()
+        .rev()
+        .filter(|entry| entry.feedback == Some(feedback))
+        .map(|entry| entry.message)
+        .collect()
+}
+
+/// Renders acceptance, token, and fallback figures for `entries`.
+pub fn format_stats(entries: &[Entry]) -> String {
+    let total = entries.len();
+    if total == 0 {
+        return "No generations recorded in this repository yet.\n".to_string();
+    }
+    let accepted = entries
+        .iter()
+        .filter(|entry| entry.refinements == 0)
+        .count();
+    let refinements: usize = entries.iter().map(|entry| entry.refinements).sum();
+    let tokens: usize = entries.iter().map(|entry| entry.prompt_tokens).sum();
+    let fallbacks = entries.iter().filter(|entry| entry.fallback).count();
+
+    let mut models: Vec<(&str, usize)> = Vec::new();
+    for entry in entries {
+        match models.iter_mut().find(|(model, _)| *model == entry.model) {
+            Some((_, count)) => *count += 1,
+            None => models.push((&entry.model, 1)),
+        }
+    }
+    models.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    let mut table = String::new();
+    let mut row = |label: &str, value: String| {
+        table.push_str(&format!("{label:<22} {value}\n"));
+    };
+    row("Generations", total.to_string());
+    row(
+        "Accepted as generated",
+        format!("{accepted} ({})", percent(accepted, total)),
+    );
+    row(
+        "Refined before commit",
+        format!(
+            "{} ({})",
+            total - accepted,
+            percent(total - accepted, total)
+        ),
+    );
+    row(
+        "Refinements per run",
+        format!("{:.1}", refinements as f64 / total as f64),
+    );
+    row("Average prompt tokens", (tokens / total).to_string());
+    row(
+        "Model fallbacks",
+        format!("{fallbacks} ({})", percent(fallbacks, total)),
+    );
+    for (model, count) in models {
+        row(&format!("  {model}"), count.to_string());
+    }
+    table
+}
+
+fn percent(part: usize, whole: usize) -> String {
+    format!("{}%", part * 100 / whole)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_stats_summarizes_acceptance_and_fallbacks() {
+        let entries = vec![
+            Entry::new("openai/gpt-4o-mini", false, 3000, 0, "fix: a\n"),
+            Entry::new("openai/gpt-4o-mini", false, 1000, 2, "fix: b\n"),
+            Entry::new("openai/gpt-4.1-mini", true, 2000, 0, "feat: c\n"),
+        ];
+        let stats = format_stats(&entries);
+        assert!(stats.contains("Generations            3\n"), "{stats}");
+        assert!(stats.contains("Accepted as generated  2 (66%)\n"));
+        assert!(stats.contains("Refinements per run    0.7\n"));
+        assert!(stats.contains("Average prompt tokens  2000\n"));
+        assert!(stats.contains("Model fallbacks        1 (33%)\n"));
+        assert!(stats.contains("  openai/gpt-4o-mini   2\n"));
+    }
+
+    #[test]
+    fn tagging_survives_a_round_trip_and_selects_messages() {
+        let mut entries = vec![
+            Entry::new("m", false, 0, 0, "fix: a\n"),
+            Entry::new("m", false, 0, 0, "fix: b\n"),
+        ];
+        assert_eq!(
+            tag_latest(&mut entries, Feedback::Bad, Some("vague".into())).unwrap(),
+            "fix: b\n"
+        );
+        entries.push(Entry::new("m", false, 0, 0, "feat: c\n"));
+        tag_latest(&mut entries, Feedback::Good, None).unwrap();
+        entries[0].feedback = Some(Feedback::Good);
+
+        let text = render(&entries).unwrap();
+        let reloaded = parse(&format!("{text}not json\n"));
+        assert_eq!(reloaded.len(), 3);
+        assert_eq!(reloaded[1].note.as_deref(), Some("vague"));
+        assert_eq!(
+            tagged(parse(&text), Feedback::Good),
+            ["feat: c\n", "fix: a\n"]
+        );
+        assert_eq!(tagged(reloaded, Feedback::Bad), ["fix: b\n"]);
+        assert!(tag_latest(&mut [], Feedback::Good, None).is_err());
+    }
+
+    #[test]
+    fn entries_without_newer_fields_still_parse() {
+        let entry: Entry =
+            serde_json::from_str(r#"{"timestamp":1,"model":"m","message":"fix: a\n"}"#).unwrap();
+        assert_eq!(entry.refinements, 0);
+        assert!(!entry.fallback);
+        assert_eq!(entry.feedback, None);
+
+        let tagged: Entry = serde_json::from_str(
+            r#"{"timestamp":1,"model":"m","message":"fix: a\n","feedback":"good","note":"clear"}"#,
+        )
+        .unwrap();
+        assert_eq!(tagged.feedback, Some(Feedback::Good));
+        assert_eq!(tagged.note.as_deref(), Some("clear"));
+    }
+}
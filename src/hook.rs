@@ -0,0 +1,236 @@
+// Git hook entry points, as run directly by git or by the pre-commit framework.
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use crate::failure::{Classify, FailureKind};
+use crate::git::{self, GitBackend};
+use crate::{
+    Cli, GenerationContext, blocked_staged_paths, convention_document, fetch_examples,
+    load_prompt_config, new_llm_client, parse_examples_count, parse_language,
+    produce_commit_message, project_docs, resolve_model_chain, scrub,
+};
+
+/// Set on the git commands sparkle runs that create commits, so the hook
+/// sparkle may also be installed as leaves those commits alone instead of
+/// asking the model a second time.
+pub const GUARD_ENV: &str = "SPARKLE_COMMITTING";
+
+/// Whether this hook run belongs to a commit sparkle started itself.
+pub fn started_by_sparkle(guard: Option<std::ffi::OsString>) -> bool {
+    guard.is_some_and(|value| !value.is_empty())
+}
+
+/// Hooks sparkle can run as.
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Kind {
+    /// Fill in the message file before git opens the editor
+    PrepareCommitMsg,
+}
+
+/// The arguments of a `prepare-commit-msg` run.
+pub struct PrepareCommitMsg {
+    pub message_file: PathBuf,
+    /// Where git took the message from: `message`, `template`, `merge`,
+    /// `squash`, or `commit`; absent for a plain `git commit`.
+    pub source: Option<String>,
+}
+
+impl PrepareCommitMsg {
+    /// Reads git's `<file> [<source> [<sha>]]` arguments. The pre-commit
+    /// framework passes only the file and exports the source as
+    /// `PRE_COMMIT_COMMIT_MSG_SOURCE`.
+    pub fn parse(args: &[String], env_source: Option<String>) -> Result<Self, Box<dyn Error>> {
+        let (message_file, rest) = args
+            .split_first()
+            .ok_or("prepare-commit-msg expects the commit message file as its first argument")?;
+        let source = rest
+            .first()
+            .cloned()
+            .or(env_source)
+            .filter(|source| !source.is_empty());
+        Ok(Self {
+            message_file: PathBuf::from(message_file),
+            source,
+        })
+    }
+
+    /// Only plain `git commit` runs get a generated message; `-m`, `-F`,
+    /// templates, merges, squashes, and amends keep theirs.
+    pub fn wants_message(&self) -> bool {
+        self.source.is_none()
+    }
+}
+
+/// Puts `generated` in front of the file's comment block. Returns `None`
+/// when the file already holds a message, e.g. from another hook.
+pub fn fill_message_file(existing: &str, generated: &str, comment_char: char) -> Option<String> {
+    let has_message = existing
+        .lines()
+        .any(|line| !line.trim().is_empty() && !line.starts_with(comment_char));
+    if has_message {
+        return None;
+    }
+    let mut filled = escape_comment_lines(generated.trim_end(), comment_char);
+    filled.push('\n');
+    let comments = existing.trim_start_matches('\n');
+    if !comments.is_empty() {
+        filled.push('\n');
+        filled.push_str(comments);
+    }
+    Some(filled)
+}
+
+/// Indents lines of `message` that start with `comment_char`, such as a
+/// `#123` reference, by one space so git's cleanup keeps them.
+pub fn escape_comment_lines(message: &str, comment_char: char) -> String {
+    message
+        .split('\n')
+        .map(|line| {
+            if line.starts_with(comment_char) {
+                format!(" {line}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Writes a generated message into git's message file. Failures are only
+/// reported, so a broken model call never blocks the commit.
+pub fn run(cli: &Cli, kind: Kind) -> Result<(), Box<dyn Error>> {
+    let Kind::PrepareCommitMsg = kind;
+    if started_by_sparkle(std::env::var_os(GUARD_ENV)) {
+        return Ok(());
+    }
+    let invocation = PrepareCommitMsg::parse(
+        &cli.hook_args,
+        std::env::var("PRE_COMMIT_COMMIT_MSG_SOURCE").ok(),
+    )
+    .classify(FailureKind::Config)?;
+    if !invocation.wants_message() {
+        return Ok(());
+    }
+    if let Err(err) = fill_from_model(cli, &invocation) {
+        eprintln!("sparkle: no commit message generated: {err}");
+    }
+    Ok(())
+}
+
+fn fill_from_model(cli: &Cli, invocation: &PrepareCommitMsg) -> Result<(), Box<dyn Error>> {
+    let repo = git::Repo::current();
+    let staged = repo.staged_diff()?;
+    if staged.patch.trim().is_empty() {
+        return Ok(());
+    }
+    let existing = String::from_utf8_lossy(&std::fs::read(&invocation.message_file)?).into_owned();
+    let comment_char = git::comment_char();
+    if fill_message_file(&existing, "", comment_char).is_none() {
+        return Ok(());
+    }
+
+    let prompt_config = load_prompt_config()?;
+    let blocked = blocked_staged_paths(&prompt_config, &git::Repo::current());
+    if !blocked.is_empty() && !cli.allow_blocked {
+        return Err(format!(
+            "staged files match security.blockPaths: {}",
+            blocked.join(", ")
+        )
+        .into());
+    }
+    let language_spec = parse_language(&cli.language)?;
+    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)?;
+    let examples_count = parse_examples_count(cli.examples.clone())?;
+    let examples = if examples_count > 0 {
+        let examples = fetch_examples(examples_count, cli.examples_subjects_only)?;
+        scrub::scrub_examples(&examples, &prompt_config.examples)
+    } else {
+        String::new()
+    };
+    let llm_client = new_llm_client(cli, &prompt_config.network)?;
+    let docs = project_docs(&prompt_config, &staged.patch);
+    let convention = convention_document(&prompt_config);
+    let context = GenerationContext {
+        examples: &examples,
+        docs: &docs,
+        convention: &convention,
+        ..GenerationContext::new(
+            &prompt_config,
+            &staged.summary,
+            &staged.patch,
+            &model_chain,
+            &language_spec,
+        )
+    };
+    let draft = produce_commit_message(
+        &llm_client,
+        &context,
+        |message| eprintln!("sparkle: {message}"),
+        |_| false,
+    )?;
+
+    if let Some(filled) = fill_message_file(&existing, &draft.message, comment_char) {
+        if let Some(warning) = git::encoding_warning(&filled) {
+            eprintln!("sparkle: {warning}");
+        }
+        std::fs::write(&invocation.message_file, filled)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guard_marks_commits_started_by_sparkle() {
+        assert!(started_by_sparkle(Some("1".into())));
+        assert!(!started_by_sparkle(Some("".into())));
+        assert!(!started_by_sparkle(None));
+    }
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_reads_git_and_pre_commit_arguments() {
+        let plain = PrepareCommitMsg::parse(&args(&[".git/COMMIT_EDITMSG"]), None).unwrap();
+        assert!(plain.wants_message());
+
+        let amend =
+            PrepareCommitMsg::parse(&args(&[".git/COMMIT_EDITMSG", "commit", "HEAD"]), None)
+                .unwrap();
+        assert_eq!(amend.source.as_deref(), Some("commit"));
+        assert!(!amend.wants_message());
+
+        let pre_commit =
+            PrepareCommitMsg::parse(&args(&[".git/COMMIT_EDITMSG"]), Some("message".to_string()))
+                .unwrap();
+        assert!(!pre_commit.wants_message());
+
+        assert!(PrepareCommitMsg::parse(&[], None).is_err());
+    }
+
+    #[test]
+    fn fill_message_file_keeps_git_comments() {
+        let existing = "\n# Please enter the commit message for your changes.\n";
+        assert_eq!(
+            fill_message_file(existing, "feat: add hook\n", '#').unwrap(),
+            "feat: add hook\n\n# Please enter the commit message for your changes.\n"
+        );
+        assert_eq!(
+            fill_message_file("fix: typed\n# comment\n", "feat: x", '#'),
+            None
+        );
+    }
+
+    #[test]
+    fn generated_comment_lines_are_escaped() {
+        assert_eq!(
+            fill_message_file("\n; status\n", "fix: x\n\n;-) kept\n#1 kept", ';').unwrap(),
+            "fix: x\n\n ;-) kept\n#1 kept\n\n; status\n"
+        );
+    }
+}
@@ -0,0 +1,271 @@
+// Hunk-level packing of the staged diff into a context budget.
+
+use crate::truncate;
+
+/// How much a hunk tells the model, lowest first.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum Value {
+    /// Only blank lines are added or removed.
+    Blank,
+    /// Only comments (and blank lines) change.
+    Comment,
+    Code,
+}
+
+struct Hunk<'a> {
+    text: &'a str,
+    width: usize,
+    value: Value,
+}
+
+struct File<'a> {
+    path: String,
+    header: &'a str,
+    hunks: Vec<Hunk<'a>>,
+}
+
+/// Fits `diff` into `max_width` columns by leaving out whole hunks: blank-line
+/// changes first, then comment-only changes, then code, later hunks before
+/// earlier ones. A closing line such as `[2 hunks omitted in a.rs, b.rs]`
+/// tells the model what is missing. Diffs without hunks are cut by lines.
+pub fn pack(diff: &str, max_width: usize) -> String {
+    if truncate::width(diff) <= max_width {
+        return diff.to_string();
+    }
+    let (preamble, files) = parse(diff);
+    if files.iter().all(|file| file.hunks.is_empty()) {
+        return truncate::truncate(diff, max_width);
+    }
+
+    let mut order: Vec<(usize, usize)> = files
+        .iter()
+        .enumerate()
+        .flat_map(|(file, entry)| (0..entry.hunks.len()).map(move |hunk| (file, hunk)))
+        .collect();
+    order.sort_by_key(|&(file, hunk)| {
+        (
+            files[file].hunks[hunk].value,
+            std::cmp::Reverse((file, hunk)),
+        )
+    });
+
+    let mut kept: Vec<Vec<bool>> = files
+        .iter()
+        .map(|file| vec![true; file.hunks.len()])
+        .collect();
+    let mut used = truncate::width(diff);
+    let mut omitted = 0;
+    for (file, hunk) in order {
+        if used + truncate::width(&omission_note(&files, &kept, omitted)) <= max_width {
+            break;
+        }
+        kept[file][hunk] = false;
+        used -= files[file].hunks[hunk].width;
+        if kept[file].iter().all(|kept| !kept) {
+            used -= truncate::width(files[file].header);
+        }
+        omitted += 1;
+    }
+
+    let mut packed = preamble.to_string();
+    for (file, kept) in files.iter().zip(&kept) {
+        if !kept.is_empty() && kept.iter().all(|kept| !kept) {
+            continue;
+        }
+        packed.push_str(file.header);
+        for (hunk, _) in file.hunks.iter().zip(kept).filter(|(_, kept)| **kept) {
+            packed.push_str(hunk.text);
+        }
+    }
+    packed.push_str(&omission_note(&files, &kept, omitted));
+    truncate::truncate(&packed, max_width)
+}
+
+fn omission_note(files: &[File<'_>], kept: &[Vec<bool>], omitted: usize) -> String {
+    if omitted == 0 {
+        return String::new();
+    }
+    let paths: Vec<&str> = files
+        .iter()
+        .zip(kept)
+        .filter(|(_, kept)| kept.iter().any(|kept| !kept))
+        .map(|(file, _)| file.path.as_str())
+        .collect();
+    let shown = paths.len().min(5);
+    let mut list = paths[..shown].join(", ");
+    if paths.len() > shown {
+        list.push_str(&format!(" and {} more", paths.len() - shown));
+    }
+    let noun = if omitted == 1 { "hunk" } else { "hunks" };
+    format!("[{omitted} {noun} omitted in {list}]\n")
+}
+
+/// Paths of the files in `diff` that `packed` leaves out entirely, and how
+/// many files `diff` has.
+pub fn omitted_files(diff: &str, packed: &str) -> (Vec<String>, usize) {
+    let kept: Vec<&str> = packed
+        .lines()
+        .filter(|line| line.starts_with("diff --git "))
+        .collect();
+    let (_, files) = parse(diff);
+    let omitted = files
+        .iter()
+        .filter(|file| {
+            let first = file.header.lines().next().unwrap_or_default();
+            !kept.contains(&first)
+        })
+        .map(|file| file.path.clone())
+        .collect();
+    (omitted, files.len())
+}
+
+/// Splits a unified diff into the text before the first file, and files with
+/// their hunks.
+fn parse(diff: &str) -> (&str, Vec<File<'_>>) {
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    for line in diff.split_inclusive('\n') {
+        if line.starts_with("diff --git ") {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    let preamble = &diff[..starts.first().copied().unwrap_or(diff.len())];
+    let files = starts
+        .iter()
+        .enumerate()
+        .map(|(index, &start)| {
+            let end = starts.get(index + 1).copied().unwrap_or(diff.len());
+            parse_file(&diff[start..end])
+        })
+        .collect();
+    (preamble, files)
+}
+
+fn parse_file(text: &str) -> File<'_> {
+    let mut hunk_starts = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.starts_with("@@ ") {
+            hunk_starts.push(offset);
+        }
+        offset += line.len();
+    }
+    let header_end = hunk_starts.first().copied().unwrap_or(text.len());
+    let hunks = hunk_starts
+        .iter()
+        .enumerate()
+        .map(|(index, &start)| {
+            let end = hunk_starts.get(index + 1).copied().unwrap_or(text.len());
+            let text = &text[start..end];
+            Hunk {
+                text,
+                width: truncate::width(text),
+                value: value_of(text),
+            }
+        })
+        .collect();
+    let path = text
+        .lines()
+        .next()
+        .and_then(|line| line.rsplit_once(" b/"))
+        .map(|(_, path)| path.to_string())
+        .unwrap_or_default();
+    File {
+        path,
+        header: &text[..header_end],
+        hunks,
+    }
+}
+
+fn value_of(hunk: &str) -> Value {
+    let changed: Vec<&str> = hunk
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.strip_prefix(['+', '-']))
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    if changed.is_empty() {
+        Value::Blank
+    } else if changed.iter().all(|line| is_comment(line)) {
+        Value::Comment
+    } else {
+        Value::Code
+    }
+}
+
+fn is_comment(line: &str) -> bool {
+    ["//", "#", "/*", "*", "--", "<!--", ";"]
+        .iter()
+        .any(|marker| line.starts_with(marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "\
+diff --git a/src/a.rs b/src/a.rs
+index 1..2 100644
+--- a/src/a.rs
++++ b/src/a.rs
+@@ -1,2 +1,2 @@
+-fn old() {}
++fn new() {}
+@@ -10,9 +10,2 @@
+ x
+-
+-
+-
+-
+-
+-
+-
+ y
+diff --git a/src/b.py b/src/b.py
+--- a/src/b.py
++++ b/src/b.py
+@@ -5 +5 @@
+-# old comment
++# a better comment
+@@ -20 +20 @@
+-value = 1
++value = 2
+";
+
+    const A_HEADER: &str = "\
+diff --git a/src/a.rs b/src/a.rs
+index 1..2 100644
+--- a/src/a.rs
++++ b/src/a.rs
+";
+    const A_CODE: &str = "@@ -1,2 +1,2 @@\n-fn old() {}\n+fn new() {}\n";
+    const A_BLANK: &str = "@@ -10,9 +10,2 @@\n x\n-\n-\n-\n-\n-\n-\n-\n y\n";
+    const B_COMMENT: &str = "@@ -5 +5 @@\n-# old comment\n+# a better comment\n";
+
+    #[test]
+    fn pack_drops_blank_and_comment_hunks_first() {
+        assert_eq!(pack(DIFF, 10_000), DIFF);
+
+        let expected = DIFF.replace(A_BLANK, "") + "[1 hunk omitted in src/a.rs]\n";
+        assert_eq!(pack(DIFF, truncate::width(&expected)), expected);
+
+        let expected = DIFF.replace(A_BLANK, "").replace(B_COMMENT, "")
+            + "[2 hunks omitted in src/a.rs, src/b.py]\n";
+        assert_eq!(pack(DIFF, truncate::width(&expected)), expected);
+    }
+
+    #[test]
+    fn pack_drops_headers_of_emptied_files_and_respects_the_budget() {
+        let expected = format!("{A_HEADER}{A_CODE}[3 hunks omitted in src/a.rs, src/b.py]\n");
+        assert_eq!(pack(DIFF, truncate::width(&expected)), expected);
+        assert_eq!(
+            omitted_files(DIFF, &expected),
+            (vec!["src/b.py".to_string()], 2)
+        );
+        assert!(truncate::width(&pack(DIFF, 50)) <= 50);
+
+        assert_eq!(pack("no hunks\nat all\n", 9), "no hunks\n");
+    }
+}
@@ -0,0 +1,152 @@
+// GitHub issue lookup for linking commits to the issue they close.
+
+use serde::Deserialize;
+use std::error::Error;
+use std::process::Command;
+
+/// The issue a commit closes, with its title and body when `gh` could fetch
+/// it.
+pub struct Issue {
+    pub number: u64,
+    pub title: Option<String>,
+    pub body: String,
+}
+
+#[derive(Deserialize)]
+struct IssueView {
+    title: String,
+    #[serde(default)]
+    body: String,
+}
+
+/// Fetches an issue's title and body via `gh issue view`.
+pub fn fetch(number: u64) -> Result<Issue, Box<dyn Error>> {
+    let output = Command::new("gh")
+        .args(["issue", "view", &number.to_string(), "--json", "title,body"])
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("gh issue view {number} failed: {}", stderr.trim()).into());
+    }
+
+    let view: IssueView = serde_json::from_slice(&output.stdout)?;
+    Ok(Issue {
+        number,
+        title: Some(view.title),
+        body: view.body,
+    })
+}
+
+/// The issue as prompt context: `#N: title` followed by the body, with
+/// HTML comments (such as issue template hints) removed.
+pub fn context(issue: &Issue) -> String {
+    let mut text = match &issue.title {
+        Some(title) => format!("#{}: {title}", issue.number),
+        None => format!("#{}", issue.number),
+    };
+    let mut body = issue.body.replace("\r\n", "\n");
+    while let Some(start) = body.find("<!--") {
+        let end = body[start..]
+            .find("-->")
+            .map_or(body.len(), |end| start + end + 3);
+        body.replace_range(start..end, "");
+    }
+    let body = body.trim();
+    if !body.is_empty() {
+        text.push_str("\n\n");
+        text.push_str(body);
+    }
+    text
+}
+
+/// Parses an issue number from branch names like `123-fix-login`,
+/// `feature/123-cache`, `issue-123`, or `gh-123`.
+pub fn number_from_branch(branch: &str) -> Option<u64> {
+    let name = branch.rsplit('/').next().unwrap_or(branch);
+    let leading: String = name.chars().take_while(char::is_ascii_digit).collect();
+    if !leading.is_empty()
+        && name[leading.len()..]
+            .chars()
+            .next()
+            .is_none_or(|ch| ch == '-' || ch == '_')
+    {
+        return leading.parse().ok();
+    }
+
+    let lowered = name.to_ascii_lowercase();
+    ["issue-", "issues-", "gh-", "#"].iter().find_map(|prefix| {
+        let start = lowered.find(prefix)? + prefix.len();
+        let digits: String = lowered[start..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        digits.parse().ok()
+    })
+}
+
+/// Prompt note describing the issue so the model can explain the "why".
+pub fn prompt_note(issue: &Issue) -> String {
+    match &issue.title {
+        Some(title) => format!(
+            "These changes address issue #{}: \"{title}\". Use it to explain why the change \
+             was made. Do not add a Closes footer; it is added automatically.",
+            issue.number
+        ),
+        None => format!(
+            "These changes address issue #{}. Do not add a Closes footer; it is added \
+             automatically.",
+            issue.number
+        ),
+    }
+}
+
+/// Appends a `Closes #N` footer unless the message already has one.
+pub fn append_closes(message: &str, number: u64) -> String {
+    let footer = format!("Closes #{number}");
+    let message = message.trim_end();
+    if message
+        .lines()
+        .any(|line| line.trim().eq_ignore_ascii_case(&footer))
+    {
+        return format!("{message}\n");
+    }
+    format!("{message}\n\n{footer}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn number_from_branch_understands_common_names() {
+        assert_eq!(number_from_branch("123-fix-login"), Some(123));
+        assert_eq!(number_from_branch("feature/45_cache"), Some(45));
+        assert_eq!(number_from_branch("fix/issue-9-typo"), Some(9));
+        assert_eq!(number_from_branch("GH-77"), Some(77));
+        assert_eq!(number_from_branch("release/2024.10"), None);
+        assert_eq!(number_from_branch("main"), None);
+    }
+
+    #[test]
+    fn context_drops_template_comments() {
+        let issue = Issue {
+            number: 7,
+            title: Some("Login fails".to_string()),
+            body: "<!-- Describe the bug -->\r\nTokens expire early.\r\n".to_string(),
+        };
+        assert_eq!(context(&issue), "#7: Login fails\n\nTokens expire early.");
+    }
+
+    #[test]
+    fn append_closes_adds_footer_once() {
+        assert_eq!(
+            append_closes("fix: handle login\n", 12),
+            "fix: handle login\n\nCloses #12\n"
+        );
+        assert_eq!(
+            append_closes("fix: handle login\n\ncloses #12\n", 12),
+            "fix: handle login\n\ncloses #12\n"
+        );
+    }
+}
@@ -0,0 +1,320 @@
+// Output language detection for generated commit messages.
+
+/// Writing systems distinguishable from character ranges alone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Script {
+    Latin,
+    Han,
+    Kana,
+    Hangul,
+    Cyrillic,
+    Greek,
+    Arabic,
+    Hebrew,
+    Thai,
+    Devanagari,
+}
+
+const LATIN_LANGUAGES: [&str; 34] = [
+    "english",
+    "en",
+    "french",
+    "fr",
+    "spanish",
+    "es",
+    "german",
+    "de",
+    "portuguese",
+    "pt",
+    "italian",
+    "it",
+    "dutch",
+    "nl",
+    "polish",
+    "pl",
+    "turkish",
+    "tr",
+    "vietnamese",
+    "vi",
+    "indonesian",
+    "id",
+    "swedish",
+    "sv",
+    "norwegian",
+    "no",
+    "danish",
+    "da",
+    "finnish",
+    "fi",
+    "czech",
+    "cs",
+    "romanian",
+    "ro",
+];
+
+/// Maps a free-form language name or code to the script it is written in.
+pub fn expected_script(language: &str) -> Option<Script> {
+    let language = language.trim().to_lowercase();
+    let code = language.split(['-', '_']).next().unwrap_or(&language);
+    let is = |names: &[&str]| names.contains(&language.as_str()) || names.contains(&code);
+
+    if language.contains("chinese") || language.contains("中文") || is(&["zh", "mandarin"]) {
+        return Some(Script::Han);
+    }
+    if is(&["japanese", "ja", "日本語"]) {
+        return Some(Script::Kana);
+    }
+    if is(&["korean", "ko", "한국어"]) {
+        return Some(Script::Hangul);
+    }
+    if is(&[
+        "russian",
+        "ru",
+        "ukrainian",
+        "uk",
+        "bulgarian",
+        "bg",
+        "serbian",
+        "sr",
+    ]) {
+        return Some(Script::Cyrillic);
+    }
+    if is(&["greek", "el"]) {
+        return Some(Script::Greek);
+    }
+    if is(&["arabic", "ar", "persian", "farsi", "fa", "urdu", "ur"]) {
+        return Some(Script::Arabic);
+    }
+    if is(&["hebrew", "he"]) {
+        return Some(Script::Hebrew);
+    }
+    if is(&["thai", "th"]) {
+        return Some(Script::Thai);
+    }
+    if is(&["hindi", "hi", "marathi", "mr", "nepali", "ne"]) {
+        return Some(Script::Devanagari);
+    }
+    if is(&LATIN_LANGUAGES) {
+        return Some(Script::Latin);
+    }
+
+    None
+}
+
+/// Requested output language, optionally split between subject and body,
+/// e.g. `en-subject,zh-body`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageSpec {
+    pub subject: String,
+    pub body: String,
+}
+
+impl LanguageSpec {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let mut subject: Option<String> = None;
+        let mut body: Option<String> = None;
+        for part in raw
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let (slot, language) = if let Some(language) = part.strip_suffix("-subject") {
+                (&mut subject, language)
+            } else if let Some(language) = part.strip_suffix("-body") {
+                (&mut body, language)
+            } else {
+                if subject.is_some() || body.is_some() {
+                    return Err(format!("invalid language spec: {raw}"));
+                }
+                subject = Some(part.to_string());
+                body = Some(part.to_string());
+                continue;
+            };
+            if slot.is_some() || language.is_empty() {
+                return Err(format!("invalid language spec: {raw}"));
+            }
+            *slot = Some(language.to_string());
+        }
+
+        match (subject, body) {
+            (Some(subject), Some(body)) => Ok(Self { subject, body }),
+            (Some(language), None) | (None, Some(language)) => Ok(Self {
+                subject: language.clone(),
+                body: language,
+            }),
+            (None, None) => Err("language must not be empty".to_string()),
+        }
+    }
+
+    pub fn is_mixed(&self) -> bool {
+        !self.subject.eq_ignore_ascii_case(&self.body)
+    }
+
+    /// Human-readable instruction rendered into `{{language}}`.
+    pub fn describe(&self) -> String {
+        if !self.is_mixed() {
+            return self.subject.clone();
+        }
+        format!(
+            "{} for the subject line and {} for the body",
+            display_name(&self.subject),
+            display_name(&self.body)
+        )
+    }
+
+    /// Whether the subject and body of `message` use the requested languages.
+    pub fn matches(&self, message: &str) -> bool {
+        let (subject, body) = match message.split_once('\n') {
+            Some((subject, body)) => (subject, body.trim()),
+            None => (message, ""),
+        };
+        if !self.is_mixed() {
+            return matches_language(message, &self.subject);
+        }
+        matches_language(subject, &self.subject)
+            && (body.is_empty() || matches_language(body, &self.body))
+    }
+}
+
+fn display_name(language: &str) -> String {
+    let name = match language.trim().to_lowercase().as_str() {
+        "en" => "English",
+        "zh" | "zh-cn" => "Simplified Chinese",
+        "zh-tw" => "Traditional Chinese",
+        "ja" => "Japanese",
+        "ko" => "Korean",
+        "ru" => "Russian",
+        "fr" => "French",
+        "de" => "German",
+        "es" => "Spanish",
+        "pt" => "Portuguese",
+        _ => return language.to_string(),
+    };
+    name.to_string()
+}
+
+/// Whether `text` looks like it is written in `language`.
+///
+/// Unknown languages always match. Non-Latin targets only need to appear,
+/// since identifiers and Conventional Commit types stay in ASCII; Latin
+/// targets fail when other scripts dominate.
+pub fn matches_language(text: &str, language: &str) -> bool {
+    let Some(expected) = expected_script(language) else {
+        return true;
+    };
+
+    let prose = strip_code(text);
+    let mut latin = 0usize;
+    let mut expected_count = 0usize;
+    let mut other = 0usize;
+    for script in prose.chars().filter_map(script_of) {
+        if script == expected {
+            expected_count += 1;
+        } else if script == Script::Latin {
+            latin += 1;
+        } else {
+            other += 1;
+        }
+    }
+
+    match expected {
+        Script::Latin => expected_count >= other,
+        // Japanese mixes kana with Han characters.
+        Script::Kana => expected_count > 0,
+        _ => expected_count > 0 || (latin == 0 && other == 0),
+    }
+}
+
+/// Drops the Conventional Commit prefix and backtick code spans.
+fn strip_code(text: &str) -> String {
+    let mut prose = String::with_capacity(text.len());
+    for (index, line) in text.lines().enumerate() {
+        let line = match (index, line.split_once(": ")) {
+            (0, Some((prefix, rest))) if !prefix.contains(' ') => rest,
+            _ => line,
+        };
+        let mut in_code = false;
+        for ch in line.chars() {
+            if ch == '`' {
+                in_code = !in_code;
+            } else if !in_code {
+                prose.push(ch);
+            }
+        }
+        prose.push('\n');
+    }
+    prose
+}
+
+fn script_of(ch: char) -> Option<Script> {
+    match ch {
+        'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' => Some(Script::Latin),
+        '\u{3040}'..='\u{30FF}' => Some(Script::Kana),
+        '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' => Some(Script::Han),
+        '\u{AC00}'..='\u{D7AF}' | '\u{1100}'..='\u{11FF}' => Some(Script::Hangul),
+        '\u{0400}'..='\u{04FF}' => Some(Script::Cyrillic),
+        '\u{0370}'..='\u{03FF}' => Some(Script::Greek),
+        '\u{0600}'..='\u{06FF}' => Some(Script::Arabic),
+        '\u{0590}'..='\u{05FF}' => Some(Script::Hebrew),
+        '\u{0E00}'..='\u{0E7F}' => Some(Script::Thai),
+        '\u{0900}'..='\u{097F}' => Some(Script::Devanagari),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expected_script_understands_names_and_codes() {
+        assert_eq!(expected_script("Chinese"), Some(Script::Han));
+        assert_eq!(expected_script("zh-CN"), Some(Script::Han));
+        assert_eq!(expected_script("japanese"), Some(Script::Kana));
+        assert_eq!(expected_script("en"), Some(Script::Latin));
+        assert_eq!(expected_script("klingon"), None);
+    }
+
+    #[test]
+    fn matches_language_detects_ignored_language() {
+        assert!(matches_language(
+            "fix(git): 修复 `get_staged_diff` 的空输出",
+            "chinese"
+        ));
+        assert!(!matches_language(
+            "fix(git): handle empty staged diff",
+            "chinese"
+        ));
+        assert!(matches_language("feat: добавить кэш токенов", "russian"));
+        assert!(!matches_language("feat: 添加令牌缓存", "english"));
+        assert!(matches_language("feat: add token cache", "english"));
+        assert!(matches_language("anything", "klingon"));
+    }
+
+    #[test]
+    fn language_spec_parses_composite_specs() {
+        let spec = LanguageSpec::parse("en-subject,zh-body").unwrap();
+        assert_eq!(spec.subject, "en");
+        assert_eq!(spec.body, "zh");
+        assert_eq!(
+            spec.describe(),
+            "English for the subject line and Simplified Chinese for the body"
+        );
+
+        let plain = LanguageSpec::parse("chinese").unwrap();
+        assert!(!plain.is_mixed());
+        assert_eq!(plain.describe(), "chinese");
+
+        assert!(LanguageSpec::parse("en-subject,fr-subject").is_err());
+        assert!(LanguageSpec::parse("").is_err());
+    }
+
+    #[test]
+    fn language_spec_checks_subject_and_body_separately() {
+        let spec = LanguageSpec::parse("en-subject,zh-body").unwrap();
+        assert!(spec.matches("feat: add token cache\n\n缓存令牌以减少延迟"));
+        assert!(spec.matches("feat: add token cache"));
+        assert!(!spec.matches("feat: 添加令牌缓存\n\n缓存令牌"));
+        assert!(!spec.matches("feat: add token cache\n\ncache tokens to cut latency"));
+    }
+}
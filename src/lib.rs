@@ -1,27 +1,114 @@
 // CLI entrypoint for gh-sparkle.
 
+mod attempts;
+mod audit;
+mod auth;
+mod batch;
+mod bench;
+mod breaking;
+mod catalog;
+mod ci;
+mod config;
+mod content_filter;
+mod context;
+mod conventions;
+mod diff_view;
+mod docs;
+mod doctor;
+mod duplicate;
+mod emoji;
+mod experiment;
+mod explain;
+mod failure;
+mod fallback;
 mod git;
+mod history;
+mod hook;
+mod hunks;
+mod issue;
+mod language;
+mod lint;
 mod llm;
+mod manpage;
+mod migrate;
+mod model_stats;
+mod notify;
+#[cfg(test)]
+mod pipeline_tests;
+mod policy;
+mod postprocess;
+mod profile;
 mod prompt;
+mod quota;
+mod recording;
+mod review;
+mod reword;
+mod sanitize;
+mod scrub;
+mod sections;
+mod security;
+mod serve;
+mod session;
+mod standup;
+mod style;
+mod summaries;
+mod template;
+mod truncate;
 mod ui;
+mod upgrade;
+mod watch;
 
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use crossterm::style::Stylize;
+use failure::{Classify, Failure, FailureKind};
+use git::GitBackend;
+use llm::LlmProvider;
 use std::error::Error;
 use std::time::{Duration, Instant};
 
+pub use context::{BuiltContext, ContextBuilder, ContextRequest};
+pub use failure::exit_code;
+
 const EXTENSION_NAME: &str = "sparkle";
 const DEFAULT_MODEL: &str = "auto";
 const MAX_EXAMPLES: usize = 20;
+const LONG_HELP: &str = "Examples:
+  gh sparkle                            commit the staged changes once you confirm
+  gh sparkle -y                         commit without asking, for scripts
+  gh sparkle -i -l chinese              refine a Chinese draft before committing
+  gh sparkle --no-commit --style short  print a brief message
+  gh sparkle describe-pr-commits        reword vague commits on the branch
+  gh sparkle doctor                     diagnose setup problems
+
+Configuration (later layers win):
+  1. built-in defaults (assets/commitmsg.prompt.yml)
+  2. --prompt-file or $SPARKLE_PROMPT
+  3. .sparkle.yml at the repository root
+  4. SPARKLE_* variables, e.g. SPARKLE_NETWORK__REQUEST_TIMEOUT=90
+  --language, --model, and --style default to git config sparkle.* values,
+  then to $SPARKLE_LANGUAGE, $SPARKLE_MODEL, and $SPARKLE_STYLE.";
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  success
+  1  unexpected error
+  2  no staged changes
+  3  authentication failed
+  4  GitHub Models API error
+  5  git error
+  6  invalid configuration or arguments
+  7  aborted by the user
+  8  commit messages break the conventions (lint)";
 
 #[derive(Parser)]
 #[command(
     name = EXTENSION_NAME,
     about = "Generate AI-powered commit messages",
-    long_about = "A GitHub CLI extension that generates commit messages using GitHub Models and staged git changes"
+    long_about = "A GitHub CLI extension that generates commit messages using GitHub Models and staged git changes",
+    after_help = EXIT_CODES_HELP,
+    after_long_help = format!("{LONG_HELP}\n\n{EXIT_CODES_HELP}")
 )]
 struct Cli {
-    /// Language to generate commit message in
+    /// Language to generate commit message in (e.g. "chinese" or "en-subject,zh-body")
     #[arg(short = 'l', long = "language", default_value = "english")]
     language: String,
 
@@ -29,102 +116,1036 @@ struct Cli {
     #[arg(short = 'e', long = "examples", num_args = 0..=1, default_missing_value = "3")]
     examples: Option<String>,
 
+    /// Use only the subject lines of example commits
+    #[arg(long = "examples-subjects-only", requires = "examples")]
+    examples_subjects_only: bool,
+
     /// GitHub Models model to use
     #[arg(short = 'm', long = "model", default_value = DEFAULT_MODEL)]
     model: String,
+
+    /// How much the message should say
+    #[arg(long = "style", value_enum, default_value_t = style::Style::Standard)]
+    style: style::Style,
+
+    /// Show the draft before committing and refine it with shortcut keys
+    #[arg(short = 'i', long = "interactive")]
+    interactive: bool,
+
+    /// Review the staged diff and exclude files or hunks from the model
+    /// context (they are still committed)
+    #[arg(long = "review")]
+    review: bool,
+
+    /// Write a single subject line with no body, using a small response
+    /// budget and the cheapest model in the chain (for quick fixups)
+    #[arg(long = "oneline", conflicts_with_all = ["style", "breaking", "closes"])]
+    oneline: bool,
+
+    /// Require a body with the sections in `output.bodySections.names`
+    /// (Motivation, Changes, Testing by default)
+    #[arg(long = "detailed", conflicts_with_all = ["style", "oneline"])]
+    detailed: bool,
+
+    /// Use the commit type inferred from the changed paths (docs-only,
+    /// tests-only, new files, ...) instead of only suggesting it
+    #[arg(long = "trust-heuristics")]
+    trust_heuristics: bool,
+
+    /// Mark the change as breaking and ask for a BREAKING CHANGE footer
+    #[arg(long = "breaking")]
+    breaking: bool,
+
+    /// Link the commit to an issue: its title goes into the prompt and a
+    /// "Closes #N" footer is appended (default: number parsed from the branch)
+    #[arg(long = "closes", value_name = "ISSUE")]
+    closes: Option<u64>,
+
+    /// What the change is for, in your words; the model uses it to explain
+    /// the motivation
+    #[arg(long = "context", value_name = "TEXT")]
+    context: Option<String>,
+
+    /// Commit even when nothing is staged, with a message written from
+    /// --context alone
+    #[arg(long = "allow-empty", requires = "context")]
+    allow_empty: bool,
+
+    /// Record timing spans and report them after the run (also enabled by
+    /// SPARKLE_PROFILE, which implies text)
+    #[arg(long = "profile", value_name = "FORMAT", num_args = 0..=1, default_missing_value = "text")]
+    profile: Option<profile::Format>,
+
+    /// Save every model request and response as JSON files in DIR
+    #[arg(long = "record", value_name = "DIR", conflicts_with = "replay")]
+    record: Option<std::path::PathBuf>,
+
+    /// Answer model requests from a --record directory instead of the
+    /// network; no token is needed
+    #[arg(long = "replay", value_name = "DIR")]
+    replay: Option<std::path::PathBuf>,
+
+    /// Build the message from the diff alone (type from paths, scope from
+    /// the common directory) without contacting GitHub Models
+    #[arg(long = "no-llm", conflicts_with_all = ["interactive", "review", "replay", "record", "allow_empty"])]
+    no_llm: bool,
+
+    /// Print the generated message without committing the staged changes
+    #[arg(long = "no-commit")]
+    no_commit: bool,
+
+    /// Commit even on a branch listed in `commit.protectedBranches`
+    #[arg(long = "force")]
+    force: bool,
+
+    /// Send the staged changes to the model even when files match
+    /// `security.blockPaths`
+    #[arg(long = "allow-blocked", conflicts_with = "no_llm")]
+    allow_blocked: bool,
+
+    /// Commit without asking for confirmation, as scripts need
+    #[arg(short = 'y', long = "yes")]
+    yes: bool,
+
+    /// Write only the commit message to stdout and every status line to
+    /// stderr, for `git commit -m "$(gh sparkle --porcelain --no-commit)"`
+    #[arg(long = "porcelain", conflicts_with = "interactive")]
+    porcelain: bool,
+
+    /// Print simple sequential status lines instead of the animated status
+    /// display, even on a terminal (for screen readers)
+    #[arg(long = "plain")]
+    plain: bool,
+
+    /// Always ask gh for a token instead of using the short-lived token cache
+    #[arg(long = "no-token-cache")]
+    no_token_cache: bool,
+
+    /// Prompt YAML layered over the built-in prompt (default: $SPARKLE_PROMPT)
+    #[arg(long = "prompt-file", value_name = "PATH")]
+    prompt_file: Option<std::path::PathBuf>,
+
+    /// Validate config, auth, and model availability without generating
+    #[arg(long = "check")]
+    check: bool,
+
+    /// Print HTTP connection statistics (requests, warm-up, time to
+    /// response headers) to stderr in plain, serve, and watch modes
+    #[arg(long = "debug")]
+    debug: bool,
+
+    /// Show how the prompt context would be assembled for each budget tier
+    /// (sections, sizes, truncation, token estimates) without calling the API
+    #[arg(long = "explain-context", conflicts_with_all = ["interactive", "review", "check", "no_llm"])]
+    explain_context: bool,
+
+    /// Describe the pull request's changes since its merge base for a CI
+    /// job instead of committing: reads the token from the environment,
+    /// never prompts, and reports through the CI system
+    #[arg(long = "ci", value_name = "SYSTEM", conflicts_with_all = ["interactive", "review", "check"])]
+    ci: Option<ci::Mode>,
+
+    /// Run as a git hook: write the generated message into git's message
+    /// file instead of committing (see .pre-commit-hooks.yaml)
+    #[arg(long = "hook", value_name = "HOOK", conflicts_with_all = ["interactive", "review", "check", "ci"])]
+    hook: Option<hook::Kind>,
+
+    /// Arguments git passes to the hook
+    #[arg(value_name = "HOOK_ARGS", requires = "hook")]
+    hook_args: Vec<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Inspect and validate configuration files
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Regenerate vague commit messages (wip, fix, asdf) on the current branch
+    DescribePrCommits {
+        /// Branch the pull request targets (default: origin/HEAD, main, or master)
+        #[arg(long = "base")]
+        base: Option<String>,
+
+        /// Only print the preview table
+        #[arg(long = "dry-run")]
+        dry_run: bool,
+    },
+    /// Show local statistics about generations in this repository
+    Stats {
+        /// Compare acceptance and refinement rates per prompt experiment
+        /// variant
+        #[arg(long = "experiment")]
+        experiment: bool,
+    },
+    /// Rate the last generated message; good ones become preferred
+    /// --examples in later runs
+    Feedback {
+        rating: history::Feedback,
+
+        /// Free-form note stored with the rating
+        #[arg(long = "note")]
+        note: Option<String>,
+    },
+    /// Regenerate messages for recent commits with several models and score
+    /// them against the real ones
+    Bench {
+        /// Number of recent non-merge commits to replay
+        #[arg(long = "commits", default_value_t = 20)]
+        commits: usize,
+
+        /// Comma-separated models to compare (default: modelPolicy.autoModels,
+        /// or --model when it is not "auto")
+        #[arg(long = "models", value_delimiter = ',')]
+        models: Vec<String>,
+    },
+    /// Check git, authentication, GitHub Models, and config, with hints for
+    /// anything that fails
+    Doctor {
+        /// Also send a one-token completion to each model in the chain and
+        /// report its latency and remaining quota
+        #[arg(long = "ping")]
+        ping: bool,
+    },
+    /// Explain a pull request hunk for a reply to a review comment
+    Explain {
+        /// Pull request number
+        #[arg(long = "pr")]
+        pr: u64,
+
+        /// File the review comment is on
+        #[arg(long = "path")]
+        path: String,
+
+        /// Line in the new version of the file
+        #[arg(long = "line")]
+        line: usize,
+    },
+    /// Summarize commits of a time window by area, for standups and weekly
+    /// reports (with --no-llm, only the grouped list)
+    Summary {
+        /// Start of the window, in any form git understands ("yesterday",
+        /// "last monday", "2024-05-01")
+        #[arg(long = "since", default_value = "yesterday")]
+        since: String,
+
+        /// End of the window (default: now)
+        #[arg(long = "until")]
+        until: Option<String>,
+
+        /// Whose commits to include; "me" is your git user.email
+        #[arg(long = "author", default_value = "me", conflicts_with = "everyone")]
+        author: String,
+
+        /// Include every author's commits
+        #[arg(long = "everyone")]
+        everyone: bool,
+
+        /// Include commits on every branch, not only HEAD
+        #[arg(long = "all-branches")]
+        all_branches: bool,
+    },
+    /// Check existing commit messages against the configured conventions;
+    /// exits with 8 when any break them
+    Lint {
+        /// Commits to check, e.g. main..HEAD (default: the commits since the
+        /// branch forked from its base)
+        #[arg(value_name = "RANGE")]
+        range: Option<String>,
+
+        /// Suggest a replacement message for each offending commit
+        #[arg(long = "fix")]
+        fix: bool,
+    },
+    /// Upgrade to the latest release of gh-sparkle
+    Upgrade {
+        /// Only report whether a newer version is available
+        #[arg(long = "check")]
+        check: bool,
+    },
+    /// Print the man page in roff format, or install it where man finds it
+    Man {
+        /// Write the page to $XDG_DATA_HOME/man/man1 (default:
+        /// ~/.local/share/man/man1) instead of stdout
+        #[arg(long = "install")]
+        install: bool,
+    },
+    /// Draft a message in the background whenever the staged changes
+    /// settle, so the next run can commit it instantly
+    Watch,
+    /// Run sparkle in each repository with staged changes, one generation
+    /// at a time; options given before `batch` apply to every run
+    Batch {
+        /// File listing repository paths, one per line (# starts a comment)
+        #[arg(
+            long = "repos-file",
+            value_name = "FILE",
+            required_unless_present = "recurse"
+        )]
+        repos_file: Option<std::path::PathBuf>,
+
+        /// Use every git repository directly under the current directory
+        #[arg(long = "recurse", conflicts_with = "repos_file")]
+        recurse: bool,
+
+        /// Seconds to wait between generations
+        #[arg(long = "interval", value_name = "SECONDS", default_value_t = 0)]
+        interval: u64,
+    },
+    /// Serve JSON-RPC requests from editor plugins, one JSON message per line
+    Serve {
+        /// Read requests from stdin and write responses to stdout
+        #[arg(long = "stdio", required = true)]
+        stdio: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Validate a config file (defaults to the repository .sparkle.yml)
+    Validate {
+        /// Path to the config file
+        path: Option<std::path::PathBuf>,
+    },
+    /// Print the JSON Schema for config files
+    Schema,
+}
+
+/// Parses arguments, exiting with the config code on invalid usage. Options
+/// left at their defaults take `git config sparkle.*` values, overridden in
+/// turn by `defaults` in `.sparkle.yml` and by `SPARKLE_LANGUAGE`,
+/// `SPARKLE_MODEL`, and `SPARKLE_STYLE`.
+fn parse_cli() -> Cli {
+    let exit = |err: clap::Error| -> ! {
+        let code = if err.use_stderr() {
+            FailureKind::Config.exit_code()
+        } else {
+            0
+        };
+        let _ = err.print();
+        std::process::exit(code);
+    };
+    let matches = Cli::command()
+        .try_get_matches()
+        .unwrap_or_else(|err| exit(err));
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| exit(err));
+    let mut settings = git::sparkle_settings();
+    settings.extend(config::repo_defaults());
+    settings.extend(config::cli_env_settings(std::env::vars()));
+    if let Err(err) = apply_defaults(&mut cli, &matches, settings) {
+        eprintln!("{err}");
+        std::process::exit(FailureKind::Config.exit_code());
+    }
+    cli
+}
+
+/// Applies `settings` in order to options not given on the command line.
+fn apply_defaults(
+    cli: &mut Cli,
+    matches: &clap::ArgMatches,
+    settings: Vec<(String, String)>,
+) -> Result<(), String> {
+    let is_default = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+    for (key, value) in settings {
+        match key.as_str() {
+            "language" if is_default("language") => cli.language = value,
+            "model" if is_default("model") => cli.model = value,
+            "style" if is_default("style") => {
+                cli.style = style::Style::from_str(&value, true).map_err(|_| {
+                    format!(
+                        "sparkle.style: unknown style `{value}` from git config, \
+                         .sparkle.yml, or SPARKLE_STYLE (use standard, short, or detailed)"
+                    )
+                })?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
 }
 
 pub fn run() -> Result<(), Box<dyn Error>> {
-    if ui::Ui::is_tty() {
-        return run_with_tui();
+    let err = match run_once() {
+        Ok(()) => return Ok(()),
+        Err(err) => err,
+    };
+
+    let err = match err.downcast::<auth::AuthError>() {
+        Ok(auth_err) if offer_auth_refresh(&auth_err)? => return run_once(),
+        Ok(auth_err) => auth_err,
+        Err(err) => err,
+    };
+
+    match err.downcast::<llm::ConnectivityError>() {
+        Ok(offline_err) if offer_offline_template(&offline_err)? => Ok(()),
+        Ok(offline_err) => Err(offline_err),
+        Err(err) => Err(err),
     }
+}
 
-    run_plain()
+/// Like [`run`], with `builder` assembling the change context instead of
+/// `contextPolicy.sections`, for programs that embed sparkle.
+pub fn run_with_context_builder(
+    builder: impl ContextBuilder + 'static,
+) -> Result<(), Box<dyn Error>> {
+    context::set_builder(Box::new(builder));
+    run()
 }
 
-fn run_plain() -> Result<(), Box<dyn Error>> {
-    let mut profile = Profile::new();
-    let cli = Cli::parse();
+fn run_once() -> Result<(), Box<dyn Error>> {
+    let mut cli = parse_cli();
+    if let Some(path) = cli.prompt_file.clone().or_else(|| {
+        std::env::var_os(config::PROMPT_FILE_ENV)
+            .filter(|path| !path.is_empty())
+            .map(Into::into)
+    }) {
+        config::set_prompt_file(path);
+    }
+    if let Some(command) = cli.command.take() {
+        return run_command(command, &cli);
+    }
+    if cli.check {
+        return run_check(&cli);
+    }
+    if cli.explain_context {
+        return run_explain_context(&cli);
+    }
+    if let Some(mode) = cli.ci {
+        return ci::run(&cli, mode);
+    }
+    if let Some(kind) = cli.hook {
+        return hook::run(&cli, kind);
+    }
+    if !cli.no_commit && !cli.force {
+        guard_protected_branch()?;
+    }
+    if !cli.no_commit && operation_in_progress() == Some(git::Operation::Bisect) {
+        return Err(Failure::new(
+            FailureKind::Git,
+            "a bisect is in progress; run 'git bisect reset' before committing",
+        )
+        .into());
+    }
+    if needs_confirmation(&cli) && !ui::Ui::is_interactive() {
+        return Err(Failure::new(
+            FailureKind::Aborted,
+            "not committing without confirmation; pass --yes to commit from scripts or \
+             --no-commit to only print the message",
+        )
+        .into());
+    }
+    if cli.no_llm {
+        return run_without_llm(&cli);
+    }
+    if !cli.allow_blocked {
+        guard_blocked_paths()?;
+    }
 
-    profile.mark("parse args");
-    let staged_changes = git::get_staged_changes()?;
-    if staged_changes.trim().is_empty() {
-        println!("No staged changes in the repository.");
-        return Ok(());
+    let profile_format = cli
+        .profile
+        .or_else(|| std::env::var_os("SPARKLE_PROFILE").map(|_| profile::Format::Text));
+    if let Some(format) = profile_format {
+        profile::init(format);
     }
 
-    let staged_summary = git::get_staged_summary()?;
+    let result = if ui::Ui::is_tty() && !cli.plain && !cli.porcelain {
+        run_with_tui(cli)
+    } else {
+        run_plain(cli)
+    };
+    profile::report()?;
+    if result.is_ok() && load_prompt_config().is_ok_and(|config| config.updates.check) {
+        upgrade::weekly_notice();
+    }
+    result
+}
 
-    print!("  Loading prompt configuration... ");
-    let prompt_config = prompt::load_prompt_config()?;
-    prompt::validate_context_policy(&prompt_config.context_policy)?;
-    println!("Done");
-    profile.mark("load prompt config");
+fn run_command(command: Command, cli: &Cli) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Config { action } => run_config_command(action),
+        Command::DescribePrCommits { base, dry_run } => reword::run(cli, base, dry_run),
+        Command::Bench { commits, models } => bench::run(cli, commits, models),
+        Command::Doctor { ping } => doctor::run(cli, ping),
+        Command::Lint { range, fix } => lint::run(cli, range, fix),
+        Command::Explain { pr, path, line } => review::run(cli, pr, &path, line),
+        Command::Batch {
+            repos_file,
+            recurse: _,
+            interval,
+        } => batch::run(cli, repos_file.as_deref(), interval),
+        Command::Summary {
+            since,
+            until,
+            author,
+            everyone,
+            all_branches,
+        } => {
+            let author = match (everyone, author.as_str()) {
+                (true, _) => None,
+                (false, "me") => Some(git::user_email().classify(FailureKind::Git)?),
+                (false, _) => Some(author),
+            };
+            standup::run(
+                cli,
+                &since,
+                until.as_deref(),
+                author.as_deref(),
+                all_branches,
+            )
+        }
+        Command::Man { install } => run_man(install),
+        Command::Upgrade { check } => upgrade::run(check).classify(FailureKind::Api),
+        Command::Watch => watch::run(cli),
+        Command::Serve { stdio: _ } => {
+            let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+            let llm_client =
+                new_llm_client(cli, &prompt_config.network).classify(FailureKind::Auth)?;
+            llm_client.warm_up();
+            let defaults = serve::Defaults {
+                language: cli.language.clone(),
+                model: cli.model.clone(),
+            };
+            serve::Server::new(
+                llm_client,
+                prompt_config,
+                defaults,
+                Box::new(std::io::stdout()),
+            )
+            .reload_with(config::Reloader::new())
+            .debug(cli.debug)
+            .run(std::io::stdin().lock())
+        }
+        Command::Feedback { rating, note } => {
+            let message = history::tag_last(rating, note).classify(FailureKind::Git)?;
+            let subject = message.lines().next().unwrap_or_default();
+            let label = match rating {
+                history::Feedback::Good => "good",
+                history::Feedback::Bad => "bad",
+            };
+            println!("  Marked \"{subject}\" as {label}.");
+            Ok(())
+        }
+        Command::Stats { experiment } => {
+            let entries = history::load().classify(FailureKind::Git)?;
+            if experiment {
+                let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+                let name = &prompt_config.experiment.name;
+                let filter = (!name.is_empty()).then_some(name.as_str());
+                print!("{}", experiment::format_comparison(&entries, filter));
+            } else {
+                print!("{}", history::format_stats(&entries));
+            }
+            Ok(())
+        }
+    }
+}
 
-    let examples_count = parse_examples_count(cli.examples)?;
+/// Sends a one-off prompt to each model of the chain in turn and returns the
+/// first reply.
+fn complete_with_chain(
+    cli: &Cli,
+    prompt_config: &prompt::PromptConfig,
+    prompt: &llm::Prompt,
+) -> Result<String, Box<dyn Error>> {
+    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)
+        .classify(FailureKind::Config)?;
+    let llm_client = new_llm_client(cli, &prompt_config.network).classify(FailureKind::Auth)?;
+    let deadline = Instant::now() + Duration::from_secs(prompt_config.network.run_deadline);
+    let mut last_error = None;
+    for model in &model_chain {
+        match llm_client.complete(prompt_config, prompt, model, deadline) {
+            Ok(reply) => return Ok(reply),
+            Err(err) => {
+                eprintln!("  {model} failed: {err}");
+                last_error = Some(err);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| "no model to ask".into())).classify(FailureKind::Api)
+}
 
-    let mut latest_commit_messages = String::new();
-    if examples_count > 0 {
-        latest_commit_messages = git::get_commit_messages(examples_count)?;
-        println!(
-            "  Adding {} example(s) of previous commit messages to context",
-            examples_count
-        );
+fn run_config_command(action: ConfigCommand) -> Result<(), Box<dyn Error>> {
+    match action {
+        ConfigCommand::Validate { path } => {
+            let path = path.or_else(config::find_repo_config).ok_or_else(|| {
+                Failure::new(
+                    FailureKind::Config,
+                    format!(
+                        "no {} found; pass a path to validate",
+                        config::REPO_CONFIG_FILE
+                    ),
+                )
+            })?;
+            config::validate_file(&path)
+                .map_err(|issue| Failure::new(FailureKind::Config, issue.to_string()))?;
+            println!("{}: valid", path.display());
+            Ok(())
+        }
+        ConfigCommand::Schema => {
+            print!("{}", config::CONFIG_SCHEMA_JSON);
+            Ok(())
+        }
     }
+}
+
+fn run_man(install: bool) -> Result<(), Box<dyn Error>> {
+    let page = manpage::render(&Cli::command(), &format!("gh-{EXTENSION_NAME}"));
+    if !install {
+        print!("{page}");
+        return Ok(());
+    }
+    let path = manpage::install_path(&format!("gh-{EXTENSION_NAME}"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, page)?;
+    println!(
+        "  Installed {}; try `man gh-{EXTENSION_NAME}`.",
+        path.display()
+    );
+    Ok(())
+}
+
+fn run_check(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    print!("  Checking prompt configuration... ");
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    parse_examples_count(cli.examples.clone()).classify(FailureKind::Config)?;
+    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)
+        .classify(FailureKind::Config)?;
+    println!("Done");
 
     print!("  Checking GitHub token... ");
-    let llm_client = llm::Client::new()?;
+    let llm_client = llm::Client::new(&prompt_config.network, !cli.no_token_cache)
+        .classify(FailureKind::Auth)?;
+    let available = llm_client.list_models().classify(FailureKind::Api)?;
     println!("Done");
-    profile.mark("init client");
 
-    println!("  Language for commit message: {}", cli.language);
+    let missing: Vec<&str> = model_chain
+        .iter()
+        .filter(|model| !available.contains(model))
+        .map(String::as_str)
+        .collect();
+    for model in &model_chain {
+        let status = if missing.contains(&model.as_str()) {
+            "unavailable"
+        } else {
+            "available"
+        };
+        println!("  Model {model}: {status}");
+    }
+    if !missing.is_empty() {
+        return Err(Failure::new(
+            FailureKind::Config,
+            format!("model(s) not available: {}", missing.join(", ")),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn offer_auth_refresh(err: &auth::AuthError) -> Result<bool, Box<dyn Error>> {
+    if !err.is_refreshable() || !ui::Ui::is_interactive() {
+        return Ok(false);
+    }
+
+    eprintln!("{err}");
+    if !ui::confirm("Run 'gh auth refresh -s models' now and retry?")? {
+        return Ok(false);
+    }
+
+    let host = auth::resolve_host();
+    auth::refresh_models_scope(&host)?;
+    auth::invalidate_cached_token(&host);
+    Ok(true)
+}
+
+fn offer_offline_template(err: &llm::ConnectivityError) -> Result<bool, Box<dyn Error>> {
+    if !ui::Ui::is_interactive() {
+        return Ok(false);
+    }
+
+    eprintln!("{err}");
+    if !ui::confirm("GitHub Models is unreachable. Write the message from a template instead?")? {
+        return Ok(false);
+    }
+
+    let staged = git::Repo::current()
+        .staged_diff()
+        .classify(FailureKind::Git)?;
+    git::commit_with_template(&template::offline_template(&staged.summary))
+        .classify(FailureKind::Aborted)?;
+    Ok(true)
+}
+
+/// Commits a message built from the staged diff alone; needs no token or
+/// network.
+fn run_without_llm(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let repo = git::Repo::current();
+    let staged = repo.staged_diff().classify(FailureKind::Git)?;
+    if staged.patch.trim().is_empty() {
+        return Err(no_staged_changes());
+    }
+    let mut commit_msg = template::deterministic_message(&staged.summary, &staged.patch);
+    if let Some(number) = cli.closes {
+        commit_msg = issue::append_closes(&commit_msg, number);
+    }
+
+    let show = |message: &str| {
+        if cli.porcelain {
+            print!("{message}");
+        } else {
+            print_commit_message(message);
+        }
+    };
+    if !needs_confirmation(cli) {
+        show(&commit_msg);
+        if cli.no_commit {
+            return Ok(());
+        }
+    } else {
+        loop {
+            show(&commit_msg);
+            match ask_commit(&commit_msg) {
+                Confirmation::Commit(message) => {
+                    commit_msg = message;
+                    break;
+                }
+                Confirmation::Regenerate => {
+                    eprintln!("  --no-llm messages are fixed; there is nothing to regenerate.");
+                }
+                Confirmation::Abort => {
+                    return Err(Failure::new(FailureKind::Aborted, "commit aborted").into());
+                }
+            }
+        }
+    }
+    if let Some(warning) = git::encoding_warning(&commit_msg) {
+        eprintln!("  Warning: {warning}");
+    }
+    repo.commit(&commit_msg, cli.porcelain)
+        .classify(FailureKind::Git)?;
+    if let Some(hint) = continue_hint() {
+        eprintln!("  {hint}");
+    }
+    Ok(())
+}
+
+fn run_plain(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    let porcelain = cli.porcelain;
+    // Porcelain output keeps stdout for the message alone.
+    let status = |message: &str| {
+        if porcelain {
+            eprintln!("  {message}");
+        } else {
+            println!("  {message}");
+        }
+    };
+    let repo = git::Repo::current().allowing_empty(cli.allow_empty);
+    let git::StagedDiff {
+        summary: staged_summary,
+        patch: staged_changes,
+    } = repo.staged_diff().classify(FailureKind::Git)?;
+    if staged_changes.trim().is_empty() && !cli.allow_empty {
+        return Err(no_staged_changes());
+    }
+    if cli.review {
+        status("--review is unavailable in plain mode; using the full staged diff.");
+    }
+
+    let mut prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    status("Loaded prompt configuration");
+    let assignment = experiment::assign(&mut prompt_config);
+    if cli.oneline {
+        apply_oneline(&mut prompt_config);
+    }
+    if cli.detailed {
+        prompt_config.output.body_sections.required = true;
+    }
+    if cli.trust_heuristics {
+        prompt_config.conventions.type_heuristics = prompt::TypeHeuristics::Enforce;
+    }
+    let notifier = notify::Notifier::new(&prompt_config.notify, started);
+
+    let examples_count =
+        parse_examples_count(cli.examples.clone()).classify(FailureKind::Config)?;
+    let language_spec = parse_language(&cli.language)?;
+    let recent_subjects =
+        recent_subjects(&repo, &prompt_config.output).classify(FailureKind::Git)?;
+    let issue = resolve_issue(cli.closes, |message| status(&message));
+    let notes = generation_notes(
+        &cli,
+        &prompt_config.context_policy,
+        &staged_changes,
+        issue.as_ref(),
+        |message| status(&message),
+    );
+
+    let mut latest_commit_messages = String::new();
+    if examples_count > 0 {
+        let examples = fetch_examples(examples_count, cli.examples_subjects_only)
+            .classify(FailureKind::Git)?;
+        latest_commit_messages = scrub::scrub_examples(&examples, &prompt_config.examples);
+        if !latest_commit_messages.is_empty() {
+            status(&format!(
+                "Adding {} example(s) of previous commit messages to context",
+                examples_count
+            ));
+        }
+    }
 
-    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)?;
+    let llm_client = new_llm_client(&cli, &prompt_config.network).classify(FailureKind::Auth)?;
+    status("Checked GitHub token");
+
+    status(&format!("Language for commit message: {}", cli.language));
+
+    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)
+        .classify(FailureKind::Config)?;
+    let model_chain = order_adaptively(
+        &cli.model,
+        model_chain,
+        &prompt_config.model_policy,
+        |message| status(&message),
+    );
+    let model_chain = order_by_quota(
+        &cli.model,
+        model_chain,
+        &prompt_config.model_policy,
+        |message| status(&message),
+    );
+    let model_chain = preflight_chain(
+        &cli,
+        model_chain,
+        &prompt_config.model_policy,
+        &llm_client,
+        |message| status(&message),
+    );
+    let model_chain = if cli.oneline {
+        cheapest_first(model_chain, &prompt_config.model_policy)
+    } else {
+        model_chain
+    };
     if cli.model == "auto" {
-        println!("  Model selection: auto -> {}", model_chain.join(", "));
+        status(&format!(
+            "Model selection: auto -> {}",
+            model_chain.join(", ")
+        ));
     } else {
-        println!("  Model selection: {}", model_chain.join(", "));
+        status(&format!("Model selection: {}", model_chain.join(", ")));
     }
 
+    let docs = project_docs(&prompt_config, &staged_changes);
+    let issue_text = issue_context(&prompt_config, issue.as_ref());
+    let convention = convention_document(&prompt_config);
     let context = GenerationContext {
-        prompt_config: &prompt_config,
-        policy: &prompt_config.context_policy,
-        staged_summary: &staged_summary,
-        staged_changes: &staged_changes,
-        model_chain: &model_chain,
-        language: &cli.language,
         examples: &latest_commit_messages,
+        recent_subjects: &recent_subjects,
+        notes: &notes,
+        docs: &docs,
+        issue: &issue_text,
+        convention: &convention,
+        ..GenerationContext::new(
+            &prompt_config,
+            &staged_summary,
+            &staged_changes,
+            &model_chain,
+            &language_spec,
+        )
     };
-    let commit_msg = generate_with_fallbacks(&llm_client, &context, |message| {
-        println!("  {message}");
-    })?;
-    profile.mark("generate message");
-
-    let mut commit_msg = sanitize_commit_message(&commit_msg);
-    if commit_msg.is_empty() {
-        return Err("generated commit message is empty".into());
+    let draft = draft_or_generate(
+        &llm_client,
+        &context,
+        |message| status(&message),
+        |question| {
+            notifier.notify(question);
+            ask_user(question)
+        },
+    )?;
+    let draft = if cli.interactive && ui::Ui::is_interactive() {
+        iterate_draft(
+            &llm_client,
+            &prompt_config,
+            draft,
+            |message| {
+                notifier.notify("Commit message ready for review");
+                print_commit_message(message);
+                ui::read_draft_action().unwrap_or(ui::DraftAction::Abort)
+            },
+            |message| status(&message),
+        )?
+    } else {
+        draft
+    };
+    let closes = issue
+        .as_ref()
+        .filter(|_| !cli.oneline)
+        .map(|issue| issue.number);
+    let commit_msg = with_closes(&draft.message, closes);
+    if let Some(quota) = llm_client.quota(&draft.model) {
+        status(&format!(
+            "GitHub Models quota for {}: {}",
+            draft.model,
+            quota.describe(quota::now())
+        ));
+    }
+    if let Some(stats) = llm_client.connection_stats().filter(|_| cli.debug) {
+        eprintln!("  {stats}");
     }
 
-    if !commit_msg.ends_with('\n') {
-        commit_msg.push('\n');
+    let show = |message: &str| {
+        if porcelain {
+            print!("{message}");
+        } else {
+            print_commit_message(message);
+        }
+    };
+    let (draft, commit_msg) = if needs_confirmation(&cli) {
+        notifier.notify("Commit message ready");
+        confirm_commit(
+            &llm_client,
+            &prompt_config,
+            draft,
+            commit_msg,
+            closes,
+            |message| {
+                show(message);
+                ask_commit(message)
+            },
+            |message| status(&message),
+        )?
+    } else {
+        show(&commit_msg);
+        if cli.no_commit {
+            return Ok(());
+        }
+        (draft, commit_msg)
+    };
+
+    if let Some(warning) = git::encoding_warning(&commit_msg) {
+        status(&format!("Warning: {warning}"));
+    }
+    status("Committing staged changes...");
+    repo.commit(&commit_msg, porcelain)
+        .classify(FailureKind::Git)?;
+    if let Some(hint) = continue_hint() {
+        status(&hint);
+    }
+    notifier.notify(&committed_notice(&commit_msg));
+    if let Err(err) =
+        history::append(&draft.history_entry(&prompt_config, assignment.as_ref(), &commit_msg))
+    {
+        status(&format!("Warning: could not record history: {err}"));
     }
 
-    print_commit_message(&commit_msg);
+    Ok(())
+}
 
-    println!("  Committing staged changes...");
-    git::commit_with_message(&commit_msg, false)?;
-    profile.mark("commit");
+/// Prints the `--explain-context` report for the staged changes.
+fn run_explain_context(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let repo = git::Repo::current();
+    let git::StagedDiff {
+        summary: staged_summary,
+        patch: staged_changes,
+    } = repo.staged_diff().classify(FailureKind::Git)?;
+    if staged_changes.trim().is_empty() {
+        return Err(no_staged_changes());
+    }
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    let examples_count =
+        parse_examples_count(cli.examples.clone()).classify(FailureKind::Config)?;
+    let language_spec = parse_language(&cli.language)?;
+    let recent_subjects =
+        recent_subjects(&repo, &prompt_config.output).classify(FailureKind::Git)?;
+    let issue = resolve_issue(cli.closes, |message| eprintln!("  {message}"));
+    let notes = generation_notes(
+        cli,
+        &prompt_config.context_policy,
+        &staged_changes,
+        issue.as_ref(),
+        |message| eprintln!("  {message}"),
+    );
+    let mut examples = String::new();
+    if examples_count > 0 {
+        examples = scrub::scrub_examples(
+            &fetch_examples(examples_count, cli.examples_subjects_only)
+                .classify(FailureKind::Git)?,
+            &prompt_config.examples,
+        );
+    }
+    let docs = project_docs(&prompt_config, &staged_changes);
+    let issue_text = issue_context(&prompt_config, issue.as_ref());
+    let convention = convention_document(&prompt_config);
+    let policy = &prompt_config.context_policy;
+    let context = GenerationContext {
+        examples: &examples,
+        recent_subjects: &recent_subjects,
+        notes: &notes,
+        docs: &docs,
+        issue: &issue_text,
+        convention: &convention,
+        ..GenerationContext::new(
+            &prompt_config,
+            &staged_summary,
+            &staged_changes,
+            &[],
+            &language_spec,
+        )
+    };
 
-    profile.print_if_enabled();
+    let overhead = template_overhead_tokens(&context);
+    let commands = context::run_commands(&policy.commands, |message| eprintln!("  {message}"));
+    let sources = ChangeSources {
+        summary: &staged_summary,
+        diff: &staged_changes,
+        docs: &docs,
+        issue: &issue_text,
+        commands: &commands,
+    };
+    let tiers: Vec<explain::Tier> = budget_tiers(policy)
+        .into_iter()
+        .map(|(budget, mode, label)| {
+            let context_tokens = context_budget(budget, policy, overhead);
+            // A custom builder does not report sections; show only its totals.
+            let (changes_context, sections) = if context::custom(&policy.builder_command).is_some()
+            {
+                let (changes_context, _) =
+                    build_changes_context(&sources, policy, context_tokens, mode)?;
+                (changes_context, Vec::new())
+            } else {
+                let (changes_context, _, sections) =
+                    assemble_changes_context(&sources, policy, context_tokens, mode);
+                (changes_context, sections)
+            };
+            Ok(explain::Tier {
+                label,
+                budget_tokens: budget,
+                context_tokens,
+                sections,
+                context_chars: truncate::width(&changes_context),
+                prompt_chars: commit_message_prompt(&context, &changes_context).char_count(),
+            })
+        })
+        .collect::<Result<_, Box<dyn Error>>>()?;
+    print!(
+        "{}",
+        explain::report(
+            &tiers,
+            policy.token_char_ratio,
+            policy.budgets.response_tokens
+        )
+    );
     Ok(())
 }
 
-fn run_with_tui() -> Result<(), Box<dyn Error>> {
+fn run_with_tui(cli: Cli) -> Result<(), Box<dyn Error>> {
     use std::sync::mpsc;
     use std::thread;
 
-    let cli = Cli::parse();
-
     let mut ui = ui::Ui::start(vec![
         "Check GitHub auth",
         "Load prompt config",
@@ -135,27 +1156,47 @@ fn run_with_tui() -> Result<(), Box<dyn Error>> {
     ])?;
 
     let (tx, rx) = mpsc::channel::<UiEvent>();
+    let no_commit = cli.no_commit;
     let worker = thread::spawn(move || {
         let result = run_pipeline(cli, tx.clone());
         match result {
-            Ok((commit_msg, profile)) => {
-                let _ = tx.send(UiEvent::Completed(commit_msg, profile));
+            Ok(commit_msg) => {
+                let _ = tx.send(UiEvent::Completed(commit_msg));
+                None
             }
             Err(err) => {
                 let _ = tx.send(UiEvent::Failed(err.to_string()));
+                Some(into_sendable(err))
             }
         }
     });
 
-    let mut finished: Option<Result<(Option<String>, Profile), String>> = None;
+    let mut finished: Option<Result<Option<String>, String>> = None;
     while finished.is_none() {
         while let Ok(event) = rx.try_recv() {
             match event {
                 UiEvent::Step { index, status } => ui.set_step_status(index, status),
                 UiEvent::Log(message) => ui.log(message),
-                UiEvent::Completed(commit_msg, profile) => {
-                    finished = Some(Ok((commit_msg, profile)))
+                UiEvent::Footer(text) => ui.set_footer(text),
+                UiEvent::Confirm(question, reply) => {
+                    ui.suspend()?;
+                    let _ = reply.send(ask_user(&question));
                 }
+                UiEvent::Draft(message, reply) => {
+                    ui.suspend()?;
+                    print_commit_message(&message);
+                    let _ = reply.send(ui::read_draft_action()?);
+                }
+                UiEvent::ConfirmCommit(message, reply) => {
+                    ui.suspend()?;
+                    print_commit_message(&message);
+                    let _ = reply.send(ask_commit(&message));
+                }
+                UiEvent::ReviewDiff(summary, patch, reply) => {
+                    ui.suspend()?;
+                    let _ = reply.send(review_diff(&summary, &patch)?);
+                }
+                UiEvent::Completed(commit_msg) => finished = Some(Ok(commit_msg)),
                 UiEvent::Failed(message) => {
                     ui.set_error();
                     ui.log(message.clone());
@@ -163,30 +1204,245 @@ fn run_with_tui() -> Result<(), Box<dyn Error>> {
                 }
             }
         }
+        if ui.handle_input()? == ui::Input::Interrupted {
+            ui.shutdown()?;
+            return Err(Failure::new(FailureKind::Aborted, "interrupted").into());
+        }
         ui.tick();
         ui.draw()?;
         thread::sleep(Duration::from_millis(40));
     }
 
+    // The status block only shows the last few lines; keep the whole log
+    // visible when the run fails.
+    let failure_log: Vec<String> = match &finished {
+        Some(Err(_)) => ui.log_lines().map(str::to_string).collect(),
+        _ => Vec::new(),
+    };
     ui.shutdown()?;
-    let _ = worker.join();
+    if failure_log.len() > 1 {
+        eprintln!("Log:");
+        for line in &failure_log[..failure_log.len() - 1] {
+            eprintln!("  {line}");
+        }
+    }
+    let worker_error = worker.join().ok().flatten();
 
     match finished.unwrap_or_else(|| Err("unknown error".to_string())) {
-        Ok((Some(commit_msg), profile)) => {
+        Ok(Some(commit_msg)) => {
             print_commit_message(&commit_msg);
-            println!("  Committed staged changes.");
-            profile.print_if_enabled();
+            if !no_commit {
+                println!("  Committed staged changes.");
+                if let Some(hint) = continue_hint() {
+                    println!("  {hint}");
+                }
+            }
             Ok(())
         }
-        Ok((None, profile)) => {
-            println!("No staged changes in the repository.");
-            profile.print_if_enabled();
-            Ok(())
+        Ok(None) => Err(no_staged_changes()),
+        Err(message) => match worker_error {
+            Some(err) => Err(err),
+            None => Err(message.into()),
+        },
+    }
+}
+
+/// Moves a pipeline error across threads, keeping the error types that
+/// `run` knows how to recover from.
+fn into_sendable(err: Box<dyn Error>) -> Box<dyn Error + Send + Sync> {
+    let err = match err.downcast::<auth::AuthError>() {
+        Ok(auth_err) => return auth_err,
+        Err(err) => err,
+    };
+    let err = match err.downcast::<llm::ConnectivityError>() {
+        Ok(offline_err) => return offline_err,
+        Err(err) => err,
+    };
+    match err.downcast::<Failure>() {
+        Ok(failure) => failure,
+        Err(err) => err.to_string().into(),
+    }
+}
+
+/// Lets the user drop files and hunks from the model context; `None` when
+/// the review is cancelled.
+fn review_diff(summary: &str, patch: &str) -> Result<Option<(String, String)>, Box<dyn Error>> {
+    let mut files = diff_view::parse_patch(patch);
+    if !diff_view::review(&mut files)? {
+        return Ok(None);
+    }
+    Ok(Some((
+        diff_view::filter_summary(summary, &files),
+        diff_view::render_patch(&files),
+    )))
+}
+
+/// Refuses to commit on a protected branch unless the user confirms.
+fn guard_protected_branch() -> Result<(), Box<dyn Error>> {
+    let Ok(branch) = git::current_branch() else {
+        return Ok(());
+    };
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    let Some(pattern) = protected_pattern(&branch, &prompt_config.commit.protected_branches) else {
+        return Ok(());
+    };
+    let branch = if pattern == branch {
+        branch
+    } else {
+        format!("{branch} (matches {pattern})")
+    };
+    if ask_user(&format!(
+        "{branch} is a protected branch. Commit to it anyway?"
+    )) {
+        return Ok(());
+    }
+    Err(Failure::new(
+        FailureKind::Aborted,
+        format!(
+            "not committing on protected branch {branch}; pass --force to commit \
+             anyway or --no-commit to only print the message"
+        ),
+    )
+    .into())
+}
+
+/// Refuses to send any context while staged files match
+/// `security.blockPaths`, unless the user acknowledges it.
+fn guard_blocked_paths() -> Result<(), Box<dyn Error>> {
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    let blocked = blocked_staged_paths(&prompt_config, &git::Repo::current());
+    if blocked.is_empty() {
+        return Ok(());
+    }
+    eprintln!("Staged files match security.blockPaths:");
+    for path in &blocked {
+        eprintln!("  {path}");
+    }
+    if ask_user("Send the staged changes to the model anyway?") {
+        return Ok(());
+    }
+    Err(Failure::new(
+        FailureKind::Aborted,
+        "not sending blocked files to the model; use --no-llm for a message built from \
+         the diff alone, or --allow-blocked to send them anyway",
+    )
+    .into())
+}
+
+/// Files staged in `repo` that match `security.blockPaths`, each with its
+/// pattern.
+fn blocked_staged_paths(prompt_config: &prompt::PromptConfig, repo: &git::Repo) -> Vec<String> {
+    let patterns = &prompt_config.security.block_paths;
+    if patterns.is_empty() {
+        return Vec::new();
+    }
+    let staged = repo.staged_paths();
+    security::blocked(&staged, patterns)
+        .into_iter()
+        .map(|(path, pattern)| format!("{path} (matches {pattern})"))
+        .collect()
+}
+
+/// The first of `patterns` that matches `branch`.
+fn protected_pattern<'a>(branch: &str, patterns: &'a [String]) -> Option<&'a str> {
+    patterns
+        .iter()
+        .map(String::as_str)
+        .find(|pattern| content_filter::glob_match(pattern, branch))
+}
+
+/// Whether the message needs a yes before it is committed: always, unless
+/// `--yes`, `--no-commit`, or the `--interactive` draft loop already asked.
+fn needs_confirmation(cli: &Cli) -> bool {
+    let interactive_loop = cli.interactive && ui::Ui::is_interactive();
+    !(cli.yes || cli.no_commit || interactive_loop)
+}
+
+/// The answer to the commit confirmation, with the message as edited.
+enum Confirmation {
+    Commit(String),
+    Regenerate,
+    Abort,
+}
+
+/// Asks whether to commit `message`; `e(dit)` opens git's editor on it and
+/// asks again.
+fn ask_commit(message: &str) -> Confirmation {
+    let mut message = message.to_string();
+    loop {
+        match ui::read_commit_action().unwrap_or(ui::CommitAction::Abort) {
+            ui::CommitAction::Commit => return Confirmation::Commit(message),
+            ui::CommitAction::Regenerate => return Confirmation::Regenerate,
+            ui::CommitAction::Abort => return Confirmation::Abort,
+            ui::CommitAction::Edit => match git::edit_message(&message) {
+                Ok(edited) => {
+                    message = edited;
+                    print_commit_message(&message);
+                }
+                Err(err) => eprintln!("  Could not edit the message: {err}"),
+            },
+        }
+    }
+}
+
+/// Asks before committing until the user commits or aborts, regenerating
+/// the draft on `r`.
+fn confirm_commit(
+    llm_client: &dyn LlmProvider,
+    prompt_config: &prompt::PromptConfig,
+    mut draft: Draft,
+    mut commit_msg: String,
+    closes: Option<u64>,
+    mut ask: impl FnMut(&str) -> Confirmation,
+    mut log: impl FnMut(String),
+) -> Result<(Draft, String), Box<dyn Error>> {
+    let mut regenerations = 0;
+    loop {
+        match ask(&commit_msg) {
+            Confirmation::Commit(message) => return Ok((draft, message)),
+            Confirmation::Abort => {
+                return Err(Failure::new(FailureKind::Aborted, "commit aborted").into());
+            }
+            Confirmation::Regenerate => {
+                log("Regenerating the message...".to_string());
+                let instruction = &prompt_config.generation.refinements.regenerate;
+                regenerations += 1;
+                let temperature = prompt_config
+                    .model_parameters
+                    .retry_temperature(regenerations);
+                draft = refine_draft(llm_client, prompt_config, &draft, instruction, temperature)?;
+                commit_msg = with_closes(&draft.message, closes);
+            }
         }
-        Err(message) => Err(message.into()),
     }
 }
 
+/// `message` with a `Closes #N` footer for the linked issue, if any.
+fn with_closes(message: &str, closes: Option<u64>) -> String {
+    match closes {
+        Some(number) => issue::append_closes(message, number),
+        None => message.to_string(),
+    }
+}
+
+/// Asks a yes/no question when a user can answer; otherwise declines.
+fn ask_user(question: &str) -> bool {
+    ui::Ui::is_interactive() && ui::confirm(question).unwrap_or(false)
+}
+
+fn committed_notice(commit_msg: &str) -> String {
+    let subject = commit_msg.lines().next().unwrap_or_default();
+    format!("Committed: {subject}")
+}
+
+fn no_staged_changes() -> Box<dyn Error> {
+    Failure::new(
+        FailureKind::NoStagedChanges,
+        "no staged changes in the repository",
+    )
+    .into()
+}
+
 fn parse_examples_count(raw: Option<String>) -> Result<usize, Box<dyn Error>> {
     let Some(raw_value) = raw else {
         return Ok(0);
@@ -199,111 +1455,212 @@ fn parse_examples_count(raw: Option<String>) -> Result<usize, Box<dyn Error>> {
     if count == 0 || count > MAX_EXAMPLES {
         return Err(format!("examples count must be between 1 and {MAX_EXAMPLES}").into());
     }
+    if let Some(reason) = policy::current()?.and_then(|policy| policy.examples_violation(count)) {
+        return Err(Failure::new(FailureKind::Config, reason).into());
+    }
 
     Ok(count)
 }
 
+fn parse_language(raw: &str) -> Result<language::LanguageSpec, Box<dyn Error>> {
+    language::LanguageSpec::parse(raw).map_err(|err| Failure::new(FailureKind::Config, err).into())
+}
+
 fn print_commit_message(commit_msg: &str) {
     let message = commit_msg.trim_end();
+    let forbid_emoji =
+        load_prompt_config().is_ok_and(|config| config.output.emoji == prompt::EmojiPolicy::Forbid);
+    let heading = if forbid_emoji {
+        "Generated commit message:"
+    } else {
+        "💬 Generated commit message:"
+    };
     if ui::Ui::is_tty() {
-        println!("💬 Generated commit message:");
+        println!("{heading}");
         println!();
         println!("{}", message.green().bold());
         println!();
     } else {
-        println!("💬 Generated commit message:");
+        println!("{heading}");
         println!();
         println!("{message}");
         println!();
     }
 }
 
-struct Profile {
-    enabled: bool,
-    last: Instant,
-    samples: Vec<(&'static str, Duration)>,
-}
-
-impl Profile {
-    fn new() -> Self {
-        let enabled = std::env::var("SPARKLE_PROFILE").is_ok();
-        Self {
-            enabled,
-            last: Instant::now(),
-            samples: Vec::new(),
-        }
-    }
-
-    fn mark(&mut self, label: &'static str) {
-        if !self.enabled {
-            return;
-        }
-        let now = Instant::now();
-        let duration = now.duration_since(self.last);
-        self.samples.push((label, duration));
-        self.last = now;
-    }
-
-    fn print_if_enabled(&self) {
-        if !self.enabled {
-            return;
-        }
-        println!();
-        println!("Profile (SPARKLE_PROFILE=1):");
-        for (label, duration) in &self.samples {
-            println!("  {label}: {:.2?}", duration);
-        }
-    }
-}
-
-enum UiEvent {
-    Step {
-        index: usize,
-        status: ui::StepStatus,
-    },
-    Log(String),
-    Completed(Option<String>, Profile),
-    Failed(String),
+enum UiEvent {
+    Step {
+        index: usize,
+        status: ui::StepStatus,
+    },
+    Log(String),
+    /// Replaces the status line under the log.
+    Footer(String),
+    /// A yes/no question; the answer goes back on the sender.
+    Confirm(String, std::sync::mpsc::Sender<bool>),
+    /// A draft to accept or refine; the chosen action goes back on the sender.
+    Draft(String, std::sync::mpsc::Sender<ui::DraftAction>),
+    /// A message to confirm before committing; the answer goes back on the
+    /// sender.
+    ConfirmCommit(String, std::sync::mpsc::Sender<Confirmation>),
+    /// Staged summary and patch to review; the filtered pair, or `None` when
+    /// the user cancels, goes back on the sender.
+    ReviewDiff(
+        String,
+        String,
+        std::sync::mpsc::Sender<Option<(String, String)>>,
+    ),
+    Completed(Option<String>),
+    Failed(String),
 }
 
 fn run_pipeline(
     cli: Cli,
     tx: std::sync::mpsc::Sender<UiEvent>,
-) -> Result<(Option<String>, Profile), Box<dyn Error>> {
-    let mut profile = Profile::new();
+) -> Result<Option<String>, Box<dyn Error>> {
+    let started = Instant::now();
+    let repo = git::Repo::current();
     let send_step = |index: usize, status: ui::StepStatus| {
         let _ = tx.send(UiEvent::Step { index, status });
     };
 
-    send_step(0, ui::StepStatus::Running);
-    let llm_client = llm::Client::new()?;
-    send_step(0, ui::StepStatus::Done);
-    profile.mark("init client");
+    let examples_count =
+        parse_examples_count(cli.examples.clone()).classify(FailureKind::Config)?;
+    let language_spec = parse_language(&cli.language)?;
+    let use_token_cache = !cli.no_token_cache;
+    let host = auth::resolve_host();
+    let startup = profile::span("pipeline", "startup");
+    let (token, prompt_config, staged) = std::thread::scope(|scope| {
+        let token_task = scope.spawn(|| {
+            if cli.replay.is_some() {
+                send_step(0, ui::StepStatus::Skipped);
+                return Ok(None);
+            }
+            send_step(0, ui::StepStatus::Running);
+            let token = auth::resolve_token(&host, use_token_cache)
+                .map(Some)
+                .map_err(into_sendable);
+            send_step(
+                0,
+                if token.is_ok() {
+                    ui::StepStatus::Done
+                } else {
+                    ui::StepStatus::Failed
+                },
+            );
+            token
+        });
+        let prompt_task = scope.spawn(|| {
+            send_step(1, ui::StepStatus::Running);
+            let config = load_prompt_config().map_err(into_sendable);
+            send_step(
+                1,
+                if config.is_ok() {
+                    ui::StepStatus::Done
+                } else {
+                    ui::StepStatus::Failed
+                },
+            );
+            config
+        });
+        let staged_task = scope.spawn(|| {
+            send_step(2, ui::StepStatus::Running);
+            let staged = collect_staged(
+                &repo,
+                examples_count,
+                cli.examples_subjects_only,
+                cli.allow_empty,
+            )
+            .map_err(into_sendable);
+            send_step(
+                2,
+                if staged.is_ok() {
+                    ui::StepStatus::Done
+                } else {
+                    ui::StepStatus::Failed
+                },
+            );
+            staged
+        });
 
-    send_step(1, ui::StepStatus::Running);
-    let prompt_config = prompt::load_prompt_config()?;
-    prompt::validate_context_policy(&prompt_config.context_policy)?;
-    send_step(1, ui::StepStatus::Done);
-    profile.mark("load prompt config");
+        let join_error = || "startup task panicked".into();
+        (
+            token_task.join().unwrap_or_else(|_| Err(join_error())),
+            prompt_task.join().unwrap_or_else(|_| Err(join_error())),
+            staged_task.join().unwrap_or_else(|_| Err(join_error())),
+        )
+    });
+    drop(startup);
 
-    send_step(2, ui::StepStatus::Running);
-    let staged_changes = git::get_staged_changes()?;
-    if staged_changes.trim().is_empty() {
+    let Some((token, mut prompt_config, staged)) = settle_startup(token, prompt_config, staged)?
+    else {
         let _ = tx.send(UiEvent::Log(
             "No staged changes in the repository.".to_string(),
         ));
-        send_step(2, ui::StepStatus::Done);
-        profile.mark("collect changes");
-        return Ok((None, profile));
+        for index in 3..=5 {
+            send_step(index, ui::StepStatus::Skipped);
+        }
+        return Ok(None);
+    };
+    let assignment = experiment::assign(&mut prompt_config);
+    if cli.oneline {
+        apply_oneline(&mut prompt_config);
     }
-    let staged_summary = git::get_staged_summary()?;
-    send_step(2, ui::StepStatus::Done);
-    profile.mark("collect changes");
-
-    let examples_count = parse_examples_count(cli.examples)?;
-    let mut latest_commit_messages = String::new();
-    if examples_count > 0 {
-        latest_commit_messages = git::get_commit_messages(examples_count)?;
+    if cli.detailed {
+        prompt_config.output.body_sections.required = true;
+    }
+    if cli.trust_heuristics {
+        prompt_config.conventions.type_heuristics = prompt::TypeHeuristics::Enforce;
+    }
+    let notifier = notify::Notifier::new(&prompt_config.notify, started);
+    let recent_subjects =
+        recent_subjects(&repo, &prompt_config.output).classify(FailureKind::Git)?;
+    let llm_client = match token {
+        Some(token) => llm::Client::from_token(host, token, &prompt_config.network)
+            .and_then(|client| Ok(client.with_recording(open_recording(&cli)?))),
+        None => new_llm_client(&cli, &prompt_config.network),
+    }
+    .classify(FailureKind::Config)?;
+    let StagedChanges {
+        changes: mut staged_changes,
+        summary: mut staged_summary,
+        examples,
+    } = staged;
+    if cli.review {
+        let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+        let _ = tx.send(UiEvent::ReviewDiff(
+            staged_summary.clone(),
+            staged_changes.clone(),
+            reply_tx,
+        ));
+        let Some((summary, patch)) = reply_rx.recv().ok().flatten() else {
+            return Err(Failure::new(FailureKind::Aborted, "review cancelled").into());
+        };
+        if patch.trim().is_empty() {
+            return Err(Failure::new(
+                FailureKind::Config,
+                "every change was excluded from the model context",
+            )
+            .into());
+        }
+        staged_summary = summary;
+        staged_changes = patch;
+    }
+    let latest_commit_messages = scrub::scrub_examples(&examples, &prompt_config.examples);
+    let issue = resolve_issue(cli.closes, |message| {
+        let _ = tx.send(UiEvent::Log(message));
+    });
+    let notes = generation_notes(
+        &cli,
+        &prompt_config.context_policy,
+        &staged_changes,
+        issue.as_ref(),
+        |message| {
+            let _ = tx.send(UiEvent::Log(message));
+        },
+    );
+
+    if examples_count > 0 && !latest_commit_messages.is_empty() {
         let _ = tx.send(UiEvent::Log(format!(
             "Adding {} example(s) of previous commit messages to context",
             examples_count
@@ -316,7 +1673,39 @@ fn run_pipeline(
     )));
 
     send_step(3, ui::StepStatus::Running);
-    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)?;
+    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)
+        .inspect_err(|_| send_step(3, ui::StepStatus::Failed))
+        .classify(FailureKind::Config)?;
+    let model_chain = order_adaptively(
+        &cli.model,
+        model_chain,
+        &prompt_config.model_policy,
+        |message| {
+            let _ = tx.send(UiEvent::Log(message));
+        },
+    );
+    let model_chain = order_by_quota(
+        &cli.model,
+        model_chain,
+        &prompt_config.model_policy,
+        |message| {
+            let _ = tx.send(UiEvent::Log(message));
+        },
+    );
+    let model_chain = preflight_chain(
+        &cli,
+        model_chain,
+        &prompt_config.model_policy,
+        &llm_client,
+        |message| {
+            let _ = tx.send(UiEvent::Log(message));
+        },
+    );
+    let model_chain = if cli.oneline {
+        cheapest_first(model_chain, &prompt_config.model_policy)
+    } else {
+        model_chain
+    };
     let model_display = if cli.model == "auto" {
         format!("auto -> {}", model_chain.join(", "))
     } else {
@@ -326,187 +1715,1497 @@ fn run_pipeline(
     send_step(3, ui::StepStatus::Done);
 
     send_step(4, ui::StepStatus::Running);
+    let docs = project_docs(&prompt_config, &staged_changes);
+    let issue_text = issue_context(&prompt_config, issue.as_ref());
+    let convention = convention_document(&prompt_config);
     let context = GenerationContext {
-        prompt_config: &prompt_config,
-        policy: &prompt_config.context_policy,
-        staged_summary: &staged_summary,
-        staged_changes: &staged_changes,
-        model_chain: &model_chain,
-        language: &cli.language,
         examples: &latest_commit_messages,
+        recent_subjects: &recent_subjects,
+        notes: &notes,
+        docs: &docs,
+        issue: &issue_text,
+        convention: &convention,
+        ..GenerationContext::new(
+            &prompt_config,
+            &staged_summary,
+            &staged_changes,
+            &model_chain,
+            &language_spec,
+        )
     };
-    let commit_msg = generate_with_fallbacks(&llm_client, &context, |message| {
-        let _ = tx.send(UiEvent::Log(message));
-    })?;
+    let draft = draft_or_generate(
+        &llm_client,
+        &context,
+        |message| {
+            let _ = tx.send(UiEvent::Log(message));
+        },
+        |question| {
+            notifier.notify(question);
+            let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+            let _ = tx.send(UiEvent::Confirm(question.to_string(), reply_tx));
+            reply_rx.recv().unwrap_or(false)
+        },
+    )?;
+    let draft = if cli.interactive && ui::Ui::is_interactive() {
+        iterate_draft(
+            &llm_client,
+            &prompt_config,
+            draft,
+            |message| {
+                notifier.notify("Commit message ready for review");
+                let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+                let _ = tx.send(UiEvent::Draft(message.to_string(), reply_tx));
+                reply_rx.recv().unwrap_or(ui::DraftAction::Abort)
+            },
+            |message| {
+                let _ = tx.send(UiEvent::Log(message));
+            },
+        )?
+    } else {
+        draft
+    };
+    let closes = issue
+        .as_ref()
+        .filter(|_| !cli.oneline)
+        .map(|issue| issue.number);
+    let commit_msg = with_closes(&draft.message, closes);
+    if let Some(quota) = llm_client.quota(&draft.model) {
+        let _ = tx.send(UiEvent::Footer(format!(
+            "{}: {}",
+            draft.model,
+            quota.describe(quota::now())
+        )));
+    }
     send_step(4, ui::StepStatus::Done);
-    profile.mark("generate message");
+    if cli.no_commit {
+        send_step(5, ui::StepStatus::Skipped);
+        return Ok(Some(commit_msg));
+    }
+    let (draft, commit_msg) = if needs_confirmation(&cli) {
+        notifier.notify("Commit message ready");
+        confirm_commit(
+            &llm_client,
+            &prompt_config,
+            draft,
+            commit_msg,
+            closes,
+            |message| {
+                let (reply_tx, reply_rx) = std::sync::mpsc::channel();
+                let _ = tx.send(UiEvent::ConfirmCommit(message.to_string(), reply_tx));
+                reply_rx.recv().unwrap_or(Confirmation::Abort)
+            },
+            |message| {
+                let _ = tx.send(UiEvent::Log(message));
+            },
+        )?
+    } else {
+        (draft, commit_msg)
+    };
+
+    if let Some(warning) = git::encoding_warning(&commit_msg) {
+        let _ = tx.send(UiEvent::Log(format!("Warning: {warning}")));
+    }
+    send_step(5, ui::StepStatus::Running);
+    repo.commit(&commit_msg, true)
+        .inspect_err(|_| send_step(5, ui::StepStatus::Failed))
+        .classify(FailureKind::Git)?;
+    send_step(5, ui::StepStatus::Done);
+    notifier.notify(&committed_notice(&commit_msg));
+    if let Err(err) =
+        history::append(&draft.history_entry(&prompt_config, assignment.as_ref(), &commit_msg))
+    {
+        let _ = tx.send(UiEvent::Log(format!(
+            "Warning: could not record history: {err}"
+        )));
+    }
+
+    Ok(Some(commit_msg))
+}
+
+struct StagedChanges {
+    changes: String,
+    summary: String,
+    examples: String,
+}
+
+/// The token, config, and staged changes once startup succeeded.
+type Started<T, C> = (T, C, StagedChanges);
+
+/// Combines the results of the concurrent startup tasks. A missing diff is
+/// reported before auth or config problems, as the sequential pipeline did;
+/// `None` means nothing is staged. Errors keep their type, so `run` can still
+/// offer an auth refresh.
+fn settle_startup<T, C>(
+    token: Result<T, Box<dyn Error + Send + Sync>>,
+    config: Result<C, Box<dyn Error + Send + Sync>>,
+    staged: Result<Option<StagedChanges>, Box<dyn Error + Send + Sync>>,
+) -> Result<Option<Started<T, C>>, Box<dyn Error>> {
+    let unsend = |err: Box<dyn Error + Send + Sync>| -> Box<dyn Error> { err };
+    let Some(staged) = staged.map_err(unsend).classify(FailureKind::Git)? else {
+        return Ok(None);
+    };
+    let token = token.map_err(unsend).classify(FailureKind::Auth)?;
+    let config = config.map_err(unsend).classify(FailureKind::Config)?;
+    Ok(Some((token, config, staged)))
+}
+
+/// Collects the staged diff, its stat summary, and optional example messages.
+fn collect_staged(
+    repo: &dyn git::GitBackend,
+    examples_count: usize,
+    subjects_only: bool,
+    allow_empty: bool,
+) -> Result<Option<StagedChanges>, Box<dyn Error>> {
+    let git::StagedDiff {
+        summary,
+        patch: changes,
+    } = repo.staged_diff()?;
+    if changes.trim().is_empty() && !allow_empty {
+        return Ok(None);
+    }
+
+    let examples = if examples_count > 0 {
+        fetch_examples(examples_count, subjects_only)?
+    } else {
+        String::new()
+    };
+
+    Ok(Some(StagedChanges {
+        changes,
+        summary,
+        examples,
+    }))
+}
+
+/// Finds the issue to close from `--closes` or the branch name. Branch
+/// guesses are dropped unless `gh` confirms the issue exists.
+fn resolve_issue(closes: Option<u64>, mut log: impl FnMut(String)) -> Option<issue::Issue> {
+    let (number, explicit) = match closes {
+        Some(number) => (number, true),
+        None => (
+            issue::number_from_branch(&git::current_branch().ok()?)?,
+            false,
+        ),
+    };
+
+    match issue::fetch(number) {
+        Ok(issue) => {
+            log(format!(
+                "Linking issue #{number}: {}",
+                issue.title.as_deref().unwrap_or_default()
+            ));
+            Some(issue)
+        }
+        Err(err) if explicit => {
+            log(format!("Warning: could not fetch issue #{number}: {err}"));
+            Some(issue::Issue {
+                number,
+                title: None,
+                body: String::new(),
+            })
+        }
+        Err(_) => None,
+    }
+}
+
+/// Collects extra prompt instructions derived from flags and the diff.
+fn generation_notes(
+    cli: &Cli,
+    policy: &prompt::ContextPolicy,
+    patch: &str,
+    issue: Option<&issue::Issue>,
+    mut log: impl FnMut(String),
+) -> Vec<String> {
+    let mut notes = Vec::new();
+    if cli.oneline {
+        notes.push("Write only a single subject line. Do not add a body or footers.".to_string());
+    } else if let Some(note) = cli.style.prompt_note() {
+        notes.push(note.to_string());
+    }
+    if let Some(issue) = issue {
+        notes.push(issue::prompt_note(issue));
+    }
+    if let Some(context) = cli.context.as_deref().map(str::trim) {
+        notes.push(format!(
+            "The author describes the change as: \"{context}\". Use it to explain why the \
+             change was made."
+        ));
+    }
+    if !git::has_head() {
+        log("No commits yet; writing an initial commit message without examples.".to_string());
+        notes.push(initial_commit_note(patch));
+    }
+    if policy.include_unstaged_note
+        && let Some(note) = unstaged_note(patch, &git::unstaged_paths())
+    {
+        notes.push(note);
+    }
+    if patch.trim().is_empty() {
+        notes.push(
+            "Nothing is staged; this is an empty commit. Write the message from the author's \
+             description alone and do not invent file changes."
+                .to_string(),
+        );
+    }
+
+    let reasons = breaking::detect(patch);
+    if cli.breaking || !reasons.is_empty() {
+        if !reasons.is_empty() {
+            log(format!("Possible breaking change: {}", reasons.join("; ")));
+        }
+        let mut note = "This is a breaking change. Mark the type with `!` and add a \
+                        `BREAKING CHANGE:` footer that explains the impact on users."
+            .to_string();
+        if !reasons.is_empty() {
+            note.push_str(&format!(" Detected: {}.", reasons.join("; ")));
+        }
+        notes.push(note);
+    }
+
+    if let Some(operation) = operation_in_progress().filter(|op| *op != git::Operation::Bisect) {
+        let name = operation.name();
+        log(format!(
+            "A {name} is in progress; writing the message for its current step"
+        ));
+        let subject = git::git_dir()
+            .ok()
+            .and_then(|dir| git::prepared_subject(&dir));
+        notes.push(match subject {
+            Some(subject) => format!(
+                "These changes conclude a stopped {name} of the commit \"{subject}\", with \
+                 its conflicts resolved. Keep that commit's intent and describe the changes \
+                 as they are now, not the conflict resolution."
+            ),
+            None => format!(
+                "These changes conclude a stopped {name}. Describe the changes as they are \
+                 now, not the conflict resolution."
+            ),
+        });
+    }
+
+    notes
+}
+
+/// The multi-step operation stopped in the current repository, if any.
+fn operation_in_progress() -> Option<git::Operation> {
+    git::operation_in_progress(&git::git_dir().ok()?)
+}
+
+/// What to run after committing the current step of a stopped rebase or
+/// `git am`; cherry-picks and reverts are concluded by the commit itself.
+fn continue_hint() -> Option<String> {
+    match operation_in_progress()? {
+        operation @ (git::Operation::Rebase | git::Operation::Am) => Some(format!(
+            "Run 'git {} --continue' to go on.",
+            operation.name()
+        )),
+        _ => None,
+    }
+}
+
+/// Reads example commits, either full log entries or subject lines only.
+/// Messages rated `good` with `sparkle feedback` come first.
+fn fetch_examples(count: usize, subjects_only: bool) -> Result<String, Box<dyn Error>> {
+    if !git::has_head() {
+        return Ok(String::new());
+    }
+    let good = history::tagged_messages(history::Feedback::Good);
+    let bad = history::tagged_messages(history::Feedback::Bad);
+    let bad: Vec<&str> = bad.iter().map(|message| message_subject(message)).collect();
+    // Read enough history that skipped commits still leave `count` examples.
+    let log_count = count + good.len().min(count) + bad.len();
+    if subjects_only {
+        let good = good
+            .iter()
+            .map(|message| message_subject(message).to_string())
+            .collect();
+        let log = git::Repo::current().recent_subjects(log_count)?;
+        let examples = select_examples(good, log, &bad, count, |subject| subject.trim());
+        return Ok(examples.join("\n"));
+    }
+
+    let good = good
+        .iter()
+        .map(|message| message.trim_end().to_string())
+        .collect();
+    let log = git::get_commit_messages(log_count)?;
+    let examples = select_examples(good, log, &bad, count, git::log_entry_subject);
+    Ok(examples.join("\n\n"))
+}
+
+fn message_subject(message: &str) -> &str {
+    message.lines().next().unwrap_or_default().trim()
+}
+
+/// Up to `count` examples: the `good` messages first, then `log` entries,
+/// skipping any whose subject is tagged bad or already used.
+fn select_examples(
+    good: Vec<String>,
+    log: Vec<String>,
+    bad: &[&str],
+    count: usize,
+    log_subject: impl Fn(&str) -> &str,
+) -> Vec<String> {
+    let mut seen: Vec<String> = Vec::new();
+    let mut examples = Vec::new();
+    let good = good
+        .into_iter()
+        .map(|message| (message_subject(&message).to_string(), message));
+    let log = log
+        .into_iter()
+        .map(|entry| (log_subject(&entry).to_string(), entry));
+    for (subject, example) in good.chain(log) {
+        if examples.len() == count {
+            break;
+        }
+        if bad.contains(&subject.as_str()) || seen.contains(&subject) {
+            continue;
+        }
+        seen.push(subject);
+        examples.push(example);
+    }
+    examples
+}
+
+fn recent_subjects(
+    repo: &dyn git::GitBackend,
+    config: &prompt::OutputConfig,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    if config.duplicate_lookback == 0 {
+        return Ok(Vec::new());
+    }
+    repo.recent_subjects(config.duplicate_lookback)
+}
+
+/// Builds the model client, honoring `--record` and `--replay`.
+fn new_llm_client(
+    cli: &Cli,
+    network: &prompt::NetworkConfig,
+) -> Result<llm::Client, Box<dyn Error>> {
+    if let Some(dir) = &cli.replay {
+        return llm::Client::replaying(recording::Recording::replay(dir.clone())?, network);
+    }
+    Ok(llm::Client::new(network, !cli.no_token_cache)?.with_recording(open_recording(cli)?))
+}
+
+fn open_recording(cli: &Cli) -> Result<Option<recording::Recording>, Box<dyn Error>> {
+    cli.record
+        .clone()
+        .map(recording::Recording::record)
+        .transpose()
+}
+
+fn load_prompt_config() -> Result<prompt::PromptConfig, Box<dyn Error>> {
+    profile::time("prompt", "load config", config::load)
+}
+
+struct GenerationContext<'a> {
+    prompt_config: &'a prompt::PromptConfig,
+    policy: &'a prompt::ContextPolicy,
+    staged_summary: &'a str,
+    staged_changes: &'a str,
+    model_chain: &'a [String],
+    language: &'a language::LanguageSpec,
+    examples: &'a str,
+    recent_subjects: &'a [String],
+    /// Extra instructions appended to the commit message request.
+    notes: &'a [String],
+    /// Excerpts of project docs near the changed files.
+    docs: &'a str,
+    /// Title and body of the linked issue.
+    issue: &'a str,
+    /// The repository's documented commit rules, added to the system prompt.
+    convention: &'a str,
+    deadline: Instant,
+}
+
+impl<'a> GenerationContext<'a> {
+    /// A context for the diff `summary` and `patch` alone, with the configured
+    /// context policy and run deadline; callers add examples, notes, docs, and
+    /// the rest with struct update syntax.
+    fn new(
+        prompt_config: &'a prompt::PromptConfig,
+        summary: &'a str,
+        patch: &'a str,
+        model_chain: &'a [String],
+        language: &'a language::LanguageSpec,
+    ) -> Self {
+        Self {
+            prompt_config,
+            policy: &prompt_config.context_policy,
+            staged_summary: summary,
+            staged_changes: patch,
+            model_chain,
+            language,
+            examples: "",
+            recent_subjects: &[],
+            notes: &[],
+            docs: "",
+            issue: "",
+            convention: "",
+            deadline: Instant::now() + Duration::from_secs(prompt_config.network.run_deadline),
+        }
+    }
+}
+
+/// Reuses the draft `sparkle watch` prepared for exactly this context, or
+/// generates a new one.
+fn draft_or_generate(
+    llm_client: &dyn LlmProvider,
+    context: &GenerationContext<'_>,
+    mut log: impl FnMut(String),
+    confirm: impl FnMut(&str) -> bool,
+) -> Result<Draft, Box<dyn Error>> {
+    if let Some(draft) = watch::take_draft(watch::draft_key(context)) {
+        log("Using the draft prepared by sparkle watch.".to_string());
+        return Ok(draft);
+    }
+    produce_commit_message(llm_client, context, log, confirm)
+}
+
+/// Generates, sanitizes, and verifies the final commit message. `confirm`
+/// asks the user a yes/no question.
+fn produce_commit_message(
+    llm_client: &dyn LlmProvider,
+    context: &GenerationContext<'_>,
+    mut log: impl FnMut(String),
+    mut confirm: impl FnMut(&str) -> bool,
+) -> Result<Draft, Box<dyn Error>> {
+    let _span = profile::span("pipeline", "generate message");
+    let generated =
+        generate_with_fallbacks(llm_client, context, &mut log).classify(FailureKind::Api)?;
+    let output = &context.prompt_config.output;
+    let mut commit_msg = generated.message.clone();
+    let mut session = session::Session::new(generated.prompt.clone());
+    let mut retries = 0;
+    // Each follow-up runs a little hotter so it does not repeat the reply.
+    let mut retry_prompt = |session: &session::Session, reply: &str, instruction: &str| {
+        retries += 1;
+        session.follow_up(reply, instruction).with_temperature(
+            context
+                .prompt_config
+                .model_parameters
+                .retry_temperature(retries),
+        )
+    };
+
+    if output.verify_language && !context.language.matches(&commit_msg) {
+        log(format!(
+            "Message is not in {}; asking the model again.",
+            context.language.describe()
+        ));
+        let prompt = retry_prompt(
+            &session,
+            &generated.reply,
+            &language_followup(&context.language.describe()),
+        );
+        let retry = llm_client
+            .complete(
+                context.prompt_config,
+                &prompt,
+                &generated.model,
+                context.deadline,
+            )
+            .classify(FailureKind::Api)?;
+        let retry = sanitize::sanitize(&retry, &output.sanitizers);
+        if !context.language.matches(&retry) {
+            log(format!(
+                "Warning: commit message may not be in {}.",
+                context.language.describe()
+            ));
+        }
+        if !sanitize::is_placeholder(&retry) {
+            commit_msg = retry;
+            session.advance(prompt);
+        }
+    }
+
+    if context.prompt_config.conventions.type_heuristics == prompt::TypeHeuristics::Enforce
+        && let Some((commit_type, reason)) = type_hint(context)
+    {
+        let (subject, body) = commit_msg.split_once('\n').unwrap_or((&commit_msg, ""));
+        if conventions::subject_type(subject).is_some_and(|current| current != commit_type)
+            && let Some(subject) = conventions::with_type(subject, commit_type)
+        {
+            log(format!("Using commit type `{commit_type}`: {reason}."));
+            commit_msg = if body.is_empty() {
+                subject
+            } else {
+                format!("{subject}\n{body}")
+            };
+        }
+    }
+
+    let subject = commit_msg.lines().next().unwrap_or_default();
+    if let Some(violation) = conventions::violation(subject, &context.prompt_config.conventions) {
+        log(format!(
+            "Message breaks commit conventions: {violation}; asking the model again."
+        ));
+        let prompt = retry_prompt(&session, &commit_msg, &conventions_followup(&violation));
+        let retry = llm_client
+            .complete(
+                context.prompt_config,
+                &prompt,
+                &generated.model,
+                context.deadline,
+            )
+            .classify(FailureKind::Api)?;
+        let retry = sanitize::sanitize(&retry, &output.sanitizers);
+        let retry_subject = retry.lines().next().unwrap_or_default();
+        match conventions::violation(retry_subject, &context.prompt_config.conventions) {
+            None => {
+                commit_msg = retry;
+                session.advance(prompt);
+            }
+            Some(violation) => log(format!(
+                "Warning: commit message breaks conventions: {violation}"
+            )),
+        }
+    }
+
+    let body_sections = &output.body_sections;
+    let missing = sections::missing(&commit_msg, &body_sections.names);
+    if body_sections.required && !missing.is_empty() {
+        log(format!(
+            "Message lacks body sections: {}; asking the model again.",
+            missing.join(", ")
+        ));
+        let prompt = retry_prompt(&session, &commit_msg, &sections_followup(&missing));
+        let retry = llm_client
+            .complete(
+                context.prompt_config,
+                &prompt,
+                &generated.model,
+                context.deadline,
+            )
+            .classify(FailureKind::Api)?;
+        let retry = sanitize::sanitize(&retry, &output.sanitizers);
+        let mut remaining = sections::missing(&retry, &body_sections.names);
+        if remaining.len() < missing.len() {
+            commit_msg = retry;
+            session.advance(prompt);
+        } else {
+            remaining = missing;
+        }
+        if !remaining.is_empty() {
+            log(format!(
+                "Warning: commit message lacks body sections: {}",
+                remaining.join(", ")
+            ));
+        }
+    }
+
+    let subject = commit_msg.lines().next().unwrap_or_default();
+    if let Some(duplicate) = duplicate::find_near_duplicate(subject, context.recent_subjects) {
+        log(format!(
+            "Warning: subject repeats a recent commit: {duplicate}"
+        ));
+        if confirm("The subject repeats a recent commit. Regenerate a more specific message?") {
+            let prompt = retry_prompt(&session, &generated.reply, &duplicate_followup(duplicate));
+            let retry = llm_client
+                .complete(
+                    context.prompt_config,
+                    &prompt,
+                    &generated.model,
+                    context.deadline,
+                )
+                .classify(FailureKind::Api)?;
+            let retry = sanitize::sanitize(&retry, &output.sanitizers);
+            if !sanitize::is_placeholder(&retry) {
+                commit_msg = retry;
+                session.advance(prompt);
+            }
+        }
+    }
+
+    let filter = &output.word_filter;
+    if let Some(pattern) = content_filter::find_denied(&commit_msg, &filter.denylist) {
+        log(format!(
+            "Message matches content filter pattern \"{pattern}\"."
+        ));
+        if filter.action == prompt::FilterAction::Regenerate {
+            let prompt = retry_prompt(&session, &commit_msg, &content_filter_followup(pattern));
+            let retry = llm_client
+                .complete(
+                    context.prompt_config,
+                    &prompt,
+                    &generated.model,
+                    context.deadline,
+                )
+                .classify(FailureKind::Api)?;
+            let retry = sanitize::sanitize(&retry, &output.sanitizers);
+            if !sanitize::is_placeholder(&retry) {
+                commit_msg = retry;
+                session.advance(prompt);
+            }
+        }
+        if content_filter::find_denied(&commit_msg, &filter.denylist).is_some() {
+            log("Masking filtered words in the commit message.".to_string());
+            commit_msg = content_filter::mask(&commit_msg, &filter.denylist);
+        }
+    }
+
+    match output.emoji {
+        prompt::EmojiPolicy::Allow => {}
+        prompt::EmojiPolicy::Forbid => {
+            if emoji::contains(&commit_msg) {
+                log("Removing emoji from the commit message.".to_string());
+                commit_msg = emoji::strip(&commit_msg);
+            }
+        }
+        prompt::EmojiPolicy::Require => {
+            let subject = commit_msg.lines().next().unwrap_or_default();
+            if !emoji::starts_with_emoji(subject) {
+                log("Subject does not start with an emoji; asking the model again.".to_string());
+                let prompt = retry_prompt(&session, &commit_msg, EMOJI_FOLLOWUP);
+                let retry = llm_client
+                    .complete(
+                        context.prompt_config,
+                        &prompt,
+                        &generated.model,
+                        context.deadline,
+                    )
+                    .classify(FailureKind::Api)?;
+                let retry = sanitize::sanitize(&retry, &output.sanitizers);
+                if emoji::starts_with_emoji(retry.lines().next().unwrap_or_default()) {
+                    commit_msg = retry;
+                    session.advance(prompt);
+                } else {
+                    log("Warning: commit subject does not start with an emoji.".to_string());
+                }
+            }
+        }
+    }
 
-    let mut commit_msg = sanitize_commit_message(&commit_msg);
     if commit_msg.is_empty() {
-        return Err("generated commit message is empty".into());
+        return Err(Failure::new(FailureKind::Api, "generated commit message is empty").into());
     }
     if !commit_msg.ends_with('\n') {
         commit_msg.push('\n');
     }
+    let commit_msg = postprocess::apply(&context.prompt_config.postprocess.command, &commit_msg)?;
+
+    Ok(Draft {
+        message: commit_msg,
+        session,
+        model: generated.model,
+        fallback: generated.fallback,
+        refinements: 0,
+    })
+}
+
+/// A finished commit message and the conversation that produced it.
+struct Draft {
+    message: String,
+    session: session::Session,
+    model: String,
+    /// Whether a smaller budget or a later model in the chain answered.
+    fallback: bool,
+    /// Interactive refinements applied so far.
+    refinements: usize,
+}
+
+impl Draft {
+    /// The history record for committing `committed` from this draft.
+    fn history_entry(
+        &self,
+        prompt_config: &prompt::PromptConfig,
+        assignment: Option<&experiment::Assignment>,
+        committed: &str,
+    ) -> history::Entry {
+        let ratio = prompt_config.context_policy.token_char_ratio.max(1);
+        let mut entry = history::Entry::new(
+            &self.model,
+            self.fallback,
+            self.session.prompt().char_count() / ratio,
+            self.refinements,
+            committed,
+        );
+        if let Some(assignment) = assignment {
+            entry.experiment = Some(assignment.experiment.clone());
+            entry.variant = Some(assignment.variant.to_string());
+        }
+        entry
+    }
+}
+
+/// Lets the user refine `draft` with shortcut keys until it is accepted.
+fn iterate_draft(
+    llm_client: &dyn LlmProvider,
+    prompt_config: &prompt::PromptConfig,
+    mut draft: Draft,
+    mut choose: impl FnMut(&str) -> ui::DraftAction,
+    mut log: impl FnMut(String),
+) -> Result<Draft, Box<dyn Error>> {
+    let refinements = &prompt_config.generation.refinements;
+    loop {
+        let instruction = match choose(&draft.message) {
+            ui::DraftAction::Accept => return Ok(draft),
+            ui::DraftAction::Abort => {
+                return Err(Failure::new(FailureKind::Aborted, "commit aborted").into());
+            }
+            ui::DraftAction::Shorter => refinements.shorter.clone(),
+            ui::DraftAction::Detailed => refinements.detailed.clone(),
+            ui::DraftAction::ChangeType(commit_type) => {
+                refinements.change_type.replace("{{type}}", &commit_type)
+            }
+            ui::DraftAction::Language(language) => {
+                refinements.language.replace("{{language}}", &language)
+            }
+        };
+        log("Refining the draft...".to_string());
+        draft = refine_draft(llm_client, prompt_config, &draft, &instruction, None)?;
+    }
+}
+
+/// Continues the draft's conversation with `instruction`.
+fn refine_draft(
+    llm_client: &dyn LlmProvider,
+    prompt_config: &prompt::PromptConfig,
+    draft: &Draft,
+    instruction: &str,
+    temperature: Option<f64>,
+) -> Result<Draft, Box<dyn Error>> {
+    let prompt = draft
+        .session
+        .follow_up(&draft.message, instruction)
+        .with_temperature(temperature);
+    let deadline = Instant::now() + Duration::from_secs(prompt_config.network.run_deadline);
+    let reply = llm_client
+        .complete(prompt_config, &prompt, &draft.model, deadline)
+        .classify(FailureKind::Api)?;
+
+    let output = &prompt_config.output;
+    let mut message = sanitize::sanitize(&reply, &output.sanitizers);
+    if sanitize::is_placeholder(&message) {
+        return Err(Failure::new(FailureKind::Api, "refined commit message is empty").into());
+    }
+    message = content_filter::mask(&message, &output.word_filter.denylist);
+    if output.emoji == prompt::EmojiPolicy::Forbid {
+        message = emoji::strip(&message);
+    }
+    message.push('\n');
+    let message = postprocess::apply(&prompt_config.postprocess.command, &message)?;
+
+    let mut session = draft.session.clone();
+    session.advance(prompt);
+    Ok(Draft {
+        message,
+        session,
+        model: draft.model.clone(),
+        fallback: draft.fallback,
+        refinements: draft.refinements + 1,
+    })
+}
+
+fn language_followup(language: &str) -> String {
+    format!(
+        "Your previous answer was not written in {language}. Rewrite the same commit \
+         message in {language}. Keep the Conventional Commit type and scope in English \
+         and output only the commit message."
+    )
+}
+
+fn duplicate_followup(duplicate: &str) -> String {
+    format!(
+        "A recent commit already uses the subject \"{duplicate}\". Rewrite the commit \
+         message so the subject says what is specific to this change. Output only the \
+         commit message."
+    )
+}
+
+fn sections_followup(missing: &[&str]) -> String {
+    format!(
+        "Your previous answer is missing the body sections {}. Rewrite the commit message \
+         with every required section heading. Output only the commit message.",
+        missing.join(", ")
+    )
+}
+
+fn conventions_followup(violation: &str) -> String {
+    format!(
+        "Your previous answer does not follow the commit conventions: {violation}. \
+         Rewrite the commit message using only the allowed types and scopes. Output only \
+         the commit message."
+    )
+}
+
+const EMOJI_FOLLOWUP: &str = "Start the subject line with a single gitmoji that matches the \
+     change (for example ✨ for a feature or 🐛 for a fix). Output only the commit message.";
+
+fn content_filter_followup(pattern: &str) -> String {
+    format!(
+        "Your previous answer used wording that is not allowed (\"{pattern}\"). Rewrite \
+         the commit message without it. Output only the commit message."
+    )
+}
+
+/// A raw model reply together with the prompt and model that produced it.
+struct Generated {
+    /// Sanitized commit message.
+    message: String,
+    /// Raw model reply, kept for follow-up turns.
+    reply: String,
+    prompt: llm::Prompt,
+    model: String,
+    fallback: bool,
+}
+
+fn generate_with_fallbacks(
+    llm_client: &dyn LlmProvider,
+    context: &GenerationContext<'_>,
+    mut log: impl FnMut(String),
+) -> Result<Generated, Box<dyn Error>> {
+    let attempts = budget_tiers(context.policy);
+    let summarized = if context.prompt_config.generation.summarize_files {
+        summarize_files(llm_client, context, &mut log)?
+    } else {
+        None
+    };
+    let staged_changes = summarized.as_deref().unwrap_or(context.staged_changes);
+    let overhead = template_overhead_tokens(context);
+    let commands = context::run_commands(&context.policy.commands, &mut log);
+    let sources = ChangeSources {
+        summary: context.staged_summary,
+        diff: staged_changes,
+        docs: context.docs,
+        issue: context.issue,
+        commands: &commands,
+    };
+
+    // Contexts and single-pass prompts only depend on the budget, so render
+    // them once and reuse them for every model in the chain.
+    let prepared: Vec<PreparedAttempt> = attempts
+        .iter()
+        .map(|(budget, mode, label)| {
+            let (changes_context, truncated) = build_changes_context(
+                &sources,
+                context.policy,
+                context_budget(*budget, context.policy, overhead),
+                *mode,
+            )?;
+            let prompt = match context.prompt_config.generation.strategy {
+                prompt::GenerationStrategy::SinglePass => {
+                    Some(commit_message_prompt(context, &changes_context))
+                }
+                prompt::GenerationStrategy::TwoPass => None,
+            };
+            Ok(PreparedAttempt {
+                label,
+                mode: *mode,
+                changes_context,
+                truncated,
+                prompt,
+            })
+        })
+        .collect::<Result<_, Box<dyn Error>>>()?;
+
+    let output = &context.prompt_config.output;
+    let generation = &context.prompt_config.generation;
+    let max_attempts = match generation.max_attempts {
+        0 => usize::MAX,
+        limit => limit,
+    };
+    let planned =
+        (context.model_chain.len() * prepared.len() + output.empty_retries).min(max_attempts);
+    let (deadline, deadline_setting, deadline_secs) = match generation.deadline_seconds {
+        0 => (
+            context.deadline,
+            "network.runDeadline",
+            context.prompt_config.network.run_deadline,
+        ),
+        secs => {
+            let own = Instant::now() + Duration::from_secs(secs);
+            if own < context.deadline {
+                (own, "generation.deadlineSeconds", secs)
+            } else {
+                (
+                    context.deadline,
+                    "network.runDeadline",
+                    context.prompt_config.network.run_deadline,
+                )
+            }
+        }
+    };
+    let mut empty_retries_left = output.empty_retries;
+    let mut last_error: Option<String> = None;
+    let mut failures: Vec<attempts::Attempt> = Vec::new();
+    for (model_index, model) in context.model_chain.iter().enumerate() {
+        let mut budget_index = 0;
+        while let Some(attempt) = prepared.get(budget_index) {
+            if Instant::now() >= deadline {
+                let mut message =
+                    deadline_error(deadline_secs, deadline_setting, last_error.as_deref())
+                        .to_string();
+                if failures.len() > 1 {
+                    message.push_str(&attempts::report(&failures));
+                }
+                return Err(message.into());
+            }
+            if failures.len() >= max_attempts {
+                return Err(format!(
+                    "gave up after {} attempts (generation.maxAttempts):{}",
+                    failures.len(),
+                    attempts::report(&failures)
+                )
+                .into());
+            }
+            if !failures.is_empty() {
+                log(format!(
+                    "Attempt {}/{}: {model} with {} budget.",
+                    failures.len() + 1,
+                    planned.max(failures.len() + 1),
+                    attempt.label
+                ));
+            }
+
+            if attempt.truncated {
+                log(format!(
+                    "Input truncated under {} context budget.",
+                    attempt.label
+                ));
+            }
+
+            let started = Instant::now();
+            let retry = output.empty_retries - empty_retries_left;
+            let result = generate_once(llm_client, context, attempt, model, retry);
+            let sample = |ok| {
+                if context.prompt_config.model_policy.strategy == prompt::ModelStrategy::Adaptive {
+                    model_stats::record(model, ok, started.elapsed());
+                }
+            };
+            let record = |status, class, error| attempts::Attempt {
+                model: model.clone(),
+                budget: attempt.label,
+                context_tokens: attempt.changes_context.chars().count()
+                    / context.policy.token_char_ratio.max(1),
+                status,
+                class,
+                duration: started.elapsed(),
+                error,
+            };
+            match result {
+                Ok((reply, prompt)) => {
+                    let message = sanitize::sanitize(&reply, &output.sanitizers);
+                    sample(!sanitize::is_placeholder(&message));
+                    if !sanitize::is_placeholder(&message) {
+                        return Ok(Generated {
+                            message,
+                            reply,
+                            prompt,
+                            model: model.clone(),
+                            fallback: model_index > 0 || budget_index > 0,
+                        });
+                    }
+                    if empty_retries_left == 0 {
+                        return Err(Failure::new(
+                            FailureKind::Api,
+                            "generated commit message is empty or a placeholder",
+                        )
+                        .into());
+                    }
+                    empty_retries_left -= 1;
+                    log(format!(
+                        "Model {model} returned an empty or placeholder message; retrying."
+                    ));
+                    failures.push(record(
+                        None,
+                        "emptyReply",
+                        format!("{model} returned an empty or placeholder message"),
+                    ));
+                    continue;
+                }
+                Err(err) => {
+                    let message = err.to_string();
+                    let Some(class) = fallback::ErrorClass::of(&message) else {
+                        return Err(err);
+                    };
+                    sample(false);
+                    let reason = class.describe();
+                    let summary_only = prepared
+                        .iter()
+                        .position(|attempt| attempt.mode == ContextMode::RequiredOnly)
+                        .filter(|index| *index > budget_index);
+                    match class.action(&context.prompt_config.model_policy.fallback) {
+                        prompt::FallbackAction::Abort => {
+                            return Err(class.explain(&message).into());
+                        }
+                        prompt::FallbackAction::SummaryOnly if summary_only.is_some() => {
+                            log(format!(
+                                "{reason}; retrying with the change summary only, leaving out \
+                                 the {} lines of diff.",
+                                context.staged_changes.lines().count()
+                            ));
+                            budget_index = summary_only.unwrap_or(budget_index);
+                        }
+                        prompt::FallbackAction::SmallerContext
+                            if budget_index + 1 < prepared.len() =>
+                        {
+                            log(format!(
+                                "{reason}; retrying with {} budget.",
+                                prepared[budget_index + 1].label
+                            ));
+                            budget_index += 1;
+                        }
+                        prompt::FallbackAction::SmallerContext
+                        | prompt::FallbackAction::SummaryOnly
+                        | prompt::FallbackAction::NextModel => {
+                            if let Some(next_model) = context.model_chain.get(model_index + 1) {
+                                log(format!("{reason}; retrying with model {next_model}."));
+                            }
+                            budget_index = prepared.len();
+                        }
+                    }
+                    let explained = class.explain(&message);
+                    failures.push(record(
+                        attempts::http_status(&message),
+                        class.key(),
+                        explained.clone(),
+                    ));
+                    last_error = Some(explained);
+                    continue;
+                }
+            }
+        }
+    }
+
+    Err(match (last_error, failures.len()) {
+        (None, _) => "request failed after all retries".to_string(),
+        (Some(last_error), 0 | 1) => last_error,
+        (Some(_), count) => format!(
+            "all {count} attempts failed:{}",
+            attempts::report(&failures)
+        ),
+    }
+    .into())
+}
 
-    send_step(5, ui::StepStatus::Running);
-    git::commit_with_message(&commit_msg, true)?;
-    send_step(5, ui::StepStatus::Done);
-    profile.mark("commit");
+fn deadline_error(deadline_secs: u64, setting: &str, last_error: Option<&str>) -> Box<dyn Error> {
+    let mut message = format!("run deadline of {deadline_secs}s exceeded ({setting})");
+    if let Some(last_error) = last_error {
+        message.push_str("; last error: ");
+        message.push_str(last_error);
+    }
+    message.into()
+}
+
+/// Summarizes each staged file with the first model of the chain, reusing
+/// cached summaries of unchanged blobs (`generation.summarizeFiles`). Files
+/// whose request fails are described by their line counts; when every
+/// request fails the diff is used as is.
+fn summarize_files(
+    llm_client: &dyn LlmProvider,
+    context: &GenerationContext<'_>,
+    log: &mut impl FnMut(String),
+) -> Result<Option<String>, Box<dyn Error>> {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    let model = context
+        .model_chain
+        .first()
+        .ok_or("no model to summarize files with")?;
+    let language = context.language.describe();
+    let file_width = context
+        .policy
+        .budgets
+        .primary_tokens
+        .saturating_mul(context.policy.token_char_ratio);
+    let mut hasher = DefaultHasher::new();
+    (model, &language, file_width).hash(&mut hasher);
+    for message in &context.prompt_config.generation.file_summary_messages {
+        (&message.role, &message.content).hash(&mut hasher);
+    }
 
-    Ok((Some(commit_msg), profile))
+    let summaries = summaries::summarize(
+        context.staged_changes,
+        summaries::cache_path().as_deref(),
+        &format!("{:016x}", hasher.finish()),
+        context.prompt_config.generation.summary_concurrency,
+        |file| {
+            let prompt = llm::Prompt::file_summary(
+                context.prompt_config,
+                &hunks::pack(file.text, file_width),
+                &language,
+            );
+            llm_client.complete(context.prompt_config, &prompt, model, context.deadline)
+        },
+    )?;
+    if let Some(last_error) = &summaries.last_error {
+        if summaries.failed == summaries.files {
+            log(format!(
+                "Could not summarize any file; using the diff instead (last error: {last_error})"
+            ));
+            return Ok(None);
+        }
+        log(format!(
+            "Could not summarize {} file(s); using line counts for them (last error: {last_error})",
+            summaries.failed
+        ));
+    }
+    log(format!(
+        "Summarized {} file(s), {} from cache",
+        summaries.files, summaries.cached
+    ));
+    Ok(Some(summaries.text))
 }
 
-struct GenerationContext<'a> {
-    prompt_config: &'a prompt::PromptConfig,
-    policy: &'a prompt::ContextPolicy,
-    staged_summary: &'a str,
-    staged_changes: &'a str,
-    model_chain: &'a [String],
-    language: &'a str,
-    examples: &'a str,
+struct PreparedAttempt {
+    label: &'static str,
+    mode: ContextMode,
+    changes_context: String,
+    truncated: bool,
+    prompt: Option<llm::Prompt>,
 }
 
-fn generate_with_fallbacks(
-    llm_client: &llm::Client,
+fn generate_once(
+    llm_client: &dyn LlmProvider,
     context: &GenerationContext<'_>,
-    mut log: impl FnMut(String),
-) -> Result<String, Box<dyn Error>> {
-    let attempts = [
-        (
-            context.policy.budgets.primary_tokens,
-            ContextMode::Full,
-            "primary",
-        ),
-        (
-            context.policy.budgets.fallback_tokens,
-            ContextMode::Full,
-            "fallback",
-        ),
-        (
-            context.policy.budgets.minimal_tokens,
-            ContextMode::RequiredOnly,
-            "minimal",
-        ),
-    ];
+    attempt: &PreparedAttempt,
+    model: &str,
+    retry: usize,
+) -> Result<(String, llm::Prompt), Box<dyn Error>> {
+    let temperature = context
+        .prompt_config
+        .model_parameters
+        .retry_temperature(retry);
+    if let Some(prompt) = &attempt.prompt {
+        let prompt = prompt.clone().with_temperature(temperature);
+        let message =
+            llm_client.complete(context.prompt_config, &prompt, model, context.deadline)?;
+        return Ok((message, prompt));
+    }
 
-    let mut last_error: Option<String> = None;
-    for (model_index, model) in context.model_chain.iter().enumerate() {
-        for (budget_index, (budget, mode, label)) in attempts.iter().enumerate() {
-            let (changes_context, truncated) = build_changes_context(
-                context.staged_summary,
-                context.staged_changes,
-                context.policy,
-                *budget,
-                *mode,
-            );
+    let intent_prompt = llm::Prompt::intent(
+        context.prompt_config,
+        &attempt.changes_context,
+        &context.language.describe(),
+    );
+    let intent = llm_client.complete(
+        context.prompt_config,
+        &intent_prompt,
+        model,
+        context.deadline,
+    )?;
+    let prompt = commit_message_prompt(
+        context,
+        &format_intent_changes(context.staged_summary, &intent),
+    )
+    .with_temperature(temperature);
+    let message = llm_client.complete(context.prompt_config, &prompt, model, context.deadline)?;
+    Ok((message, prompt))
+}
 
-            if truncated {
-                log(format!("Input truncated under {label} context budget."));
-            }
+/// The commit type the staged paths point to, if heuristics are on and the
+/// type is allowed by the conventions.
+fn type_hint(context: &GenerationContext<'_>) -> Option<(&'static str, &'static str)> {
+    let conventions = &context.prompt_config.conventions;
+    if conventions.type_heuristics == prompt::TypeHeuristics::Off {
+        return None;
+    }
+    template::type_hint(context.staged_changes).filter(|(commit_type, _)| {
+        conventions
+            .types
+            .iter()
+            .any(|allowed| allowed == commit_type)
+    })
+}
 
-            match llm_client.generate_commit_message(
-                context.prompt_config,
-                &changes_context,
-                model,
-                context.language,
-                context.examples,
-            ) {
-                Ok(message) => return Ok(message),
-                Err(err) if is_payload_too_large(&err.to_string()) => {
-                    if let Some((_, _, next_label)) = attempts.get(budget_index + 1) {
-                        log(format!(
-                            "Request too large; retrying with {next_label} budget."
-                        ));
-                    } else if let Some(next_model) = context.model_chain.get(model_index + 1) {
-                        log(format!(
-                            "Request too large; retrying with model {next_model}."
-                        ));
-                    }
-                    last_error = Some(err.to_string());
-                    continue;
-                }
-                Err(err) => return Err(err),
+/// Renders the commit message prompt with the context's extra notes.
+fn commit_message_prompt(context: &GenerationContext<'_>, changes: &str) -> llm::Prompt {
+    let mut prompt = llm::Prompt::commit_message(
+        context.prompt_config,
+        changes,
+        &context.language.describe(),
+        context.examples,
+    );
+    if !context.convention.is_empty() {
+        prompt = prompt.with_system_note(context.convention);
+    }
+    if let Some((commit_type, reason)) = type_hint(context) {
+        prompt = prompt.with_note(&match context.prompt_config.conventions.type_heuristics {
+            prompt::TypeHeuristics::Enforce => {
+                format!("Use the commit type `{commit_type}`: {reason}.")
             }
-        }
+            _ => format!(
+                "The diff suggests the commit type `{commit_type}`: {reason}. Use it unless \
+                 the change clearly calls for another type."
+            ),
+        });
+    }
+    let body_sections = &context.prompt_config.output.body_sections;
+    if body_sections.required {
+        prompt = prompt.with_note(&sections::prompt_note(&body_sections.names));
     }
+    context
+        .notes
+        .iter()
+        .fold(prompt, |prompt, note| prompt.with_note(note))
+}
 
-    Err(last_error
-        .unwrap_or_else(|| "request failed after all retries".to_string())
-        .into())
+fn format_intent_changes(summary: &str, intent: &str) -> String {
+    format!(
+        "Summary of staged changes:\n{}\n\nKey intent of the changes:\n{}",
+        summary.trim_end(),
+        intent.trim()
+    )
 }
 
 fn build_changes_context(
-    summary: &str,
-    diff: &str,
+    sources: &ChangeSources<'_>,
+    policy: &prompt::ContextPolicy,
+    budget_tokens: usize,
+    mode: ContextMode,
+) -> Result<(String, bool), Box<dyn Error>> {
+    if let Some(builder) = context::custom(&policy.builder_command) {
+        let request = context::ContextRequest {
+            summary: sources.summary,
+            diff: sources.diff,
+            docs: sources.docs,
+            issue: sources.issue,
+            commands: sources.commands,
+            budget_tokens,
+            token_char_ratio: policy.token_char_ratio,
+            summary_only: mode == ContextMode::RequiredOnly,
+        };
+        let built = context::build(builder.as_ref(), &request)?;
+        return Ok((built.context, built.truncated));
+    }
+    let (context, truncated, _) = assemble_changes_context(sources, policy, budget_tokens, mode);
+    Ok((context, truncated))
+}
+
+/// The texts the change context is assembled from.
+struct ChangeSources<'a> {
+    summary: &'a str,
+    diff: &'a str,
+    docs: &'a str,
+    issue: &'a str,
+    commands: &'a [context::CommandOutput],
+}
+
+/// One section of the change context: a configured source or a context
+/// command.
+struct Slot<'a> {
+    name: &'a str,
+    text: &'a str,
+    header: &'a str,
+    max_ratio: f64,
+    required: bool,
+    /// Packed hunk by hunk instead of cut by lines.
+    diff: bool,
+}
+
+/// The sections in the order they are filled, context commands last.
+fn context_slots<'a>(
+    sources: &ChangeSources<'a>,
+    policy: &'a prompt::ContextPolicy,
+) -> Vec<Slot<'a>> {
+    let sections = policy.sections.iter().map(|section| Slot {
+        name: section.source.name(),
+        text: match section.source {
+            prompt::ContextSource::Summary => sources.summary,
+            prompt::ContextSource::Diff => sources.diff,
+            prompt::ContextSource::Docs => sources.docs,
+            prompt::ContextSource::Issue => sources.issue,
+        },
+        header: &section.header,
+        max_ratio: section.max_ratio,
+        required: section.required,
+        diff: matches!(section.source, prompt::ContextSource::Diff),
+    });
+    let commands = policy.commands.iter().map(|command| Slot {
+        name: &command.name,
+        text: sources
+            .commands
+            .iter()
+            .find(|output| output.name == command.name)
+            .map_or("", |output| output.output.as_str()),
+        header: &command.header,
+        max_ratio: command.max_ratio,
+        required: false,
+        diff: false,
+    });
+    sections.chain(commands).collect()
+}
+
+/// Builds the change context and reports what happened to each section.
+fn assemble_changes_context(
+    sources: &ChangeSources<'_>,
     policy: &prompt::ContextPolicy,
     budget_tokens: usize,
     mode: ContextMode,
-) -> (String, bool) {
+) -> (String, bool, Vec<explain::Section>) {
     let max_chars = budget_tokens.saturating_mul(policy.token_char_ratio);
     let mut truncated = false;
     let mut remaining = max_chars;
     let mut carry = 0usize;
     let mut context = String::new();
-
-    let sections = policy
-        .sections
-        .iter()
-        .filter(|section| mode == ContextMode::Full || section.required);
-
-    for section in sections {
+    let mut reports = Vec::new();
+
+    for slot in context_slots(sources, policy) {
+        let source = slot.text;
+        let mut report = explain::Section {
+            name: slot.name.to_string(),
+            allowed: 0,
+            available: truncate::width(source),
+            used: 0,
+            outcome: "",
+        };
+        if mode == ContextMode::RequiredOnly && !slot.required {
+            report.outcome = "left out (summary only)";
+            context.push_str(&truncation_marker(&slot, source, ""));
+            reports.push(report);
+            continue;
+        }
         if remaining == 0 {
-            break;
+            report.outcome = "no budget left";
+            context.push_str(&truncation_marker(&slot, source, ""));
+            reports.push(report);
+            continue;
         }
 
-        let base_limit = ((max_chars as f64) * section.max_ratio).floor() as usize;
+        let base_limit = ((max_chars as f64) * slot.max_ratio).floor() as usize;
         let mut allowed = base_limit.saturating_add(carry);
         if allowed > remaining {
             allowed = remaining;
         }
+        report.allowed = allowed;
         if allowed == 0 {
             carry = 0;
+            report.outcome = "no budget left";
+            context.push_str(&truncation_marker(&slot, source, ""));
+            reports.push(report);
             continue;
         }
 
-        let header_len = section.header.len();
+        let header_len = truncate::width(slot.header);
         if header_len >= allowed {
-            if section.required {
-                let header_trimmed = truncate_to_len(&section.header, allowed);
-                if header_trimmed.len() < section.header.len() {
+            report.outcome = "dropped: header does not fit";
+            if slot.required {
+                let header_trimmed = truncate::truncate(slot.header, allowed);
+                if header_trimmed.len() < slot.header.len() {
                     truncated = true;
                 }
                 context.push_str(&header_trimmed);
-                remaining = remaining.saturating_sub(header_trimmed.len());
+                report.used = truncate::width(&header_trimmed);
+                remaining = remaining.saturating_sub(report.used);
+                report.outcome = "header only";
             }
             carry = 0;
+            reports.push(report);
             continue;
         }
 
         let content_limit = allowed - header_len;
-        let source = match section.source {
-            prompt::ContextSource::Summary => summary,
-            prompt::ContextSource::Diff => diff,
+        let trim = |limit: usize| {
+            if slot.diff {
+                hunks::pack(source, limit)
+            } else {
+                truncate::truncate(source, limit)
+            }
         };
-        let content_trimmed = truncate_to_len(source, content_limit);
-        if content_trimmed.len() < source.len() {
+        let mut content_trimmed = trim(content_limit);
+        let mut marker = String::new();
+        if content_trimmed != source {
             truncated = true;
+            // Make room for the marker, then describe what actually got cut.
+            marker = truncation_marker(&slot, source, &content_trimmed);
+            content_trimmed = trim(content_limit.saturating_sub(truncate::width(&marker)));
+            marker = truncation_marker(&slot, source, &content_trimmed);
         }
 
-        if content_trimmed.is_empty() && !section.required {
+        if content_trimmed.is_empty() && !slot.required {
             carry = allowed;
+            report.outcome = if source.is_empty() {
+                "empty; budget passed on"
+            } else {
+                "dropped; budget passed on"
+            };
+            context.push_str(&marker);
+            reports.push(report);
             continue;
         }
 
-        context.push_str(&section.header);
+        context.push_str(slot.header);
         context.push_str(&content_trimmed);
+        context.push_str(&marker);
 
-        let used = header_len + content_trimmed.len();
+        let used = header_len + truncate::width(&content_trimmed) + truncate::width(&marker);
         remaining = remaining.saturating_sub(used);
         carry = allowed.saturating_sub(used);
+        report.used = used;
+        report.outcome = if content_trimmed == source {
+            "complete"
+        } else {
+            "truncated"
+        };
+        reports.push(report);
+    }
+
+    (context, truncated, reports)
+}
+
+/// A line telling the model that `kept` is only part of `original`, so the
+/// message does not describe a partial change as the whole one. Empty when
+/// nothing was cut.
+fn truncation_marker(slot: &Slot<'_>, original: &str, kept: &str) -> String {
+    if original.is_empty() || kept == original {
+        return String::new();
+    }
+    let name = slot.name;
+    if slot.diff {
+        let (omitted, total) = hunks::omitted_files(original, kept);
+        if !omitted.is_empty() {
+            let shown = omitted.len().min(5);
+            let mut list = omitted[..shown].join(", ");
+            if omitted.len() > shown {
+                list.push_str(", …");
+            }
+            return format!(
+                "\n[{name} truncated: {} of {total} files omitted: {list}]\n",
+                omitted.len()
+            );
+        }
+    }
+    if kept.is_empty() {
+        format!("\n[{name} left out]\n")
+    } else {
+        format!("\n[{name} truncated]\n")
     }
+}
+
+/// Tokens the rendered prompt takes without any change context.
+fn template_overhead_tokens(context: &GenerationContext<'_>) -> usize {
+    commit_message_prompt(context, "")
+        .char_count()
+        .div_ceil(context.policy.token_char_ratio.max(1))
+}
 
-    (context, truncated)
+/// What is left of `budget` for the change context once the reply, at most
+/// `responseTokens`, and the template overhead are taken out. At least one
+/// token is left so the required sections still get their headers.
+fn context_budget(budget: usize, policy: &prompt::ContextPolicy, overhead: usize) -> usize {
+    budget
+        .saturating_sub(policy.budgets.response_tokens)
+        .saturating_sub(overhead)
+        .max(1)
+}
+
+/// The context budgets tried in order for each model.
+fn budget_tiers(policy: &prompt::ContextPolicy) -> [(usize, ContextMode, &'static str); 3] {
+    [
+        (policy.budgets.primary_tokens, ContextMode::Full, "primary"),
+        (
+            policy.budgets.fallback_tokens,
+            ContextMode::Full,
+            "fallback",
+        ),
+        (
+            policy.budgets.minimal_tokens,
+            ContextMode::RequiredOnly,
+            "minimal",
+        ),
+    ]
 }
 
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -515,74 +3214,301 @@ enum ContextMode {
     RequiredOnly,
 }
 
-fn truncate_to_len(input: &str, max_len: usize) -> String {
-    if input.len() <= max_len {
-        return input.to_string();
+/// Collects project docs for the changed files when a context section uses
+/// them.
+fn project_docs(prompt_config: &prompt::PromptConfig, patch: &str) -> String {
+    let wanted = prompt_config
+        .context_policy
+        .sections
+        .iter()
+        .any(|section| matches!(section.source, prompt::ContextSource::Docs));
+    if !wanted {
+        return String::new();
     }
+    git::toplevel()
+        .map(|root| docs::excerpts(&root, patch))
+        .unwrap_or_default()
+}
 
-    let mut end = max_len;
-    while end > 0 && !input.is_char_boundary(end) {
-        end -= 1;
+/// The linked issue for the `issue` context section, empty when no section
+/// uses it.
+pub(crate) fn issue_context(
+    prompt_config: &prompt::PromptConfig,
+    issue: Option<&issue::Issue>,
+) -> String {
+    let wanted = prompt_config
+        .context_policy
+        .sections
+        .iter()
+        .any(|section| matches!(section.source, prompt::ContextSource::Issue));
+    match issue {
+        Some(issue) if wanted => issue::context(issue),
+        _ => String::new(),
     }
+}
 
-    input[..end].to_string()
+/// The commit rules the repository documents, as a system prompt addendum
+/// within `conventions.documentTokens`.
+fn convention_document(prompt_config: &prompt::PromptConfig) -> String {
+    let conventions = &prompt_config.conventions;
+    if conventions.document_tokens == 0 {
+        return String::new();
+    }
+    let Some((path, rules)) = git::toplevel()
+        .ok()
+        .and_then(|root| conventions::document(&root, conventions))
+    else {
+        return String::new();
+    };
+    let max_width = conventions
+        .document_tokens
+        .saturating_mul(prompt_config.context_policy.token_char_ratio);
+    format!(
+        "The repository documents its commit conventions in {path}. Follow them \
+         where they are more specific than the rules above:\n\n{}",
+        truncate::truncate(&rules, max_width)
+    )
 }
 
-fn is_payload_too_large(message: &str) -> bool {
-    let lower = message.to_lowercase();
-    lower.contains("status 413")
-        || lower.contains("payload too large")
-        || lower.contains("tokens_limit_reached")
+/// In auto mode with modelPolicy.preflight, drops models the catalog does not
+/// list for this token. A catalog that cannot be fetched changes nothing.
+fn preflight_chain(
+    cli: &Cli,
+    chain: Vec<String>,
+    policy: &prompt::ModelPolicy,
+    llm_client: &llm::Client,
+    mut log: impl FnMut(String),
+) -> Vec<String> {
+    if cli.model != "auto" || !policy.preflight || cli.replay.is_some() {
+        return chain;
+    }
+    let available = match catalog::models(llm_client, policy.catalog_cache_seconds) {
+        Ok(available) => available,
+        Err(err) => {
+            log(format!(
+                "Could not read the model catalog ({err}); keeping all models."
+            ));
+            return chain;
+        }
+    };
+    let (chain, dropped) = catalog::entitled(chain, &available);
+    for model in dropped {
+        log(format!(
+            "Model {model} is not in the GitHub Models catalog for this token; skipping it."
+        ));
+    }
+    chain
 }
 
-fn resolve_model_chain(
+/// With `modelPolicy.strategy: adaptive`, tries the auto model that has
+/// recently been fastest without failing first.
+fn order_adaptively(
     requested: &str,
+    chain: Vec<String>,
     policy: &prompt::ModelPolicy,
-) -> Result<Vec<String>, Box<dyn Error>> {
-    if requested == "auto" {
-        if policy.auto_models.is_empty() {
-            return Err("auto model list is empty in prompt config".into());
-        }
-        return Ok(policy.auto_models.clone());
+    mut log: impl FnMut(String),
+) -> Vec<String> {
+    if requested != "auto" || policy.strategy != prompt::ModelStrategy::Adaptive {
+        return chain;
+    }
+    let samples = model_stats::load();
+    let ordered = model_stats::rank(chain.clone(), &samples);
+    if ordered.first() != chain.first()
+        && let Some(score) = model_stats::score(&samples, &ordered[0])
+    {
+        log(format!(
+            "Trying {} first: {:.0}% of its recent requests succeeded, median {:.1}s.",
+            ordered[0],
+            score.success_rate * 100.0,
+            score.p50.as_secs_f64()
+        ));
     }
+    ordered
+}
 
-    Ok(vec![requested.to_string()])
+/// In auto mode, tries models whose remembered quota is nearly spent last.
+fn order_by_quota(
+    requested: &str,
+    chain: Vec<String>,
+    policy: &prompt::ModelPolicy,
+    mut log: impl FnMut(String),
+) -> Vec<String> {
+    if requested != "auto" {
+        return chain;
+    }
+    let quotas = quota::load();
+    let now = quota::now();
+    let (chain, demoted) = quota::prefer_available(chain, &quotas, policy.quota_reserve, now);
+    for model in demoted {
+        log(format!(
+            "Quota for {model} is nearly used up ({}); trying it last.",
+            quotas[&model].describe(now)
+        ));
+    }
+    chain
 }
 
-fn sanitize_commit_message(message: &str) -> String {
-    let mut lines = Vec::new();
-    for line in message.lines() {
-        let trimmed = line.trim();
-        if trimmed.starts_with("```") {
-            let rest = trimmed.trim_start_matches("```").trim_start();
-            if !rest.is_empty() && !is_fence_language(rest) {
-                lines.push(rest);
-            }
-            continue;
-        }
-        lines.push(line);
+/// Names the staged files that also have unstaged edits; `None` when the
+/// staged files are complete.
+fn unstaged_note(patch: &str, unstaged: &[String]) -> Option<String> {
+    let partial: Vec<&str> = summaries::split(patch)
+        .into_iter()
+        .map(|file| file.path)
+        .filter(|path| unstaged.iter().any(|unstaged| unstaged == path))
+        .collect();
+    if partial.is_empty() {
+        return None;
     }
+    Some(format!(
+        "These files have further unstaged edits left out of this commit: {}. If the staged \
+         part is unfinished work, say so (e.g. \"partially migrate X\") instead of implying \
+         it is complete.",
+        partial.join(", ")
+    ))
+}
 
-    let mut sanitized = lines.join("\n").trim().to_string();
-    if sanitized.starts_with("```") {
-        sanitized = sanitized.trim_start_matches("```").trim_start().to_string();
+/// Asks for an initial commit message that names the top-level files and
+/// directories the first commit scaffolds.
+fn initial_commit_note(patch: &str) -> String {
+    let mut components: Vec<String> = Vec::new();
+    for file in summaries::split(patch) {
+        let component = match file.path.split_once('/') {
+            Some((dir, _)) => format!("{dir}/"),
+            None => file.path.to_string(),
+        };
+        if !components.contains(&component) {
+            components.push(component);
+        }
+    }
+    let mut note = "This is the first commit of the repository. Write an initial commit \
+                    message (for example \"chore: initial commit\" or \"feat: scaffold the \
+                    project\") whose body lists the scaffolded components rather than \
+                    describing changes."
+        .to_string();
+    if !components.is_empty() {
+        note.push_str(&format!(" Components: {}.", components.join(", ")));
     }
-    if sanitized.ends_with("```") {
-        sanitized = sanitized.trim_end_matches("```").trim_end().to_string();
+    note
+}
+
+/// Shrinks the response budget for `--oneline` and makes sure the reply is
+/// cut down to its subject whatever the model writes.
+fn apply_oneline(prompt_config: &mut prompt::PromptConfig) {
+    let budgets = &mut prompt_config.context_policy.budgets;
+    budgets.response_tokens = budgets.response_tokens.min(budgets.oneline_response_tokens);
+    let sanitizers = &mut prompt_config.output.sanitizers;
+    if !sanitizers.contains(&sanitize::Sanitizer::SubjectOnly) {
+        sanitizers.push(sanitize::Sanitizer::SubjectOnly);
     }
+}
 
-    sanitized
+/// Moves the cheapest model of `chain`, ranked by `modelPolicy.costOrder`,
+/// to the front; the others stay behind it as fallbacks.
+fn cheapest_first(mut chain: Vec<String>, policy: &prompt::ModelPolicy) -> Vec<String> {
+    let rank = |model: &String| {
+        policy
+            .cost_order
+            .iter()
+            .position(|cheap| cheap.eq_ignore_ascii_case(model))
+            .unwrap_or(usize::MAX)
+    };
+    if let Some(cheapest) = (0..chain.len()).min_by_key(|&index| rank(&chain[index])) {
+        let model = chain.remove(cheapest);
+        chain.insert(0, model);
+    }
+    chain
 }
 
-fn is_fence_language(tag: &str) -> bool {
-    tag.chars()
-        .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+fn resolve_model_chain(
+    requested: &str,
+    policy: &prompt::ModelPolicy,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let org_policy = policy::current().map_err(|err| Failure::new(FailureKind::Config, err))?;
+    if requested == "auto" {
+        if policy.auto_models.is_empty() {
+            return Err("auto model list is empty in prompt config".into());
+        }
+        let Some(org_policy) = org_policy else {
+            return Ok(policy.auto_models.clone());
+        };
+        let (allowed, forbidden): (Vec<String>, Vec<String>) = policy
+            .auto_models
+            .iter()
+            .cloned()
+            .partition(|model| org_policy.model_violation(model).is_none());
+        if allowed.is_empty() {
+            let reason = org_policy
+                .model_violation(&forbidden[0])
+                .unwrap_or_default();
+            return Err(Failure::new(
+                FailureKind::Config,
+                format!("no model in modelPolicy.autoModels may be used: {reason}"),
+            )
+            .into());
+        }
+        return Ok(allowed);
+    }
+
+    if let Some(reason) = org_policy.and_then(|org_policy| org_policy.model_violation(requested)) {
+        return Err(Failure::new(FailureKind::Config, reason).into());
+    }
+    Ok(vec![requested.to_string()])
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn protected_pattern_matches_globs() {
+        let patterns = ["main".to_string(), "release/*".to_string()];
+        assert_eq!(protected_pattern("main", &patterns), Some("main"));
+        assert_eq!(
+            protected_pattern("release/1.2", &patterns),
+            Some("release/*")
+        );
+        assert_eq!(protected_pattern("maintenance", &patterns), None);
+        assert_eq!(protected_pattern("feature/x", &patterns), None);
+    }
+
+    #[test]
+    fn cheapest_first_moves_the_lowest_ranked_model_ahead() {
+        let policy = prompt::ModelPolicy {
+            cost_order: vec![
+                "openai/gpt-4.1-nano".to_string(),
+                "openai/gpt-4o-mini".to_string(),
+            ],
+            ..Default::default()
+        };
+        let chain = |models: &[&str]| models.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            cheapest_first(chain(&["openai/gpt-4.1", "openai/gpt-4o-mini"]), &policy),
+            chain(&["openai/gpt-4o-mini", "openai/gpt-4.1"])
+        );
+        assert_eq!(
+            cheapest_first(chain(&["xai/grok-3", "meta/llama"]), &policy),
+            chain(&["xai/grok-3", "meta/llama"])
+        );
+    }
+
+    #[test]
+    fn unstaged_note_names_only_partially_staged_files() {
+        let patch = "diff --git a/src/a.rs b/src/a.rs\n+x\n\
+                     diff --git a/src/b.rs b/src/b.rs\n+y\n";
+        let unstaged = ["src/b.rs".to_string(), "notes.txt".to_string()];
+        let note = unstaged_note(patch, &unstaged).unwrap();
+        assert!(note.contains("commit: src/b.rs."));
+        assert_eq!(unstaged_note(patch, &unstaged[1..]), None);
+    }
+
+    #[test]
+    fn initial_commit_note_lists_top_level_components() {
+        let patch = "diff --git a/Cargo.toml b/Cargo.toml\n+x\n\
+                     diff --git a/src/main.rs b/src/main.rs\n+y\n\
+                     diff --git a/src/lib.rs b/src/lib.rs\n+z\n";
+        assert!(initial_commit_note(patch).ends_with(" Components: Cargo.toml, src/."));
+    }
+
     #[test]
     fn parse_examples_count_accepts_valid_values() {
         assert_eq!(parse_examples_count(None).unwrap(), 0);
@@ -596,6 +3522,113 @@ mod tests {
         assert!(parse_examples_count(Some("nope".to_string())).is_err());
     }
 
+    #[test]
+    fn git_config_and_env_defaults_yield_to_flags() {
+        let settings = || {
+            let mut settings = vec![
+                ("language".to_string(), "chinese".to_string()),
+                ("model".to_string(), "openai/gpt-4.1".to_string()),
+            ];
+            settings.extend(config::cli_env_settings(
+                [("SPARKLE_STYLE", "Short"), ("SPARKLE_PROMPT", "p.yml")]
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string())),
+            ));
+            settings
+        };
+        let matches = Cli::command().get_matches_from(["gh-sparkle", "--model", "xai/grok-3"]);
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        apply_defaults(&mut cli, &matches, settings()).unwrap();
+        assert_eq!(cli.language, "chinese");
+        assert_eq!(cli.model, "xai/grok-3");
+        assert_eq!(cli.style, style::Style::Short);
+
+        let bad = vec![("style".to_string(), "poetic".to_string())];
+        assert!(apply_defaults(&mut cli, &matches, bad).is_err());
+    }
+
+    #[test]
+    fn settle_startup_reports_a_missing_diff_first() {
+        let staged = || {
+            Ok(Some(StagedChanges {
+                changes: "+x\n".to_string(),
+                summary: String::new(),
+                examples: String::new(),
+            }))
+        };
+        let auth_failed = || Err::<(), _>("no GitHub token found".into());
+        let config_failed = || Err::<(), _>("bad config".into());
+
+        let nothing = settle_startup(auth_failed(), config_failed(), Ok(None)).unwrap();
+        assert!(nothing.is_none());
+        let code = |result: Result<Option<Started<(), ()>>, Box<dyn Error>>| {
+            failure::exit_code(result.err().unwrap().as_ref())
+        };
+        assert_eq!(
+            code(settle_startup(Ok(()), Ok(()), Err("not a repo".into()))),
+            failure::exit_code(&Failure::new(FailureKind::Git, ""))
+        );
+        assert_eq!(
+            code(settle_startup(auth_failed(), config_failed(), staged())),
+            failure::exit_code(&Failure::new(FailureKind::Auth, ""))
+        );
+        assert_eq!(
+            code(settle_startup(Ok(()), config_failed(), staged())),
+            failure::exit_code(&Failure::new(FailureKind::Config, ""))
+        );
+        let rejected = auth::AuthError::classify(401, "", auth::TokenSource::GhCli).unwrap();
+        let err = settle_startup::<(), ()>(Err(Box::new(rejected)), Ok(()), staged())
+            .err()
+            .unwrap();
+        assert!(err.is::<auth::AuthError>());
+        let ready = settle_startup(Ok(()), Ok(()), staged()).unwrap().unwrap();
+        assert_eq!(ready.2.changes, "+x\n");
+    }
+
+    #[test]
+    fn select_examples_prefers_good_and_skips_bad_and_repeats() {
+        let good = vec!["feat: add cache\n\nBody.".to_string()];
+        let log = [
+            "commit 1\nAuthor: a\n\n    feat: add cache\n\n    Body.",
+            "commit 2\nAuthor: a\n\n    fix: typo",
+            "commit 3\nAuthor: a\n\n    chore: stuff",
+            "commit 4\nAuthor: a\n\n    docs: explain cache",
+            "commit 5\nAuthor: a\n\n    test: cover cache",
+        ]
+        .map(String::from)
+        .to_vec();
+        let examples = select_examples(good, log, &["chore: stuff"], 3, git::log_entry_subject);
+        assert_eq!(
+            examples,
+            [
+                "feat: add cache\n\nBody.",
+                "commit 2\nAuthor: a\n\n    fix: typo",
+                "commit 4\nAuthor: a\n\n    docs: explain cache",
+            ]
+        );
+
+        let subjects = ["fix: a", "fix: b", "fix: a", "fix: c"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(
+            select_examples(vec!["fix: b".into()], subjects, &["fix: c"], 5, str::trim),
+            ["fix: b", "fix: a"]
+        );
+    }
+
+    #[test]
+    fn context_budget_subtracts_the_reply_and_template() {
+        let mut config: prompt::PromptConfig =
+            serde_yaml::from_value(prompt::default_config_value().unwrap()).unwrap();
+        assert_eq!(context_budget(12000, &config.context_policy, 500), 11244);
+        // A small window is not propped up at the expense of the reply.
+        assert_eq!(context_budget(1000, &config.context_policy, 600), 144);
+        assert_eq!(context_budget(700, &config.context_policy, 600), 1);
+
+        apply_oneline(&mut config);
+        assert_eq!(context_budget(1000, &config.context_policy, 600), 336);
+    }
+
     #[test]
     fn build_changes_context_keeps_content_when_budget_allows() {
         let policy = prompt::ContextPolicy {
@@ -604,6 +3637,8 @@ mod tests {
                 primary_tokens: 10,
                 fallback_tokens: 5,
                 minimal_tokens: 2,
+                response_tokens: 1,
+                oneline_response_tokens: 1,
             },
             sections: vec![
                 prompt::ContextSection {
@@ -619,11 +3654,25 @@ mod tests {
                     required: false,
                 },
             ],
+            include_unstaged_note: false,
+            builder_command: String::new(),
+            commands: Vec::new(),
         };
         let summary = "summary";
         let diff = "diff";
-        let (context, truncated) =
-            build_changes_context(summary, diff, &policy, 200, ContextMode::Full);
+        let (context, truncated) = build_changes_context(
+            &ChangeSources {
+                summary,
+                diff,
+                docs: "",
+                issue: "",
+                commands: &[],
+            },
+            &policy,
+            200,
+            ContextMode::Full,
+        )
+        .unwrap();
         assert!(!truncated);
         assert!(context.contains(summary));
         assert!(context.contains(diff));
@@ -637,6 +3686,8 @@ mod tests {
                 primary_tokens: 10,
                 fallback_tokens: 5,
                 minimal_tokens: 2,
+                response_tokens: 1,
+                oneline_response_tokens: 1,
             },
             sections: vec![
                 prompt::ContextSection {
@@ -652,32 +3703,198 @@ mod tests {
                     required: false,
                 },
             ],
+            include_unstaged_note: false,
+            builder_command: String::new(),
+            commands: Vec::new(),
         };
         let summary = "summary";
         let diff = "diff";
-        let (context, truncated) =
-            build_changes_context(summary, diff, &policy, 1, ContextMode::Full);
+        let (context, truncated) = build_changes_context(
+            &ChangeSources {
+                summary,
+                diff,
+                docs: "",
+                issue: "",
+                commands: &[],
+            },
+            &policy,
+            1,
+            ContextMode::Full,
+        )
+        .unwrap();
         assert!(truncated);
         assert!(!context.is_empty());
     }
 
     #[test]
-    fn sanitize_commit_message_removes_code_fences() {
-        let input = "```\nfeat: add tests\n```\n";
-        assert_eq!(sanitize_commit_message(input), "feat: add tests");
+    fn build_changes_context_adds_command_output_as_a_budgeted_section() {
+        let policy = prompt::ContextPolicy {
+            token_char_ratio: 1,
+            budgets: prompt::ContextBudgets {
+                primary_tokens: 10,
+                fallback_tokens: 5,
+                minimal_tokens: 2,
+                response_tokens: 1,
+                oneline_response_tokens: 1,
+            },
+            sections: vec![prompt::ContextSection {
+                source: prompt::ContextSource::Summary,
+                header: "Summary:\n".to_string(),
+                max_ratio: 0.5,
+                required: true,
+            }],
+            include_unstaged_note: false,
+            builder_command: String::new(),
+            commands: vec![prompt::ContextCommand {
+                name: "check".to_string(),
+                command: "cargo check".to_string(),
+                header: "\nCompiler:\n".to_string(),
+                max_ratio: 0.5,
+                timeout: 10,
+            }],
+        };
+        let commands = [context::CommandOutput {
+            name: "check".to_string(),
+            output: "warning: unused\n".repeat(20),
+        }];
+        let sources = ChangeSources {
+            summary: "a.rs | 1 +\n",
+            diff: "",
+            docs: "",
+            issue: "",
+            commands: &commands,
+        };
+
+        let (context, truncated, sections) =
+            assemble_changes_context(&sources, &policy, 100, ContextMode::Full);
+        assert!(truncated);
+        assert!(
+            context.contains("\nCompiler:\nwarning: unused\n"),
+            "{context}"
+        );
+        assert!(context.ends_with("[check truncated]\n"), "{context}");
+        assert_eq!(sections[1].name, "check");
+        assert!(sections[1].used <= sections[1].allowed);
+
+        let (context, _, _) =
+            assemble_changes_context(&sources, &policy, 100, ContextMode::RequiredOnly);
+        assert!(!context.contains("warning"));
+    }
+
+    #[test]
+    fn build_changes_context_names_the_files_it_leaves_out() {
+        let policy = prompt::ContextPolicy {
+            token_char_ratio: 1,
+            budgets: prompt::ContextBudgets {
+                primary_tokens: 10,
+                fallback_tokens: 5,
+                minimal_tokens: 2,
+                response_tokens: 1,
+                oneline_response_tokens: 1,
+            },
+            sections: vec![prompt::ContextSection {
+                source: prompt::ContextSource::Diff,
+                header: "Diff:\n".to_string(),
+                max_ratio: 1.0,
+                required: true,
+            }],
+            include_unstaged_note: false,
+            builder_command: String::new(),
+            commands: Vec::new(),
+        };
+        let diff: String = ["a.rs", "b.rs", "c.rs"]
+            .iter()
+            .map(|path| {
+                format!(
+                    "diff --git a/{path} b/{path}\n@@ -1 +1 @@\n-old\n+{}\n",
+                    "x".repeat(40)
+                )
+            })
+            .collect();
+        let (context, truncated) = build_changes_context(
+            &ChangeSources {
+                summary: "",
+                diff: &diff,
+                docs: "",
+                issue: "",
+                commands: &[],
+            },
+            &policy,
+            220,
+            ContextMode::Full,
+        )
+        .unwrap();
+        assert!(truncated);
+        assert!(context.contains("diff --git a/a.rs"), "{context}");
+        assert!(
+            context.ends_with("[diff truncated: 2 of 3 files omitted: b.rs, c.rs]\n"),
+            "{context}"
+        );
+        assert!(truncate::width(&context) <= 220);
+    }
+
+    #[test]
+    fn embedded_prompt_config_is_valid() {
+        let config: prompt::PromptConfig =
+            serde_yaml::from_value(prompt::default_config_value().unwrap()).unwrap();
+        prompt::validate_prompt_config(&config).unwrap();
+    }
+
+    #[test]
+    fn validate_model_parameters_rejects_bad_stop_sequences() {
+        let mut params = prompt::ModelParameters {
+            stop: vec!["a".to_string(); 5],
+            ..Default::default()
+        };
+        assert!(prompt::validate_model_parameters(&params).is_err());
+        params.stop = vec![String::new()];
+        assert!(prompt::validate_model_parameters(&params).is_err());
+    }
+
+    #[test]
+    fn format_intent_changes_includes_summary_and_bullets() {
+        let changes = format_intent_changes(" src/a.rs | 2 +-\n", "- add retry\n");
+        assert!(changes.starts_with("Summary of staged changes:\n src/a.rs | 2 +-"));
+        assert!(changes.ends_with("Key intent of the changes:\n- add retry"));
+    }
+
+    #[test]
+    fn deadline_error_mentions_setting_and_last_error() {
+        let message = deadline_error(30, "network.runDeadline", Some("status 413")).to_string();
+        assert!(message.contains("30s"));
+        assert!(message.contains("network.runDeadline"));
+        assert!(message.ends_with("last error: status 413"));
+    }
+
+    #[test]
+    fn cli_accepts_check_flag() {
+        let cli = Cli::try_parse_from(["sparkle", "--check"]).unwrap();
+        assert!(cli.check);
     }
 
     #[test]
-    fn sanitize_commit_message_preserves_inline_message_after_fence() {
-        let input = "```feat: add tests\n";
-        assert_eq!(sanitize_commit_message(input), "feat: add tests");
+    fn cli_requires_examples_for_subjects_only() {
+        assert!(Cli::try_parse_from(["sparkle", "--examples-subjects-only"]).is_err());
+        let cli = Cli::try_parse_from(["sparkle", "-e", "--examples-subjects-only"]).unwrap();
+        assert!(cli.examples_subjects_only);
+    }
+
+    #[test]
+    fn yes_and_no_commit_skip_the_commit_confirmation() {
+        let needs = |args: &[&str]| {
+            let cli = Cli::try_parse_from(["sparkle"].iter().chain(args)).unwrap();
+            needs_confirmation(&cli)
+        };
+        assert!(needs(&[]));
+        assert!(!needs(&["--yes"]));
+        assert!(!needs(&["-y"]));
+        assert!(!needs(&["--no-commit"]));
     }
 
     #[test]
-    fn is_payload_too_large_detects_error_signals() {
-        assert!(is_payload_too_large("status 413"));
-        assert!(is_payload_too_large("Payload Too Large"));
-        assert!(is_payload_too_large("tokens_limit_reached"));
-        assert!(!is_payload_too_large("other error"));
+    fn cli_porcelain_combines_with_no_commit_but_not_interactive() {
+        let cli = Cli::try_parse_from(["sparkle", "--porcelain", "--no-commit"]).unwrap();
+        assert!(cli.porcelain && cli.no_commit);
+        assert!(Cli::try_parse_from(["sparkle", "--porcelain", "--interactive"]).is_err());
     }
 }
@@ -0,0 +1,180 @@
+// Checks existing commit messages against the configured conventions.
+
+use std::error::Error;
+
+use crate::failure::{Classify, Failure, FailureKind};
+use crate::git::BranchCommit;
+use crate::prompt::ConventionsConfig;
+use crate::{
+    Cli, GenerationContext, convention_document, git, load_prompt_config, new_llm_client,
+    parse_language, produce_commit_message, project_docs, resolve_model_chain,
+};
+use crate::{conventions, reword};
+
+/// Longest subject git tooling shows without cutting it off.
+const MAX_SUBJECT_CHARS: usize = 72;
+
+/// A commit whose subject breaks at least one rule.
+pub struct Finding<'a> {
+    pub commit: &'a BranchCommit,
+    pub problems: Vec<String>,
+    /// A generated replacement (`sparkle lint --fix`).
+    pub suggestion: Option<String>,
+}
+
+/// What is wrong with `subject`; empty when it follows the rules. Commits
+/// made for `git rebase --autosquash` and reverts made by git are skipped.
+pub fn problems(subject: &str, config: &ConventionsConfig) -> Vec<String> {
+    let lowered = subject.to_lowercase();
+    if ["fixup!", "squash!", "amend!", "revert \""]
+        .iter()
+        .any(|prefix| lowered.starts_with(prefix))
+    {
+        return Vec::new();
+    }
+
+    let mut problems = Vec::new();
+    if let Some(violation) = conventions::violation(subject, config) {
+        problems.push(violation);
+    }
+    if reword::is_bad_message(subject) {
+        problems.push("the subject is too vague to describe the change".to_string());
+    }
+    let chars = subject.chars().count();
+    if chars > MAX_SUBJECT_CHARS {
+        problems.push(format!(
+            "the subject is {chars} characters long (at most {MAX_SUBJECT_CHARS})"
+        ));
+    }
+    if subject.trim_end().ends_with('.') {
+        problems.push("the subject ends with a period".to_string());
+    }
+    problems
+}
+
+/// Lists each finding with its problems and suggestion.
+pub fn format_report(findings: &[Finding<'_>], checked: usize) -> String {
+    let mut report = String::new();
+    for finding in findings {
+        let sha = &finding.commit.sha[..finding.commit.sha.len().min(7)];
+        report.push_str(&format!("  {sha}  {}\n", finding.commit.subject));
+        for problem in &finding.problems {
+            report.push_str(&format!("           - {problem}\n"));
+        }
+        if let Some(suggestion) = &finding.suggestion {
+            let subject = suggestion.lines().next().unwrap_or_default();
+            report.push_str(&format!("           suggested: {subject}\n"));
+        }
+    }
+    report.push_str(&format!(
+        "  {} of {checked} commit(s) break the commit conventions.\n",
+        findings.len()
+    ));
+    report
+}
+
+pub fn run(cli: &Cli, range: Option<String>, fix: bool) -> Result<(), Box<dyn Error>> {
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    let range = match range {
+        Some(range) => range,
+        None => {
+            let base = git::default_base().classify(FailureKind::Git)?;
+            format!(
+                "{}..HEAD",
+                git::merge_base(&base).classify(FailureKind::Git)?
+            )
+        }
+    };
+    let commits = git::range_commits(&range).classify(FailureKind::Git)?;
+    let mut findings: Vec<Finding> = commits
+        .iter()
+        .filter_map(|commit| {
+            let problems = problems(&commit.subject, &prompt_config.conventions);
+            (!problems.is_empty()).then_some(Finding {
+                commit,
+                problems,
+                suggestion: None,
+            })
+        })
+        .collect();
+
+    if fix && !findings.is_empty() {
+        let language_spec = parse_language(&cli.language)?;
+        let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)
+            .classify(FailureKind::Config)?;
+        let llm_client = new_llm_client(cli, &prompt_config.network).classify(FailureKind::Auth)?;
+        let convention = convention_document(&prompt_config);
+        for finding in &mut findings {
+            let sha = &finding.commit.sha;
+            eprintln!("  Suggesting a message for {}...", &sha[..sha.len().min(7)]);
+            let diff = git::commit_diff(sha).classify(FailureKind::Git)?;
+            let docs = project_docs(&prompt_config, &diff.patch);
+            let context = GenerationContext {
+                docs: &docs,
+                convention: &convention,
+                ..GenerationContext::new(
+                    &prompt_config,
+                    &diff.summary,
+                    &diff.patch,
+                    &model_chain,
+                    &language_spec,
+                )
+            };
+            let draft = produce_commit_message(&llm_client, &context, |_| {}, |_| false)?;
+            finding.suggestion = Some(draft.message);
+        }
+    }
+
+    print!("{}", format_report(&findings, commits.len()));
+    if findings.is_empty() {
+        return Ok(());
+    }
+    Err(Failure::new(
+        FailureKind::Lint,
+        "commit messages break the conventions; 'gh sparkle describe-pr-commits' rewrites \
+         vague ones on this branch",
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn problems_cover_conventions_vagueness_and_length() {
+        let config = ConventionsConfig::default();
+        assert!(problems("feat(api): add retry with backoff", &config).is_empty());
+        assert!(problems("fixup! feat: add retry", &config).is_empty());
+        assert!(problems("Revert \"feat: add retry\"", &config).is_empty());
+        assert_eq!(problems("wip", &config).len(), 2);
+        let long = format!("fix: {}.", "a".repeat(80));
+        assert_eq!(
+            problems(&long, &config),
+            [
+                "the subject is 86 characters long (at most 72)",
+                "the subject ends with a period"
+            ]
+        );
+    }
+
+    #[test]
+    fn report_lists_problems_and_suggestions() {
+        let commit = BranchCommit {
+            sha: "0123456789abcdef".to_string(),
+            subject: "wip".to_string(),
+        };
+        let findings = [Finding {
+            commit: &commit,
+            problems: vec!["the subject is too vague to describe the change".to_string()],
+            suggestion: Some("feat: add retry\n\nBody.".to_string()),
+        }];
+        assert_eq!(
+            format_report(&findings, 3),
+            "  0123456  wip\n           \
+             - the subject is too vague to describe the change\n           \
+             suggested: feat: add retry\n  \
+             1 of 3 commit(s) break the commit conventions.\n"
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::error::Error;
 fn main() {
     if let Err(err) = run() {
         eprintln!("Error: {err}");
-        std::process::exit(1);
+        std::process::exit(gh_sparkle::exit_code(err.as_ref()));
     }
 }
 
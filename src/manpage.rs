@@ -0,0 +1,207 @@
+// Man page rendering from the clap command definition.
+
+use clap::{Arg, Command};
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Where `man --install` writes the page named `name`:
+/// `$XDG_DATA_HOME/man/man1`, by default `~/.local/share/man/man1`.
+pub fn install_path(name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .ok_or("neither XDG_DATA_HOME nor HOME is set")?;
+    Ok(data_home.join("man/man1").join(format!("{name}.1")))
+}
+
+/// Renders `cmd` as a roff man page: options, every subcommand with its
+/// options, and each `Heading:` section of the long help epilogue.
+pub fn render(cmd: &Command, name: &str) -> String {
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{name} {}\" \"User Commands\"\n",
+        escape(&name.to_uppercase()),
+        env!("CARGO_PKG_VERSION")
+    );
+    page.push_str(".SH NAME\n");
+    page.push_str(&format!(
+        "{} \\- {}\n",
+        escape(name),
+        escape(&text(cmd.get_about()))
+    ));
+    page.push_str(".SH SYNOPSIS\n");
+    page.push_str(&format!(
+        ".B gh {}\n[\\fIOPTIONS\\fR] [\\fICOMMAND\\fR]\n",
+        cmd.get_name()
+    ));
+    if let Some(about) = cmd.get_long_about() {
+        page.push_str(".SH DESCRIPTION\n");
+        page.push_str(&format!("{}\n", escape(&about.to_string())));
+    }
+
+    page.push_str(".SH OPTIONS\n");
+    push_args(&mut page, cmd);
+
+    if cmd.has_subcommands() {
+        page.push_str(".SH COMMANDS\n");
+        push_subcommands(&mut page, cmd, cmd.get_name());
+    }
+
+    if let Some(epilogue) = cmd.get_after_long_help() {
+        for section in epilogue.to_string().split("\n\n") {
+            let (heading, body) = section.split_once('\n').unwrap_or((section, ""));
+            let heading = heading.trim_end_matches(':');
+            page.push_str(&format!(".SH {}\n", escape(&heading.to_uppercase())));
+            page.push_str(".nf\n");
+            for line in body.lines() {
+                page.push_str(&format!("{}\n", escape(line)));
+            }
+            page.push_str(".fi\n");
+        }
+    }
+    page
+}
+
+fn push_subcommands(page: &mut String, cmd: &Command, prefix: &str) {
+    for sub in cmd.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let path = format!("{prefix} {}", sub.get_name());
+        page.push_str(&format!(".SS \"gh {}\"\n", escape(&path)));
+        page.push_str(&format!("{}\n", escape(&text(sub.get_about()))));
+        push_args(page, sub);
+        push_subcommands(page, sub, &path);
+    }
+}
+
+fn push_args(page: &mut String, cmd: &Command) {
+    for arg in cmd
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set() && !is_builtin(arg))
+    {
+        page.push_str(".TP\n");
+        page.push_str(&format!("{}\n", term(arg)));
+        let mut help = text(arg.get_long_help().or(arg.get_help()));
+        if !arg.get_action().takes_values() {
+            page.push_str(&format!("{}\n", escape(help.trim())));
+            continue;
+        }
+        let values: Vec<String> = arg
+            .get_possible_values()
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| value.get_name().to_string())
+            .collect();
+        if !values.is_empty() {
+            help.push_str(&format!(" [possible values: {}]", values.join(", ")));
+        }
+        let defaults: Vec<String> = arg
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy().into_owned())
+            .collect();
+        if !defaults.is_empty() {
+            help.push_str(&format!(" [default: {}]", defaults.join(",")));
+        }
+        page.push_str(&format!("{}\n", escape(help.trim())));
+    }
+}
+
+fn is_builtin(arg: &Arg) -> bool {
+    matches!(arg.get_id().as_str(), "help" | "version")
+}
+
+/// `-l, --language <LANGUAGE>` in roff bold and italics.
+fn term(arg: &Arg) -> String {
+    let value_name = arg
+        .get_value_names()
+        .and_then(|names| names.first())
+        .map(ToString::to_string)
+        .unwrap_or_else(|| arg.get_id().as_str().to_uppercase());
+    let value = format!("\\fI<{}>\\fR", escape(&value_name));
+    let mut flags = Vec::new();
+    if let Some(short) = arg.get_short() {
+        flags.push(format!("\\fB\\-{short}\\fR"));
+    }
+    if let Some(long) = arg.get_long() {
+        flags.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    if flags.is_empty() {
+        value
+    } else if arg.get_action().takes_values() {
+        format!("{} {value}", flags.join(", "))
+    } else {
+        flags.join(", ")
+    }
+}
+
+fn text(styled: Option<&clap::builder::StyledStr>) -> String {
+    styled.map(ToString::to_string).unwrap_or_default()
+}
+
+/// Escapes backslashes and hyphens that are not already roff escapes, and
+/// lines that would start with a control character.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if chars
+                .peek()
+                .is_some_and(|next| matches!(next, 'f' | '-' | '&' | 'e')) =>
+            {
+                escaped.push(ch);
+            }
+            '\\' => escaped.push_str("\\e"),
+            '-' => escaped.push_str("\\-"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+        .lines()
+        .map(|line| match line.chars().next() {
+            Some('.' | '\'') => format!("\\&{line}"),
+            _ => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ArgAction;
+
+    #[test]
+    fn render_lists_options_commands_and_epilogue_sections() {
+        let cmd = Command::new("tool")
+            .about("Do things")
+            .after_long_help("Exit codes:\n  0  success\n  .5 odd")
+            .arg(
+                Arg::new("language")
+                    .short('l')
+                    .long("language")
+                    .help("Language to use")
+                    .default_value("english"),
+            )
+            .arg(
+                Arg::new("plain")
+                    .long("plain")
+                    .help("No colors")
+                    .action(ArgAction::SetTrue),
+            )
+            .subcommand(
+                Command::new("config")
+                    .about("Inspect config")
+                    .subcommand(Command::new("schema").about("Print the schema")),
+            );
+        let page = render(&cmd, "gh-tool");
+        assert!(page.starts_with(".TH GH\\-TOOL 1 "));
+        assert!(page.contains(".SH NAME\ngh\\-tool \\- Do things\n"));
+        assert!(page.contains(
+            ".TP\n\\fB\\-l\\fR, \\fB\\-\\-language\\fR \\fI<LANGUAGE>\\fR\n\
+             Language to use [default: english]\n"
+        ));
+        assert!(page.contains(".TP\n\\fB\\-\\-plain\\fR\nNo colors\n"));
+        assert!(page.contains(".SS \"gh tool config schema\"\nPrint the schema\n"));
+        assert!(page.contains(".SH EXIT CODES\n.nf\n  0  success\n  .5 odd\n.fi\n"));
+        assert_eq!(escape(".start\nC:\\path a-b"), "\\&.start\nC:\\epath a\\-b");
+    }
+}
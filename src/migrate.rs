@@ -0,0 +1,117 @@
+// Upgrades of config layers written for older config versions.
+
+use serde_yaml::{Mapping, Value};
+
+/// Version of the config format this build reads.
+pub const CURRENT_VERSION: u64 = 2;
+
+/// Changes that bring a layer up to version `to`. Keys added in a version
+/// need no migration: every layer is merged over the embedded defaults.
+struct Migration {
+    to: u64,
+    /// Dotted key paths, old name first.
+    renames: &'static [(&'static str, &'static str)],
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    // Not to be confused with modelPolicy.fallback.contentFilter, which
+    // reacts to the content filter of GitHub Models.
+    to: 2,
+    renames: &[("output.contentFilter", "output.wordFilter")],
+}];
+
+/// Upgrades `layer` to [`CURRENT_VERSION`] in place. Layers without a
+/// `version` key predate versioning and count as version 1. Returns the
+/// version the layer declared and a description of every change made.
+pub fn migrate(layer: &mut Value) -> Result<(u64, Vec<String>), String> {
+    let Value::Mapping(map) = layer else {
+        return Ok((CURRENT_VERSION, Vec::new()));
+    };
+    let version = match map.get("version") {
+        None => 1,
+        Some(value) => value
+            .as_u64()
+            .filter(|version| *version >= 1)
+            .ok_or("version must be a positive integer")?,
+    };
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "config version {version} is newer than this gh-sparkle supports \
+             ({CURRENT_VERSION}); run `gh extension upgrade sparkle`"
+        ));
+    }
+
+    let mut changes = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|migration| migration.to > version) {
+        for (from, to) in migration.renames {
+            if let Some(value) = take(map, from) {
+                if put(map, to, value) {
+                    changes.push(format!("renamed {from} to {to}"));
+                } else {
+                    changes.push(format!("dropped {from} because {to} is also set"));
+                }
+            }
+        }
+    }
+    map.insert("version".into(), CURRENT_VERSION.into());
+    Ok((version, changes))
+}
+
+fn take(map: &mut Mapping, path: &str) -> Option<Value> {
+    match path.split_once('.') {
+        None => map.remove(path),
+        Some((head, rest)) => match map.get_mut(head)? {
+            Value::Mapping(child) => take(child, rest),
+            _ => None,
+        },
+    }
+}
+
+/// Stores `value` at `path` unless something is already there.
+fn put(map: &mut Mapping, path: &str, value: Value) -> bool {
+    match path.split_once('.') {
+        None if map.contains_key(path) => false,
+        None => {
+            map.insert(path.into(), value);
+            true
+        }
+        Some((head, rest)) => {
+            let child = map
+                .entry(head.into())
+                .or_insert_with(|| Value::Mapping(Mapping::new()));
+            match child {
+                Value::Mapping(child) => put(child, rest, value),
+                _ => false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_renames_keys_of_older_layers() {
+        let mut layer: Value =
+            serde_yaml::from_str("output:\n  contentFilter:\n    denylist: [wip]\n").unwrap();
+        let (version, changes) = migrate(&mut layer).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(
+            changes,
+            ["renamed output.contentFilter to output.wordFilter"]
+        );
+        assert_eq!(
+            layer,
+            serde_yaml::from_str::<Value>(
+                "output:\n  wordFilter:\n    denylist: [wip]\nversion: 2\n"
+            )
+            .unwrap()
+        );
+
+        let mut current: Value =
+            serde_yaml::from_str("version: 2\noutput:\n  contentFilter: {}\n").unwrap();
+        assert_eq!(migrate(&mut current).unwrap(), (2, Vec::new()));
+        assert!(migrate(&mut serde_yaml::from_str("version: 9\n").unwrap()).is_err());
+    }
+}
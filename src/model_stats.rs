@@ -0,0 +1,161 @@
+// Per-model success and latency samples for `modelPolicy.strategy: adaptive`,
+// kept next to the generation history.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::git;
+use crate::quota;
+
+const SAMPLES_FILE: &str = "sparkle/models.jsonl";
+/// Recent requests per model that the ranking looks at.
+const WINDOW: usize = 20;
+/// With fewer samples a model keeps its configured place.
+const MIN_SAMPLES: usize = 3;
+/// Models below this success rate are tried after all others.
+const MIN_SUCCESS_RATE: f64 = 0.8;
+/// The file is cut back to its newest lines beyond this.
+const MAX_LINES: usize = 1000;
+
+/// The outcome of one model request.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sample {
+    pub timestamp: u64,
+    pub model: String,
+    pub ok: bool,
+    pub millis: u64,
+}
+
+/// How a model fared over its recent requests.
+#[derive(Debug, PartialEq)]
+pub struct Score {
+    pub success_rate: f64,
+    /// Median time of the successful requests.
+    pub p50: Duration,
+}
+
+fn samples_path() -> Option<PathBuf> {
+    git::git_dir().ok().map(|dir| dir.join(SAMPLES_FILE))
+}
+
+/// Records one request of `model`. Failures are ignored; a missing sample
+/// only makes the ranking less informed.
+pub fn record(model: &str, ok: bool, elapsed: Duration) {
+    let Some(path) = samples_path() else {
+        return;
+    };
+    let sample = Sample {
+        timestamp: quota::now(),
+        model: model.to_string(),
+        ok,
+        millis: elapsed.as_millis() as u64,
+    };
+    let Ok(line) = serde_json::to_string(&sample) else {
+        return;
+    };
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&path) {
+        let _ = writeln!(file, "{line}");
+    }
+    if let Ok(text) = fs::read_to_string(&path) {
+        let lines: Vec<&str> = text.lines().collect();
+        if lines.len() > MAX_LINES {
+            let _ = fs::write(&path, lines[lines.len() - MAX_LINES..].join("\n") + "\n");
+        }
+    }
+}
+
+/// Every recorded sample, oldest first.
+pub fn load() -> Vec<Sample> {
+    samples_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|text| {
+            text.lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The score of `model` over its last requests; `None` until it has enough.
+pub fn score(samples: &[Sample], model: &str) -> Option<Score> {
+    let recent: Vec<&Sample> = samples
+        .iter()
+        .rev()
+        .filter(|sample| sample.model == model)
+        .take(WINDOW)
+        .collect();
+    if recent.len() < MIN_SAMPLES {
+        return None;
+    }
+    let mut millis: Vec<u64> = recent
+        .iter()
+        .filter(|sample| sample.ok)
+        .map(|sample| sample.millis)
+        .collect();
+    millis.sort_unstable();
+    Some(Score {
+        success_rate: millis.len() as f64 / recent.len() as f64,
+        p50: Duration::from_millis(millis.get(millis.len() / 2).copied().unwrap_or(u64::MAX)),
+    })
+}
+
+/// Orders `chain` as reliable models by median latency, then models without
+/// enough samples, then unreliable ones, each group keeping the configured
+/// order where it has nothing better to go by.
+pub fn rank(chain: Vec<String>, samples: &[Sample]) -> Vec<String> {
+    let mut keyed: Vec<((u8, Duration), String)> = chain
+        .into_iter()
+        .map(|model| {
+            let key = match score(samples, &model) {
+                Some(score) if score.success_rate >= MIN_SUCCESS_RATE => (0, score.p50),
+                None => (1, Duration::ZERO),
+                Some(_) => (2, Duration::ZERO),
+            };
+            (key, model)
+        })
+        .collect();
+    keyed.sort_by_key(|(key, _)| *key);
+    keyed.into_iter().map(|(_, model)| model).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(model: &str, outcomes: &[(bool, u64)]) -> Vec<Sample> {
+        outcomes
+            .iter()
+            .map(|&(ok, millis)| Sample {
+                timestamp: 0,
+                model: model.to_string(),
+                ok,
+                millis,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rank_puts_the_fastest_reliable_model_first() {
+        let mut history = samples("slow", &[(true, 3000), (true, 2500), (true, 2800)]);
+        history.extend(samples("fast", &[(true, 900), (true, 700), (true, 1200)]));
+        history.extend(samples("flaky", &[(false, 100), (true, 100), (false, 100)]));
+        history.extend(samples("new", &[(true, 10)]));
+
+        assert_eq!(
+            score(&history, "fast"),
+            Some(Score {
+                success_rate: 1.0,
+                p50: Duration::from_millis(900),
+            })
+        );
+        let chain = ["flaky", "new", "slow", "fast"].map(String::from).to_vec();
+        assert_eq!(rank(chain, &history), ["fast", "slow", "new", "flaky"]);
+    }
+}
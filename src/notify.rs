@@ -0,0 +1,85 @@
+// Desktop notifications for long-running generations, on Linux and macOS.
+
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::prompt::NotifyConfig;
+
+const TITLE: &str = "gh sparkle";
+
+/// Sends notifications once a run has been going for a while.
+pub struct Notifier {
+    enabled: bool,
+    after: Duration,
+    started: Instant,
+}
+
+impl Notifier {
+    pub fn new(config: &NotifyConfig, started: Instant) -> Self {
+        Self {
+            enabled: config.enabled,
+            after: Duration::from_secs(config.after_seconds),
+            started,
+        }
+    }
+
+    /// Shows `body` as a desktop notification; failures are ignored since
+    /// the terminal output already carries the same information.
+    pub fn notify(&self, body: &str) {
+        if !self.enabled || self.started.elapsed() < self.after {
+            return;
+        }
+        if let Some(mut command) = notification_command(body) {
+            let _ = command.stdout(Stdio::null()).stderr(Stdio::null()).spawn();
+        }
+    }
+}
+
+/// The notifier for this platform; `None` on Windows, where notifications
+/// are not supported.
+fn notification_command(body: &str) -> Option<Command> {
+    if cfg!(target_os = "macos") {
+        let mut command = Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification {} with title {}",
+            applescript_string(body),
+            applescript_string(TITLE)
+        ));
+        Some(command)
+    } else if cfg!(unix) {
+        let mut command = Command::new("notify-send");
+        // `--` keeps a body starting with `-` from being read as an option.
+        command.args(["--app-name", TITLE, "--", TITLE, body]);
+        Some(command)
+    } else {
+        None
+    }
+}
+
+fn applescript_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applescript_string_escapes_quotes() {
+        assert_eq!(
+            applescript_string(r#"fix: handle "quoted" \ paths"#),
+            r#""fix: handle \"quoted\" \\ paths""#
+        );
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn notify_send_keeps_dashed_bodies_positional() {
+        let command = notification_command("--urgency=critical").unwrap();
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            ["--app-name", TITLE, "--", TITLE, "--urgency=critical"]
+        );
+    }
+}
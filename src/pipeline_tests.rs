@@ -0,0 +1,524 @@
+// End-to-end pipeline tests against fake and temporary-repository backends.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::failure::{self, FailureKind};
+use crate::git::{self, GitBackend, StagedDiff};
+use crate::llm::{LlmProvider, Prompt};
+use crate::prompt::PromptConfig;
+use crate::{
+    Confirmation, Draft, GenerationContext, confirm_commit, iterate_draft, language,
+    produce_commit_message, prompt, ui, with_closes,
+};
+
+/// Answers requests from a script and remembers what was asked.
+struct ScriptedLlm {
+    replies: Mutex<VecDeque<Result<String, String>>>,
+    requests: Mutex<Vec<(String, String)>>,
+    temperatures: Mutex<Vec<Option<f64>>>,
+}
+
+impl ScriptedLlm {
+    fn new(replies: Vec<Result<&str, &str>>) -> Self {
+        Self {
+            replies: Mutex::new(
+                replies
+                    .into_iter()
+                    .map(|reply| reply.map(str::to_string).map_err(str::to_string))
+                    .collect(),
+            ),
+            requests: Mutex::new(Vec::new()),
+            temperatures: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn models(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(model, _)| model.clone())
+            .collect()
+    }
+}
+
+impl LlmProvider for ScriptedLlm {
+    fn complete(
+        &self,
+        _prompt_config: &PromptConfig,
+        prompt: &Prompt,
+        model: &str,
+        _deadline: Instant,
+    ) -> Result<String, Box<dyn Error>> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push((model.to_string(), prompt.text()));
+        self.temperatures.lock().unwrap().push(prompt.temperature());
+        let reply = self
+            .replies
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or("script exhausted")?;
+        Ok(reply?)
+    }
+}
+
+/// A repository held in memory.
+struct MemoryGit {
+    summary: String,
+    patch: String,
+    subjects: Vec<String>,
+    commits: RefCell<Vec<String>>,
+}
+
+impl MemoryGit {
+    fn staging(patch: &str) -> Self {
+        Self {
+            summary: " src/lib.rs | 2 +-".to_string(),
+            patch: patch.to_string(),
+            subjects: Vec::new(),
+            commits: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl GitBackend for MemoryGit {
+    fn staged_diff(&self) -> Result<StagedDiff, Box<dyn Error>> {
+        Ok(StagedDiff {
+            summary: self.summary.clone(),
+            patch: self.patch.clone(),
+        })
+    }
+
+    fn recent_subjects(&self, count: usize) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self.subjects.iter().take(count).cloned().collect())
+    }
+
+    fn commit(&self, message: &str, _quiet: bool) -> Result<(), Box<dyn Error>> {
+        self.commits.borrow_mut().push(message.to_string());
+        Ok(())
+    }
+}
+
+fn default_config() -> PromptConfig {
+    serde_yaml::from_value(prompt::default_config_value().unwrap()).unwrap()
+}
+
+/// Runs the non-interactive pipeline: generate from the staged diff, then
+/// commit. Returns the committed message and the log.
+fn run(
+    repo: &dyn GitBackend,
+    llm: &dyn LlmProvider,
+    config: &PromptConfig,
+    model_chain: &[String],
+) -> Result<(String, Vec<String>), Box<dyn Error>> {
+    let mut log = Vec::new();
+    let message = run_logged(repo, llm, config, model_chain, &mut log)?;
+    Ok((message, log))
+}
+
+/// Like `run`, but keeps the log when the run fails.
+fn run_logged(
+    repo: &dyn GitBackend,
+    llm: &dyn LlmProvider,
+    config: &PromptConfig,
+    model_chain: &[String],
+    log: &mut Vec<String>,
+) -> Result<String, Box<dyn Error>> {
+    let draft = draft(repo, llm, config, model_chain, log)?;
+    repo.commit(&draft.message, true)?;
+    Ok(draft.message)
+}
+
+/// Generates a draft from the staged diff without committing it.
+fn draft(
+    repo: &dyn GitBackend,
+    llm: &dyn LlmProvider,
+    config: &PromptConfig,
+    model_chain: &[String],
+    log: &mut Vec<String>,
+) -> Result<Draft, Box<dyn Error>> {
+    let staged = repo.staged_diff()?;
+    let recent_subjects = repo.recent_subjects(config.output.duplicate_lookback)?;
+    let language = language::LanguageSpec::parse("english")?;
+    let context = GenerationContext {
+        recent_subjects: &recent_subjects,
+        deadline: Instant::now() + Duration::from_secs(60),
+        ..GenerationContext::new(
+            config,
+            &staged.summary,
+            &staged.patch,
+            model_chain,
+            &language,
+        )
+    };
+    produce_commit_message(llm, &context, |line| log.push(line), |_| false)
+}
+
+#[test]
+fn oversized_diff_falls_back_through_budgets_and_models() {
+    let patch = format!(
+        "diff --git a/src/lib.rs b/src/lib.rs\n+++ b/src/lib.rs\n{}",
+        "+let value = compute();\n".repeat(5000)
+    );
+    let repo = MemoryGit::staging(&patch);
+    let too_large = Err("API request failed with status 413 Payload Too Large");
+    let llm = ScriptedLlm::new(vec![
+        too_large,
+        too_large,
+        too_large,
+        Ok("```\nperf: cache computed values\n```"),
+    ]);
+    let models = vec!["primary/model".to_string(), "backup/model".to_string()];
+
+    let (message, log) = run(&repo, &llm, &default_config(), &models).unwrap();
+
+    assert_eq!(message, "perf: cache computed values\n");
+    assert_eq!(*repo.commits.borrow(), vec![message]);
+    assert_eq!(
+        llm.models(),
+        [
+            "primary/model",
+            "primary/model",
+            "primary/model",
+            "backup/model"
+        ]
+    );
+    let sizes: Vec<usize> = llm
+        .requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, prompt)| prompt.len())
+        .collect();
+    assert!(sizes[0] > sizes[1] && sizes[1] > sizes[2], "{sizes:?}");
+    assert!(log.contains(&"Input truncated under primary context budget.".to_string()));
+    assert!(log.contains(&"Request too large; retrying with model backup/model.".to_string()));
+}
+
+#[test]
+fn draft_shortcuts_refine_until_accepted() {
+    let repo = MemoryGit::staging("diff --git a/a b/a\n+fix\n");
+    let llm = ScriptedLlm::new(vec![
+        Ok("fix: guard empty input in the parser"),
+        Ok("fix: guard empty input"),
+        Ok("fix: gérer les entrées vides"),
+    ]);
+    let config = default_config();
+    let draft = draft(&repo, &llm, &config, &["m".to_string()], &mut Vec::new()).unwrap();
+
+    let mut actions = VecDeque::from([
+        ui::DraftAction::Shorter,
+        ui::DraftAction::Language("French".to_string()),
+        ui::DraftAction::Accept,
+    ]);
+    let mut shown = Vec::new();
+    let accepted = iterate_draft(
+        &llm,
+        &config,
+        draft,
+        |message| {
+            shown.push(message.to_string());
+            actions.pop_front().unwrap()
+        },
+        |_| {},
+    )
+    .unwrap();
+
+    assert_eq!(accepted.message, "fix: gérer les entrées vides\n");
+    assert_eq!(accepted.refinements, 2);
+    assert_eq!(
+        shown,
+        [
+            "fix: guard empty input in the parser\n",
+            "fix: guard empty input\n",
+            "fix: gérer les entrées vides\n",
+        ]
+    );
+    let refinements = &config.generation.refinements;
+    let requests = llm.requests.lock().unwrap();
+    assert!(requests[1].1.ends_with(refinements.shorter.as_str()));
+    assert!(
+        requests[2]
+            .1
+            .ends_with(&refinements.language.replace("{{language}}", "French"))
+    );
+}
+
+#[test]
+fn aborting_a_draft_fails_without_another_request() {
+    let repo = MemoryGit::staging("diff --git a/a b/a\n+fix\n");
+    let llm = ScriptedLlm::new(vec![Ok("fix: guard empty input")]);
+    let config = default_config();
+    let draft = draft(&repo, &llm, &config, &["m".to_string()], &mut Vec::new()).unwrap();
+
+    let err = iterate_draft(&llm, &config, draft, |_| ui::DraftAction::Abort, |_| {})
+        .err()
+        .unwrap();
+
+    assert_eq!(failure::kind_of(err.as_ref()), Some(FailureKind::Aborted));
+    assert_eq!(llm.models().len(), 1);
+}
+
+#[test]
+fn confirmation_regenerates_then_commits_the_edited_message() {
+    let repo = MemoryGit::staging("diff --git a/a b/a\n+fix\n");
+    let llm = ScriptedLlm::new(vec![
+        Ok("fix: guard empty input"),
+        Ok("fix: reject empty input early"),
+    ]);
+    let config = default_config();
+    let draft = draft(&repo, &llm, &config, &["m".to_string()], &mut Vec::new()).unwrap();
+    let commit_msg = with_closes(&draft.message, Some(7));
+
+    let mut answers = VecDeque::from([
+        Confirmation::Regenerate,
+        Confirmation::Commit("fix: reject empty input\n\nCloses #7\n".to_string()),
+    ]);
+    let mut shown = Vec::new();
+    let (draft, committed) = confirm_commit(
+        &llm,
+        &config,
+        draft,
+        commit_msg,
+        Some(7),
+        |message| {
+            shown.push(message.to_string());
+            answers.pop_front().unwrap()
+        },
+        |_| {},
+    )
+    .unwrap();
+
+    assert_eq!(
+        shown,
+        [
+            "fix: guard empty input\n\nCloses #7\n",
+            "fix: reject empty input early\n\nCloses #7\n",
+        ]
+    );
+    assert_eq!(committed, "fix: reject empty input\n\nCloses #7\n");
+    assert_eq!(draft.refinements, 1);
+    let requests = llm.requests.lock().unwrap();
+    assert!(
+        requests[1]
+            .1
+            .ends_with(config.generation.refinements.regenerate.as_str())
+    );
+}
+
+#[test]
+fn declining_the_confirmation_aborts_the_commit() {
+    let repo = MemoryGit::staging("diff --git a/a b/a\n+fix\n");
+    let llm = ScriptedLlm::new(vec![Ok("fix: guard empty input")]);
+    let config = default_config();
+    let draft = draft(&repo, &llm, &config, &["m".to_string()], &mut Vec::new()).unwrap();
+    let commit_msg = draft.message.clone();
+
+    let err = confirm_commit(
+        &llm,
+        &config,
+        draft,
+        commit_msg,
+        None,
+        |_| Confirmation::Abort,
+        |_| {},
+    )
+    .err()
+    .unwrap();
+
+    assert_eq!(failure::kind_of(err.as_ref()), Some(FailureKind::Aborted));
+    assert!(repo.commits.borrow().is_empty());
+}
+
+#[test]
+fn placeholder_replies_are_retried_and_sanitized() {
+    let repo = MemoryGit::staging("diff --git a/a b/a\n+fix\n");
+    let llm = ScriptedLlm::new(vec![
+        Ok("TODO"),
+        Ok("<think>short fix</think>\nSure! Here's a commit message:\n\nfix: guard empty input"),
+    ]);
+
+    let (message, _) = run(&repo, &llm, &default_config(), &["m".to_string()]).unwrap();
+
+    assert_eq!(message, "fix: guard empty input\n");
+    assert_eq!(llm.models().len(), 2);
+    assert_eq!(*llm.temperatures.lock().unwrap(), [None, Some(0.5)]);
+}
+
+#[test]
+fn fallback_policy_decides_how_errors_are_retried() {
+    let repo = MemoryGit::staging("diff --git a/a b/a\n+fix\n");
+    let models = vec!["primary/model".to_string(), "backup/model".to_string()];
+    let rate_limited = Err("API request failed with status 429 Too Many Requests: wait");
+
+    let llm = ScriptedLlm::new(vec![rate_limited, Ok("fix: retry on backup")]);
+    let (message, log) = run(&repo, &llm, &default_config(), &models).unwrap();
+    assert_eq!(message, "fix: retry on backup\n");
+    assert_eq!(llm.models(), ["primary/model", "backup/model"]);
+    assert!(log.contains(&"Rate limited; retrying with model backup/model.".to_string()));
+
+    let mut config = default_config();
+    config.model_policy.fallback.rate_limit = prompt::FallbackAction::Abort;
+    let llm = ScriptedLlm::new(vec![rate_limited, Ok("fix: unused")]);
+    assert!(run(&repo, &llm, &config, &models).is_err());
+    assert_eq!(llm.models(), ["primary/model"]);
+}
+
+#[test]
+fn timeouts_retry_the_same_model_with_a_smaller_budget() {
+    let patch = format!(
+        "diff --git a/src/lib.rs b/src/lib.rs\n+++ b/src/lib.rs\n{}",
+        "+let value = compute();\n".repeat(5000)
+    );
+    let repo = MemoryGit::staging(&patch);
+    let models = vec!["primary/model".to_string(), "backup/model".to_string()];
+    let timed_out = Err("request to primary/model timed out after 60s (network.requestTimeout)");
+
+    let llm = ScriptedLlm::new(vec![timed_out, Ok("perf: cache computed values")]);
+    let (message, log) = run(&repo, &llm, &default_config(), &models).unwrap();
+    assert_eq!(message, "perf: cache computed values\n");
+    assert_eq!(llm.models(), ["primary/model", "primary/model"]);
+    assert!(log.contains(&"Request timed out; retrying with fallback budget.".to_string()));
+}
+
+#[test]
+fn attempt_limit_ends_the_run_with_every_error() {
+    let repo = MemoryGit::staging("diff --git a/a b/a\n+fix\n");
+    let models = vec![
+        "a/one".to_string(),
+        "b/two".to_string(),
+        "c/three".to_string(),
+    ];
+    let rate_limited = Err("API request failed with status 429 Too Many Requests: wait");
+    let mut config = default_config();
+    config.generation.max_attempts = 2;
+
+    let llm = ScriptedLlm::new(vec![rate_limited, rate_limited, Ok("fix: unused")]);
+    let mut log = Vec::new();
+    let err = run_logged(&repo, &llm, &config, &models, &mut log).unwrap_err();
+    assert_eq!(llm.models(), ["a/one", "b/two"]);
+    assert!(log.contains(&"Attempt 2/2: b/two with primary budget.".to_string()));
+    let message = err.to_string();
+    assert!(message.starts_with("gave up after 2 attempts (generation.maxAttempts):\n  #  model"));
+    assert!(message.contains("\n  1  a/one  primary  "));
+    assert!(message.contains("\n  2  b/two  primary  "));
+    assert!(message.contains("  429     rateLimit  "));
+    assert!(
+        message
+            .ends_with("\nLast error: API request failed with status 429 Too Many Requests: wait")
+    );
+}
+
+#[test]
+fn content_filter_blocks_retry_without_the_diff() {
+    let repo = MemoryGit::staging("diff --git a/a b/a\n+flagged words\n");
+    let models = vec!["m".to_string()];
+    let blocked = Err(
+        r#"API request failed with status 400 Bad Request: {"error":{"code":"content_filter"}}"#,
+    );
+
+    let llm = ScriptedLlm::new(vec![blocked, Ok("fix: adjust a")]);
+    let (message, log) = run(&repo, &llm, &default_config(), &models).unwrap();
+    assert_eq!(message, "fix: adjust a\n");
+    let requests = llm.requests.lock().unwrap();
+    assert!(requests[0].1.contains("+flagged words"));
+    assert!(!requests[1].1.contains("+flagged words"));
+    assert!(
+        log.contains(
+            &"Blocked by the content filter; retrying with the change summary only, leaving out \
+          the 2 lines of diff."
+                .to_string()
+        )
+    );
+
+    let llm = ScriptedLlm::new(vec![blocked, blocked]);
+    let err = run(&repo, &llm, &default_config(), &models).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("content filter rejected the request")
+    );
+    assert!(!err.to_string().contains("status 400"));
+}
+
+/// A scratch repository, removed when dropped.
+pub(crate) struct TempRepo {
+    dir: PathBuf,
+}
+
+impl TempRepo {
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("sparkle-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let repo = Self { dir };
+        repo.git(&["init", "--quiet"]);
+        repo.git(&["config", "user.name", "Sparkle Test"]);
+        repo.git(&["config", "user.email", "sparkle@example.com"]);
+        repo.git(&["config", "commit.gpgsign", "false"]);
+        repo
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    pub(crate) fn git(&self, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(&self.dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+}
+
+impl Drop for TempRepo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn commits_generated_message_in_a_real_repository() {
+    let fixture = TempRepo::new("pipeline-test");
+    std::fs::write(fixture.path().join("notes.txt"), "first\n").unwrap();
+    fixture.git(&["add", "notes.txt"]);
+    let repo = git::Repo::at(fixture.path());
+    let llm = ScriptedLlm::new(vec![Ok("Commit message: \"docs: add release notes\"")]);
+
+    let (message, _) = run(&repo, &llm, &default_config(), &["m".to_string()]).unwrap();
+
+    assert_eq!(message, "docs: add release notes\n");
+    assert_eq!(
+        fixture.git(&["log", "-1", "--format=%B"]),
+        "docs: add release notes\n\n"
+    );
+    assert!(llm.requests.lock().unwrap()[0].1.contains("+first"));
+    assert!(repo.staged_diff().unwrap().patch.is_empty());
+}
+
+#[test]
+fn commits_utf8_messages_under_the_configured_commit_encoding() {
+    let fixture = TempRepo::new("pipeline-encoding");
+    fixture.git(&["config", "i18n.commitEncoding", "GBK"]);
+    std::fs::write(fixture.path().join("notes.txt"), b"+\xd6\xd0\xce\xc4\n").unwrap();
+    fixture.git(&["add", "notes.txt"]);
+    let repo = git::Repo::at(fixture.path());
+
+    assert!(repo.staged_diff().unwrap().patch.contains("+\u{fffd}"));
+    repo.commit("docs: 添加说明\n", true).unwrap();
+
+    let object = fixture.git(&["cat-file", "commit", "HEAD"]);
+    assert!(object.contains("\nencoding GBK\n"), "{object}");
+    assert!(object.ends_with("docs: 添加说明\n"));
+}
@@ -0,0 +1,196 @@
+// Organization policy file (`SPARKLE_ORG_POLICY`): settings that user and
+// repository config cannot override.
+
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::content_filter::glob_match;
+
+pub const POLICY_FILE_ENV: &str = "SPARKLE_ORG_POLICY";
+
+static POLICY: OnceLock<Result<Option<OrgPolicy>, String>> = OnceLock::new();
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OrgPolicy {
+    /// Config values layered over everything else; other layers may repeat
+    /// them but not change them.
+    #[serde(default)]
+    pub locked: Value,
+    /// Model publishers (`openai` in `openai/gpt-4o`) that may be used; empty
+    /// allows all.
+    #[serde(default)]
+    pub allowed_providers: Vec<String>,
+    /// Glob patterns of model ids that may not be used.
+    #[serde(default)]
+    pub blocked_models: Vec<String>,
+    /// Upper bound for `--examples`.
+    #[serde(default)]
+    pub max_examples: Option<usize>,
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+/// The policy named by `SPARKLE_ORG_POLICY`, read once per run.
+pub fn current() -> Result<Option<&'static OrgPolicy>, String> {
+    POLICY
+        .get_or_init(|| match std::env::var_os(POLICY_FILE_ENV) {
+            Some(path) if !path.is_empty() => load(Path::new(&path)).map(Some),
+            _ => Ok(None),
+        })
+        .as_ref()
+        .map(Option::as_ref)
+        .map_err(Clone::clone)
+}
+
+fn load(path: &Path) -> Result<OrgPolicy, String> {
+    let source = fs::read_to_string(path).map_err(|err| {
+        format!(
+            "reading the organization policy {} failed: {err}",
+            path.display()
+        )
+    })?;
+    let policy = parse(&source).map_err(|err| format!("{}: {err}", path.display()))?;
+    Ok(OrgPolicy {
+        path: path.to_path_buf(),
+        ..policy
+    })
+}
+
+fn parse(source: &str) -> Result<OrgPolicy, String> {
+    let policy: OrgPolicy = serde_yaml::from_str(source).map_err(|err| err.to_string())?;
+    if !matches!(policy.locked, Value::Null | Value::Mapping(_)) {
+        return Err("locked must be a mapping of config keys".to_string());
+    }
+    Ok(policy)
+}
+
+impl OrgPolicy {
+    /// Why the first forbidden model of `models` may not be used, if any.
+    pub fn models_violation(&self, models: &[String]) -> Option<String> {
+        models.iter().find_map(|model| self.model_violation(model))
+    }
+
+    /// Why `model` may not be used, if the policy forbids it.
+    pub fn model_violation(&self, model: &str) -> Option<String> {
+        let path = self.path.display();
+        let lower = model.to_lowercase();
+        if let Some(pattern) = self
+            .blocked_models
+            .iter()
+            .find(|pattern| glob_match(&pattern.to_lowercase(), &lower))
+        {
+            return Some(format!(
+                "model {model} is blocked by the organization policy {path} ({pattern})"
+            ));
+        }
+        let provider = model.split_once('/').map_or("", |(provider, _)| provider);
+        let allowed = self.allowed_providers.is_empty()
+            || self
+                .allowed_providers
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(provider));
+        (!allowed).then(|| {
+            format!(
+                "model {model} is not from a provider the organization policy {path} allows \
+                 ({})",
+                self.allowed_providers.join(", ")
+            )
+        })
+    }
+
+    /// Why `count` examples may not be used, if it exceeds the policy.
+    pub fn examples_violation(&self, count: usize) -> Option<String> {
+        let max = self.max_examples?;
+        (count > max).then(|| {
+            format!(
+                "--examples {count} exceeds the limit of {max} set by the organization policy {}",
+                self.path.display()
+            )
+        })
+    }
+
+    /// The first locked key `layer` changes, as a message naming `source`.
+    pub fn locked_violation(&self, layer: &Value, source: &str) -> Option<String> {
+        let key = changed_keys(&self.locked, layer, "").into_iter().next()?;
+        Some(format!(
+            "{key} is locked by the organization policy {}; remove it from {source}",
+            self.path.display()
+        ))
+    }
+}
+
+/// Dotted paths of the `locked` leaves that `layer` sets to another value.
+fn changed_keys(locked: &Value, layer: &Value, prefix: &str) -> Vec<String> {
+    let (Value::Mapping(locked), Value::Mapping(layer)) = (locked, layer) else {
+        return Vec::new();
+    };
+    let mut keys = Vec::new();
+    for (key, value) in locked {
+        let Some(set) = layer.get(key) else {
+            continue;
+        };
+        let name = key.as_str().unwrap_or_default();
+        let path = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{prefix}.{name}")
+        };
+        if value.is_mapping() && set.is_mapping() {
+            keys.extend(changed_keys(value, set, &path));
+        } else if set != value {
+            keys.push(path);
+        }
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_rejects_locked_keys_and_forbidden_models() {
+        let policy = parse(
+            "locked:\n  examples:\n    scrubEmails: true\nallowedProviders: [openai]\n\
+             blockedModels: [\"openai/o1*\"]\nmaxExamples: 3\n",
+        )
+        .unwrap();
+
+        let same: Value = serde_yaml::from_str("examples: {scrubEmails: true}").unwrap();
+        assert_eq!(policy.locked_violation(&same, "x"), None);
+        let changed: Value =
+            serde_yaml::from_str("examples: {scrubEmails: false, scrubUrls: false}").unwrap();
+        let message = policy.locked_violation(&changed, ".sparkle.yml").unwrap();
+        assert!(message.starts_with("examples.scrubEmails is locked"));
+        assert!(message.ends_with("remove it from .sparkle.yml"));
+
+        assert_eq!(policy.model_violation("openai/gpt-4o-mini"), None);
+        assert!(
+            policy
+                .model_violation("openai/o1-mini")
+                .unwrap()
+                .contains("blocked")
+        );
+        assert!(
+            policy
+                .model_violation("meta/llama-3")
+                .unwrap()
+                .contains("provider")
+        );
+        let models = ["openai/gpt-4o".to_string(), "openai/o1".to_string()];
+        assert!(
+            policy
+                .models_violation(&models)
+                .unwrap()
+                .starts_with("model openai/o1 is blocked")
+        );
+        assert_eq!(policy.models_violation(&models[..1]), None);
+        assert_eq!(policy.examples_violation(3), None);
+        assert!(policy.examples_violation(4).is_some());
+        assert!(parse("locked: [a]").is_err());
+    }
+}
@@ -0,0 +1,61 @@
+// `postprocess.command`: pipes each generated message through an external
+// program, such as a company linter or rewriter.
+
+use std::error::Error;
+use std::time::Duration;
+
+use crate::context;
+use crate::failure::{Failure, FailureKind};
+
+/// How long the command may run before it is stopped and the run fails.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs `command` through the shell with `message` on stdin and returns its
+/// stdout, ending in a newline. A non-zero exit, a timeout, or an empty
+/// output fails with the command's stderr; an empty `command` keeps the
+/// message.
+pub fn apply(command: &str, message: &str) -> Result<String, Box<dyn Error>> {
+    if command.trim().is_empty() {
+        return Ok(message.to_string());
+    }
+    let failed = |detail: String| {
+        Failure::new(
+            FailureKind::Lint,
+            format!("postprocess.command `{command}` {detail}"),
+        )
+    };
+    let processed = context::shell(command, message.as_bytes(), TIMEOUT)
+        .map_err(|detail| failed(format!("rejected the message ({detail})")))?;
+    let processed = processed.trim_end();
+    if processed.trim().is_empty() {
+        return Err(failed("printed no message".to_string()).into());
+    }
+    Ok(format!("{processed}\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failure::exit_code;
+
+    #[test]
+    fn apply_uses_stdout_and_fails_on_non_zero_exit() {
+        assert_eq!(apply("", "feat: x\n").unwrap(), "feat: x\n");
+        assert_eq!(
+            apply("tr a-z A-Z", "feat: add x\n").unwrap(),
+            "FEAT: ADD X\n"
+        );
+
+        let err = apply("echo 'subject too long' >&2; exit 1", "feat: x\n").unwrap_err();
+        assert!(err.to_string().contains("subject too long"), "{err}");
+        assert_eq!(exit_code(err.as_ref()), 8);
+        assert!(apply("true", "feat: x\n").is_err());
+    }
+
+    #[test]
+    fn apply_streams_large_messages() {
+        let body = "x".repeat(1 << 20);
+        let message = format!("feat: x\n\n{body}");
+        assert_eq!(apply("cat", &message).unwrap(), format!("{message}\n"));
+    }
+}
@@ -0,0 +1,236 @@
+// Span recording for SPARKLE_PROFILE and --profile.
+
+use serde::Serialize;
+use std::cell::Cell;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+const CHROME_TRACE_FILE: &str = "sparkle-trace.json";
+
+/// How recorded spans are reported when the run ends.
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// Total time per span name, printed after the run
+    Text,
+    /// One JSON object per span, printed after the run
+    Json,
+    /// Chrome trace-event file for chrome://tracing or Perfetto
+    Chrome,
+}
+
+struct Recorder {
+    format: Format,
+    epoch: Instant,
+    spans: Mutex<Vec<Span>>,
+    /// Totals such as bytes sent, by category and name.
+    counters: Mutex<Vec<(&'static str, String, u64)>>,
+}
+
+#[derive(Serialize)]
+struct Span {
+    category: &'static str,
+    name: String,
+    thread: u64,
+    start_us: u64,
+    duration_us: u64,
+}
+
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static THREAD: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Starts recording; spans are no-ops until this is called.
+pub fn init(format: Format) {
+    let _ = RECORDER.set(Recorder {
+        format,
+        epoch: Instant::now(),
+        spans: Mutex::new(Vec::new()),
+        counters: Mutex::new(Vec::new()),
+    });
+}
+
+/// Adds `amount` to a counter reported after the spans in text format.
+pub fn count(category: &'static str, name: &str, amount: u64) {
+    let Some(recorder) = RECORDER.get() else {
+        return;
+    };
+    let Ok(mut counters) = recorder.counters.lock() else {
+        return;
+    };
+    match counters
+        .iter_mut()
+        .find(|(known, known_name, _)| *known == category && known_name == name)
+    {
+        Some(counter) => counter.2 += amount,
+        None => counters.push((category, name.to_string(), amount)),
+    }
+}
+
+/// Measures until the returned guard is dropped.
+pub fn span(category: &'static str, name: &str) -> SpanGuard {
+    SpanGuard {
+        active: RECORDER
+            .get()
+            .map(|_| (category, name.to_string(), Instant::now())),
+    }
+}
+
+/// Runs `work` inside a span.
+pub fn time<T>(category: &'static str, name: &str, work: impl FnOnce() -> T) -> T {
+    let _span = span(category, name);
+    work()
+}
+
+pub struct SpanGuard {
+    active: Option<(&'static str, String, Instant)>,
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let (Some(recorder), Some((category, name, started))) =
+            (RECORDER.get(), self.active.take())
+        else {
+            return;
+        };
+        let span = Span {
+            category,
+            name,
+            thread: thread_number(),
+            start_us: micros(started.saturating_duration_since(recorder.epoch)),
+            duration_us: micros(started.elapsed()),
+        };
+        if let Ok(mut spans) = recorder.spans.lock() {
+            spans.push(span);
+        }
+    }
+}
+
+fn thread_number() -> u64 {
+    THREAD.with(|thread| {
+        if thread.get() == 0 {
+            thread.set(NEXT_THREAD.fetch_add(1, Ordering::Relaxed));
+        }
+        thread.get()
+    })
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros().try_into().unwrap_or(u64::MAX)
+}
+
+/// Prints or writes the recorded spans in the requested format.
+pub fn report() -> Result<(), Box<dyn Error>> {
+    let Some(recorder) = RECORDER.get() else {
+        return Ok(());
+    };
+    let spans = recorder.spans.lock().map_err(|_| "profile lock poisoned")?;
+    match recorder.format {
+        Format::Text => {
+            println!();
+            println!("Profile:");
+            print!("{}", summarize(&spans));
+            if let Ok(counters) = recorder.counters.lock() {
+                for (category, name, total) in counters.iter() {
+                    println!("  {category} {name}: {total}");
+                }
+            }
+        }
+        Format::Json => println!("{}", serde_json::to_string(&*spans)?),
+        Format::Chrome => {
+            std::fs::write(CHROME_TRACE_FILE, chrome_trace(&spans)?)?;
+            eprintln!("Profile trace written to {CHROME_TRACE_FILE}");
+        }
+    }
+    Ok(())
+}
+
+/// Total duration and count per span, in order of first appearance.
+fn summarize(spans: &[Span]) -> String {
+    let mut totals: Vec<(&str, &str, u64, usize)> = Vec::new();
+    for span in spans {
+        match totals
+            .iter_mut()
+            .find(|(category, name, _, _)| *category == span.category && *name == span.name)
+        {
+            Some(total) => {
+                total.2 += span.duration_us;
+                total.3 += 1;
+            }
+            None => totals.push((span.category, &span.name, span.duration_us, 1)),
+        }
+    }
+
+    let mut text = String::new();
+    for (category, name, duration_us, count) in totals {
+        let duration = Duration::from_micros(duration_us);
+        if count > 1 {
+            text.push_str(&format!("  {category} {name}: {duration:.2?} ({count}x)\n"));
+        } else {
+            text.push_str(&format!("  {category} {name}: {duration:.2?}\n"));
+        }
+    }
+    text
+}
+
+fn chrome_trace(spans: &[Span]) -> Result<String, Box<dyn Error>> {
+    let events: Vec<serde_json::Value> = spans
+        .iter()
+        .map(|span| {
+            serde_json::json!({
+                "name": span.name,
+                "cat": span.category,
+                "ph": "X",
+                "ts": span.start_us,
+                "dur": span.duration_us,
+                "pid": std::process::id(),
+                "tid": span.thread,
+            })
+        })
+        .collect();
+    Ok(serde_json::to_string(
+        &serde_json::json!({ "traceEvents": events }),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(category: &'static str, name: &str, duration_us: u64) -> Span {
+        Span {
+            category,
+            name: name.to_string(),
+            thread: 1,
+            start_us: 0,
+            duration_us,
+        }
+    }
+
+    #[test]
+    fn summarize_totals_repeated_spans() {
+        let spans = [
+            span("git", "diff", 2000),
+            span("ui", "draw", 100),
+            span("ui", "draw", 300),
+        ];
+        assert_eq!(
+            summarize(&spans),
+            "  git diff: 2.00ms\n  ui draw: 400.00µs (2x)\n"
+        );
+    }
+
+    #[test]
+    fn chrome_trace_emits_complete_events() {
+        let trace: serde_json::Value =
+            serde_json::from_str(&chrome_trace(&[span("llm", "chat", 1500)]).unwrap()).unwrap();
+        let event = &trace["traceEvents"][0];
+        assert_eq!(event["ph"], "X");
+        assert_eq!(event["cat"], "llm");
+        assert_eq!(event["dur"], 1500);
+    }
+}
@@ -3,25 +3,97 @@
 use serde::Deserialize;
 use std::error::Error;
 
+use crate::sanitize::{DEFAULT_SANITIZERS, Sanitizer};
+
 const COMMITMSG_PROMPT_YAML: &str = include_str!("../assets/commitmsg.prompt.yml");
+const MAX_STOP_SEQUENCES: usize = 4;
 
 #[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct PromptConfig {
+    // Descriptive metadata; accepted so prompt files validate, never read.
+    #[serde(default, rename = "name")]
+    _name: Option<serde::de::IgnoredAny>,
+    #[serde(default, rename = "description")]
+    _description: Option<serde::de::IgnoredAny>,
+    // Checked and upgraded by migrate::migrate before deserializing.
+    #[serde(default, rename = "version")]
+    _version: Option<serde::de::IgnoredAny>,
+    // Read by config::repo_defaults before the command line is resolved.
+    #[serde(default, rename = "defaults")]
+    _defaults: Option<DefaultsConfig>,
     #[serde(default)]
     pub model_parameters: ModelParameters,
     #[serde(default)]
     pub model_policy: ModelPolicy,
     pub context_policy: ContextPolicy,
     #[serde(default)]
+    pub generation: GenerationConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub output: OutputConfig,
+    #[serde(default)]
+    pub examples: ExamplesConfig,
+    #[serde(default)]
+    pub conventions: ConventionsConfig,
+    #[serde(default)]
+    pub commit: CommitConfig,
+    #[serde(default)]
+    pub security: SecurityConfig,
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    #[serde(default)]
+    pub updates: UpdatesConfig,
+    #[serde(default)]
+    pub postprocess: PostprocessConfig,
+    #[serde(default)]
+    pub experiment: ExperimentConfig,
+    #[serde(default)]
+    pub watch: WatchConfig,
+    #[serde(default)]
+    pub summary: SummaryConfig,
+    #[serde(default)]
+    pub explain: ExplainConfig,
+    #[serde(default)]
     pub messages: Vec<PromptMessage>,
 }
 
+/// Option defaults a repository or one of its directories sets, below
+/// `SPARKLE_*` variables and flags.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct DefaultsConfig {
+    pub language: Option<String>,
+    pub model: Option<String>,
+    pub style: Option<String>,
+}
+
+/// Compliance controls on what may leave the machine.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SecurityConfig {
+    /// Gitignore-style patterns; while a staged file matches, no context is
+    /// sent without `--no-llm` or an explicit acknowledgment.
+    #[serde(default)]
+    pub block_paths: Vec<String>,
+    /// File every outbound request is appended to; empty disables.
+    #[serde(default)]
+    pub audit_log: String,
+}
+
 #[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct ModelParameters {
     pub temperature: f64,
     pub top_p: f64,
+    #[serde(default)]
+    pub stop: Vec<String>,
+    /// Temperatures for the first, second, ... retry of a message that failed
+    /// a check or was regenerated; the last one repeats. Empty keeps
+    /// `temperature`.
+    #[serde(default)]
+    pub retry_temperatures: Vec<f64>,
 }
 
 impl Default for ModelParameters {
@@ -29,41 +101,645 @@ impl Default for ModelParameters {
         Self {
             temperature: 0.2,
             top_p: 0.9,
+            stop: Vec::new(),
+            retry_temperatures: Vec::new(),
         }
     }
 }
 
-#[derive(Deserialize, Default)]
-#[serde(rename_all = "camelCase")]
+impl ModelParameters {
+    /// The temperature for the `retry`-th retry (counted from 1); `None` for
+    /// the first try or without a schedule.
+    pub fn retry_temperature(&self, retry: usize) -> Option<f64> {
+        let index = retry.checked_sub(1)?;
+        self.retry_temperatures
+            .get(index)
+            .or(self.retry_temperatures.last())
+            .copied()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct ModelPolicy {
     #[serde(default)]
     pub auto_models: Vec<String>,
+    #[serde(default)]
+    pub fallback: FallbackPolicy,
+    /// In auto mode, models with at most this many requests left in their
+    /// rate-limit window are tried last.
+    #[serde(default = "default_quota_reserve")]
+    pub quota_reserve: u64,
+    /// In auto mode, skip models missing from the GitHub Models catalog.
+    #[serde(default)]
+    pub preflight: bool,
+    /// How long a fetched catalog is reused for the preflight.
+    #[serde(default = "default_catalog_cache_seconds")]
+    pub catalog_cache_seconds: u64,
+    /// Models from cheapest to most expensive; `--oneline` tries the
+    /// cheapest one in the chain first.
+    #[serde(default)]
+    pub cost_order: Vec<String>,
+    /// How the auto chain is ordered before each run.
+    #[serde(default)]
+    pub strategy: ModelStrategy,
+}
+
+#[derive(Deserialize, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModelStrategy {
+    /// Keep the order of `autoModels`.
+    #[default]
+    Ordered,
+    /// Try the model that has recently been fastest without failing first.
+    Adaptive,
+}
+
+fn default_catalog_cache_seconds() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_quota_reserve() -> u64 {
+    5
+}
+
+impl Default for ModelPolicy {
+    fn default() -> Self {
+        Self {
+            auto_models: Vec::new(),
+            fallback: FallbackPolicy::default(),
+            quota_reserve: default_quota_reserve(),
+            preflight: false,
+            catalog_cache_seconds: default_catalog_cache_seconds(),
+            cost_order: Vec::new(),
+            strategy: ModelStrategy::Ordered,
+        }
+    }
+}
+
+/// What a failed request leads to, per class of error.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct FallbackPolicy {
+    #[serde(default = "default_smaller_context")]
+    pub payload_too_large: FallbackAction,
+    #[serde(default = "default_next_model")]
+    pub rate_limit: FallbackAction,
+    #[serde(default = "default_next_model")]
+    pub model_unavailable: FallbackAction,
+    #[serde(default = "default_summary_only")]
+    pub content_filter: FallbackAction,
+    /// Large prompts are likelier to hit server-side time limits.
+    #[serde(default = "default_smaller_context")]
+    pub timeout: FallbackAction,
+}
+
+impl Default for FallbackPolicy {
+    fn default() -> Self {
+        Self {
+            payload_too_large: default_smaller_context(),
+            rate_limit: default_next_model(),
+            model_unavailable: default_next_model(),
+            content_filter: default_summary_only(),
+            timeout: default_smaller_context(),
+        }
+    }
+}
+
+/// `smaller-context` moves to the next context budget and then to the next
+/// model once the budgets run out; `summary-only` jumps straight to the
+/// context without the diff.
+#[derive(Deserialize, Default, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum FallbackAction {
+    SmallerContext,
+    SummaryOnly,
+    NextModel,
+    #[default]
+    Abort,
+}
+
+fn default_smaller_context() -> FallbackAction {
+    FallbackAction::SmallerContext
+}
+
+fn default_summary_only() -> FallbackAction {
+    FallbackAction::SummaryOnly
+}
+
+fn default_next_model() -> FallbackAction {
+    FallbackAction::NextModel
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct GenerationConfig {
+    #[serde(default)]
+    pub strategy: GenerationStrategy,
+    #[serde(default)]
+    pub intent_messages: Vec<PromptMessage>,
+    /// Summarize each staged file first and give the model the summaries
+    /// instead of the diff.
+    #[serde(default)]
+    pub summarize_files: bool,
+    /// Prompt for one file's summary; `{{changes}}` is that file's diff.
+    #[serde(default)]
+    pub file_summary_messages: Vec<PromptMessage>,
+    /// File summary requests in flight at once.
+    #[serde(default = "default_summary_concurrency")]
+    pub summary_concurrency: usize,
+    #[serde(default)]
+    pub refinements: Refinements,
+    /// Model requests across all models and budgets before giving up; 0
+    /// means no limit.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: usize,
+    /// Seconds the fallback attempts may take; 0 leaves only
+    /// network.runDeadline.
+    #[serde(default)]
+    pub deadline_seconds: u64,
+}
+
+impl Default for GenerationConfig {
+    fn default() -> Self {
+        Self {
+            strategy: GenerationStrategy::default(),
+            intent_messages: Vec::new(),
+            summarize_files: false,
+            file_summary_messages: Vec::new(),
+            summary_concurrency: default_summary_concurrency(),
+            refinements: Refinements::default(),
+            max_attempts: default_max_attempts(),
+            deadline_seconds: 0,
+        }
+    }
+}
+
+fn default_max_attempts() -> usize {
+    6
+}
+
+fn default_summary_concurrency() -> usize {
+    4
+}
+
+/// Follow-up instructions sent by the draft shortcut keys.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Refinements {
+    pub shorter: String,
+    pub detailed: String,
+    /// `{{type}}` is replaced with the requested commit type.
+    pub change_type: String,
+    /// `{{language}}` is replaced with the requested language.
+    pub language: String,
+    /// Sent for `r(egenerate)` at the commit confirmation.
+    pub regenerate: String,
+}
+
+impl Default for Refinements {
+    fn default() -> Self {
+        Self {
+            shorter: "Make the commit message shorter. Output only the commit message.".to_string(),
+            detailed: "Make the commit message more detailed. Output only the commit message."
+                .to_string(),
+            change_type: "Use the commit type {{type}}. Output only the commit message."
+                .to_string(),
+            language: "Rewrite the commit message in {{language}}. Output only the commit message."
+                .to_string(),
+            regenerate: "Write a different commit message for the same changes. Output only the \
+                         commit message."
+                .to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum GenerationStrategy {
+    #[default]
+    SinglePass,
+    TwoPass,
+}
+
+/// HTTP limits, all in seconds, and transport settings.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub connect_timeout: u64,
+    pub request_timeout: u64,
+    pub run_deadline: u64,
+    /// Proxy for every request (`http://`, `https://`, `socks5://`, or
+    /// `socks5h://`); empty uses `HTTPS_PROXY` and `ALL_PROXY`.
+    #[serde(default)]
+    pub proxy: String,
+    /// PEM file of CA certificates trusted on top of the system ones, for
+    /// proxies that intercept TLS; empty falls back to `SSL_CERT_FILE`.
+    #[serde(default)]
+    pub ca_bundle: String,
+    /// PEM client certificate for gateways that require mutual TLS.
+    #[serde(default)]
+    pub client_cert: String,
+    /// PEM private key for `client_cert`; empty when the certificate file
+    /// holds the key too.
+    #[serde(default)]
+    pub client_key: String,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: 10,
+            request_timeout: 60,
+            run_deadline: 180,
+            proxy: String::new(),
+            ca_bundle: String::new(),
+            client_cert: String::new(),
+            client_key: String::new(),
+        }
+    }
+}
+
+/// Conventional Commit types and scopes the model may use.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ConventionsConfig {
+    #[serde(default = "default_commit_types")]
+    pub types: Vec<String>,
+    /// Empty means any scope is allowed.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    /// Repository file documenting the commit rules; empty looks for
+    /// `docs/COMMIT_CONVENTION.md` or the commit section of `CONTRIBUTING.md`.
+    #[serde(default)]
+    pub document: String,
+    /// Token budget for the document excerpt; 0 leaves it out.
+    #[serde(default = "default_document_tokens")]
+    pub document_tokens: usize,
+    /// How the commit type inferred from the changed paths is used.
+    #[serde(default)]
+    pub type_heuristics: TypeHeuristics,
+}
+
+#[derive(Deserialize, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TypeHeuristics {
+    Off,
+    /// Suggest the type to the model.
+    #[default]
+    Hint,
+    /// Rewrite the subject to the inferred type.
+    Enforce,
+}
+
+impl Default for ConventionsConfig {
+    fn default() -> Self {
+        Self {
+            types: default_commit_types(),
+            scopes: Vec::new(),
+            document: String::new(),
+            document_tokens: default_document_tokens(),
+            type_heuristics: TypeHeuristics::default(),
+        }
+    }
+}
+
+fn default_document_tokens() -> usize {
+    500
+}
+
+fn default_commit_types() -> Vec<String> {
+    [
+        "feat", "fix", "refactor", "docs", "test", "chore", "perf", "build", "ci", "revert",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Personal data removed from example commits before they are sent.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ExamplesConfig {
+    #[serde(default = "default_true")]
+    pub scrub_emails: bool,
+    #[serde(default = "default_true")]
+    pub scrub_names: bool,
+    #[serde(default = "default_true")]
+    pub scrub_urls: bool,
+}
+
+impl Default for ExamplesConfig {
+    fn default() -> Self {
+        Self {
+            scrub_emails: true,
+            scrub_names: true,
+            scrub_urls: true,
+        }
+    }
+}
+
+/// A prompt A/B test: runs use `messages` instead of the top-level prompt
+/// with probability `split`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ExperimentConfig {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default = "default_experiment_split")]
+    pub split: f64,
+    /// Variant `b`; the experiment is off while this is empty.
+    #[serde(default)]
+    pub messages: Vec<PromptMessage>,
+}
+
+impl Default for ExperimentConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            split: default_experiment_split(),
+            messages: Vec::new(),
+        }
+    }
+}
+
+fn default_experiment_split() -> f64 {
+    0.5
+}
+
+/// Desktop notifications for runs that take long enough to switch away.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct NotifyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Seconds a run must have taken before a notification is sent.
+    #[serde(default = "default_notify_after_seconds")]
+    pub after_seconds: u64,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            after_seconds: default_notify_after_seconds(),
+        }
+    }
+}
+
+fn default_notify_after_seconds() -> u64 {
+    10
+}
+
+/// Safety checks before sparkle commits.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct CommitConfig {
+    /// Branch patterns (`*` matches anything) that need a confirmation or
+    /// `--force` before committing.
+    #[serde(default = "default_protected_branches")]
+    pub protected_branches: Vec<String>,
+}
+
+impl Default for CommitConfig {
+    fn default() -> Self {
+        Self {
+            protected_branches: default_protected_branches(),
+        }
+    }
+}
+
+fn default_protected_branches() -> Vec<String> {
+    ["main", "master", "release/*"].map(str::to_string).to_vec()
+}
+
+/// The opt-in notice about new gh-sparkle releases.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct UpdatesConfig {
+    /// Check for a new release at most once a week after a run.
+    #[serde(default)]
+    pub check: bool,
+}
+
+/// An external program every generated message is piped through.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct PostprocessConfig {
+    /// Shell command whose stdout becomes the message; empty disables it.
+    #[serde(default)]
+    pub command: String,
+}
+
+/// Prompt for `sparkle summary`; `{{changes}}` is the commit list grouped by
+/// area.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct SummaryConfig {
+    #[serde(default)]
+    pub messages: Vec<PromptMessage>,
+}
+
+/// Prompt for `sparkle explain`; `{{changes}}` is the pull request hunk under
+/// review.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ExplainConfig {
+    #[serde(default)]
+    pub messages: Vec<PromptMessage>,
+}
+
+/// Limits for `sparkle watch`, which drafts messages as files are staged.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct WatchConfig {
+    /// Quiet period after the last index change before drafting.
+    #[serde(default = "default_watch_debounce_millis")]
+    pub debounce_millis: u64,
+    /// Minimum seconds between two drafts.
+    #[serde(default = "default_watch_min_interval_seconds")]
+    pub min_interval_seconds: u64,
+    /// Estimated prompt tokens allowed per rolling hour; 0 disables the cap.
+    #[serde(default = "default_watch_max_tokens_per_hour")]
+    pub max_tokens_per_hour: usize,
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            debounce_millis: default_watch_debounce_millis(),
+            min_interval_seconds: default_watch_min_interval_seconds(),
+            max_tokens_per_hour: default_watch_max_tokens_per_hour(),
+        }
+    }
+}
+
+fn default_watch_debounce_millis() -> u64 {
+    1500
+}
+
+fn default_watch_min_interval_seconds() -> u64 {
+    30
+}
+
+fn default_watch_max_tokens_per_hour() -> usize {
+    100_000
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct OutputConfig {
+    #[serde(default = "default_true")]
+    pub verify_language: bool,
+    #[serde(default = "default_sanitizers")]
+    pub sanitizers: Vec<Sanitizer>,
+    /// Extra requests allowed when the model returns an empty or placeholder
+    /// message.
+    #[serde(default = "default_empty_retries")]
+    pub empty_retries: usize,
+    /// Recent commit subjects checked for near-duplicates; `0` disables.
+    #[serde(default = "default_duplicate_lookback")]
+    pub duplicate_lookback: usize,
+    #[serde(default)]
+    pub word_filter: WordFilterConfig,
+    #[serde(default)]
+    pub emoji: EmojiPolicy,
+    #[serde(default)]
+    pub body_sections: BodySectionsConfig,
+}
+
+/// Body sections every message must contain; `--detailed` turns them on.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct BodySectionsConfig {
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default = "default_body_section_names")]
+    pub names: Vec<String>,
+}
+
+impl Default for BodySectionsConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            names: default_body_section_names(),
+        }
+    }
+}
+
+fn default_body_section_names() -> Vec<String> {
+    ["Motivation", "Changes", "Testing"]
+        .map(String::from)
+        .to_vec()
+}
+
+/// Whether commit messages may, must not, or must carry emoji.
+#[derive(Deserialize, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EmojiPolicy {
+    Forbid,
+    #[default]
+    Allow,
+    Require,
+}
+
+/// Words or phrases that must never reach git history.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct WordFilterConfig {
+    #[serde(default)]
+    pub action: FilterAction,
+    #[serde(default)]
+    pub denylist: Vec<String>,
+}
+
+#[derive(Deserialize, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterAction {
+    #[default]
+    Regenerate,
+    Mask,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            verify_language: true,
+            sanitizers: default_sanitizers(),
+            empty_retries: default_empty_retries(),
+            duplicate_lookback: default_duplicate_lookback(),
+            word_filter: WordFilterConfig::default(),
+            emoji: EmojiPolicy::default(),
+            body_sections: BodySectionsConfig::default(),
+        }
+    }
+}
+
+fn default_empty_retries() -> usize {
+    2
+}
+
+fn default_duplicate_lookback() -> usize {
+    10
+}
+
+fn default_sanitizers() -> Vec<Sanitizer> {
+    DEFAULT_SANITIZERS.to_vec()
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct PromptMessage {
     pub role: String,
     pub content: String,
 }
 
 #[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct ContextPolicy {
     pub token_char_ratio: usize,
     pub budgets: ContextBudgets,
     pub sections: Vec<ContextSection>,
+    /// Name staged files that also have unstaged edits, so partial work is
+    /// not described as complete.
+    #[serde(default)]
+    pub include_unstaged_note: bool,
+    /// Shell command that assembles the context instead of `sections`; empty
+    /// uses the sections.
+    #[serde(default)]
+    pub builder_command: String,
+    /// Commands whose output is added as extra sections after `sections`.
+    #[serde(default)]
+    pub commands: Vec<ContextCommand>,
 }
 
 #[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct ContextBudgets {
     pub primary_tokens: usize,
     pub fallback_tokens: usize,
     pub minimal_tokens: usize,
+    /// Sent as `max_tokens` and held back from every budget for the reply.
+    #[serde(default = "default_response_tokens")]
+    pub response_tokens: usize,
+    /// Response budget for `--oneline`, which needs only a subject.
+    #[serde(default = "default_oneline_response_tokens")]
+    pub oneline_response_tokens: usize,
+}
+
+fn default_response_tokens() -> usize {
+    256
+}
+
+fn default_oneline_response_tokens() -> usize {
+    64
 }
 
 #[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
 pub struct ContextSection {
     pub source: ContextSource,
     pub header: String,
@@ -72,17 +748,164 @@ pub struct ContextSection {
     pub required: bool,
 }
 
+/// A section filled with the stdout of a shell command run before
+/// generation, such as `cargo check` or a ticket fetcher.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ContextCommand {
+    pub name: String,
+    pub command: String,
+    pub header: String,
+    pub max_ratio: f64,
+    /// Seconds the command may run before it is stopped and left out.
+    #[serde(default = "default_context_command_timeout")]
+    pub timeout: u64,
+}
+
+fn default_context_command_timeout() -> u64 {
+    10
+}
+
 #[derive(Deserialize, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum ContextSource {
     Summary,
     Diff,
+    /// First paragraphs of READMEs and module docs near the changed files.
+    Docs,
+    /// Title and body of the issue from `--closes` or the branch name.
+    Issue,
+}
+
+impl ContextSource {
+    /// The name used in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Summary => "summary",
+            Self::Diff => "diff",
+            Self::Docs => "docs",
+            Self::Issue => "issue",
+        }
+    }
 }
 
-pub fn load_prompt_config() -> Result<PromptConfig, Box<dyn Error>> {
+/// The embedded prompt config as a YAML value, used as the base layer.
+pub fn default_config_value() -> Result<serde_yaml::Value, Box<dyn Error>> {
     Ok(serde_yaml::from_str(COMMITMSG_PROMPT_YAML)?)
 }
 
+pub fn validate_prompt_config(config: &PromptConfig) -> Result<(), Box<dyn Error>> {
+    validate_model_parameters(&config.model_parameters)?;
+    validate_context_policy(&config.context_policy)?;
+    validate_generation(&config.generation)?;
+    validate_network(&config.network)?;
+    validate_output(&config.output)?;
+    validate_conventions(&config.conventions)?;
+    validate_experiment(&config.experiment)?;
+    Ok(())
+}
+
+pub fn validate_experiment(experiment: &ExperimentConfig) -> Result<(), Box<dyn Error>> {
+    if !(0.0..=1.0).contains(&experiment.split) {
+        return Err("experiment.split must be between 0 and 1".into());
+    }
+    if !experiment.messages.is_empty() && experiment.name.trim().is_empty() {
+        return Err("experiment.name is required when experiment.messages is set".into());
+    }
+    Ok(())
+}
+
+pub fn validate_model_parameters(params: &ModelParameters) -> Result<(), Box<dyn Error>> {
+    if params.stop.len() > MAX_STOP_SEQUENCES {
+        return Err(format!(
+            "modelParameters.stop must contain at most {MAX_STOP_SEQUENCES} sequences"
+        )
+        .into());
+    }
+    if params.stop.iter().any(|sequence| sequence.is_empty()) {
+        return Err("modelParameters.stop entries must not be empty".into());
+    }
+    if params
+        .retry_temperatures
+        .iter()
+        .any(|temperature| !(0.0..=2.0).contains(temperature))
+    {
+        return Err("modelParameters.retryTemperatures must be between 0 and 2".into());
+    }
+
+    Ok(())
+}
+
+pub fn validate_generation(config: &GenerationConfig) -> Result<(), Box<dyn Error>> {
+    if config.strategy == GenerationStrategy::TwoPass && config.intent_messages.is_empty() {
+        return Err("generation.intentMessages must not be empty for two-pass strategy".into());
+    }
+    if config.summarize_files && config.file_summary_messages.is_empty() {
+        return Err(
+            "generation.fileSummaryMessages must not be empty when summarizeFiles is set".into(),
+        );
+    }
+    if config.summary_concurrency == 0 {
+        return Err("generation.summaryConcurrency must be at least 1".into());
+    }
+
+    Ok(())
+}
+
+pub fn validate_conventions(config: &ConventionsConfig) -> Result<(), Box<dyn Error>> {
+    if config.types.is_empty() {
+        return Err("conventions.types must not be empty".into());
+    }
+    let is_valid = |value: &String| {
+        !value.is_empty()
+            && value
+                .chars()
+                .all(|ch| ch.is_alphanumeric() || ch == '-' || ch == '_' || ch == '/')
+    };
+    if !config.types.iter().all(is_valid) || !config.scopes.iter().all(is_valid) {
+        return Err(
+            "conventions.types and conventions.scopes entries must be non-empty words".into(),
+        );
+    }
+    let document = std::path::Path::new(&config.document);
+    if document.has_root()
+        || document
+            .components()
+            .any(|part| !matches!(part, std::path::Component::Normal(_)))
+    {
+        return Err("conventions.document must be a relative path inside the repository".into());
+    }
+
+    Ok(())
+}
+
+pub fn validate_network(network: &NetworkConfig) -> Result<(), Box<dyn Error>> {
+    if network.connect_timeout == 0 || network.request_timeout == 0 || network.run_deadline == 0 {
+        return Err("network timeouts must be greater than 0".into());
+    }
+    if network.connect_timeout > network.request_timeout {
+        return Err("network.connectTimeout must not exceed network.requestTimeout".into());
+    }
+    if network.client_cert.is_empty() && !network.client_key.is_empty() {
+        return Err("network.clientKey requires network.clientCert".into());
+    }
+
+    Ok(())
+}
+
+pub fn validate_output(output: &OutputConfig) -> Result<(), Box<dyn Error>> {
+    if output
+        .word_filter
+        .denylist
+        .iter()
+        .any(|pattern| pattern.trim().is_empty())
+    {
+        return Err("output.wordFilter.denylist must not contain empty entries".into());
+    }
+
+    Ok(())
+}
+
 pub fn validate_context_policy(policy: &ContextPolicy) -> Result<(), Box<dyn Error>> {
     if policy.token_char_ratio == 0 {
         return Err("contextPolicy.tokenCharRatio must be greater than 0".into());
@@ -90,6 +913,7 @@ pub fn validate_context_policy(policy: &ContextPolicy) -> Result<(), Box<dyn Err
     if policy.budgets.primary_tokens == 0
         || policy.budgets.fallback_tokens == 0
         || policy.budgets.minimal_tokens == 0
+        || policy.budgets.response_tokens == 0
     {
         return Err("contextPolicy.budgets must be greater than 0".into());
     }
@@ -111,6 +935,32 @@ pub fn validate_context_policy(policy: &ContextPolicy) -> Result<(), Box<dyn Err
             required_count += 1;
         }
     }
+    for (index, command) in policy.commands.iter().enumerate() {
+        if command.name.trim().is_empty() || command.command.trim().is_empty() {
+            return Err(
+                format!("contextPolicy.commands[{index}] needs a name and a command").into(),
+            );
+        }
+        if policy.commands[..index]
+            .iter()
+            .any(|other| other.name == command.name)
+        {
+            return Err(format!(
+                "contextPolicy.commands has two entries named {}",
+                command.name
+            )
+            .into());
+        }
+        if command.header.is_empty() {
+            return Err("contextPolicy.commands.header must not be empty".into());
+        }
+        if !(0.0 < command.max_ratio && command.max_ratio <= 1.0) {
+            return Err("contextPolicy.commands.maxRatio must be within (0, 1]".into());
+        }
+        if command.timeout == 0 {
+            return Err("contextPolicy.commands.timeout must be greater than 0".into());
+        }
+    }
 
     if ratio_sum > 1.0 + f64::EPSILON {
         return Err("contextPolicy.sections.maxRatio sum must be <= 1.0".into());
@@ -121,3 +971,40 @@ pub fn validate_context_policy(policy: &ContextPolicy) -> Result<(), Box<dyn Err
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conventions_document_must_stay_in_the_repository() {
+        let with_document = |document: &str| ConventionsConfig {
+            document: document.to_string(),
+            ..Default::default()
+        };
+        assert!(validate_conventions(&with_document("")).is_ok());
+        assert!(validate_conventions(&with_document("docs/RULES.md")).is_ok());
+        assert!(validate_conventions(&with_document("../RULES.md")).is_err());
+        assert!(validate_conventions(&with_document("/home/me/.ssh/id_ed25519")).is_err());
+    }
+
+    #[test]
+    fn retry_temperatures_follow_the_schedule_and_repeat_the_last() {
+        let params = ModelParameters {
+            retry_temperatures: vec![0.5, 0.9],
+            ..Default::default()
+        };
+        assert_eq!(params.retry_temperature(0), None);
+        assert_eq!(params.retry_temperature(1), Some(0.5));
+        assert_eq!(params.retry_temperature(2), Some(0.9));
+        assert_eq!(params.retry_temperature(5), Some(0.9));
+        assert_eq!(ModelParameters::default().retry_temperature(1), None);
+
+        assert!(validate_model_parameters(&params).is_ok());
+        let out_of_range = ModelParameters {
+            retry_temperatures: vec![0.5, 2.5],
+            ..Default::default()
+        };
+        assert!(validate_model_parameters(&out_of_range).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+// Rate-limit quota reported by GitHub Models, remembered between runs.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::auth;
+
+const QUOTA_FILE: &str = "quota.json";
+/// How long a quota without a reset time is trusted; the free tiers are
+/// daily.
+const DEFAULT_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+/// Requests left for one model.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    pub remaining: u64,
+    #[serde(default)]
+    pub limit: Option<u64>,
+    /// Unix seconds at which the quota refills.
+    #[serde(default)]
+    pub reset_at: Option<u64>,
+    /// Unix seconds at which the headers were read.
+    pub seen_at: u64,
+}
+
+impl Quota {
+    /// Reads `x-ratelimit-remaining[-requests]`, `x-ratelimit-limit[-requests]`,
+    /// and `x-ratelimit-reset[-requests]` through `header`.
+    pub fn from_headers(header: impl Fn(&str) -> Option<String>, now: u64) -> Option<Self> {
+        let read = |name: &str| {
+            header(&format!("x-ratelimit-{name}-requests"))
+                .or_else(|| header(&format!("x-ratelimit-{name}")))
+        };
+        let remaining = read("remaining")?.trim().parse().ok()?;
+        Some(Self {
+            remaining,
+            limit: read("limit").and_then(|limit| limit.trim().parse().ok()),
+            reset_at: read("reset").and_then(|reset| parse_reset(&reset, now)),
+            seen_at: now,
+        })
+    }
+
+    /// Whether the numbers still describe the current window.
+    fn is_current(&self, now: u64) -> bool {
+        match self.reset_at {
+            Some(reset_at) => now < reset_at,
+            None => now < self.seen_at + DEFAULT_WINDOW_SECS,
+        }
+    }
+
+    /// Renders e.g. `142/150 requests left, resets in 3h 10m`.
+    pub fn describe(&self, now: u64) -> String {
+        let mut text = match self.limit {
+            Some(limit) => format!("{}/{limit} requests left", self.remaining),
+            None => format!("{} requests left", self.remaining),
+        };
+        if let Some(reset_at) = self.reset_at {
+            let secs = reset_at.saturating_sub(now);
+            text.push_str(&format!(
+                ", resets in {}h {}m",
+                secs / 3600,
+                secs % 3600 / 60
+            ));
+        }
+        text
+    }
+}
+
+/// Accepts Unix seconds, seconds from now, or durations such as `1h2m3s`.
+fn parse_reset(value: &str, now: u64) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        // Anything past 2001 is an absolute timestamp.
+        return Some(if secs > 1_000_000_000 {
+            secs
+        } else {
+            now + secs
+        });
+    }
+    let mut total = 0.0;
+    let mut number = String::new();
+    for ch in value.chars() {
+        if ch.is_ascii_digit() || ch == '.' {
+            number.push(ch);
+            continue;
+        }
+        let amount: f64 = number.parse().ok()?;
+        number.clear();
+        total += amount
+            * match ch {
+                'h' => 3600.0,
+                'm' => 60.0,
+                's' => 1.0,
+                _ => return None,
+            };
+    }
+    number.is_empty().then_some(now + total as u64)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// Quotas seen in earlier runs, by model.
+pub fn load() -> HashMap<String, Quota> {
+    auth::cache_dir()
+        .and_then(|dir| fs::read_to_string(dir.join(QUOTA_FILE)).ok())
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+/// Remembers the latest quota of `model`. Failures are ignored; the next
+/// response will report it again.
+pub fn remember(model: &str, quota: &Quota) {
+    let Some(dir) = auth::cache_dir() else {
+        return;
+    };
+    let mut quotas = load();
+    quotas.insert(model.to_string(), quota.clone());
+    if let Ok(text) = serde_json::to_string(&quotas) {
+        let _ = fs::create_dir_all(&dir);
+        let _ = fs::write(dir.join(QUOTA_FILE), text);
+    }
+}
+
+/// Moves models with at most `reserve` requests left to the end of the
+/// chain, keeping the order otherwise. Returns the chain and the models that
+/// were moved.
+pub fn prefer_available(
+    chain: Vec<String>,
+    quotas: &HashMap<String, Quota>,
+    reserve: u64,
+    now: u64,
+) -> (Vec<String>, Vec<String>) {
+    let (exhausted, available): (Vec<String>, Vec<String>) = chain.into_iter().partition(|model| {
+        quotas
+            .get(model)
+            .is_some_and(|quota| quota.is_current(now) && quota.remaining <= reserve)
+    });
+    let mut ordered = available;
+    ordered.extend(exhausted.iter().cloned());
+    (ordered, exhausted)
+}
+
+/// Unix seconds at which a request can be made again when every model in
+/// `chain` has used up its window; `None` while one still has requests left
+/// or an unknown reset time.
+pub fn next_refill(chain: &[String], quotas: &HashMap<String, Quota>, now: u64) -> Option<u64> {
+    chain
+        .iter()
+        .map(|model| {
+            let quota = quotas
+                .get(model)
+                .filter(|quota| quota.is_current(now) && quota.remaining == 0)?;
+            quota.reset_at
+        })
+        .collect::<Option<Vec<u64>>>()?
+        .into_iter()
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_headers_reads_github_models_headers() {
+        let headers: HashMap<&str, &str> = [
+            ("x-ratelimit-remaining-requests", "12"),
+            ("x-ratelimit-limit-requests", "150"),
+            ("x-ratelimit-reset-requests", "2h30m0s"),
+        ]
+        .into_iter()
+        .collect();
+        let quota = Quota::from_headers(
+            |name| headers.get(name).map(|value| value.to_string()),
+            1000,
+        )
+        .unwrap();
+        assert_eq!(quota.remaining, 12);
+        assert_eq!(quota.reset_at, Some(1000 + 9000));
+        assert_eq!(
+            quota.describe(1000),
+            "12/150 requests left, resets in 2h 30m"
+        );
+        assert_eq!(parse_reset("1700000000", 5), Some(1_700_000_000));
+        assert_eq!(parse_reset("60", 5), Some(65));
+        assert_eq!(parse_reset("soon", 5), None);
+    }
+
+    #[test]
+    fn next_refill_waits_only_when_every_model_is_exhausted() {
+        let quota = |remaining, reset_at| Quota {
+            remaining,
+            limit: None,
+            reset_at: Some(reset_at),
+            seen_at: 100,
+        };
+        let chain = vec!["a".to_string(), "b".to_string()];
+        let mut quotas = HashMap::from([("a".to_string(), quota(0, 400))]);
+        assert_eq!(next_refill(&chain, &quotas, 200), None);
+        quotas.insert("b".to_string(), quota(0, 300));
+        assert_eq!(next_refill(&chain, &quotas, 200), Some(300));
+        quotas.insert("b".to_string(), quota(3, 300));
+        assert_eq!(next_refill(&chain, &quotas, 200), None);
+    }
+
+    #[test]
+    fn prefer_available_demotes_nearly_exhausted_models() {
+        let quota = |remaining, reset_at| Quota {
+            remaining,
+            limit: None,
+            reset_at: Some(reset_at),
+            seen_at: 0,
+        };
+        let quotas: HashMap<String, Quota> = [
+            ("a".to_string(), quota(2, 500)),
+            ("b".to_string(), quota(100, 500)),
+            ("c".to_string(), quota(0, 50)),
+        ]
+        .into_iter()
+        .collect();
+        let chain = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let (ordered, moved) = prefer_available(chain, &quotas, 5, 100);
+        assert_eq!(ordered, ["b", "c", "a"]);
+        assert_eq!(moved, ["a"]);
+    }
+}
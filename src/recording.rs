@@ -0,0 +1,118 @@
+// Recording and replay of model exchanges for bug reports and tests.
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// One request and the raw reply the API gave for it.
+#[derive(Serialize, Deserialize)]
+pub struct Exchange {
+    /// Request body as sent; the token travels in a header and is never
+    /// written.
+    pub request: serde_json::Value,
+    pub status: u16,
+    pub body: String,
+}
+
+enum Mode {
+    Record,
+    Replay,
+}
+
+/// Writes exchanges to, or serves them back from, a directory of numbered
+/// JSON files (`0001.json`, `0002.json`, ...), in request order.
+pub struct Recording {
+    mode: Mode,
+    dir: PathBuf,
+    next: AtomicUsize,
+}
+
+impl Recording {
+    pub fn record(dir: PathBuf) -> Result<Self, Box<dyn Error>> {
+        fs::create_dir_all(&dir).map_err(|err| {
+            format!(
+                "could not create recording directory {}: {err}",
+                dir.display()
+            )
+        })?;
+        Ok(Self {
+            mode: Mode::Record,
+            dir,
+            next: AtomicUsize::new(1),
+        })
+    }
+
+    pub fn replay(dir: PathBuf) -> Result<Self, Box<dyn Error>> {
+        if !dir.is_dir() {
+            return Err(format!("recording directory {} does not exist", dir.display()).into());
+        }
+        Ok(Self {
+            mode: Mode::Replay,
+            dir,
+            next: AtomicUsize::new(1),
+        })
+    }
+
+    pub fn is_replay(&self) -> bool {
+        matches!(self.mode, Mode::Replay)
+    }
+
+    /// Stores an exchange when recording; a no-op when replaying.
+    pub fn save(&self, exchange: &Exchange) -> Result<(), Box<dyn Error>> {
+        if self.is_replay() {
+            return Ok(());
+        }
+        let path = self.next_path();
+        fs::write(&path, serde_json::to_string_pretty(exchange)?)
+            .map_err(|err| format!("could not write {}: {err}", path.display()))?;
+        Ok(())
+    }
+
+    /// Returns the next recorded exchange.
+    pub fn next_exchange(&self) -> Result<Exchange, Box<dyn Error>> {
+        let path = self.next_path();
+        let text = fs::read_to_string(&path).map_err(|_| {
+            format!(
+                "recording in {} has no response for request #{}",
+                self.dir.display(),
+                path.file_stem().unwrap_or_default().to_string_lossy()
+            )
+        })?;
+        Ok(serde_json::from_str(&text)
+            .map_err(|err| format!("invalid recording {}: {err}", path.display()))?)
+    }
+
+    fn next_path(&self) -> PathBuf {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        self.dir.join(format!("{index:04}.json"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_serves_recorded_exchanges_in_order() {
+        let dir =
+            std::env::temp_dir().join(format!("sparkle-recording-test-{}", std::process::id()));
+        let recording = Recording::record(dir.clone()).unwrap();
+        for (status, body) in [(429, "rate limited"), (200, "{\"choices\":[]}")] {
+            recording
+                .save(&Exchange {
+                    request: serde_json::json!({ "model": "openai/gpt-4o-mini" }),
+                    status,
+                    body: body.to_string(),
+                })
+                .unwrap();
+        }
+
+        let replay = Recording::replay(dir.clone()).unwrap();
+        assert_eq!(replay.next_exchange().unwrap().status, 429);
+        assert_eq!(replay.next_exchange().unwrap().body, "{\"choices\":[]}");
+        assert!(replay.next_exchange().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
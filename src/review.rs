@@ -0,0 +1,113 @@
+// Pull request diff hunks for `sparkle explain` review replies.
+
+use std::error::Error;
+use std::process::Command;
+
+use crate::failure::{Classify, Failure, FailureKind};
+use crate::summaries;
+use crate::{Cli, complete_with_chain, llm, load_prompt_config, parse_language};
+
+/// Fetches the unified diff of pull request `number` via the GitHub API.
+pub fn fetch_pr_diff(number: u64) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("gh")
+        .args([
+            "api",
+            "-H",
+            "Accept: application/vnd.github.diff",
+            &format!("repos/{{owner}}/{{repo}}/pulls/{number}"),
+        ])
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "fetching the diff of PR #{number} failed: {}",
+            stderr.trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// The hunk of `diff` that covers `line` of the new version of `path`,
+/// starting at its `@@` line.
+pub fn hunk_at<'a>(diff: &'a str, path: &str, line: usize) -> Option<&'a str> {
+    let file = summaries::split(diff)
+        .into_iter()
+        .find(|file| file.path == path)?;
+    let text = file.text;
+
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    for diff_line in text.split_inclusive('\n') {
+        if diff_line.starts_with("@@ ") {
+            starts.push(offset);
+        }
+        offset += diff_line.len();
+    }
+    starts.iter().enumerate().find_map(|(index, &start)| {
+        let end = starts.get(index + 1).copied().unwrap_or(text.len());
+        let hunk = &text[start..end];
+        let (first, count) = new_range(hunk.lines().next()?)?;
+        (first <= line && line < first + count.max(1)).then_some(hunk)
+    })
+}
+
+/// The new-file range of a hunk header such as `@@ -10,4 +12,6 @@ fn main`.
+fn new_range(header: &str) -> Option<(usize, usize)> {
+    let range = header
+        .split_whitespace()
+        .find_map(|part| part.strip_prefix('+'))?;
+    match range.split_once(',') {
+        Some((first, count)) => Some((first.parse().ok()?, count.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+pub fn run(cli: &Cli, pr: u64, path: &str, line: usize) -> Result<(), Box<dyn Error>> {
+    let diff = fetch_pr_diff(pr).classify(FailureKind::Api)?;
+    let hunk = hunk_at(&diff, path, line).ok_or_else(|| {
+        Failure::new(
+            FailureKind::Git,
+            format!("line {line} of {path} is not part of the diff of PR #{pr}"),
+        )
+    })?;
+    if cli.no_llm {
+        print!("{hunk}");
+        return Ok(());
+    }
+
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    if prompt_config.explain.messages.is_empty() {
+        return Err(Failure::new(FailureKind::Config, "explain.messages is empty").into());
+    }
+    let language_spec = parse_language(&cli.language)?;
+    let context = format!("{path}, line {line}:\n{hunk}");
+    let prompt = llm::Prompt::explain(&prompt_config, &context, &language_spec.describe());
+    println!("{}", complete_with_chain(cli, &prompt_config, &prompt)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hunk_at_finds_the_hunk_covering_a_new_line() {
+        let diff = "diff --git a/src/a.rs b/src/a.rs\nindex 1111111..2222222 100644\n\
+                    --- a/src/a.rs\n+++ b/src/a.rs\n@@ -1,2 +1,2 @@\n-a\n+b\n c\n\
+                    @@ -10,2 +10,3 @@ fn run()\n x\n+y\n z\n\
+                    diff --git a/b.rs b/b.rs\n@@ -0,0 +1 @@\n+new\n";
+        assert_eq!(
+            hunk_at(diff, "src/a.rs", 2),
+            Some("@@ -1,2 +1,2 @@\n-a\n+b\n c\n")
+        );
+        assert_eq!(
+            hunk_at(diff, "src/a.rs", 11),
+            Some("@@ -10,2 +10,3 @@ fn run()\n x\n+y\n z\n")
+        );
+        assert_eq!(hunk_at(diff, "src/a.rs", 5), None);
+        assert_eq!(hunk_at(diff, "b.rs", 1), Some("@@ -0,0 +1 @@\n+new\n"));
+        assert_eq!(hunk_at(diff, "c.rs", 1), None);
+    }
+}
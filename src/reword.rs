@@ -0,0 +1,241 @@
+// Bulk rewording of low-quality commit messages on a branch.
+
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::failure::{Classify, Failure, FailureKind};
+use crate::git::BranchCommit;
+use crate::{
+    Cli, GenerationContext, ask_user, convention_document, git, load_prompt_config, new_llm_client,
+    parse_language, produce_commit_message, project_docs, resolve_model_chain,
+};
+
+const BAD_SUBJECTS: [&str; 19] = [
+    "wip", "fix", "fixes", "fixed", "asdf", "tmp", "temp", "test", "tests", "update", "updates",
+    "changes", "misc", "stuff", "minor", "cleanup", "typo", "oops", "commit",
+];
+const MIN_SUBJECT_CHARS: usize = 8;
+const TABLE_COLUMN_CHARS: usize = 40;
+
+/// A commit whose message will be replaced.
+pub struct Reword {
+    pub sha: String,
+    pub old_subject: String,
+    pub new_message: String,
+}
+
+/// Whether `subject` is too vague to keep, e.g. `wip`, `fix`, or `asdf`.
+///
+/// `fixup!` and `squash!` commits are left for `git rebase --autosquash`.
+pub fn is_bad_message(subject: &str) -> bool {
+    let lowered = subject.trim().to_lowercase();
+    if ["fixup!", "squash!", "amend!"]
+        .iter()
+        .any(|prefix| lowered.starts_with(prefix))
+    {
+        return false;
+    }
+
+    let words = lowered.trim_end_matches(['.', '!']).trim();
+    words.chars().count() < MIN_SUBJECT_CHARS
+        || BAD_SUBJECTS.contains(&words)
+        || words.starts_with("wip ")
+        || words.starts_with("wip:")
+        || !words.chars().any(char::is_alphabetic)
+}
+
+/// Renders a preview of old and new subjects.
+pub fn format_table(rewords: &[Reword]) -> String {
+    let mut table = format!(
+        "{:<8} {:<width$} NEW SUBJECT\n",
+        "COMMIT",
+        "OLD SUBJECT",
+        width = TABLE_COLUMN_CHARS
+    );
+    for reword in rewords {
+        let new_subject = reword.new_message.lines().next().unwrap_or_default();
+        table.push_str(&format!(
+            "{:<8} {:<width$} {new_subject}\n",
+            &reword.sha[..reword.sha.len().min(7)],
+            ellipsize(&reword.old_subject, TABLE_COLUMN_CHARS),
+            width = TABLE_COLUMN_CHARS
+        ));
+    }
+    table
+}
+
+fn ellipsize(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max_chars - 1).collect();
+    format!("{kept}…")
+}
+
+/// Writes the new messages into `dir` and returns the rebase todo list that
+/// picks every commit and amends the reworded ones.
+pub fn write_rebase_todo(
+    dir: &Path,
+    commits: &[BranchCommit],
+    rewords: &[Reword],
+) -> Result<String, Box<dyn Error>> {
+    let mut todo = String::new();
+    for commit in commits {
+        todo.push_str(&format!("pick {} {}\n", commit.sha, commit.subject));
+        if let Some(reword) = rewords.iter().find(|reword| reword.sha == commit.sha) {
+            let path = dir.join(format!("{}.msg", commit.sha));
+            fs::write(&path, &reword.new_message)?;
+            todo.push_str(&format!(
+                "exec git commit --amend --no-verify --quiet -F \"{}\"\n",
+                path.display()
+            ));
+        }
+    }
+    Ok(todo)
+}
+
+/// Regenerates vague commit messages on the current branch and rewrites
+/// them with an interactive rebase after showing a preview.
+pub fn run(cli: &Cli, base: Option<String>, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    let language_spec = parse_language(&cli.language)?;
+    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)
+        .classify(FailureKind::Config)?;
+
+    let base = match base {
+        Some(base) => base,
+        None => git::default_base().classify(FailureKind::Git)?,
+    };
+    let fork_point = git::merge_base(&base).classify(FailureKind::Git)?;
+    let commits = git::branch_commits(&fork_point).classify(FailureKind::Git)?;
+    let candidates: Vec<&git::BranchCommit> = commits
+        .iter()
+        .filter(|commit| is_bad_message(&commit.subject))
+        .collect();
+    if candidates.is_empty() {
+        println!(
+            "  All {} commit(s) since {base} have descriptive messages.",
+            commits.len()
+        );
+        return Ok(());
+    }
+
+    let llm_client = new_llm_client(cli, &prompt_config.network).classify(FailureKind::Auth)?;
+    let mut rewords = Vec::with_capacity(candidates.len());
+    let convention = convention_document(&prompt_config);
+    for commit in candidates {
+        println!("  Describing {} ({})...", &commit.sha[..7], commit.subject);
+        let diff = git::commit_diff(&commit.sha).classify(FailureKind::Git)?;
+        let docs = project_docs(&prompt_config, &diff.patch);
+        let context = GenerationContext {
+            docs: &docs,
+            convention: &convention,
+            ..GenerationContext::new(
+                &prompt_config,
+                &diff.summary,
+                &diff.patch,
+                &model_chain,
+                &language_spec,
+            )
+        };
+        let new_message = produce_commit_message(
+            &llm_client,
+            &context,
+            |message| println!("  {message}"),
+            |_| false,
+        )?
+        .message;
+        rewords.push(Reword {
+            sha: commit.sha.clone(),
+            old_subject: commit.subject.clone(),
+            new_message,
+        });
+    }
+
+    println!();
+    print!("{}", format_table(&rewords));
+    println!();
+    if dry_run {
+        return Ok(());
+    }
+    if !ask_user(&format!(
+        "Rewrite {} commit message(s) with git rebase onto {}?",
+        rewords.len(),
+        &fork_point[..7]
+    )) {
+        return Err(Failure::new(FailureKind::Aborted, "no commits were rewritten").into());
+    }
+
+    let dir = std::env::temp_dir().join(format!("sparkle-reword-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let result = write_rebase_todo(&dir, &commits, &rewords).and_then(|todo| {
+        let todo_path = dir.join("git-rebase-todo");
+        std::fs::write(&todo_path, todo)?;
+        git::rebase_with_todo(&fork_point, &todo_path)
+    });
+    let _ = std::fs::remove_dir_all(&dir);
+    result.classify(FailureKind::Git)?;
+
+    println!("  Rewrote {} commit message(s).", rewords.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_bad_message_flags_vague_subjects() {
+        for subject in [
+            "wip",
+            "fix",
+            "asdf",
+            "Update.",
+            "WIP: cache",
+            "...",
+            "fix it",
+        ] {
+            assert!(is_bad_message(subject), "{subject}");
+        }
+        for subject in ["fix(auth): refresh stale tokens", "fixup! feat: add cache"] {
+            assert!(!is_bad_message(subject), "{subject}");
+        }
+    }
+
+    #[test]
+    fn write_rebase_todo_amends_only_reworded_commits() {
+        let dir = std::env::temp_dir().join(format!("sparkle-reword-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let commits = vec![
+            BranchCommit {
+                sha: "aaa".to_string(),
+                subject: "feat: add cache".to_string(),
+            },
+            BranchCommit {
+                sha: "bbb".to_string(),
+                subject: "wip".to_string(),
+            },
+        ];
+        let rewords = vec![Reword {
+            sha: "bbb".to_string(),
+            old_subject: "wip".to_string(),
+            new_message: "fix: expire cache entries\n".to_string(),
+        }];
+
+        let todo = write_rebase_todo(&dir, &commits, &rewords).unwrap();
+        let message_path = dir.join("bbb.msg");
+        assert_eq!(
+            todo,
+            format!(
+                "pick aaa feat: add cache\npick bbb wip\nexec git commit --amend --no-verify --quiet -F \"{}\"\n",
+                message_path.display()
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(&message_path).unwrap(),
+            "fix: expire cache entries\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
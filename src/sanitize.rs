@@ -0,0 +1,398 @@
+// Deterministic cleanup of model output before it becomes a commit message.
+
+use serde::Deserialize;
+
+/// A named transform; `output.sanitizers` lists them in the order they run.
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Sanitizer {
+    StripReasoning,
+    StripPreamble,
+    StripCodeFences,
+    StripMarkdownHeaders,
+    StripLeadingLabels,
+    StripQuotes,
+    CollapseBlankLines,
+    SubjectOnly,
+}
+
+pub const DEFAULT_SANITIZERS: [Sanitizer; 7] = [
+    Sanitizer::StripReasoning,
+    Sanitizer::StripPreamble,
+    Sanitizer::StripCodeFences,
+    Sanitizer::StripMarkdownHeaders,
+    Sanitizer::StripLeadingLabels,
+    Sanitizer::StripQuotes,
+    Sanitizer::CollapseBlankLines,
+];
+
+const LEADING_LABELS: [&str; 6] = [
+    "here is the commit message",
+    "suggested commit message",
+    "commit message",
+    "commit",
+    "message",
+    "subject",
+];
+
+const REASONING_TAGS: [&str; 4] = ["think", "thinking", "reasoning", "reflection"];
+
+const PREAMBLE_OPENERS: [&str; 9] = [
+    "sure",
+    "certainly",
+    "of course",
+    "okay",
+    "ok,",
+    "here is",
+    "here's",
+    "below is",
+    "based on",
+];
+
+const EPILOGUE_OPENERS: [&str; 6] = [
+    "let me know",
+    "i hope",
+    "hope this",
+    "feel free",
+    "this commit message",
+    "this message",
+];
+
+const PLACEHOLDERS: [&str; 7] = [
+    "todo",
+    "tbd",
+    "n/a",
+    "commit message",
+    "<commit message>",
+    "[commit message]",
+    "<type>(<scope>): <subject>",
+];
+
+impl Sanitizer {
+    pub fn apply(self, message: &str) -> String {
+        match self {
+            Sanitizer::StripReasoning => strip_reasoning(message),
+            Sanitizer::StripPreamble => strip_preamble(message),
+            Sanitizer::StripCodeFences => strip_code_fences(message),
+            Sanitizer::StripMarkdownHeaders => strip_markdown_headers(message),
+            Sanitizer::StripLeadingLabels => strip_leading_labels(message),
+            Sanitizer::StripQuotes => strip_quotes(message),
+            Sanitizer::CollapseBlankLines => collapse_blank_lines(message),
+            Sanitizer::SubjectOnly => subject_only(message),
+        }
+    }
+}
+
+/// Runs `sanitizers` in order and trims the result.
+pub fn sanitize(message: &str, sanitizers: &[Sanitizer]) -> String {
+    let mut sanitized = message.trim().to_string();
+    for sanitizer in sanitizers {
+        sanitized = sanitizer.apply(&sanitized).trim().to_string();
+    }
+    sanitized
+}
+
+/// Whether a sanitized message is empty or an obvious template placeholder.
+pub fn is_placeholder(message: &str) -> bool {
+    let subject = message.lines().next().unwrap_or("").trim();
+    let lowered = subject
+        .trim_end_matches(['.', '!'])
+        .trim()
+        .to_ascii_lowercase();
+    lowered.is_empty()
+        || PLACEHOLDERS.contains(&lowered.as_str())
+        || lowered.contains("your commit message")
+        || lowered.contains("commit message here")
+}
+
+/// Removes `<think>…</think>` style blocks, including an unterminated one
+/// cut off by the token limit.
+fn strip_reasoning(message: &str) -> String {
+    let mut text = message.to_string();
+    for tag in REASONING_TAGS {
+        let open = format!("<{tag}>");
+        let close = format!("</{tag}>");
+        while let Some(start) = find_ignore_case(&text, &open) {
+            let end = match find_ignore_case(&text[start..], &close) {
+                Some(offset) => start + offset + close.len(),
+                None => text.len(),
+            };
+            text.replace_range(start..end, "");
+        }
+        // A closing tag without an opener means the reasoning started before
+        // the visible output, as some providers strip the opening tag.
+        if let Some(end) = find_ignore_case(&text, &close) {
+            text.replace_range(..end + close.len(), "");
+        }
+    }
+    text
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .to_ascii_lowercase()
+        .find(&needle.to_ascii_lowercase())
+}
+
+/// Drops chatty lead-in lines ("Sure! Here's a commit message:") and
+/// sign-offs ("Let me know if you need changes.").
+fn strip_preamble(message: &str) -> String {
+    let mut lines: Vec<&str> = message.lines().collect();
+    while let Some(first) = lines.iter().position(|line| !line.trim().is_empty()) {
+        if !is_preamble(lines[first]) {
+            lines.drain(..first);
+            break;
+        }
+        lines.drain(..=first);
+    }
+    while let Some(last) = lines.iter().rposition(|line| !line.trim().is_empty()) {
+        // Keep a lone subject line even if it happens to look like a sign-off.
+        if last == 0 || !starts_with_any(lines[last], &EPILOGUE_OPENERS) {
+            lines.truncate(last + 1);
+            break;
+        }
+        lines.truncate(last);
+    }
+    lines.join("\n")
+}
+
+fn is_preamble(line: &str) -> bool {
+    let line = line.trim().trim_matches('*').trim();
+    starts_with_any(line, &PREAMBLE_OPENERS) && (line.ends_with(':') || line.ends_with('!'))
+}
+
+fn starts_with_any(line: &str, openers: &[&str]) -> bool {
+    let lowered = line.trim().to_lowercase();
+    openers.iter().any(|opener| lowered.starts_with(opener))
+}
+
+fn strip_code_fences(message: &str) -> String {
+    let mut lines = Vec::new();
+    for line in message.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            let rest = trimmed.trim_start_matches("```").trim_start();
+            if !rest.is_empty() && !is_fence_language(rest) {
+                lines.push(rest);
+            }
+            continue;
+        }
+        lines.push(line);
+    }
+
+    let mut sanitized = lines.join("\n").trim().to_string();
+    if sanitized.starts_with("```") {
+        sanitized = sanitized.trim_start_matches("```").trim_start().to_string();
+    }
+    if sanitized.ends_with("```") {
+        sanitized = sanitized.trim_end_matches("```").trim_end().to_string();
+    }
+
+    sanitized
+}
+
+fn is_fence_language(tag: &str) -> bool {
+    tag.chars()
+        .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+}
+
+/// Turns `## Title` into `Title`; lone `#` comment lines are left alone.
+fn strip_markdown_headers(message: &str) -> String {
+    message
+        .lines()
+        .map(|line| {
+            let hashes = line.len() - line.trim_start_matches('#').len();
+            match line[hashes..].strip_prefix(' ') {
+                Some(rest) if (1..=6).contains(&hashes) && !rest.trim().is_empty() => rest,
+                _ => line,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Drops a leading `Commit message:` style label, either on its own line or
+/// in front of the subject.
+fn strip_leading_labels(message: &str) -> String {
+    let message = message.trim_start();
+    let (first, rest) = message.split_once('\n').unwrap_or((message, ""));
+    let Some(remainder) = strip_label(first) else {
+        return message.to_string();
+    };
+
+    if remainder.is_empty() {
+        rest.trim_start().to_string()
+    } else if rest.is_empty() {
+        remainder.to_string()
+    } else {
+        format!("{remainder}\n{rest}")
+    }
+}
+
+fn strip_label(line: &str) -> Option<&str> {
+    let unbolded = line.trim().trim_matches('*').trim();
+    let lowered = unbolded.to_ascii_lowercase();
+    let label = LEADING_LABELS
+        .iter()
+        .find(|label| lowered.starts_with(*label) && lowered[label.len()..].starts_with(':'))?;
+    Some(unbolded[label.len() + 1..].trim_start_matches('*').trim())
+}
+
+/// Removes quotes or single backticks wrapped around the whole message.
+fn strip_quotes(message: &str) -> String {
+    let trimmed = message.trim();
+    for (open, close) in [('"', '"'), ('\'', '\''), ('`', '`'), ('“', '”')] {
+        if let Some(inner) = trimmed
+            .strip_prefix(open)
+            .and_then(|rest| rest.strip_suffix(close))
+            && !inner.contains(open)
+            && !inner.contains(close)
+        {
+            return inner.trim().to_string();
+        }
+    }
+    trimmed.to_string()
+}
+
+fn collapse_blank_lines(message: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in message.lines() {
+        let blank = line.trim().is_empty();
+        if blank && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(if blank { "" } else { line });
+    }
+    lines.join("\n")
+}
+
+/// Keeps only the first non-blank line, for subject-only messages.
+fn subject_only(message: &str) -> String {
+    message
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_reasoning_removes_think_blocks() {
+        // DeepSeek-R1 / Qwen style.
+        assert_eq!(
+            strip_reasoning("<think>\nThe diff adds a cache.\n</think>\n\nfeat: add cache").trim(),
+            "feat: add cache"
+        );
+        // Phi-4-reasoning style, opening tag already stripped by the provider.
+        assert_eq!(
+            strip_reasoning("The user wants a message.</THINK>fix: typo").trim(),
+            "fix: typo"
+        );
+        // Reasoning truncated by the token limit leaves nothing usable.
+        assert_eq!(strip_reasoning("<thinking>Let me look").trim(), "");
+    }
+
+    #[test]
+    fn strip_preamble_removes_chatty_lead_ins_and_sign_offs() {
+        // GPT-4o style.
+        assert_eq!(
+            strip_preamble("Sure! Here's a commit message for your changes:\n\nfeat: add cache"),
+            "feat: add cache"
+        );
+        // Llama / Mistral style with a trailing offer.
+        assert_eq!(
+            strip_preamble(
+                "Here is the commit message:\nfix: handle empty diff\n\nLet me know if you need changes."
+            ),
+            "fix: handle empty diff"
+        );
+        // Claude style bold lead-in.
+        assert_eq!(
+            strip_preamble(
+                "**Certainly!**\nBased on the diff, here is a suggestion:\nrefactor: split module"
+            ),
+            "refactor: split module"
+        );
+        assert_eq!(strip_preamble("feat: add cache"), "feat: add cache");
+    }
+
+    #[test]
+    fn strip_code_fences_removes_fences() {
+        assert_eq!(
+            strip_code_fences("```\nfeat: add tests\n```\n"),
+            "feat: add tests"
+        );
+        assert_eq!(strip_code_fences("```feat: add tests\n"), "feat: add tests");
+        assert_eq!(
+            strip_code_fences("```text\nfix: handle empty diff\n```"),
+            "fix: handle empty diff"
+        );
+    }
+
+    #[test]
+    fn strip_markdown_headers_keeps_header_text() {
+        assert_eq!(
+            strip_markdown_headers("## feat: add cache\n\n#123 stays"),
+            "feat: add cache\n\n#123 stays"
+        );
+    }
+
+    #[test]
+    fn strip_leading_labels_handles_label_lines_and_prefixes() {
+        assert_eq!(
+            strip_leading_labels("Commit message:\n\nfeat: add cache"),
+            "feat: add cache"
+        );
+        assert_eq!(
+            strip_leading_labels("**Commit message:** feat: add cache\n\nbody"),
+            "feat: add cache\n\nbody"
+        );
+        assert_eq!(strip_leading_labels("feat: add cache"), "feat: add cache");
+    }
+
+    #[test]
+    fn strip_quotes_unwraps_whole_message_only() {
+        assert_eq!(strip_quotes("\"feat: add cache\""), "feat: add cache");
+        assert_eq!(strip_quotes("`fix: typo`"), "fix: typo");
+        assert_eq!(
+            strip_quotes("feat: support \"quoted\" names"),
+            "feat: support \"quoted\" names"
+        );
+    }
+
+    #[test]
+    fn collapse_blank_lines_keeps_single_separators() {
+        assert_eq!(
+            collapse_blank_lines("feat: add cache\n\n  \n\nbody\n\n\nmore"),
+            "feat: add cache\n\nbody\n\nmore"
+        );
+    }
+
+    #[test]
+    fn subject_only_drops_the_body() {
+        assert_eq!(
+            subject_only("\nfix: handle empty input\n\nThe parser panicked."),
+            "fix: handle empty input"
+        );
+    }
+
+    #[test]
+    fn is_placeholder_flags_empty_and_template_output() {
+        assert!(is_placeholder(""));
+        assert!(is_placeholder("TODO"));
+        assert!(is_placeholder("Your commit message here"));
+        assert!(is_placeholder("<type>(<scope>): <subject>"));
+        assert!(!is_placeholder("feat: add todo list export"));
+    }
+
+    #[test]
+    fn sanitize_runs_transforms_in_order() {
+        let input = "<think>hmm</think>\nSure! Here you go:\n```\n### Commit message:\n\"feat: add cache\"\n```";
+        assert_eq!(sanitize(input, &DEFAULT_SANITIZERS), "feat: add cache");
+        assert_eq!(sanitize(input, &[]), input);
+    }
+}
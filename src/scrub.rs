@@ -0,0 +1,117 @@
+// Removal of personal data from example commits before they reach the model.
+
+use crate::prompt::ExamplesConfig;
+
+const IDENTITY_HEADERS: [&str; 2] = ["Author:", "Commit:"];
+const IDENTITY_TRAILERS: [&str; 6] = [
+    "Signed-off-by:",
+    "Co-authored-by:",
+    "Reviewed-by:",
+    "Acked-by:",
+    "Tested-by:",
+    "Reported-by:",
+];
+
+/// Scrubs `git log` output according to `config`.
+pub fn scrub_examples(log: &str, config: &ExamplesConfig) -> String {
+    let mut lines = Vec::new();
+    for line in log.lines() {
+        let trimmed = line.trim_start();
+        if config.scrub_names
+            && IDENTITY_HEADERS
+                .iter()
+                .any(|header| trimmed.starts_with(header))
+        {
+            continue;
+        }
+
+        let mut line = line.to_string();
+        if config.scrub_names
+            && let Some(trailer) = IDENTITY_TRAILERS
+                .iter()
+                .find(|trailer| trimmed.starts_with(*trailer))
+        {
+            let indent = &line[..line.len() - trimmed.len()];
+            line = format!("{indent}{trailer} [name]");
+        }
+        if config.scrub_emails || config.scrub_urls {
+            line = scrub_tokens(&line, config);
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+fn scrub_tokens(line: &str, config: &ExamplesConfig) -> String {
+    line.split(' ')
+        .map(|token| {
+            let bare = token.trim_matches(|ch: char| "<>()[],;\"'".contains(ch));
+            if config.scrub_urls && is_url(bare) {
+                token.replace(bare, "[url]")
+            } else if config.scrub_emails && is_email(bare) {
+                token.replace(bare, "[email]")
+            } else {
+                token.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_url(token: &str) -> bool {
+    ["http://", "https://", "ssh://", "git@"]
+        .iter()
+        .any(|scheme| token.starts_with(scheme))
+}
+
+fn is_email(token: &str) -> bool {
+    let Some((local, domain)) = token.split_once('@') else {
+        return false;
+    };
+    !local.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !domain.contains('@')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_enabled() -> ExamplesConfig {
+        ExamplesConfig {
+            scrub_emails: true,
+            scrub_names: true,
+            scrub_urls: true,
+        }
+    }
+
+    #[test]
+    fn scrub_examples_removes_identities_emails_and_urls() {
+        let log = "commit 1a2b3c\nAuthor: Jane Doe <jane@example.com>\nDate:   Mon Jan 1\n\n    \
+                   fix: handle retries (see https://github.com/o/r/issues/1)\n\n    \
+                   Reported by ops@example.org\n    Signed-off-by: Jane Doe <jane@example.com>";
+        let scrubbed = scrub_examples(log, &all_enabled());
+        assert!(!scrubbed.contains("Jane"));
+        assert!(!scrubbed.contains("example.com"));
+        assert!(!scrubbed.contains("github.com"));
+        assert!(scrubbed.contains("fix: handle retries (see [url])"));
+        assert!(scrubbed.contains("Reported by [email]"));
+        assert!(scrubbed.contains("    Signed-off-by: [name]"));
+    }
+
+    #[test]
+    fn scrub_examples_respects_disabled_stages() {
+        let config = ExamplesConfig {
+            scrub_emails: false,
+            scrub_names: false,
+            scrub_urls: true,
+        };
+        let log = "Author: Jane <jane@example.com>\n    docs: link https://example.com";
+        assert_eq!(
+            scrub_examples(log, &config),
+            "Author: Jane <jane@example.com>\n    docs: link [url]"
+        );
+    }
+}
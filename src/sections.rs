@@ -0,0 +1,55 @@
+// Structured commit body sections requested by --detailed.
+
+/// Instruction appended to the commit message request.
+pub fn prompt_note(names: &[String]) -> String {
+    let headings = names
+        .iter()
+        .map(|name| format!("\"{name}:\""))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!(
+        "After the subject, write a body with the sections {headings} in that order. Put each \
+         heading on its own line, followed by its content."
+    )
+}
+
+/// The section names `message` has no heading for.
+pub fn missing<'a>(message: &str, names: &'a [String]) -> Vec<&'a str> {
+    let body: Vec<&str> = message.lines().skip(1).collect();
+    names
+        .iter()
+        .map(String::as_str)
+        .filter(|name| !body.iter().any(|line| is_heading(line, name)))
+        .collect()
+}
+
+/// Whether `line` opens section `name`: `Motivation:`, `## Motivation`, or
+/// `**Motivation**`, optionally with content after the colon.
+fn is_heading(line: &str, name: &str) -> bool {
+    let line = line.trim().trim_start_matches('#').trim().trim_matches('*');
+    let Some(rest) = line
+        .get(..name.len())
+        .filter(|head| head.eq_ignore_ascii_case(name))
+    else {
+        return false;
+    };
+    let rest = line[rest.len()..].trim_start_matches('*');
+    rest.is_empty() || rest.starts_with(':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_lists_sections_without_a_heading() {
+        let names = ["Motivation", "Changes", "Testing"].map(String::from);
+        let message = "feat: add cache\n\nMotivation: lookups were slow.\n\n## Changes\n\
+                       - add an LRU cache\n\n**Testing**\n- cargo test";
+        assert!(missing(message, &names).is_empty());
+
+        let message = "feat: add cache\n\nMotivation:\nlookups were slow.\n\nChanges in detail";
+        assert_eq!(missing(message, &names), ["Changes", "Testing"]);
+        assert_eq!(missing("Testing: none", &names).len(), 3);
+    }
+}
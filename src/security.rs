@@ -0,0 +1,63 @@
+// Staged paths that must never be sent to the model (`security.blockPaths`).
+
+use crate::content_filter::glob_match;
+
+/// The first of `patterns` each blocked path in `paths` matches.
+pub fn blocked<'a>(paths: &'a [String], patterns: &'a [String]) -> Vec<(&'a str, &'a str)> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            let pattern = patterns.iter().find(|pattern| path_match(pattern, path))?;
+            Some((path.as_str(), pattern.as_str()))
+        })
+        .collect()
+}
+
+/// Matches `path` against a gitignore-style pattern: without a slash it
+/// matches any path component (`*.pem`, `secrets`); with one it is anchored
+/// at the repository root, and `**` spans any number of directories.
+fn path_match(pattern: &str, path: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    let components: Vec<&str> = path.split('/').collect();
+    if !pattern.contains('/') {
+        return components
+            .iter()
+            .any(|component| glob_match(pattern, component));
+    }
+    let parts: Vec<&str> = pattern.trim_start_matches('/').split('/').collect();
+    match_parts(&parts, &components)
+}
+
+/// Whether `parts` match a prefix of `components`; a match on a directory
+/// covers everything below it.
+fn match_parts(parts: &[&str], components: &[&str]) -> bool {
+    match parts.split_first() {
+        None => true,
+        Some((&"**", rest)) => {
+            (0..=components.len()).any(|skip| match_parts(rest, &components[skip..]))
+        }
+        Some((part, rest)) => components.split_first().is_some_and(|(component, below)| {
+            glob_match(part, component) && match_parts(rest, below)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_patterns_follow_gitignore_rules() {
+        assert!(path_match("*.pem", "deploy/certs/server.pem"));
+        assert!(path_match("secrets/**", "secrets/prod/db.env"));
+        assert!(path_match("secrets", "config/secrets/key.txt"));
+        assert!(path_match("config/**/*.key", "config/a/b/tls.key"));
+        assert!(path_match("/infra/prod", "infra/prod/main.tf"));
+        assert!(!path_match("secrets/**", "docs/secrets/readme.md"));
+        assert!(!path_match("*.pem", "src/pem.rs"));
+
+        let paths = ["src/main.rs".to_string(), "keys/id.pem".to_string()];
+        let patterns = ["*.pem".to_string()];
+        assert_eq!(blocked(&paths, &patterns), [("keys/id.pem", "*.pem")]);
+    }
+}
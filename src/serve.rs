@@ -0,0 +1,449 @@
+// JSON-RPC 2.0 server over stdio for editor plugins.
+//
+// Messages are single-line JSON objects separated by newlines. Requests run on
+// their own threads against one long-lived model client, so the token and
+// HTTP connections are reused across requests.
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::config::Reloader;
+use crate::git::{self, GitBackend};
+use crate::llm::{LlmProvider, Prompt};
+use crate::prompt::PromptConfig;
+use crate::{
+    GenerationContext, blocked_staged_paths, language, produce_commit_message, resolve_model_chain,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const GENERATION_FAILED: i64 = -32000;
+const REQUEST_CANCELLED: i64 = -32800;
+
+/// Parameters of the `generate` method.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct GenerateParams {
+    /// A unified diff to describe.
+    #[serde(default)]
+    diff: Option<String>,
+    /// A repository whose staged changes to describe.
+    #[serde(default)]
+    repo_path: Option<PathBuf>,
+    #[serde(default)]
+    options: GenerateOptions,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct GenerateOptions {
+    language: Option<String>,
+    model: Option<String>,
+}
+
+/// Defaults for options a request leaves out.
+pub struct Defaults {
+    pub language: String,
+    pub model: String,
+}
+
+type Output = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// Dispatches requests read from one connection.
+pub struct Server<L> {
+    llm: Arc<L>,
+    config: Arc<PromptConfig>,
+    defaults: Arc<Defaults>,
+    output: Output,
+    /// Cancellation flags of `generate` requests still running, keyed by
+    /// their serialized id. Whoever removes an entry sends its response.
+    pending: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+    workers: Vec<JoinHandle<()>>,
+    reloader: Option<Reloader>,
+    /// Log connection statistics to stderr after each request.
+    debug: bool,
+}
+
+impl<L: LlmProvider + Send + Sync + 'static> Server<L> {
+    pub fn new(
+        llm: L,
+        config: PromptConfig,
+        defaults: Defaults,
+        output: Box<dyn Write + Send>,
+    ) -> Self {
+        Self {
+            llm: Arc::new(llm),
+            config: Arc::new(config),
+            defaults: Arc::new(defaults),
+            output: Arc::new(Mutex::new(output)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            workers: Vec::new(),
+            reloader: None,
+            debug: false,
+        }
+    }
+
+    /// Logs the model client's connection statistics after each request.
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Picks up config edits before each `generate` request. Requests
+    /// already running keep the config they started with.
+    pub fn reload_with(mut self, reloader: Reloader) -> Self {
+        self.reloader = Some(reloader);
+        self
+    }
+
+    fn reload_config(&mut self) {
+        let Some(outcome) = self.reloader.as_mut().and_then(Reloader::poll) else {
+            return;
+        };
+        match outcome {
+            Ok(config) => {
+                self.config = Arc::new(config);
+                eprintln!("sparkle: config reloaded");
+            }
+            Err(err) => eprintln!("sparkle: keeping the previous config: {err}"),
+        }
+    }
+
+    /// Serves requests until `input` ends, then waits for running requests.
+    pub fn run(mut self, input: impl BufRead) -> Result<(), Box<dyn Error>> {
+        for line in input.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                self.handle_line(&line);
+            }
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        Ok(())
+    }
+
+    fn handle_line(&mut self, line: &str) {
+        let message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(err) => return send(&self.output, error(Value::Null, PARSE_ERROR, err)),
+        };
+        let id = message.get("id").cloned();
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            return send(
+                &self.output,
+                error(id.unwrap_or(Value::Null), INVALID_REQUEST, "missing method"),
+            );
+        };
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+        match (method, id) {
+            ("generate", Some(id)) => self.generate(id, params),
+            ("cancel", id) => {
+                let cancelled = self.cancel(&params);
+                if let Some(id) = id {
+                    send(&self.output, result(id, json!(cancelled)));
+                }
+            }
+            ("generate", None) => {}
+            (_, Some(id)) => send(
+                &self.output,
+                error(id, METHOD_NOT_FOUND, format!("unknown method {method}")),
+            ),
+            (_, None) => {}
+        }
+    }
+
+    fn generate(&mut self, id: Value, params: Value) {
+        let params: GenerateParams = match serde_json::from_value(params) {
+            Ok(params) => params,
+            Err(err) => return send(&self.output, error(id, INVALID_PARAMS, err)),
+        };
+        self.reload_config();
+        let key = id.to_string();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(key.clone(), cancelled.clone());
+
+        let llm = self.llm.clone();
+        let config = self.config.clone();
+        let defaults = self.defaults.clone();
+        let output = self.output.clone();
+        let pending = self.pending.clone();
+        let debug = self.debug;
+        self.workers.push(thread::spawn(move || {
+            let provider = Cancellable {
+                inner: llm.as_ref(),
+                cancelled: &cancelled,
+            };
+            let progress = |message: String| {
+                if !cancelled.load(Ordering::Relaxed) {
+                    send(
+                        &output,
+                        json!({
+                            "jsonrpc": "2.0",
+                            "method": "progress",
+                            "params": { "id": id, "message": message },
+                        }),
+                    );
+                }
+            };
+            let outcome = generate(&provider, &config, &defaults, params, progress);
+            if let Some(stats) = llm.connection_stats().filter(|_| debug) {
+                eprintln!("sparkle: {stats}");
+            }
+            if pending.lock().unwrap().remove(&key).is_none() {
+                return;
+            }
+            send(
+                &output,
+                match outcome {
+                    Ok((message, model)) => {
+                        result(id, json!({ "message": message, "model": model }))
+                    }
+                    Err(err) => error(id, GENERATION_FAILED, err),
+                },
+            );
+        }));
+    }
+
+    /// Stops a running `generate` request and answers it as cancelled.
+    fn cancel(&self, params: &Value) -> bool {
+        let Some(target) = params.get("id") else {
+            return false;
+        };
+        let Some(flag) = self.pending.lock().unwrap().remove(&target.to_string()) else {
+            return false;
+        };
+        flag.store(true, Ordering::Relaxed);
+        send(
+            &self.output,
+            error(target.clone(), REQUEST_CANCELLED, "request cancelled"),
+        );
+        true
+    }
+}
+
+fn generate(
+    llm: &dyn LlmProvider,
+    config: &PromptConfig,
+    defaults: &Defaults,
+    params: GenerateParams,
+    progress: impl FnMut(String),
+) -> Result<(String, String), Box<dyn Error>> {
+    let staged = match (params.diff, params.repo_path) {
+        (Some(diff), None) => git::StagedDiff {
+            summary: String::new(),
+            patch: diff,
+        },
+        (None, Some(path)) => {
+            let repo = git::Repo::at(&path);
+            let blocked = blocked_staged_paths(config, &repo);
+            if !blocked.is_empty() {
+                return Err(format!(
+                    "staged files match security.blockPaths: {}",
+                    blocked.join(", ")
+                )
+                .into());
+            }
+            repo.staged_diff()?
+        }
+        _ => return Err("pass exactly one of diff and repoPath".into()),
+    };
+    if staged.patch.trim().is_empty() {
+        return Err("no changes to describe".into());
+    }
+    let language = language::LanguageSpec::parse(
+        params
+            .options
+            .language
+            .as_deref()
+            .unwrap_or(&defaults.language),
+    )?;
+    let model_chain = resolve_model_chain(
+        params.options.model.as_deref().unwrap_or(&defaults.model),
+        &config.model_policy,
+    )?;
+    let context = GenerationContext::new(
+        config,
+        &staged.summary,
+        &staged.patch,
+        &model_chain,
+        &language,
+    );
+    let draft = produce_commit_message(llm, &context, progress, |_| false)?;
+    Ok((draft.message, draft.model))
+}
+
+/// Refuses further model calls once its request is cancelled.
+struct Cancellable<'a> {
+    inner: &'a dyn LlmProvider,
+    cancelled: &'a AtomicBool,
+}
+
+impl LlmProvider for Cancellable<'_> {
+    fn complete(
+        &self,
+        prompt_config: &PromptConfig,
+        prompt: &Prompt,
+        model: &str,
+        deadline: Instant,
+    ) -> Result<String, Box<dyn Error>> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err("request cancelled".into());
+        }
+        self.inner.complete(prompt_config, prompt, model, deadline)
+    }
+}
+
+fn result(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn error(id: Value, code: i64, message: impl ToString) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message.to_string() },
+    })
+}
+
+fn send(output: &Output, message: Value) {
+    let mut output = output.lock().unwrap();
+    let _ = writeln!(output, "{message}");
+    let _ = output.flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline_tests::TempRepo;
+
+    struct Fixed;
+
+    impl LlmProvider for Fixed {
+        fn complete(
+            &self,
+            _prompt_config: &PromptConfig,
+            _prompt: &Prompt,
+            _model: &str,
+            _deadline: Instant,
+        ) -> Result<String, Box<dyn Error>> {
+            Ok("feat: add server mode".to_string())
+        }
+    }
+
+    /// A writer tests can read back after the server is done.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn serve(input: &str) -> Vec<Value> {
+        let config =
+            serde_yaml::from_value(crate::prompt::default_config_value().unwrap()).unwrap();
+        serve_with(config, input)
+    }
+
+    fn serve_with(config: PromptConfig, input: &str) -> Vec<Value> {
+        let defaults = Defaults {
+            language: "english".to_string(),
+            model: "m".to_string(),
+        };
+        let output = Shared::default();
+        Server::new(Fixed, config, defaults, Box::new(output.clone()))
+            .run(input.as_bytes())
+            .unwrap();
+        let text = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        text.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn generate_answers_with_the_message() {
+        let replies = serve(concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"generate","params":{"diff":"+fn serve() {}\n"}}"#,
+            "\n"
+        ));
+        let reply = replies
+            .iter()
+            .find(|reply| reply.get("id") == Some(&json!(1)));
+        assert_eq!(
+            reply.unwrap()["result"],
+            json!({ "message": "feat: add server mode\n", "model": "m" })
+        );
+    }
+
+    #[test]
+    fn malformed_and_unknown_requests_get_errors() {
+        let replies = serve(concat!(
+            "not json\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"explode"}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":3,"method":"generate","params":{}}"#,
+            "\n",
+            r#"{"jsonrpc":"2.0","id":4,"method":"cancel","params":{"id":99}}"#,
+            "\n",
+        ));
+        let reply = |id: Value| {
+            replies
+                .iter()
+                .find(|reply| reply["id"] == id)
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(reply(Value::Null)["error"]["code"], json!(PARSE_ERROR));
+        assert_eq!(reply(json!(2))["error"]["code"], json!(METHOD_NOT_FOUND));
+        assert_eq!(reply(json!(3))["error"]["code"], json!(GENERATION_FAILED));
+        assert_eq!(reply(json!(4))["result"], json!(false));
+    }
+
+    #[test]
+    fn generate_refuses_blocked_files_in_repo_path() {
+        let fixture = TempRepo::new("serve-blocked");
+        std::fs::write(fixture.path().join(".env"), "TOKEN=secret\n").unwrap();
+        fixture.git(&["add", ".env"]);
+        let mut config: PromptConfig =
+            serde_yaml::from_value(crate::prompt::default_config_value().unwrap()).unwrap();
+        config.security.block_paths = vec![".env".to_string()];
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 5,
+            "method": "generate",
+            "params": { "repoPath": fixture.path() },
+        });
+
+        let replies = serve_with(config, &format!("{request}\n"));
+
+        let reply = replies
+            .iter()
+            .find(|reply| reply["id"] == json!(5))
+            .unwrap();
+        assert_eq!(reply["error"]["code"], json!(GENERATION_FAILED));
+        assert_eq!(
+            reply["error"]["message"],
+            json!("staged files match security.blockPaths: .env (matches .env)")
+        );
+    }
+}
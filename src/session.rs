@@ -0,0 +1,63 @@
+// The conversation behind a draft, continued by regeneration and refinement.
+
+use serde::{Deserialize, Serialize};
+
+use crate::llm::Prompt;
+
+/// Every turn that led to the current message: the rendered prompt, then
+/// each accepted correction or refinement, so a follow-up revises the
+/// message in context instead of starting over.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Session {
+    /// The request the current message answers.
+    prompt: Prompt,
+}
+
+impl Session {
+    pub fn new(prompt: Prompt) -> Self {
+        Self { prompt }
+    }
+
+    /// The next request: the conversation so far, `reply` as the model's
+    /// latest answer, and the user's `instruction`.
+    pub fn follow_up(&self, reply: &str, instruction: &str) -> Prompt {
+        self.prompt.with_followup(reply, instruction)
+    }
+
+    /// Continues from `prompt`, a request from `follow_up` whose answer
+    /// became the current message.
+    pub fn advance(&mut self, prompt: Prompt) {
+        self.prompt = prompt;
+    }
+
+    pub fn prompt(&self) -> &Prompt {
+        &self.prompt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prompt::{self, PromptConfig};
+
+    #[test]
+    fn follow_ups_carry_every_earlier_turn() {
+        let config: PromptConfig =
+            serde_yaml::from_value(prompt::default_config_value().unwrap()).unwrap();
+        let mut session = Session::new(Prompt::commit_message(&config, "+x", "english", ""));
+
+        let first = session.follow_up("fix: add x", "Make it shorter.");
+        session.advance(first);
+        let second = session.follow_up("fix: x", "Use the feat type.");
+
+        let text = second.text();
+        let turns = [
+            "fix: add x",
+            "Make it shorter.",
+            "fix: x",
+            "Use the feat type.",
+        ];
+        let positions: Vec<usize> = turns.iter().map(|turn| text.find(turn).unwrap()).collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "{text}");
+    }
+}
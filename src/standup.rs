@@ -0,0 +1,140 @@
+// Commit history grouped by area for `sparkle summary` standup reports.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use crate::failure::{Classify, Failure, FailureKind};
+use crate::{Cli, complete_with_chain, git, llm, load_prompt_config, parse_language};
+
+/// One commit from `git log`, with the files it touched.
+pub struct Entry {
+    pub sha: String,
+    pub subject: String,
+    pub files: Vec<String>,
+}
+
+/// `git log` format parsed by [`parse_log`]: a record separator, then the
+/// hash and subject separated by a unit separator, then `--name-only` paths.
+pub const LOG_FORMAT: &str = "--format=%x1e%H%x1f%s";
+
+pub fn parse_log(output: &str) -> Vec<Entry> {
+    output
+        .split('\u{1e}')
+        .filter_map(|record| {
+            let mut lines = record.lines();
+            let (sha, subject) = lines.next()?.split_once('\u{1f}')?;
+            Some(Entry {
+                sha: sha.to_string(),
+                subject: subject.to_string(),
+                files: lines
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// The area a commit belongs to: its Conventional Commit scope, else the
+/// top-level directory most of its files are in.
+pub fn area(entry: &Entry) -> String {
+    let scope = entry
+        .subject
+        .split_once(':')
+        .and_then(|(prefix, _)| prefix.split_once('('))
+        .and_then(|(_, rest)| rest.trim_end_matches('!').strip_suffix(')'));
+    if let Some(scope) = scope.filter(|scope| !scope.is_empty()) {
+        return scope.to_string();
+    }
+
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for file in &entry.files {
+        let top = match file.split_once('/') {
+            Some((dir, _)) => dir,
+            None => "(root)",
+        };
+        *counts.entry(top).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, count)| *count)
+        .map_or_else(|| "other".to_string(), |(dir, _)| dir.to_string())
+}
+
+/// Commits grouped by area, areas sorted by name, commits oldest first as
+/// given.
+pub fn render(entries: &[Entry]) -> String {
+    let mut groups: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries {
+        groups.entry(area(entry)).or_default().push(entry);
+    }
+    let mut text = String::new();
+    for (area, entries) in groups {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&format!("{area}:\n"));
+        for entry in entries {
+            text.push_str(&format!(
+                "- {} ({})\n",
+                entry.subject,
+                &entry.sha[..entry.sha.len().min(7)]
+            ));
+        }
+    }
+    text
+}
+
+/// Prints a standup report of the commits in the given window, grouped by
+/// area and summarized by the model unless `--no-llm` is set.
+pub fn run(
+    cli: &Cli,
+    since: &str,
+    until: Option<&str>,
+    author: Option<&str>,
+    all_branches: bool,
+) -> Result<(), Box<dyn Error>> {
+    let log = git::log_with_files(LOG_FORMAT, since, until, author, all_branches)
+        .classify(FailureKind::Git)?;
+    let entries = parse_log(&log);
+    if entries.is_empty() {
+        println!("  No commits since {since}.");
+        return Ok(());
+    }
+    let grouped = render(&entries);
+    if cli.no_llm {
+        print!("{grouped}");
+        return Ok(());
+    }
+
+    let prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    if prompt_config.summary.messages.is_empty() {
+        return Err(Failure::new(FailureKind::Config, "summary.messages is empty").into());
+    }
+    let language_spec = parse_language(&cli.language)?;
+    let prompt = llm::Prompt::summary(&prompt_config, &grouped, &language_spec.describe());
+    println!("{}", complete_with_chain(cli, &prompt_config, &prompt)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_entries_are_grouped_by_scope_or_directory() {
+        let log = "\u{1e}1111111aaaa\u{1f}feat(api): add retry\n\nsrc/api.rs\n\
+                   \u{1e}2222222bbbb\u{1f}update docs\n\ndocs/a.md\ndocs/b.md\nREADME.md\n\
+                   \u{1e}3333333cccc\u{1f}chore: tidy\n\n";
+        let entries = parse_log(log);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].files, ["docs/a.md", "docs/b.md", "README.md"]);
+        assert_eq!(
+            render(&entries),
+            "api:\n- feat(api): add retry (1111111)\n\n\
+             docs:\n- update docs (2222222)\n\n\
+             other:\n- chore: tidy (3333333)\n"
+        );
+    }
+}
@@ -0,0 +1,29 @@
+// Message styles selected with --style or `git config sparkle.style`.
+
+/// How much the generated message should say.
+#[derive(clap::ValueEnum, Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Style {
+    /// Follow the prompt: a subject, and a body only when needed
+    #[default]
+    Standard,
+    /// A single short subject line
+    Short,
+    /// A subject and a body explaining what changed and why
+    Detailed,
+}
+
+impl Style {
+    /// Instruction appended to the commit message request.
+    pub fn prompt_note(self) -> Option<&'static str> {
+        match self {
+            Self::Standard => None,
+            Self::Short => {
+                Some("Write only a subject line of at most 50 characters. Do not add a body.")
+            }
+            Self::Detailed => Some(
+                "After the subject, add a body of a few sentences that explains what \
+                 changed and why.",
+            ),
+        }
+    }
+}
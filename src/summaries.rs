@@ -0,0 +1,281 @@
+// Per-file summaries of the staged diff, cached by blob hash.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::git;
+
+const CACHE_FILE: &str = "sparkle/file-summaries.json";
+
+#[derive(Serialize, Deserialize, Default)]
+struct Cache {
+    /// Fingerprint of the prompt and model that wrote the summaries; a
+    /// different one invalidates them all.
+    fingerprint: String,
+    /// Summary by `<path> <old blob>..<new blob>`.
+    summaries: BTreeMap<String, String>,
+}
+
+/// One file's part of a unified diff.
+pub struct FileDiff<'a> {
+    pub path: &'a str,
+    pub text: &'a str,
+    /// `<path> <old blob>..<new blob>` from the `index` line; `None` for
+    /// changes without one, such as pure renames, which are never cached.
+    key: Option<String>,
+}
+
+/// The summaries of one run.
+pub struct Summaries {
+    pub text: String,
+    pub files: usize,
+    pub cached: usize,
+    /// Files whose summary request failed; they appear as stat-only notes.
+    pub failed: usize,
+    pub last_error: Option<String>,
+}
+
+pub fn cache_path() -> Option<PathBuf> {
+    git::git_dir().ok().map(|dir| dir.join(CACHE_FILE))
+}
+
+/// Summarizes every file of `diff` with `summarize`, at most `concurrency`
+/// requests at a time, reusing summaries in the cache at `path` for blobs
+/// that have not changed since they were written. The cache then keeps only
+/// this diff's files.
+pub fn summarize(
+    diff: &str,
+    path: Option<&Path>,
+    fingerprint: &str,
+    concurrency: usize,
+    summarize: impl Fn(&FileDiff<'_>) -> Result<String, Box<dyn Error>> + Sync,
+) -> Result<Summaries, Box<dyn Error>> {
+    let previous = path
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|text| serde_json::from_str::<Cache>(&text).ok())
+        .filter(|cache| cache.fingerprint == fingerprint)
+        .unwrap_or_default();
+    let mut cache = Cache {
+        fingerprint: fingerprint.to_string(),
+        summaries: BTreeMap::new(),
+    };
+    let mut summaries = Summaries {
+        text: String::new(),
+        files: 0,
+        cached: 0,
+        failed: 0,
+        last_error: None,
+    };
+
+    let files = split(diff);
+    let cached: Vec<Option<&String>> = files
+        .iter()
+        .map(|file| {
+            file.key
+                .as_ref()
+                .and_then(|key| previous.summaries.get(key))
+        })
+        .collect();
+    let misses: Vec<usize> = (0..files.len())
+        .filter(|&index| cached[index].is_none())
+        .collect();
+    let fresh = run_bounded(&misses, concurrency, |index| {
+        summarize(&files[index]).map_err(|err| err.to_string())
+    });
+
+    let mut fresh = fresh.into_iter();
+    for (file, cached) in files.into_iter().zip(cached) {
+        summaries.files += 1;
+        let summary = match cached {
+            Some(summary) => {
+                summaries.cached += 1;
+                summary.clone()
+            }
+            None => match fresh.next().expect("one result per miss") {
+                Ok(summary) => summary.trim().to_string(),
+                Err(err) => {
+                    summaries.failed += 1;
+                    summaries.last_error = Some(err);
+                    summaries.text.push_str(&format!(
+                        "{}:\n{}\n\n",
+                        file.path,
+                        stat_note(file.text)
+                    ));
+                    continue;
+                }
+            },
+        };
+        summaries
+            .text
+            .push_str(&format!("{}:\n{}\n\n", file.path, summary));
+        if let Some(key) = file.key {
+            cache.summaries.insert(key, summary);
+        }
+    }
+
+    if let Some(path) = path {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = fs::write(path, serde_json::to_string(&cache)?);
+    }
+    summaries.text.truncate(summaries.text.trim_end().len());
+    Ok(summaries)
+}
+
+/// Runs `job` for every item on up to `concurrency` threads and returns the
+/// results in the order of `items`.
+fn run_bounded<T: Send>(
+    items: &[usize],
+    concurrency: usize,
+    job: impl Fn(usize) -> T + Sync,
+) -> Vec<T> {
+    let results: Vec<Mutex<Option<T>>> = items.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+    let workers = concurrency.clamp(1, items.len().max(1));
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                loop {
+                    let slot = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(&item) = items.get(slot) else {
+                        break;
+                    };
+                    let result = job(item);
+                    *results[slot].lock().unwrap() = Some(result);
+                }
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|result| result.into_inner().unwrap().expect("every slot is filled"))
+        .collect()
+}
+
+/// `[no summary; 12 lines added, 3 removed]` for a file whose summary failed.
+fn stat_note(text: &str) -> String {
+    let (mut added, mut removed) = (0, 0);
+    for line in text.lines().skip_while(|line| !line.starts_with("@@ ")) {
+        if line.starts_with('+') {
+            added += 1;
+        } else if line.starts_with('-') {
+            removed += 1;
+        }
+    }
+    format!("[no summary; {added} lines added, {removed} removed]")
+}
+
+/// Splits a unified diff at its `diff --git` lines.
+pub fn split(diff: &str) -> Vec<FileDiff<'_>> {
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    for line in diff.split_inclusive('\n') {
+        if line.starts_with("diff --git ") {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+    starts
+        .iter()
+        .enumerate()
+        .map(|(index, &start)| {
+            let end = starts.get(index + 1).copied().unwrap_or(diff.len());
+            let text = &diff[start..end];
+            let header = text.lines().next().unwrap_or_default();
+            let path = header
+                .split_once(" b/")
+                .map_or(header, |(_, path)| path)
+                .trim_end();
+            let key = text
+                .lines()
+                .take_while(|line| !line.starts_with("@@ "))
+                .find_map(|line| line.strip_prefix("index "))
+                .and_then(|blobs| blobs.split_whitespace().next())
+                .map(|blobs| format!("{path} {blobs}"));
+            FileDiff { path, text, key }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIFF: &str = "diff --git a/src/a.rs b/src/a.rs\nindex 1111111..2222222 100644\n\
+                        --- a/src/a.rs\n+++ b/src/a.rs\n@@ -1 +1 @@\n-a\n+b\n\
+                        diff --git a/old.rs b/new.rs\nsimilarity index 100%\n\
+                        rename from old.rs\nrename to new.rs\n";
+
+    #[test]
+    fn split_keys_files_by_path_and_blobs() {
+        let files = split(DIFF);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "src/a.rs");
+        assert_eq!(files[0].key.as_deref(), Some("src/a.rs 1111111..2222222"));
+        assert!(files[0].text.ends_with("+b\n"));
+        assert_eq!(files[1].path, "new.rs");
+        assert_eq!(files[1].key, None);
+    }
+
+    #[test]
+    fn summarize_reuses_cached_blobs_only() {
+        let dir = std::env::temp_dir().join(format!("sparkle-summaries-{}", std::process::id()));
+        let path = dir.join("cache.json");
+        let mut calls = Vec::new();
+        let run = |fingerprint: &str, calls: &mut Vec<String>| {
+            let seen = Mutex::new(Vec::new());
+            let summaries = summarize(DIFF, Some(&path), fingerprint, 1, |file| {
+                seen.lock().unwrap().push(file.path.to_string());
+                Ok(format!("changes {}\n", file.path))
+            })
+            .unwrap();
+            calls.extend(seen.into_inner().unwrap());
+            summaries
+        };
+
+        let first = run("v1", &mut calls);
+        assert_eq!(
+            first.text,
+            "src/a.rs:\nchanges src/a.rs\n\nnew.rs:\nchanges new.rs"
+        );
+        assert_eq!((first.files, first.cached), (2, 0));
+        let second = run("v1", &mut calls);
+        assert_eq!((second.files, second.cached), (2, 1));
+        assert_eq!(second.text, first.text);
+        let third = run("v2", &mut calls);
+        assert_eq!(third.cached, 0);
+        assert_eq!(
+            calls,
+            ["src/a.rs", "new.rs", "new.rs", "src/a.rs", "new.rs"]
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn failed_summaries_become_stat_notes() {
+        let summaries = summarize(DIFF, None, "v1", 4, |file| {
+            if file.path == "src/a.rs" {
+                Err("rate limited".into())
+            } else {
+                Ok("renamed".to_string())
+            }
+        })
+        .unwrap();
+        assert_eq!(
+            summaries.text,
+            "src/a.rs:\n[no summary; 1 lines added, 1 removed]\n\nnew.rs:\nrenamed"
+        );
+        assert_eq!(
+            (summaries.failed, summaries.last_error.as_deref()),
+            (1, Some("rate limited"))
+        );
+        assert_eq!(run_bounded(&[3, 1, 2], 2, |item| item * 10), [30, 10, 20]);
+    }
+}
@@ -0,0 +1,352 @@
+// Deterministic commit message templates built without the model.
+
+/// Builds an editor template from a `git diff --stat` summary.
+pub fn offline_template(summary: &str) -> String {
+    let files = stat_files(summary);
+    let mut template = format!("<type>(<scope>): {}\n", describe_files(&files));
+
+    let stat = summary.trim_end();
+    if !stat.is_empty() {
+        template.push('\n');
+        template.push_str(stat);
+        template.push('\n');
+    }
+
+    template
+}
+
+/// How a file changed in a patch.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Change {
+    Added,
+    Deleted,
+    Modified,
+    /// Only the mode changed, to executable.
+    MadeExecutable,
+    /// Only the mode changed, otherwise.
+    ModeChanged,
+}
+
+/// Builds a complete Conventional Commit message from the diff alone: the
+/// type from paths and extensions, the scope from the common directory, and
+/// the subject from which files were added, removed, or changed.
+pub fn deterministic_message(summary: &str, patch: &str) -> String {
+    let mut files = patch_files(patch);
+    if files.is_empty() {
+        files = stat_files(summary)
+            .into_iter()
+            .map(|path| (path.to_string(), Change::Modified))
+            .collect();
+    }
+    let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+
+    let verb = if files.iter().all(|(_, change)| *change == Change::Added) {
+        "add"
+    } else if files.iter().all(|(_, change)| *change == Change::Deleted) {
+        "remove"
+    } else {
+        "update"
+    };
+    let names: Vec<&str> = paths
+        .iter()
+        .map(|path| path.rsplit('/').next().unwrap_or(path))
+        .collect();
+    let mode_only =
+        |change: &Change| matches!(change, Change::MadeExecutable | Change::ModeChanged);
+    let subject = match names.as_slice() {
+        [] => format!("{verb} files"),
+        names
+            if files
+                .iter()
+                .all(|(_, change)| *change == Change::MadeExecutable) =>
+        {
+            format!("make {} executable", list_names(names))
+        }
+        names if files.iter().all(|(_, change)| mode_only(change)) => {
+            format!("change the file mode of {}", list_names(names))
+        }
+        names => format!("{verb} {}", list_names(names)),
+    };
+    let commit_type = infer_type(&files);
+    let scope = common_scope(&paths)
+        .filter(|scope| *scope != commit_type)
+        .map(|scope| format!("({scope})"))
+        .unwrap_or_default();
+
+    let mut message = format!("{commit_type}{scope}: {subject}\n");
+    let stat = summary.trim_end();
+    if !stat.is_empty() {
+        message.push('\n');
+        message.push_str(stat);
+        message.push('\n');
+    }
+    message
+}
+
+/// Reads each file's path and change kind from `diff --git` headers.
+fn patch_files(patch: &str) -> Vec<(String, Change)> {
+    let mut files: Vec<(String, Change)> = Vec::new();
+    for line in patch.lines() {
+        if let Some(paths) = line.strip_prefix("diff --git a/") {
+            let path = paths.split_once(" b/").map_or(paths, |(_, new)| new);
+            files.push((path.to_string(), Change::Modified));
+        } else if let Some((_, change)) = files.last_mut() {
+            if line.starts_with("new file mode") {
+                *change = Change::Added;
+            } else if line.starts_with("deleted file mode") {
+                *change = Change::Deleted;
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                *change = if mode.ends_with("755") {
+                    Change::MadeExecutable
+                } else {
+                    Change::ModeChanged
+                };
+            } else if (line.starts_with("@@") || line.starts_with("Binary files"))
+                && matches!(*change, Change::MadeExecutable | Change::ModeChanged)
+            {
+                *change = Change::Modified;
+            }
+        }
+    }
+    files
+}
+
+fn infer_type(files: &[(String, Change)]) -> &'static str {
+    let all = |matches: fn(&str) -> bool| {
+        !files.is_empty() && files.iter().all(|(path, _)| matches(path))
+    };
+    if all(is_docs) {
+        "docs"
+    } else if all(is_test) {
+        "test"
+    } else if all(|path| path.starts_with(".github/")) {
+        "ci"
+    } else if all(is_build) {
+        "build"
+    } else if files
+        .iter()
+        .any(|(path, change)| *change == Change::Added && !is_docs(path) && !is_test(path))
+    {
+        "feat"
+    } else if files.iter().all(|(_, change)| *change == Change::Deleted) {
+        "refactor"
+    } else {
+        "chore"
+    }
+}
+
+/// The commit type the diff clearly points to, with the reason, for the
+/// model to follow; `None` when only the model can tell (e.g. fix versus
+/// refactor without tests).
+pub fn type_hint(patch: &str) -> Option<(&'static str, &'static str)> {
+    let files = patch_files(patch);
+    let all = |matches: fn(&str) -> bool| {
+        !files.is_empty() && files.iter().all(|(path, _)| matches(path))
+    };
+    let is_source = |path: &str| !is_docs(path) && !is_test(path) && !is_build(path);
+    if all(is_docs) {
+        Some(("docs", "only documentation files changed"))
+    } else if all(is_test) {
+        Some(("test", "only test files changed"))
+    } else if all(|path| path.starts_with(".github/")) {
+        Some(("ci", "only CI workflow files changed"))
+    } else if all(is_dependency) {
+        Some(("chore", "only dependency manifests or lockfiles changed"))
+    } else if files
+        .iter()
+        .any(|(path, change)| *change == Change::Added && is_source(path))
+    {
+        Some(("feat", "new source files were added"))
+    } else if files.iter().any(|(path, _)| is_test(path))
+        && files.iter().any(|(path, _)| is_source(path))
+        && files.iter().all(|(_, change)| *change == Change::Modified)
+    {
+        Some(("fix", "existing code changed together with its tests"))
+    } else {
+        None
+    }
+}
+
+fn is_dependency(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    matches!(
+        name,
+        "Cargo.toml"
+            | "Cargo.lock"
+            | "package.json"
+            | "package-lock.json"
+            | "pnpm-lock.yaml"
+            | "yarn.lock"
+            | "go.mod"
+            | "go.sum"
+            | "pyproject.toml"
+            | "poetry.lock"
+            | "requirements.txt"
+            | "Gemfile"
+            | "Gemfile.lock"
+    )
+}
+
+fn is_docs(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path).to_ascii_lowercase();
+    path.starts_with("docs/")
+        || [".md", ".rst", ".adoc", ".txt"]
+            .iter()
+            .any(|extension| name.ends_with(extension))
+        || name.starts_with("license")
+}
+
+fn is_test(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    path.split('/')
+        .any(|part| matches!(part, "test" | "tests" | "spec" | "__tests__"))
+        || name.starts_with("test_")
+        || [
+            "_test.go", "_test.rs", "_test.py", ".test.ts", ".test.js", ".spec.ts", ".spec.js",
+        ]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+}
+
+fn is_build(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    matches!(
+        name,
+        "Cargo.toml"
+            | "Cargo.lock"
+            | "package.json"
+            | "package-lock.json"
+            | "pnpm-lock.yaml"
+            | "yarn.lock"
+            | "go.mod"
+            | "go.sum"
+            | "pyproject.toml"
+            | "Makefile"
+            | "Dockerfile"
+            | "build.rs"
+    )
+}
+
+/// The last component of the deepest directory holding every path, skipping
+/// generic roots such as `src`.
+fn common_scope<'a>(paths: &[&'a str]) -> Option<&'a str> {
+    let (first, rest) = paths.split_first()?;
+    let mut common: Vec<&str> = first.split('/').collect();
+    common.pop();
+    for path in rest {
+        let dirs: Vec<&str> = path.split('/').collect();
+        let shared = common
+            .iter()
+            .zip(&dirs[..dirs.len() - 1])
+            .take_while(|(left, right)| left == right)
+            .count();
+        common.truncate(shared);
+    }
+    common
+        .into_iter()
+        .rev()
+        .find(|dir| !matches!(*dir, "src" | "lib" | "pkg" | "internal" | "app"))
+}
+
+fn stat_files(summary: &str) -> Vec<&str> {
+    summary
+        .lines()
+        .filter_map(|line| line.split_once(" | "))
+        .map(|(path, _)| path.trim())
+        .filter(|path| !path.is_empty())
+        .collect()
+}
+
+fn describe_files(files: &[&str]) -> String {
+    let names: Vec<&str> = files
+        .iter()
+        .map(|path| path.rsplit('/').next().unwrap_or(path))
+        .collect();
+
+    match names.as_slice() {
+        [] => "update files".to_string(),
+        names => format!("update {}", list_names(names)),
+    }
+}
+
+fn list_names(names: &[&str]) -> String {
+    match names {
+        [] => String::new(),
+        [one] => one.to_string(),
+        [first, second] => format!("{first} and {second}"),
+        [first, second, rest @ ..] => format!("{first}, {second} and {} more", rest.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_template_lists_changed_files() {
+        let summary =
+            " src/a.rs | 2 +-\n docs/b.md | 1 +\n 2 files changed, 2 insertions(+), 1 deletion(-)";
+        let template = offline_template(summary);
+        assert!(template.starts_with("<type>(<scope>): update a.rs and b.md\n\n"));
+        assert!(template.contains("2 files changed"));
+    }
+
+    #[test]
+    fn deterministic_message_infers_type_scope_and_subject() {
+        let patch = "diff --git a/src/ui/spinner.rs b/src/ui/spinner.rs\n\
+                     new file mode 100644\n\
+                     +pub fn spin() {}\n\
+                     diff --git a/src/ui/mod.rs b/src/ui/mod.rs\n\
+                     +mod spinner;\n";
+        let summary = " src/ui/mod.rs     | 1 +\n src/ui/spinner.rs | 1 +\n 2 files changed";
+        assert_eq!(
+            deterministic_message(summary, patch),
+            format!("feat(ui): update spinner.rs and mod.rs\n\n{summary}\n")
+        );
+
+        let docs = "diff --git a/README.md b/README.md\n+more\n";
+        assert_eq!(deterministic_message("", docs), "docs: update README.md\n");
+        let removed = "diff --git a/src/old.rs b/src/old.rs\ndeleted file mode 100644\n";
+        assert_eq!(
+            deterministic_message("", removed),
+            "refactor: remove old.rs\n"
+        );
+        let chmod =
+            "diff --git a/scripts/run.sh b/scripts/run.sh\nold mode 100644\nnew mode 100755\n";
+        assert_eq!(
+            deterministic_message("", chmod),
+            "chore(scripts): make run.sh executable\n"
+        );
+    }
+
+    #[test]
+    fn type_hint_reads_the_kind_of_files_changed() {
+        let modified = |path: &str| format!("diff --git a/{path} b/{path}\n@@ -1 +1 @@\n");
+        let added = |path: &str| format!("diff --git a/{path} b/{path}\nnew file mode 100644\n");
+        assert_eq!(type_hint(&modified("docs/guide.md")).unwrap().0, "docs");
+        assert_eq!(type_hint(&modified("tests/cli.rs")).unwrap().0, "test");
+        assert_eq!(
+            type_hint(&(modified("Cargo.toml") + &modified("Cargo.lock")))
+                .unwrap()
+                .0,
+            "chore"
+        );
+        assert_eq!(type_hint(&added("src/cache.rs")).unwrap().0, "feat");
+        assert_eq!(
+            type_hint(&(modified("src/parse.rs") + &modified("tests/parse.rs")))
+                .unwrap()
+                .0,
+            "fix"
+        );
+        assert_eq!(type_hint(&modified("src/parse.rs")), None);
+    }
+
+    #[test]
+    fn describe_files_collapses_long_lists() {
+        assert_eq!(describe_files(&[]), "update files");
+        assert_eq!(
+            describe_files(&["a.rs", "b.rs", "c.rs", "d.rs"]),
+            "update a.rs, b.rs and 2 more"
+        );
+    }
+}
@@ -0,0 +1,218 @@
+// Budget-aware truncation of prompt context.
+//
+// Budgets are measured in columns of grapheme clusters, so a cut never splits
+// an emoji sequence or a letter from its combining marks, and wide CJK
+// characters count double. Cuts land on line boundaries, preferably between
+// diff hunks.
+
+const ZWJ: char = '\u{200D}';
+
+/// Splits `text` into grapheme clusters. This approximates the Unicode rules
+/// for the cases that show up in diffs: CRLF, combining marks, variation
+/// selectors, emoji modifiers and ZWJ sequences, flags, and Hangul jamo.
+pub fn graphemes(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let mut chars = rest.char_indices();
+        let (_, first) = chars.next()?;
+        let mut end = first.len_utf8();
+        let mut prev = first;
+        let mut regional = usize::from(is_regional_indicator(first));
+        for (index, ch) in chars {
+            let joins = if prev.is_control() {
+                prev == '\r' && ch == '\n'
+            } else {
+                prev == ZWJ || is_extend(ch) || (regional == 1 && is_regional_indicator(ch))
+            };
+            if !joins {
+                break;
+            }
+            if is_regional_indicator(ch) {
+                regional += 1;
+            }
+            end = index + ch.len_utf8();
+            prev = ch;
+        }
+        let (cluster, tail) = rest.split_at(end);
+        rest = tail;
+        Some(cluster)
+    })
+}
+
+/// Columns `text` takes up: one per cluster, two for wide ones.
+pub fn width(text: &str) -> usize {
+    graphemes(text).map(cluster_width).sum()
+}
+
+/// Cuts `input` to at most `max_width` columns. Whole lines are kept, and the
+/// cut moves back to the end of the last complete hunk when that keeps at
+/// least half of the text. Only when not even the first line fits is a line
+/// split, and then between clusters.
+pub fn truncate(input: &str, max_width: usize) -> String {
+    if width(input) <= max_width {
+        return input.to_string();
+    }
+
+    let mut used = 0;
+    let mut kept = 0;
+    let mut hunk_start = 0;
+    let mut seen_hunk = false;
+    for line in input.split_inclusive('\n') {
+        let line_width = width(line);
+        if used + line_width > max_width {
+            break;
+        }
+        if is_hunk_start(line) {
+            // Only a hunk after a complete one is worth cutting back to.
+            if seen_hunk {
+                hunk_start = kept;
+            }
+            seen_hunk |= line.starts_with("@@ ");
+        }
+        used += line_width;
+        kept += line.len();
+    }
+
+    if kept == 0 {
+        let mut end = 0;
+        let mut used = 0;
+        for cluster in graphemes(input) {
+            used += cluster_width(cluster);
+            if used > max_width {
+                break;
+            }
+            end += cluster.len();
+        }
+        return input[..end].to_string();
+    }
+
+    let at_boundary = input[kept..].lines().next().is_none_or(is_hunk_start);
+    let end = if !at_boundary && hunk_start > 0 && hunk_start * 2 >= kept {
+        hunk_start
+    } else {
+        kept
+    };
+    input[..end].to_string()
+}
+
+fn is_hunk_start(line: &str) -> bool {
+    line.starts_with("diff --git ") || line.starts_with("@@ ")
+}
+
+fn cluster_width(cluster: &str) -> usize {
+    if cluster.chars().any(|ch| is_wide(ch) || ch == '\u{FE0F}') {
+        2
+    } else {
+        1
+    }
+}
+
+fn is_regional_indicator(ch: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&ch)
+}
+
+/// Characters that attach to the one before them.
+fn is_extend(ch: char) -> bool {
+    matches!(ch,
+        '\u{0300}'..='\u{036F}'
+        | '\u{0483}'..='\u{0489}'
+        | '\u{0591}'..='\u{05BD}'
+        | '\u{0610}'..='\u{061A}'
+        | '\u{064B}'..='\u{065F}'
+        | '\u{093A}'..='\u{094F}'
+        | '\u{0E31}'
+        | '\u{0E34}'..='\u{0E3A}'
+        | '\u{0E47}'..='\u{0E4E}'
+        | '\u{1160}'..='\u{11FF}'
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{200C}'..='\u{200D}'
+        | '\u{20D0}'..='\u{20FF}'
+        | '\u{302A}'..='\u{302F}'
+        | '\u{3099}'..='\u{309A}'
+        | '\u{D7B0}'..='\u{D7FF}'
+        | '\u{FE00}'..='\u{FE0F}'
+        | '\u{FE20}'..='\u{FE2F}'
+        | '\u{1F3FB}'..='\u{1F3FF}'
+        | '\u{E0020}'..='\u{E007F}'
+        | '\u{E0100}'..='\u{E01EF}')
+}
+
+/// East Asian wide and fullwidth characters, and emoji.
+fn is_wide(ch: char) -> bool {
+    matches!(ch,
+        '\u{1100}'..='\u{115F}'
+        | '\u{2E80}'..='\u{303E}'
+        | '\u{3041}'..='\u{33FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{A000}'..='\u{A4CF}'
+        | '\u{AC00}'..='\u{D7A3}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FE30}'..='\u{FE4F}'
+        | '\u{FF00}'..='\u{FF60}'
+        | '\u{FFE0}'..='\u{FFE6}'
+        | '\u{1F1E6}'..='\u{1F1FF}'
+        | '\u{1F300}'..='\u{1F64F}'
+        | '\u{1F680}'..='\u{1F6FF}'
+        | '\u{1F900}'..='\u{1F9FF}'
+        | '\u{1FA70}'..='\u{1FAFF}'
+        | '\u{20000}'..='\u{2FFFD}'
+        | '\u{30000}'..='\u{3FFFD}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn graphemes_keep_clusters_together() {
+        let text = "e\u{301}👨\u{200D}👩\u{200D}👧🇯🇵👍🏽漢\r\nx";
+        let clusters: Vec<&str> = graphemes(text).collect();
+        assert_eq!(
+            clusters,
+            [
+                "e\u{301}",
+                "👨\u{200D}👩\u{200D}👧",
+                "🇯🇵",
+                "👍🏽",
+                "漢",
+                "\r\n",
+                "x"
+            ]
+        );
+        assert_eq!(width(text), 1 + 2 + 2 + 2 + 2 + 1 + 1);
+        assert_eq!(truncate("ab👨\u{200D}👩\u{200D}👧c", 3), "ab");
+    }
+
+    #[test]
+    fn truncate_cuts_multibyte_diffs_at_line_and_hunk_boundaries() {
+        let diff = "\
+diff --git a/i18n.txt b/i18n.txt
+@@ -1,2 +1,2 @@
+-こんにちは
++こんばんは 🌙
+@@ -10,3 +10,3 @@
+-café
++cafe\u{301} ☕
++naïve
+";
+        assert_eq!(truncate(diff, 1000), diff);
+
+        // The second hunk does not fit entirely, so it is dropped as a whole.
+        let budget = width(diff) - 2;
+        let cut = truncate(diff, budget);
+        assert!(cut.ends_with("+こんばんは 🌙\n"));
+
+        // Only part of the first hunk fits: keep whole lines.
+        let cut = truncate(
+            diff,
+            width("diff --git a/i18n.txt b/i18n.txt\n@@ -1,2 +1,2 @@\n") + 3,
+        );
+        assert_eq!(cut, "diff --git a/i18n.txt b/i18n.txt\n@@ -1,2 +1,2 @@\n");
+        assert!(
+            cut.lines()
+                .all(|line| diff.lines().any(|kept| kept == line))
+        );
+    }
+}
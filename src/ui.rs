@@ -1,27 +1,49 @@
 // Inline terminal UI rendering for gh-sparkle.
 
 use crossterm::ExecutableCommand;
-use crossterm::cursor::{Hide, MoveToColumn, Show};
-use crossterm::terminal::{Clear, ClearType};
+use crossterm::cursor::{Hide, MoveToColumn, MoveUp, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::Stylize;
+use crossterm::terminal::{self, Clear, ClearType};
+use std::collections::VecDeque;
 use std::error::Error;
 use std::io::{self, IsTerminal, Write};
 use std::time::{Duration, Instant};
 
+use crate::profile;
+
+const LOG_CAPACITY: usize = 200;
+const LOG_VISIBLE_LINES: usize = 3;
 const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum StepStatus {
     Running,
     Done,
+    Failed,
+    Skipped,
 }
 
 pub struct Ui {
-    steps_total: usize,
-    current_step: usize,
-    current_label: String,
+    steps: Vec<(String, Option<StepStatus>)>,
     spinner_index: usize,
     last_tick: Instant,
-    last_log: Option<String>,
+    log: LogBuffer,
+    /// Terminal columns, updated on resize.
+    width: usize,
+    /// Visible width of each line currently on screen, so the block can be
+    /// cleared even after a resize rewrapped it.
+    drawn_widths: Vec<usize>,
+    /// Status shown below the log, such as the remaining model quota.
+    footer: Option<String>,
+    raw_mode: bool,
+}
+
+/// Outcome of handling pending key presses.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Input {
+    Continue,
+    Interrupted,
 }
 
 impl Ui {
@@ -29,26 +51,31 @@ impl Ui {
         io::stdout().is_terminal()
     }
 
+    pub fn is_interactive() -> bool {
+        io::stdin().is_terminal() && io::stderr().is_terminal()
+    }
+
     pub fn start(step_labels: Vec<&str>) -> Result<Self, Box<dyn Error>> {
         let mut stdout = io::stdout();
         stdout.execute(Hide)?;
-        let label = step_labels
-            .first()
-            .map(|value| value.to_string())
-            .unwrap_or_else(|| "Starting".to_string());
 
         Ok(Self {
-            steps_total: step_labels.len().max(1),
-            current_step: 1,
-            current_label: label,
+            steps: step_labels
+                .into_iter()
+                .map(|label| (label.to_string(), None))
+                .collect(),
             spinner_index: 0,
             last_tick: Instant::now(),
-            last_log: None,
+            log: LogBuffer::default(),
+            width: terminal_width(),
+            drawn_widths: Vec::new(),
+            footer: None,
+            raw_mode: false,
         })
     }
 
     pub fn shutdown(mut self) -> Result<(), Box<dyn Error>> {
-        self.clear_line()?;
+        self.suspend()?;
         let mut stdout = io::stdout();
         stdout.execute(Show)?;
         stdout.flush()?;
@@ -56,28 +83,71 @@ impl Ui {
     }
 
     pub fn set_step_status(&mut self, index: usize, status: StepStatus) {
-        if status == StepStatus::Running {
-            self.current_step = index.saturating_add(1);
+        if let Some((_, step_status)) = self.steps.get_mut(index) {
+            *step_status = Some(status);
         }
-
-        let label = match status {
-            StepStatus::Running => format!("Step {} of {}", self.current_step, self.steps_total),
-            StepStatus::Done => "Completed".to_string(),
-        };
-
-        self.current_label = label;
     }
 
+    /// Marks steps still running as failed, for errors no step claimed.
     pub fn set_error(&mut self) {
-        self.current_label = "Failed".to_string();
+        for (_, status) in &mut self.steps {
+            if *status == Some(StepStatus::Running) {
+                *status = Some(StepStatus::Failed);
+            }
+        }
+    }
+
+    pub fn set_footer(&mut self, footer: String) {
+        self.footer = Some(footer);
     }
 
     pub fn log(&mut self, message: impl Into<String>) {
-        let message = message.into();
-        if message.is_empty() {
-            return;
+        self.log.push(message.into());
+    }
+
+    /// Every retained log line, oldest first.
+    pub fn log_lines(&self) -> impl Iterator<Item = &str> {
+        self.log.lines.iter().map(String::as_str)
+    }
+
+    /// Clears the status block and leaves raw mode so other output and
+    /// prompts can use the terminal; the next `draw` takes over again.
+    pub fn suspend(&mut self) -> Result<(), Box<dyn Error>> {
+        self.clear_block()?;
+        if self.raw_mode {
+            terminal::disable_raw_mode()?;
+            self.raw_mode = false;
+        }
+        Ok(())
+    }
+
+    /// Applies pending key presses: arrows scroll the log, Ctrl-C interrupts.
+    pub fn handle_input(&mut self) -> Result<Input, Box<dyn Error>> {
+        if !self.raw_mode {
+            return Ok(Input::Continue);
+        }
+        while event::poll(Duration::ZERO)? {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                Event::Resize(columns, _) => {
+                    self.width = columns as usize;
+                    continue;
+                }
+                _ => continue,
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Up => self.log.scroll_up(),
+                KeyCode::Down => self.log.scroll_down(),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(Input::Interrupted);
+                }
+                _ => {}
+            }
         }
-        self.last_log = Some(message);
+        Ok(Input::Continue)
     }
 
     pub fn tick(&mut self) {
@@ -88,41 +158,313 @@ impl Ui {
     }
 
     pub fn draw(&mut self) -> Result<(), Box<dyn Error>> {
+        let _span = profile::span("ui", "draw");
+        if !self.raw_mode && io::stdin().is_terminal() {
+            terminal::enable_raw_mode()?;
+            self.raw_mode = true;
+        }
+
+        // Without raw mode no resize events arrive, so poll the size.
+        if !self.raw_mode {
+            self.width = terminal_width();
+        }
+        // Leave the last column free so no line triggers an automatic wrap.
+        let width = self.width.saturating_sub(1).max(4);
         let spinner = SPINNER_FRAMES[self.spinner_index];
-        let log = self.last_log.as_deref().unwrap_or("");
-        let message = if log.is_empty() {
-            format!("{spinner} {label}", label = self.current_label)
-        } else {
-            format!("{spinner} {label} — {log}", label = self.current_label)
-        };
+        let mut lines: Vec<(usize, String)> = self
+            .steps
+            .iter()
+            .map(|(label, status)| step_line(label, *status, spinner, width))
+            .collect();
+        lines.extend(self.log.visible().map(|line| {
+            let line = ellipsize(line, width - 2);
+            (line.chars().count() + 2, format!("  {}", line.dim()))
+        }));
+        if self.log.scroll > 0 {
+            let line = ellipsize(&format!("  ↓ {} newer", self.log.scroll), width);
+            lines.push((line.chars().count(), line.dim().to_string()));
+        }
+        if let Some(footer) = &self.footer {
+            let line = ellipsize(footer, width);
+            lines.push((line.chars().count(), line.dark_grey().to_string()));
+        }
 
-        self.render_line(&message)?;
-        Ok(())
+        self.render_block(&lines)
     }
 
-    fn render_line(&mut self, message: &str) -> Result<(), Box<dyn Error>> {
+    fn render_block(&mut self, lines: &[(usize, String)]) -> Result<(), Box<dyn Error>> {
+        self.clear_block()?;
         let mut stdout = io::stdout();
-        stdout.execute(MoveToColumn(0))?;
-        stdout.execute(Clear(ClearType::CurrentLine))?;
-        write!(stdout, "{message}")?;
+        let text: Vec<&str> = lines.iter().map(|(_, line)| line.as_str()).collect();
+        write!(stdout, "{}", text.join("\r\n"))?;
         stdout.flush()?;
+        self.drawn_widths = lines.iter().map(|(width, _)| *width).collect();
         Ok(())
     }
 
-    fn clear_line(&mut self) -> Result<(), Box<dyn Error>> {
+    fn clear_block(&mut self) -> Result<(), Box<dyn Error>> {
         let mut stdout = io::stdout();
+        let columns = self.width.max(1);
+        let rows: usize = self
+            .drawn_widths
+            .iter()
+            .map(|width| width.div_ceil(columns).max(1))
+            .sum();
+        if rows > 1 {
+            stdout.execute(MoveUp((rows - 1) as u16))?;
+        }
         stdout.execute(MoveToColumn(0))?;
-        stdout.execute(Clear(ClearType::CurrentLine))?;
+        stdout.execute(Clear(ClearType::FromCursorDown))?;
         stdout.flush()?;
+        self.drawn_widths.clear();
         Ok(())
     }
 }
 
 impl Drop for Ui {
     fn drop(&mut self) {
-        let _ = self.clear_line();
+        let _ = self.suspend();
         let mut stdout = io::stdout();
         let _ = stdout.execute(Show);
         let _ = stdout.flush();
     }
 }
+
+/// Renders a step as its status icon and label, with the label's visible
+/// width.
+fn step_line(
+    label: &str,
+    status: Option<StepStatus>,
+    spinner: &str,
+    width: usize,
+) -> (usize, String) {
+    let label = ellipsize(label, width - 2);
+    let visible = label.chars().count() + 2;
+    let text = match status {
+        Some(StepStatus::Running) => format!("{} {label}", spinner.cyan()),
+        Some(StepStatus::Done) => format!("{} {label}", "✔".green()),
+        Some(StepStatus::Failed) => format!("{} {}", "✖".red(), label.as_str().red()),
+        Some(StepStatus::Skipped) => format!("{} {}", "↷".yellow(), label.as_str().dim()),
+        None => format!("{} {}", "·".dim(), label.as_str().dim()),
+    };
+    (visible, text)
+}
+
+/// The most recent log lines and how far the view is scrolled back.
+#[derive(Default)]
+struct LogBuffer {
+    lines: VecDeque<String>,
+    /// Lines scrolled up from the newest entry.
+    scroll: usize,
+}
+
+impl LogBuffer {
+    /// Appends `line`, dropping the oldest beyond `LOG_CAPACITY`, and
+    /// scrolls back to the newest entry.
+    fn push(&mut self, line: String) {
+        if line.is_empty() {
+            return;
+        }
+        if self.lines.len() == LOG_CAPACITY {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+        self.scroll = 0;
+    }
+
+    fn scroll_up(&mut self) {
+        let max_scroll = self.lines.len().saturating_sub(LOG_VISIBLE_LINES);
+        self.scroll = (self.scroll + 1).min(max_scroll);
+    }
+
+    fn scroll_down(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    /// The lines in view, oldest first.
+    fn visible(&self) -> impl Iterator<Item = &str> {
+        let end = self.lines.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(LOG_VISIBLE_LINES);
+        self.lines.range(start..end).map(String::as_str)
+    }
+}
+
+fn terminal_width() -> usize {
+    terminal::size()
+        .map(|(columns, _)| columns as usize)
+        .unwrap_or(80)
+}
+
+/// Shortens `text` to `max_chars`, marking the cut with an ellipsis.
+fn ellipsize(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    format!("{kept}…")
+}
+
+/// What to do with a generated draft.
+pub enum DraftAction {
+    Accept,
+    Shorter,
+    Detailed,
+    ChangeType(String),
+    Language(String),
+    Abort,
+}
+
+/// Reads a single shortcut key for the draft shown above it.
+pub fn read_draft_action() -> Result<DraftAction, Box<dyn Error>> {
+    let key = read_key(
+        "[enter] commit  [s] shorter  [d] more detail  [t] change type  [l] language  [q] abort ",
+    )?;
+    Ok(match key {
+        KeyCode::Enter | KeyCode::Char('y') => DraftAction::Accept,
+        KeyCode::Char('s') => DraftAction::Shorter,
+        KeyCode::Char('d') => DraftAction::Detailed,
+        KeyCode::Char('t') => match read_line("Commit type: ")? {
+            Some(commit_type) => DraftAction::ChangeType(commit_type),
+            None => return read_draft_action(),
+        },
+        KeyCode::Char('l') => match read_line("Language: ")? {
+            Some(language) => DraftAction::Language(language),
+            None => return read_draft_action(),
+        },
+        KeyCode::Char('q') | KeyCode::Esc => DraftAction::Abort,
+        _ => return read_draft_action(),
+    })
+}
+
+/// The answer to the commit confirmation.
+pub enum CommitAction {
+    Commit,
+    Edit,
+    Regenerate,
+    Abort,
+}
+
+/// Asks `Commit? [Y/n/e(dit)/r(egenerate)]` and reads a single key.
+pub fn read_commit_action() -> Result<CommitAction, Box<dyn Error>> {
+    Ok(match read_key("Commit? [Y/n/e(dit)/r(egenerate)] ")? {
+        KeyCode::Enter | KeyCode::Char('y' | 'Y') => CommitAction::Commit,
+        KeyCode::Char('e' | 'E') => CommitAction::Edit,
+        KeyCode::Char('r' | 'R') => CommitAction::Regenerate,
+        KeyCode::Char('n' | 'N' | 'q') | KeyCode::Esc => CommitAction::Abort,
+        _ => return read_commit_action(),
+    })
+}
+
+/// Shows `prompt` on stderr and reads one key press in raw mode.
+fn read_key(prompt: &str) -> Result<KeyCode, Box<dyn Error>> {
+    let mut stderr = io::stderr();
+    write!(stderr, "{prompt}")?;
+    stderr.flush()?;
+
+    terminal::enable_raw_mode()?;
+    let key = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => break Ok(key.code),
+            Ok(_) => continue,
+            Err(err) => break Err(err),
+        }
+    };
+    terminal::disable_raw_mode()?;
+    writeln!(stderr)?;
+    Ok(key?)
+}
+
+fn read_line(prompt: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let mut stderr = io::stderr();
+    write!(stderr, "{prompt}")?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok((!answer.is_empty()).then(|| answer.to_string()))
+}
+
+/// Asks a yes/no question on stderr and reads the answer from stdin.
+pub fn confirm(question: &str) -> Result<bool, Box<dyn Error>> {
+    let mut stderr = io::stderr();
+    write!(stderr, "{question} [y/N] ")?;
+    stderr.flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ellipsize_fits_lines_to_the_terminal() {
+        assert_eq!(
+            ellipsize("Generating commit message", 40),
+            "Generating commit message"
+        );
+        assert_eq!(ellipsize("Generating commit message", 10), "Generatin…");
+        assert_eq!(ellipsize("生成提交信息", 4), "生成提…");
+    }
+
+    #[test]
+    fn step_lines_show_an_icon_per_status() {
+        let icon = |status| {
+            let (visible, text) = step_line("Commit", status, "⠋", 40);
+            assert_eq!(visible, 8);
+            assert!(text.contains("Commit"), "{text:?}");
+            ["⠋", "✔", "✖", "↷", "·"]
+                .into_iter()
+                .find(|icon| text.contains(icon))
+                .unwrap()
+        };
+        assert_eq!(icon(Some(StepStatus::Running)), "⠋");
+        assert_eq!(icon(Some(StepStatus::Done)), "✔");
+        assert_eq!(icon(Some(StepStatus::Failed)), "✖");
+        assert_eq!(icon(Some(StepStatus::Skipped)), "↷");
+        assert_eq!(icon(None), "·");
+
+        let (visible, text) = step_line("Generating commit message", None, "⠋", 10);
+        assert_eq!(visible, 10);
+        assert!(text.contains("Generat…"), "{text:?}");
+    }
+
+    #[test]
+    fn log_buffer_scrolls_within_retained_lines() {
+        let mut log = LogBuffer::default();
+        for index in 0..LOG_CAPACITY + 5 {
+            log.push(format!("line {index}"));
+        }
+        log.push(String::new());
+        assert_eq!(log.lines.len(), LOG_CAPACITY);
+        assert_eq!(log.lines.front().unwrap(), "line 5");
+        assert_eq!(
+            log.visible().collect::<Vec<_>>(),
+            ["line 202", "line 203", "line 204"]
+        );
+
+        log.scroll_up();
+        log.scroll_up();
+        assert_eq!(
+            log.visible().collect::<Vec<_>>(),
+            ["line 200", "line 201", "line 202"]
+        );
+        log.scroll_down();
+        assert_eq!(log.scroll, 1);
+
+        for _ in 0..LOG_CAPACITY * 2 {
+            log.scroll_up();
+        }
+        assert_eq!(
+            log.visible().collect::<Vec<_>>(),
+            ["line 5", "line 6", "line 7"]
+        );
+
+        log.push("line 205".to_string());
+        assert_eq!(log.scroll, 0);
+        assert_eq!(log.visible().last(), Some("line 205"));
+    }
+}
@@ -0,0 +1,287 @@
+// Release checks and self-upgrade for `gh sparkle upgrade`.
+
+use reqwest::blocking::Client as HttpClient;
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use crate::audit;
+use crate::auth;
+use crate::manpage;
+
+const REPO: &str = "dyxushuai/gh-sparkle";
+const CURRENT: &str = env!("CARGO_PKG_VERSION");
+const NOTICE_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// How this binary was installed, which decides how it is replaced.
+#[derive(Debug, PartialEq, Eq)]
+enum Install {
+    /// Managed by `gh extension install`.
+    GhExtension,
+    /// A release binary or `cargo install` build somewhere on PATH.
+    Standalone(PathBuf),
+}
+
+/// Reports the installed and latest versions and, unless `check_only`,
+/// upgrades to the latest release.
+pub fn run(check_only: bool) -> Result<(), Box<dyn Error>> {
+    let latest = latest_release(&http(Duration::from_secs(30))?)?;
+    if !is_newer(&latest, CURRENT) {
+        println!("  gh-sparkle {CURRENT} is the latest version.");
+        return Ok(());
+    }
+    println!("  gh-sparkle {latest} is available (installed: {CURRENT}).");
+    if check_only {
+        return Ok(());
+    }
+
+    // The upgraded binary renders its own page, so it runs `man --install`.
+    let mut upgraded = match install_kind(&std::env::current_exe()?) {
+        Install::GhExtension => {
+            let status = Command::new("gh")
+                .args(["extension", "upgrade", "sparkle"])
+                .status()?;
+            if !status.success() {
+                return Err(format!("gh extension upgrade failed with status {status}").into());
+            }
+            let mut command = Command::new("gh");
+            command.arg("sparkle");
+            command
+        }
+        Install::Standalone(exe) => {
+            let asset = asset_name().ok_or_else(|| {
+                format!(
+                    "no release binary for {}/{}; rebuild with `cargo install --git \
+                     https://github.com/{REPO}`",
+                    std::env::consts::OS,
+                    std::env::consts::ARCH
+                )
+            })?;
+            replace_binary(&exe, &latest, asset)?;
+            println!("  Upgraded {} to {latest}.", exe.display());
+            Command::new(exe)
+        }
+    };
+    if manpage::install_path("gh-sparkle").is_ok_and(|path| path.exists()) {
+        let refreshed = upgraded
+            .args(["man", "--install"])
+            .status()
+            .is_ok_and(|status| status.success());
+        if !refreshed {
+            eprintln!("  Could not refresh the man page; run `gh sparkle man --install`.");
+        }
+    }
+    Ok(())
+}
+
+/// Mentions a newer release on stderr, looking it up at most once a week.
+/// Any failure is silent: the notice must never get in the way of a commit.
+pub fn weekly_notice() {
+    let Some(path) = auth::cache_dir().map(|dir| dir.join("update-check")) else {
+        return;
+    };
+    let now = SystemTime::now();
+    let checked = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+    if checked.is_some_and(|checked| {
+        now.duration_since(checked)
+            .is_ok_and(|age| age < NOTICE_INTERVAL)
+    }) {
+        return;
+    }
+
+    let latest = http(Duration::from_secs(5))
+        .and_then(|http| latest_release(&http))
+        .ok();
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    // Only the modification time of this file matters.
+    let _ = fs::write(&path, "");
+    if let Some(latest) = latest.filter(|latest| is_newer(latest, CURRENT)) {
+        eprintln!(
+            "sparkle: gh-sparkle {latest} is available (installed: {CURRENT}); run `gh sparkle upgrade`"
+        );
+    }
+}
+
+fn http(timeout: Duration) -> Result<HttpClient, Box<dyn Error>> {
+    Ok(HttpClient::builder()
+        .connect_timeout(timeout.min(Duration::from_secs(10)))
+        .timeout(timeout)
+        .user_agent(concat!("gh-sparkle/", env!("CARGO_PKG_VERSION")))
+        .build()?)
+}
+
+fn latest_release(http: &HttpClient) -> Result<String, Box<dyn Error>> {
+    let response = http
+        .get(format!(
+            "https://api.github.com/repos/{REPO}/releases/latest"
+        ))
+        .header("Accept", "application/vnd.github+json")
+        .send()?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("could not look up the latest release (status {status})").into());
+    }
+    Ok(response.json::<Release>()?.tag_name)
+}
+
+/// Downloads `asset` of release `tag` next to `exe`, checks it against the
+/// release's `<asset>.sha256`, then swaps it in.
+fn replace_binary(exe: &Path, tag: &str, asset: &str) -> Result<(), Box<dyn Error>> {
+    let http = http(Duration::from_secs(300))?;
+    let base = format!("https://github.com/{REPO}/releases/download/{tag}");
+    let bytes = download(&http, &format!("{base}/{asset}"))?;
+    let checksums = download(&http, &format!("{base}/{asset}.sha256"))?;
+    verify_checksum(&bytes, &String::from_utf8_lossy(&checksums), asset)?;
+
+    let staged = exe.with_extension("new");
+    fs::write(&staged, &bytes)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+    }
+    // A running executable cannot be overwritten on Windows, but it can be
+    // renamed out of the way.
+    #[cfg(windows)]
+    {
+        let old = exe.with_extension("old");
+        let _ = fs::remove_file(&old);
+        fs::rename(exe, &old)?;
+    }
+    fs::rename(&staged, exe)?;
+    Ok(())
+}
+
+fn download(http: &HttpClient, url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let response = http.get(url).send()?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("downloading {url} failed with status {status}").into());
+    }
+    Ok(response.bytes()?.to_vec())
+}
+
+/// Fails unless `bytes` hash to the digest `checksums` lists for `asset`,
+/// in `sha256sum` format (`<hex>  <name>`, or a bare digest).
+fn verify_checksum(bytes: &[u8], checksums: &str, asset: &str) -> Result<(), Box<dyn Error>> {
+    let expected = checksums
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let digest = fields.next()?;
+            let name = fields.next().map(|name| name.trim_start_matches('*'));
+            name.is_none_or(|name| name == asset).then_some(digest)
+        })
+        .next()
+        .ok_or_else(|| format!("{asset}.sha256 does not list a checksum for {asset}"))?;
+    let actual = audit::sha256_hex(bytes);
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(format!(
+            "{asset} does not match its published SHA-256 (expected {expected}, got {actual}); \
+             the binary was left unchanged"
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The release asset built for this platform, as named by release.yml.
+fn asset_name() -> Option<&'static str> {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => Some("gh-sparkle-linux-amd64"),
+        ("macos", "x86_64") => Some("gh-sparkle-darwin-amd64"),
+        ("macos", "aarch64") => Some("gh-sparkle-darwin-arm64"),
+        ("windows", "x86_64") => Some("gh-sparkle-windows-amd64.exe"),
+        _ => None,
+    }
+}
+
+fn install_kind(exe: &Path) -> Install {
+    let path = exe.to_string_lossy().replace('\\', "/");
+    // gh keeps extensions in `gh/extensions` under the XDG data directory,
+    // or `GitHub CLI\extensions` under %LOCALAPPDATA% on Windows.
+    if path.contains("/extensions/gh-sparkle/") {
+        Install::GhExtension
+    } else {
+        Install::Standalone(exe.to_path_buf())
+    }
+}
+
+/// Whether release tag `latest` (e.g. `v1.2.0`) is newer than `current`.
+/// Unparseable versions never count as newer.
+fn is_newer(latest: &str, current: &str) -> bool {
+    match (parse_version(latest), parse_version(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.trim().trim_start_matches('v');
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.').map(|part| part.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    let patch = parts.next().unwrap_or(Some(0))?;
+    Some((major, minor, patch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_release_tags_with_the_installed_version() {
+        assert!(is_newer("v0.2.0", "0.1.0"));
+        assert!(is_newer("v0.1.10", "0.1.9"));
+        assert!(is_newer("1", "0.9.9"));
+        assert!(!is_newer("v0.1.0", "0.1.0"));
+        assert!(!is_newer("v0.1.0-rc.1", "0.1.0"));
+        assert!(!is_newer("nightly", "0.1.0"));
+    }
+
+    #[test]
+    fn verify_checksum_matches_the_listed_digest() {
+        let digest = audit::sha256_hex(b"binary");
+        let asset = "gh-sparkle-linux-amd64";
+        assert!(verify_checksum(b"binary", &format!("{digest}  {asset}\n"), asset).is_ok());
+        assert!(verify_checksum(b"binary", &format!("{}\n", digest.to_uppercase()), asset).is_ok());
+        assert!(verify_checksum(b"binary", &format!("{digest} *{asset}\n"), asset).is_ok());
+
+        let err = verify_checksum(b"tampered", &format!("{digest}  {asset}\n"), asset);
+        assert!(err.unwrap_err().to_string().contains("does not match"));
+        let other = format!("{digest}  gh-sparkle-darwin-arm64\n");
+        assert!(verify_checksum(b"binary", &other, asset).is_err());
+        assert!(verify_checksum(b"binary", "", asset).is_err());
+    }
+
+    #[test]
+    fn detects_gh_extension_installs() {
+        assert_eq!(
+            install_kind(Path::new(
+                "/home/me/.local/share/gh/extensions/gh-sparkle/gh-sparkle"
+            )),
+            Install::GhExtension
+        );
+        assert_eq!(
+            install_kind(Path::new(
+                r"C:\Users\me\AppData\Local\GitHub CLI\extensions\gh-sparkle\gh-sparkle.exe"
+            )),
+            Install::GhExtension
+        );
+        assert_eq!(
+            install_kind(Path::new("/usr/local/bin/gh-sparkle")),
+            Install::Standalone(PathBuf::from("/usr/local/bin/gh-sparkle"))
+        );
+    }
+}
@@ -0,0 +1,283 @@
+// Background drafting of commit messages while changes are being staged.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config;
+use crate::failure::{Classify, FailureKind};
+use crate::git::{self, GitBackend};
+use crate::llm::{self, LlmProvider};
+use crate::session::Session;
+use crate::{
+    Cli, Draft, GenerationContext, blocked_staged_paths, convention_document, fetch_examples,
+    generation_notes, issue_context, load_prompt_config, new_llm_client, parse_examples_count,
+    parse_language, produce_commit_message, project_docs, recent_subjects, resolve_issue,
+    resolve_model_chain, scrub,
+};
+
+const DRAFT_FILE: &str = "sparkle/draft.json";
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const BUDGET_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// A draft prepared by `sparkle watch`, valid only for the inputs it was
+/// generated from.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedDraft {
+    key: u64,
+    message: String,
+    session: Session,
+    model: String,
+    fallback: bool,
+}
+
+/// Identifies everything that shapes a generated message, so a cached draft
+/// is only reused for the same staged changes, flags, and prompt.
+pub fn draft_key(context: &GenerationContext<'_>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    context.staged_summary.hash(&mut hasher);
+    context.staged_changes.hash(&mut hasher);
+    context.model_chain.hash(&mut hasher);
+    context.language.describe().hash(&mut hasher);
+    context.examples.hash(&mut hasher);
+    context.notes.hash(&mut hasher);
+    context.docs.hash(&mut hasher);
+    context.issue.hash(&mut hasher);
+    context.convention.hash(&mut hasher);
+    for message in &context.prompt_config.messages {
+        message.role.hash(&mut hasher);
+        message.content.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn draft_path() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(git::git_dir()?.join(DRAFT_FILE))
+}
+
+fn save_draft(key: u64, draft: &Draft) -> Result<(), Box<dyn Error>> {
+    let path = draft_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let cached = CachedDraft {
+        key,
+        message: draft.message.clone(),
+        session: draft.session.clone(),
+        model: draft.model.clone(),
+        fallback: draft.fallback,
+    };
+    fs::write(path, serde_json::to_string(&cached)?)?;
+    Ok(())
+}
+
+/// Returns and removes the watched draft when it was made for `key`.
+pub fn take_draft(key: u64) -> Option<Draft> {
+    let path = draft_path().ok()?;
+    let cached: CachedDraft = serde_json::from_str(&fs::read_to_string(&path).ok()?).ok()?;
+    if cached.key != key {
+        return None;
+    }
+    let _ = fs::remove_file(&path);
+    Some(Draft {
+        message: cached.message,
+        session: cached.session,
+        model: cached.model,
+        fallback: cached.fallback,
+        refinements: 0,
+    })
+}
+
+/// Estimated prompt tokens spent over the last hour.
+struct TokenBudget {
+    limit: usize,
+    spent: VecDeque<(Instant, usize)>,
+}
+
+impl TokenBudget {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            spent: VecDeque::new(),
+        }
+    }
+
+    fn allows(&mut self, now: Instant, tokens: usize) -> bool {
+        while let Some((at, _)) = self.spent.front() {
+            if now.duration_since(*at) < BUDGET_WINDOW {
+                break;
+            }
+            self.spent.pop_front();
+        }
+        let used: usize = self.spent.iter().map(|(_, tokens)| tokens).sum();
+        self.limit == 0 || used + tokens <= self.limit
+    }
+
+    fn record(&mut self, now: Instant, tokens: usize) {
+        self.spent.push_back((now, tokens));
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Polls the index and drafts a message once staging has settled. Runs until
+/// interrupted. Config edits apply without a restart, except for network
+/// settings.
+pub fn run(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    let mut prompt_config = load_prompt_config().classify(FailureKind::Config)?;
+    let mut reloader = config::Reloader::new();
+    let llm_client = new_llm_client(cli, &prompt_config.network).classify(FailureKind::Auth)?;
+    llm_client.warm_up();
+    let index = git::git_dir().classify(FailureKind::Git)?.join("index");
+
+    println!("  Watching the index for staged changes; press Ctrl-C to stop.");
+    let mut budget = TokenBudget::new(prompt_config.watch.max_tokens_per_hour);
+    let mut seen = modified(&index);
+    // Draft whatever is already staged once the watch starts.
+    let mut changed_at = Some(Instant::now());
+    let mut last_draft: Option<(Instant, u64)> = None;
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        match reloader.poll() {
+            Some(Ok(config)) => {
+                println!("  Config reloaded.");
+                prompt_config = config;
+                budget.limit = prompt_config.watch.max_tokens_per_hour;
+                changed_at = Some(Instant::now());
+            }
+            Some(Err(err)) => println!("  Warning: keeping the previous config: {err}"),
+            None => {}
+        }
+        let current = modified(&index);
+        if current != seen {
+            seen = current;
+            changed_at = Some(Instant::now());
+        }
+        let Some(at) = changed_at else {
+            continue;
+        };
+        let limits = &prompt_config.watch;
+        let min_interval = Duration::from_secs(limits.min_interval_seconds);
+        if at.elapsed() < Duration::from_millis(limits.debounce_millis)
+            || last_draft.is_some_and(|(drafted, _)| drafted.elapsed() < min_interval)
+        {
+            continue;
+        }
+        changed_at = None;
+
+        let last_key = last_draft.map(|(_, key)| key);
+        match draft_staged(cli, &prompt_config, &llm_client, &mut budget, last_key) {
+            Ok(Some(key)) => last_draft = Some((Instant::now(), key)),
+            Ok(None) => {}
+            Err(err) => println!("  Warning: could not draft a message: {err}"),
+        }
+        if let Some(stats) = llm_client.connection_stats().filter(|_| cli.debug) {
+            eprintln!("  {stats}");
+        }
+    }
+}
+
+/// Drafts a message for the staged changes unless it already exists or the
+/// hourly budget is spent. Returns the key of a new draft.
+fn draft_staged(
+    cli: &Cli,
+    prompt_config: &crate::prompt::PromptConfig,
+    llm_client: &llm::Client,
+    budget: &mut TokenBudget,
+    last_key: Option<u64>,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    let repo = git::Repo::current();
+    let staged = repo.staged_diff()?;
+    if staged.patch.trim().is_empty()
+        || (!cli.allow_blocked && !blocked_staged_paths(prompt_config, &repo).is_empty())
+    {
+        return Ok(None);
+    }
+
+    let examples_count = parse_examples_count(cli.examples.clone())?;
+    let examples = if examples_count > 0 {
+        let examples = fetch_examples(examples_count, cli.examples_subjects_only)?;
+        scrub::scrub_examples(&examples, &prompt_config.examples)
+    } else {
+        String::new()
+    };
+    let language_spec = parse_language(&cli.language)?;
+    let model_chain = resolve_model_chain(&cli.model, &prompt_config.model_policy)?;
+    let recent_subjects = recent_subjects(&repo, &prompt_config.output)?;
+    let issue = resolve_issue(cli.closes, |_| {});
+    let notes = generation_notes(
+        cli,
+        &prompt_config.context_policy,
+        &staged.patch,
+        issue.as_ref(),
+        |_| {},
+    );
+    let docs = project_docs(prompt_config, &staged.patch);
+    let issue_text = issue_context(prompt_config, issue.as_ref());
+    let convention = convention_document(prompt_config);
+    let context = GenerationContext {
+        examples: &examples,
+        recent_subjects: &recent_subjects,
+        notes: &notes,
+        docs: &docs,
+        issue: &issue_text,
+        convention: &convention,
+        ..GenerationContext::new(
+            prompt_config,
+            &staged.summary,
+            &staged.patch,
+            &model_chain,
+            &language_spec,
+        )
+    };
+    let key = draft_key(&context);
+    if last_key == Some(key) {
+        return Ok(None);
+    }
+
+    let ratio = prompt_config.context_policy.token_char_ratio.max(1);
+    let estimate = (staged.summary.len() + staged.patch.len() + examples.len()) / ratio;
+    if !budget.allows(Instant::now(), estimate) {
+        println!("  Hourly token budget reached; not drafting until it frees up.");
+        return Ok(None);
+    }
+
+    println!("  Drafting a message for the staged changes...");
+    let draft = produce_commit_message(llm_client, &context, |_| {}, |_| false)?;
+    budget.record(Instant::now(), draft.session.prompt().char_count() / ratio);
+    save_draft(key, &draft)?;
+    println!(
+        "  Draft ready: {}",
+        draft.message.lines().next().unwrap_or_default()
+    );
+    Ok(Some(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_budget_frees_up_after_an_hour() {
+        let start = Instant::now();
+        let mut budget = TokenBudget::new(1000);
+        assert!(budget.allows(start, 800));
+        budget.record(start, 800);
+        assert!(!budget.allows(start + Duration::from_secs(60), 300));
+        assert!(budget.allows(start + Duration::from_secs(60), 200));
+        assert!(budget.allows(start + BUDGET_WINDOW, 900));
+
+        let mut unlimited = TokenBudget::new(0);
+        unlimited.record(start, usize::MAX / 2);
+        assert!(unlimited.allows(start, usize::MAX / 2));
+    }
+}